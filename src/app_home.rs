@@ -0,0 +1,442 @@
+//! The Slack app of the flow: its App Home tab, a dashboard of the
+//! schedules, subscriptions, recent digests and token spend with quick
+//! actions, and the requests of its shortcuts.
+
+use crate::audit::{audit, load_audit_log};
+use crate::command::{handler, last_run_key, LastRun};
+use crate::config::{self, channel_timezone, is_slack, served_channels};
+use crate::http::request_json;
+use crate::logging::{start_run, Event};
+use crate::modal::{compose_command, open_compose_modal, COMPOSE_CALLBACK};
+use crate::ops::{report_ops, OpsFailure};
+use crate::schedule::{load_schedules, load_subscriptions, save_schedules, Schedule};
+use crate::shortcut::{summarize_link, SUMMARIZE_LINK_CALLBACK};
+use crate::sink::send_message;
+use crate::slash::{handle_slash_command, repo_options};
+use crate::store;
+use chrono::{Duration, Utc, Weekday};
+use hmac_sha256::HMAC;
+use http_req::request::Method;
+use serde_json::{json, Value};
+use slack_flows::SlackMessage;
+use std::collections::HashMap;
+use std::time::Instant;
+use webhook_flows::send_response;
+
+/// How old a signed request may be, against replays.
+const SIGNATURE_MAX_AGE_SECS: i64 = 5 * 60;
+
+/// Slack's limit for the blocks of a view.
+const VIEW_BLOCK_LIMIT: usize = 100;
+
+/// The options of the schedule menu, with the weekday they set.
+const SCHEDULE_OPTIONS: [(&str, &str); 8] = [
+    ("daily", "Every day"),
+    ("mon", "Every Monday"),
+    ("tue", "Every Tuesday"),
+    ("wed", "Every Wednesday"),
+    ("thu", "Every Thursday"),
+    ("fri", "Every Friday"),
+    ("sat", "Every Saturday"),
+    ("sun", "Every Sunday"),
+];
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+/// Whether the request is from the Slack app, which signs every request.
+pub(crate) fn is_slack_request(headers: &[(String, String)]) -> bool {
+    header(headers, "x-slack-signature").is_some()
+}
+
+/// Whether the App Home is set up, with the token of the Slack app's bot
+/// in `slack_bot_token`.
+pub(crate) fn app_home_enabled() -> bool {
+    config::var("slack_bot_token").is_some_and(|token| !token.trim().is_empty())
+}
+
+/// Whether the request is signed with the `slack_signing_secret` of the
+/// Slack app: `v0=` and the hex HMAC-SHA256 of `v0:<timestamp>:<body>`, in
+/// the `X-Slack-Signature` header.
+fn is_signed(headers: &[(String, String)], body: &[u8]) -> bool {
+    let (Some(secret), Some(timestamp), Some(signature)) = (
+        config::var("slack_signing_secret"),
+        header(headers, "x-slack-request-timestamp"),
+        header(headers, "x-slack-signature"),
+    ) else {
+        return false;
+    };
+    let fresh = timestamp
+        .parse::<i64>()
+        .is_ok_and(|t| (Utc::now().timestamp() - t).abs() <= SIGNATURE_MAX_AGE_SECS);
+    let mut signed = format!("v0:{timestamp}:").into_bytes();
+    signed.extend_from_slice(body);
+    let mac = HMAC::mac(&signed, secret.trim().as_bytes());
+    let expected = format!(
+        "v0={}",
+        mac.iter().map(|b| format!("{b:02x}")).collect::<String>()
+    );
+    fresh && signature.trim() == expected
+}
+
+fn respond(status: u16, body: &str) {
+    send_response(
+        status,
+        vec![("Content-Type".to_string(), "text/plain".to_string())],
+        body.as_bytes().to_vec(),
+    );
+}
+
+fn respond_json(body: &Value) {
+    send_response(
+        200,
+        vec![("Content-Type".to_string(), "application/json".to_string())],
+        body.to_string().into_bytes(),
+    );
+}
+
+fn text(text: &str) -> Value {
+    json!({ "type": "mrkdwn", "text": text })
+}
+
+fn button(label: &str, action_id: &str, value: &str) -> Value {
+    json!({
+        "type": "button",
+        "text": { "type": "plain_text", "text": label },
+        "action_id": action_id,
+        "value": value,
+    })
+}
+
+fn schedule_menu(channel: &str, index: usize, schedule: &Schedule) -> Value {
+    let option = |(value, label): (&str, &str)| {
+        json!({
+            "text": { "type": "plain_text", "text": label },
+            "value": format!("{channel}|{index}|{value}"),
+        })
+    };
+    let current = match schedule.weekday {
+        Some(day) => day.to_string().to_lowercase(),
+        None => "daily".to_string(),
+    };
+    let mut menu = json!({
+        "type": "static_select",
+        "action_id": "change_schedule",
+        "placeholder": { "type": "plain_text", "text": "Change schedule" },
+        "options": SCHEDULE_OPTIONS.map(option),
+    });
+    if let Some(selected) = SCHEDULE_OPTIONS.iter().find(|(v, _)| *v == current) {
+        menu["initial_option"] = option(*selected);
+    }
+    menu
+}
+
+/// The blocks of the user's App Home: the token spend, and for every
+/// served Slack channel its last digest, schedules and subscriptions.
+fn home_blocks(user: &str) -> Vec<Value> {
+    let week_ago = Utc::now() - Duration::days(7);
+    let log = load_audit_log();
+    let recent = log.iter().filter(|entry| entry.at >= week_ago);
+    let (mine, everyone) = recent.fold((0, 0), |(mine, everyone), entry| {
+        match entry.actor == user {
+            true => (mine + entry.tokens, everyone + entry.tokens),
+            false => (mine, everyone + entry.tokens),
+        }
+    });
+
+    let mut blocks = vec![
+        json!({ "type": "header", "text": { "type": "plain_text", "text": "Issue summaries" } }),
+        json!({ "type": "section", "text": text(&format!(
+            "*Token spend, last 7 days:* {mine} tokens by you, {everyone} in all"
+        )) }),
+    ];
+
+    for (workspace, channel) in served_channels().into_iter().filter(|(ws, _)| is_slack(ws)) {
+        let tz = channel_timezone(&workspace, &channel);
+        blocks.push(json!({ "type": "divider" }));
+        let last = match store::load::<LastRun>(&last_run_key(&workspace, &channel)) {
+            Some(run) => format!(
+                "last digest: {}/{} at {}, {}",
+                run.owner,
+                run.repo,
+                run.finished_at
+                    .with_timezone(&tz)
+                    .format("%Y-%m-%d %H:%M %Z"),
+                run.outcome
+            ),
+            None => "no digest yet".to_string(),
+        };
+        blocks.push(json!({ "type": "section", "text": text(&format!("*#{channel}*\n{last}")) }));
+
+        for (i, schedule) in load_schedules(&workspace, &channel).iter().enumerate() {
+            let run = format!(
+                "{channel}|{}/{}|{}",
+                schedule.owner, schedule.repo, schedule.days
+            );
+            blocks.push(json!({ "type": "section", "text": text(&schedule.describe(tz)) }));
+            blocks.push(json!({
+                "type": "actions",
+                "elements": [
+                    button("Run now", "run_now", &run),
+                    schedule_menu(&channel, i, schedule),
+                ],
+            }));
+        }
+        for subscription in load_subscriptions(&workspace, &channel) {
+            let run = format!(
+                "{channel}|{}/{}|{}",
+                subscription.owner,
+                subscription.repo,
+                subscription.frequency.days()
+            );
+            blocks.push(json!({
+                "type": "section",
+                "text": text(&format!("subscription: {}", subscription.describe(tz))),
+                "accessory": button("Run now", "run_now", &run),
+            }));
+        }
+    }
+
+    if blocks.len() > VIEW_BLOCK_LIMIT {
+        blocks.truncate(VIEW_BLOCK_LIMIT - 1);
+        blocks.push(json!({ "type": "context", "elements": [text(
+            "More channels are served than the tab can show, see `flows status` in each."
+        )] }));
+    }
+    blocks
+}
+
+/// Calls the method of Slack's Web API as the bot of `slack_bot_token`.
+/// Slack answers errors with `ok: false` rather than a status.
+pub(crate) fn slack_api(method: &str, body: &Value) -> Result<Value, String> {
+    let token = config::var("slack_bot_token").ok_or("slack_bot_token is not set".to_string())?;
+    let authorization = format!("Bearer {}", token.trim());
+    request_json(
+        Method::POST,
+        &format!("https://slack.com/api/{method}"),
+        &[("Authorization", authorization.as_str())],
+        Some(body),
+    )
+    .and_then(|answer| match answer["ok"].as_bool() {
+        Some(true) => Ok(answer),
+        _ => Err(answer["error"]
+            .as_str()
+            .unwrap_or("unknown error")
+            .to_string()),
+    })
+    .inspect_err(|e| {
+        if matches!(
+            e.as_str(),
+            "invalid_auth" | "not_authed" | "token_revoked" | "token_expired" | "account_inactive"
+        ) {
+            report_ops(
+                OpsFailure::SlackAuth,
+                &format!("`{method}` was answered with `{e}`."),
+            );
+        }
+    })
+}
+
+/// Publishes the App Home of the user through `views.publish`.
+fn publish_home(user: &str) {
+    let view = json!({
+        "user_id": user,
+        "view": { "type": "home", "blocks": home_blocks(user) },
+    });
+    if let Err(e) = slack_api("views.publish", &view) {
+        Event::error("app-home")
+            .err(e)
+            .log("the App Home could not be published");
+    }
+}
+
+/// The served Slack channel of the name, with its workspace.
+pub(crate) fn slack_channel(name: &str) -> Option<(String, String)> {
+    served_channels()
+        .into_iter()
+        .find(|(ws, ch)| is_slack(ws) && ch == name)
+}
+
+/// The words that start a digest, as in `flows summarize`.
+pub(crate) fn trigger_word() -> String {
+    config::var("trigger_word").unwrap_or("flows summarize".to_string())
+}
+
+/// Runs the digest of the button in its channel.
+fn run_now(user: &str, value: &str) {
+    let mut parts = value.splitn(3, '|');
+    let (Some(channel), Some(repo), Some(days)) = (parts.next(), parts.next(), parts.next()) else {
+        return;
+    };
+    let Some((workspace, channel)) = slack_channel(channel) else {
+        return;
+    };
+    run_as_command(
+        user,
+        &workspace,
+        &channel,
+        &format!("{} {repo} {days}", trigger_word()),
+    );
+}
+
+/// Runs the command in the channel like the message of the user, so it goes
+/// through the same permissions and quotas.
+pub(crate) fn run_as_command(user: &str, workspace: &str, channel: &str, text: &str) {
+    let sm = SlackMessage {
+        event_type: "message".to_string(),
+        channel: channel.to_string(),
+        user: user.to_string(),
+        text: text.to_string(),
+        channel_type: "channel".to_string(),
+    };
+    let started = Instant::now();
+    match handler(workspace, channel, sm) {
+        Ok(()) => Event::info("command").took(started).log("handled"),
+        Err(e) => {
+            Event::error("command").took(started).err(&e).log("failed");
+            send_message(workspace, channel, format!(":warning: {e}"));
+        }
+    }
+}
+
+/// Moves the schedule of the menu to every day or to another weekday, at
+/// the same hour, and tells its channel.
+fn change_schedule(user: &str, value: &str) {
+    let mut parts = value.splitn(3, '|');
+    let (Some(channel), Some(index), Some(when)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return;
+    };
+    let (Some((workspace, channel)), Ok(index)) = (slack_channel(channel), index.parse::<usize>())
+    else {
+        return;
+    };
+    let weekday = match when {
+        "daily" => None,
+        day => match day.parse::<Weekday>() {
+            Ok(day) => Some(day),
+            Err(_) => return,
+        },
+    };
+    let mut schedules = load_schedules(&workspace, &channel);
+    let Some(schedule) = schedules.get_mut(index) else {
+        return;
+    };
+    schedule.weekday = weekday;
+    let description = schedule.describe(channel_timezone(&workspace, &channel));
+    let repo = (schedule.owner.clone(), schedule.repo.clone());
+    save_schedules(&workspace, &channel, &schedules);
+    audit(
+        user,
+        "changed a schedule in the App Home",
+        Some((&repo.0, &repo.1)),
+        &description,
+        false,
+    );
+    send_message(
+        &workspace,
+        &channel,
+        format!("<@{user}> changed the scheduled digest to {description}."),
+    );
+}
+
+/// Handles a request of the Slack app: the URL verification, the
+/// `app_home_opened` event, the actions of the App Home, the slash command
+/// and the shortcuts. Slack waits only 3 seconds for the response, so
+/// the request is acknowledged before it is handled.
+pub(crate) fn handle_slack_request(headers: Vec<(String, String)>, body: Vec<u8>) {
+    start_run("app-home");
+    if !is_signed(&headers, &body) {
+        Event::warn("app-home").log("refused a request without a valid signature");
+        respond(
+            401,
+            "The request is not signed with the slack_signing_secret.",
+        );
+        return;
+    }
+
+    // slash commands arrive as a form, actions as a form with the JSON in
+    // `payload`, events as JSON
+    let fields = form_urlencoded::parse(&body)
+        .into_owned()
+        .collect::<HashMap<String, String>>();
+    if fields.contains_key("command") {
+        handle_slash_command(&fields);
+        return;
+    }
+    let request = match fields.get("payload") {
+        Some(payload) => serde_json::from_str::<Value>(payload),
+        None => serde_json::from_slice::<Value>(&body),
+    };
+    let Ok(request) = request else {
+        respond(400, "The request is not a Slack event or action.");
+        return;
+    };
+
+    match request["type"].as_str() {
+        Some("url_verification") => {
+            respond(200, request["challenge"].as_str().unwrap_or(""));
+        }
+        Some("event_callback") => {
+            respond(200, "");
+            let event = &request["event"];
+            if event["type"] == "app_home_opened" && event["tab"] == "home" {
+                if let Some(user) = event["user"].as_str() {
+                    publish_home(user);
+                }
+            }
+        }
+        Some("block_actions") => {
+            respond(200, "");
+            let Some(user) = request["user"]["id"].as_str() else {
+                return;
+            };
+            let action = &request["actions"][0];
+            match action["action_id"].as_str() {
+                Some("run_now") => run_now(user, action["value"].as_str().unwrap_or("")),
+                Some("change_schedule") => change_schedule(
+                    user,
+                    action["selected_option"]["value"].as_str().unwrap_or(""),
+                ),
+                _ => {}
+            }
+            publish_home(user);
+        }
+        Some("shortcut" | "message_action") if request["callback_id"] == COMPOSE_CALLBACK => {
+            respond(200, "");
+            let Some(trigger_id) = request["trigger_id"].as_str() else {
+                return;
+            };
+            open_compose_modal(trigger_id, request["channel"]["name"].as_str());
+        }
+        Some("block_suggestion") if request["block_id"] == "repo" => {
+            respond_json(&repo_options(request["value"].as_str().unwrap_or("")));
+        }
+        Some("message_action") if request["callback_id"] == SUMMARIZE_LINK_CALLBACK => {
+            respond(200, "");
+            summarize_link(request);
+        }
+        Some("view_submission") if request["view"]["callback_id"] == COMPOSE_CALLBACK => {
+            let Some(user) = request["user"]["id"].as_str() else {
+                respond(200, "");
+                return;
+            };
+            match compose_command(&request["view"]["state"]["values"]) {
+                Ok((workspace, channel, command)) => {
+                    // an empty answer closes the modal
+                    respond(200, "");
+                    run_as_command(user, &workspace, &channel, &command);
+                }
+                Err(errors) => respond_json(&json!({
+                    "response_action": "errors",
+                    "errors": errors,
+                })),
+            }
+        }
+        _ => respond(200, ""),
+    }
+}
@@ -0,0 +1,84 @@
+//! The audit log of commands, scheduled runs and actions taken on GitHub.
+
+use crate::logging::take_tokens;
+use crate::store;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+pub(crate) const AUDIT_LOG_KEY: &str = "audit_log";
+
+pub(crate) const AUDIT_LOG_KEPT: usize = 1000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct AuditEntry {
+    pub(crate) at: DateTime<Utc>,
+    /// The Slack user id of a command, `schedule` for scheduled runs or
+    /// `flow` for actions the flow took on its own.
+    pub(crate) actor: String,
+    pub(crate) action: String,
+    /// The `<github_owner>/<github_repo>` acted on, if any.
+    pub(crate) repo: Option<String>,
+    pub(crate) outcome: String,
+    /// Estimated tokens the model used for it.
+    pub(crate) tokens: usize,
+}
+
+impl AuditEntry {
+    pub(crate) fn describe(&self) -> String {
+        let repo = self
+            .repo
+            .as_deref()
+            .map(|repo| format!(" on {repo}"))
+            .unwrap_or_default();
+        let actor = match self.actor.as_str() {
+            "schedule" | "flow" => self.actor.clone(),
+            user => format!("<@{user}>"),
+        };
+        format!(
+            "{} {actor}: {}{repo}, {} ({} tokens)",
+            self.at.format("%Y-%m-%d %H:%M UTC"),
+            self.action,
+            self.outcome,
+            self.tokens
+        )
+    }
+
+    /// Whether the entry is by the Slack user or on the repository.
+    pub(crate) fn matches(&self, filter: &str) -> bool {
+        let filter = filter.trim_start_matches("<@").trim_end_matches('>');
+        self.actor.eq_ignore_ascii_case(filter)
+            || self
+                .repo
+                .as_deref()
+                .is_some_and(|repo| repo.eq_ignore_ascii_case(filter))
+    }
+}
+
+pub(crate) fn load_audit_log() -> Vec<AuditEntry> {
+    store::load(AUDIT_LOG_KEY).unwrap_or_default()
+}
+
+/// Appends an entry to the audit log, charging it the tokens the run used
+/// since the last entry of a trigger. Automated actions on GitHub are
+/// recorded without tokens, which belong to the trigger they are part of.
+pub(crate) fn audit(
+    actor: &str,
+    action: &str,
+    repo: Option<(&str, &str)>,
+    outcome: &str,
+    charge_tokens: bool,
+) {
+    let mut log = load_audit_log();
+    log.push(AuditEntry {
+        at: Utc::now(),
+        actor: actor.to_string(),
+        action: action.to_string(),
+        repo: repo.map(|(owner, repo)| format!("{owner}/{repo}")),
+        outcome: outcome.to_string(),
+        tokens: if charge_tokens { take_tokens() } else { 0 },
+    });
+    if log.len() > AUDIT_LOG_KEPT {
+        log.drain(0..log.len() - AUDIT_LOG_KEPT);
+    }
+    store::save(AUDIT_LOG_KEY, &log);
+}
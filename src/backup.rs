@@ -0,0 +1,186 @@
+//! Export and import of the stored state, to migrate or recover a deployment.
+
+use crate::audit::AUDIT_LOG_KEY;
+use crate::command::{last_run_key, trends_key};
+use crate::config::{
+    channel_settings_key, env_repos, home_channel, issue_routes, load_channel_settings,
+    load_routes, org_settings_key, routes_key, served_channels, slack_users_key, timezone_key,
+    OPT_OUTS_KEY,
+};
+use crate::feed::{FEED_GIST_KEY, FEED_KEY};
+use crate::github::{label_audit_key, watchlist_channels_key, watchlist_key};
+use crate::schedule::{
+    load_schedules, load_subscriptions, rotations_key, schedules_key, slas_key, snoozes_key,
+    subscriptions_key,
+};
+use crate::store;
+use crate::summarizer::history_key;
+use github_flows::{get_octo, GithubLogin::Default};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+
+pub(crate) const EXPORT_FILE: &str = "summarizer-state.json";
+
+/// The exported records by key, as they are stored.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct StateExport {
+    pub(crate) exported_at: String,
+    pub(crate) records: BTreeMap<String, Value>,
+}
+
+/// The store cannot list its keys, so the keys of the durable state are
+/// derived from the served channels and the repositories they know about.
+/// Caches, counters and markers that expire are left out, they are rebuilt
+/// as the flow runs.
+pub(crate) fn state_keys() -> Vec<String> {
+    let channels = served_channels();
+    let mut keys = BTreeSet::from([
+        OPT_OUTS_KEY.to_string(),
+        AUDIT_LOG_KEY.to_string(),
+        FEED_KEY.to_string(),
+        FEED_GIST_KEY.to_string(),
+    ]);
+    let mut repos = BTreeSet::new();
+
+    for (workspace, channel) in &channels {
+        keys.extend([
+            routes_key(workspace),
+            org_settings_key(workspace),
+            slack_users_key(workspace),
+            snoozes_key(workspace),
+            watchlist_channels_key(workspace),
+            channel_settings_key(workspace, channel),
+            timezone_key(workspace, channel),
+            schedules_key(workspace, channel),
+            subscriptions_key(workspace, channel),
+            slas_key(workspace, channel),
+            rotations_key(workspace, channel),
+            watchlist_key(workspace, channel),
+            last_run_key(workspace, channel),
+        ]);
+
+        repos.extend(
+            load_schedules(workspace, channel)
+                .into_iter()
+                .map(|s| (s.owner, s.repo)),
+        );
+        repos.extend(
+            load_subscriptions(workspace, channel)
+                .into_iter()
+                .map(|s| (s.owner, s.repo)),
+        );
+        repos.extend(
+            load_routes(workspace)
+                .into_iter()
+                .map(|r| (r.owner, r.repo)),
+        );
+        if let Some((owner, repo)) = load_channel_settings(workspace, channel)
+            .default_repo
+            .as_deref()
+            .and_then(|r| r.split_once('/'))
+        {
+            repos.insert((owner.to_string(), repo.to_string()));
+        }
+    }
+    if let Some((workspace, _)) = channels.first() {
+        repos.extend(
+            issue_routes(workspace, &home_channel().1)
+                .into_iter()
+                .map(|r| (r.owner, r.repo)),
+        );
+    }
+    for name in ["publish_repos", "stale_pr_repos", "auto_label_repos"] {
+        repos.extend(env_repos(name));
+    }
+
+    for (owner, repo) in &repos {
+        keys.extend([
+            history_key(owner, repo),
+            trends_key(owner, repo),
+            label_audit_key(owner, repo),
+        ]);
+    }
+    keys.into_iter().collect()
+}
+
+pub(crate) fn export_state() -> StateExport {
+    StateExport {
+        exported_at: chrono::Utc::now().to_rfc3339(),
+        records: state_keys()
+            .into_iter()
+            .filter_map(|key| store::load_raw(&key).map(|value| (key, value)))
+            .collect(),
+    }
+}
+
+/// Writes the exported state to a new secret gist of the connected GitHub
+/// account and returns its URL with the number of exported records.
+pub(crate) async fn export_to_gist() -> Result<(String, usize), String> {
+    let export = export_state();
+    let records = export.records.len();
+    let content = serde_json::to_string_pretty(&export)
+        .map_err(|e| format!("the state could not be serialized: {e}"))?;
+
+    let octocrab = get_octo(&Default);
+    match octocrab
+        .gists()
+        .create()
+        .description(format!("Issue summarizer state, {}", export.exported_at))
+        .public(false)
+        .file(EXPORT_FILE, content)
+        .send()
+        .await
+    {
+        Ok(gist) => Ok((gist.html_url.to_string(), records)),
+        Err(github_flows::octocrab::Error::GitHub { source, .. }) => Err(format!(
+            "GitHub refused the gist ({}). Please make sure the token of the connected GitHub account has the `gist` scope.",
+            source.message
+        )),
+        Err(_e) => Err("the request to GitHub failed, please try again later.".to_string()),
+    }
+}
+
+/// Reads an export from the gist, given by its id or URL, and writes its
+/// records to the store, replacing the stored ones. Returns the number of
+/// records written.
+pub(crate) async fn import_from_gist(gist: &str) -> Result<usize, String> {
+    let id = gist
+        .trim()
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or("");
+    if id.is_empty() {
+        return Err("please give the id or URL of the gist".to_string());
+    }
+
+    let octocrab = get_octo(&Default);
+    let gist = octocrab
+        .gists()
+        .get(id)
+        .await
+        .map_err(|_e| format!("the gist {id} could not be read"))?;
+    let file = gist
+        .files
+        .get(EXPORT_FILE)
+        .ok_or(format!("the gist has no {EXPORT_FILE} file"))?;
+    // gists only include the first megabyte of a file
+    let content = match file.truncated {
+        false => file.content.clone(),
+        true => octocrab
+            ._get(file.raw_url.clone(), None::<&()>)
+            .await
+            .map_err(|_e| format!("{EXPORT_FILE} could not be downloaded"))?
+            .text()
+            .await
+            .map_err(|_e| format!("{EXPORT_FILE} could not be downloaded"))?,
+    };
+
+    let export = serde_json::from_str::<StateExport>(&content)
+        .map_err(|e| format!("{EXPORT_FILE} is not an export of the summarizer: {e}"))?;
+    for (key, value) in &export.records {
+        store::save_raw(key, value.clone());
+    }
+    Ok(export.records.len())
+}
@@ -0,0 +1,2892 @@
+//! Slack commands: parsing the messages of the channel and answering them.
+
+use crate::audit::{audit, load_audit_log, AUDIT_LOG_KEPT};
+use crate::backup::{export_to_gist, import_from_gist};
+use crate::config::{
+    self, channel_timezone, config_file, cooldown_minutes, deployment_settings, effective_settings,
+    env_repos, feature_enabled, google_chat_spaces, hard_max, home_channel, is_channel_admin,
+    is_google_chat, is_opted_out, issue_limit, issue_routes, issue_timeout_secs,
+    load_channel_settings, load_opt_outs, load_org_settings, load_routes, load_slack_users,
+    may_run_expensive, priority_labels, publish_mode, reload_config_file, remove_channel_settings,
+    remove_org_settings, repo_refusal, resolved_settings, route_destination, run_deadline_secs,
+    save_channel_settings, save_opt_outs, save_org_settings, save_routes, save_slack_users,
+    served_channels, settings_diff, settings_snapshot_key, timezone_key, user_cooldown_minutes,
+    user_daily_quota, ChannelSettings, Feature, IssueRoute, OutputMode,
+};
+use crate::confluence::publish_digest_to_confluence;
+use crate::deps::{Deps, IssueSource};
+use crate::discussion::post_digest_discussion;
+use crate::feed::{publish_feed, published_feed_url, record_feed_entry};
+use crate::github::{
+    archive_digest, check_github_token, github_request, is_rate_limited, is_timed_out,
+    load_watchlist, publish_summary, rate_limit_summary, rate_limited_message, save_watchlist,
+};
+use crate::jira::create_jira_ticket;
+use crate::linear::{linear_team, LINEAR_OWNER};
+use crate::logging::{run_id, Event};
+use crate::metrics::{count_by, stats_report, time_stage, Counter};
+use crate::notion::export_digest_to_notion;
+use crate::ops::report_digest_failures;
+use crate::render::format_minutes;
+use crate::schedule::{
+    load_rotations, load_schedules, load_slas, load_snoozes, load_subscriptions, save_rotations,
+    save_schedules, save_slas, save_snoozes, save_subscriptions, Frequency, Rotation, Schedule,
+    Sla, Snooze, Subscription,
+};
+use crate::sink::{
+    is_delivered, mark_delivered, send_message, MessageBatch, Sinks, DELIVERY_MARKER_SECS,
+};
+use crate::site::export_digest_to_site;
+use crate::summarizer::{
+    cached_summary, chat_completion, check_model, classify_issue, get_one_line_summary,
+    get_sentiment, get_summary, load_history, moderate, plan_summary, record_history, CacheStats,
+    IssueType, Summarizer, CACHE_STATS_KEY, HISTORY_KEPT,
+};
+use crate::vault::{export_notes_to_vault, VaultNote};
+use crate::{store, Error, Result};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use chrono_tz::Tz;
+use futures::{future, stream, StreamExt};
+use github_flows::{get_octo, octocrab::models::IssueState, GithubLogin::Default};
+use openai_flows::chat::{ChatModel, ChatOptions};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use slack_flows::SlackMessage;
+use std::{
+    collections::BTreeMap,
+    time::{Duration as StdDuration, Instant},
+};
+
+pub(crate) const TREND_WEEKS_KEPT: usize = 12;
+
+/// How many issues of a digest are summarized at the same time. openai-flows
+/// sends its requests synchronously, so this overlaps the GitHub requests of
+/// the issues rather than the model calls.
+pub(crate) const SUMMARY_CONCURRENCY: usize = 4;
+
+#[no_mangle]
+#[tokio::main(flavor = "current_thread")]
+pub(crate) async fn handler(worksapce: &str, channel: &str, sm: SlackMessage) -> Result<()> {
+    // Misconfiguration otherwise only shows as commands that do nothing, so
+    // the first message the flow receives gets the diagnostic.
+    if store::load::<DateTime<Utc>>(SELFCHECK_KEY).is_none() {
+        store::save(SELFCHECK_KEY, &Utc::now());
+        send_message(worksapce, channel, selfcheck_report().await);
+    }
+
+    let command = strip_command(&sm.text, &command_word()).map(|_| {
+        let action = sm
+            .text
+            .split_whitespace()
+            .take(2)
+            .collect::<Vec<_>>()
+            .join(" ");
+        let repo = Regex::new(r"\b([\w.-]+)/([\w.-]+)\b")
+            .unwrap()
+            .captures(&sm.text)
+            .map(|cap| (cap[1].to_string(), cap[2].to_string()));
+        (sm.user.clone(), action, repo)
+    });
+    let handled = handle_message(&Deps::live(), worksapce, channel, sm).await;
+    if let Some((user, action, repo)) = command {
+        let outcome = match &handled {
+            Ok(()) => "handled".to_string(),
+            Err(e) => format!("failed: {e}"),
+        };
+        let repo = repo.as_ref().map(|(o, r)| (o.as_str(), r.as_str()));
+        audit(&user, &action, repo, &outcome, true);
+    }
+    handled
+}
+
+/// The first word of `trigger_word`, which starts every command.
+pub(crate) fn command_word() -> String {
+    config::var("trigger_word")
+        .unwrap_or("flows summarize".to_string())
+        .split_whitespace()
+        .next()
+        .unwrap_or("flows")
+        .to_string()
+}
+
+pub(crate) const SELFCHECK_KEY: &str = "selfcheck:first_run";
+
+/// Checks the GitHub account, the OpenAI key and the configuration of the
+/// flow and tells what is missing.
+pub(crate) async fn selfcheck_report() -> String {
+    let mut lines = vec!["*Self-check*".to_string()];
+
+    match check_github_token().await {
+        Ok(scopes) => {
+            // commenting, labeling, archiving, the site, the vault and the
+            // discussions write to the repositories
+            let writes = [
+                "publish_repos",
+                "auto_label_repos",
+                "archive_repo",
+                "site_repo",
+                "vault_repo",
+                "discussion_category",
+            ]
+            .into_iter()
+            .filter(|name| config::var(name).is_some_and(|v| !v.trim().is_empty()))
+            .collect::<Vec<_>>();
+            match scopes {
+                Some(scopes)
+                    if !writes.is_empty()
+                        && !scopes.iter().any(|s| s == "repo" || s == "public_repo") =>
+                {
+                    lines.push(format!(
+                        ":x: GitHub: the token has the scopes `{}` but `{}` need `repo` or `public_repo` to write to the repositories.",
+                        scopes.join(", "),
+                        writes.join("`, `")
+                    ))
+                }
+                Some(scopes) => lines.push(format!(
+                    ":white_check_mark: GitHub: connected, token scopes `{}`.",
+                    scopes.join(", ")
+                )),
+                None => lines.push(
+                    ":white_check_mark: GitHub: connected, the token does not report its scopes."
+                        .to_string(),
+                ),
+            }
+        }
+        Err(e) => lines.push(format!(":x: GitHub: {e}")),
+    }
+
+    match check_model().await {
+        Ok(()) => lines.push(":white_check_mark: OpenAI: the model answers.".to_string()),
+        Err(e) => lines.push(format!(
+            ":x: OpenAI: the model did not answer ({e}), please check the OpenAI key of the flow."
+        )),
+    }
+
+    // slack-flows does not report failed posts, so only the channel this
+    // report reaches is known to work.
+    lines.push(":white_check_mark: Slack: this report was posted.".to_string());
+    let others = served_channels().len().saturating_sub(1);
+    if others > 0 {
+        lines.push(format!(
+            ":grey_question: Slack: posting to the other {others} served channels cannot be checked."
+        ));
+    }
+
+    match &config_file().error {
+        Some(e) => lines.push(format!(":x: Configuration: {e}")),
+        None => lines.push(":white_check_mark: Configuration: loaded.".to_string()),
+    }
+    lines.join("\n")
+}
+
+/// Answers a message of the channel. The digest runs against `deps`, so its
+/// whole path from the command to the posted messages can be driven with
+/// fakes.
+pub(crate) async fn handle_message<I: IssueSource, S: Summarizer>(
+    deps: &Deps<'_, I, S>,
+    worksapce: &str,
+    channel: &str,
+    sm: SlackMessage,
+) -> Result<()> {
+    let trigger_word = config::var("trigger_word").unwrap_or("flows summarize".to_string());
+    let command_word = command_word();
+
+    // Channel admins run the commands of the other served channels from the
+    // listened one, since the flow only receives the messages of one channel.
+    let served;
+    let (worksapce, channel, sm) = match strip_command(&sm.text, &format!("{command_word} in")) {
+        None => (worksapce, channel, sm),
+        Some(args) => {
+            let (target, command) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+            let target = target.replace("/#", "/");
+            let Some(found) = served_channels()
+                .into_iter()
+                .find(|(ws, ch)| format!("{ws}/{ch}").eq_ignore_ascii_case(&target))
+            else {
+                return Err(Error::Usage(format!(
+                    "`{target}` is not one of the channels in `slack_channels`, usage: {command_word} in <slack_workspace>/<slack_channel> <command>"
+                )));
+            };
+            if !is_channel_admin(&sm.user) {
+                return not_allowed(deps, worksapce, channel, &sm.user);
+            }
+            served = found;
+            let text = format!("{command_word} {}", command.trim());
+            (
+                served.0.as_str(),
+                served.1.as_str(),
+                SlackMessage { text, ..sm },
+            )
+        }
+    };
+
+    if let Some(args) = strip_command(&sm.text, &format!("{command_word} trends")) {
+        if !may_run_expensive(&sm.user) {
+            return not_allowed(deps, worksapce, channel, &sm.user);
+        }
+        trends(worksapce, channel, args).await;
+        return Ok(());
+    }
+
+    if let Some(args) = strip_command(&sm.text, &format!("{command_word} schedule")) {
+        schedule_command(worksapce, channel, &sm.user, args);
+        return Ok(());
+    }
+
+    if strip_command(&sm.text, &format!("{command_word} resume")).is_some() {
+        if !may_run_expensive(&sm.user) {
+            return not_allowed(deps, worksapce, channel, &sm.user);
+        }
+        if !resume_run(deps, worksapce, channel).await? {
+            deps.sink.send(
+                worksapce,
+                channel,
+                "There is no interrupted digest to resume in this channel.",
+            );
+        }
+        return Ok(());
+    }
+
+    if strip_command(&sm.text, &format!("{command_word} reload")).is_some() {
+        reload_command(worksapce, channel, &sm.user);
+        return Ok(());
+    }
+
+    if strip_command(&sm.text, &format!("{command_word} export")).is_some() {
+        if !is_channel_admin(&sm.user) {
+            return not_allowed(deps, worksapce, channel, &sm.user);
+        }
+        let reply = match export_to_gist().await {
+            Ok((url, records)) => format!("Exported {records} records to the secret gist {url}"),
+            Err(e) => format!("The state could not be exported: {e}"),
+        };
+        deps.sink.send(worksapce, channel, &reply);
+        return Ok(());
+    }
+
+    if let Some(args) = strip_command(&sm.text, &format!("{command_word} import")) {
+        if !is_channel_admin(&sm.user) {
+            return not_allowed(deps, worksapce, channel, &sm.user);
+        }
+        let reply = match import_from_gist(args).await {
+            Ok(records) => format!("Imported {records} records, they replace the stored ones."),
+            Err(e) => format!("The state could not be imported: {e}."),
+        };
+        deps.sink.send(worksapce, channel, &reply);
+        return Ok(());
+    }
+
+    if let Some(args) = strip_command(&sm.text, &format!("{command_word} audit-log")) {
+        if !is_channel_admin(&sm.user) {
+            return not_allowed(deps, worksapce, channel, &sm.user);
+        }
+        audit_log_command(worksapce, channel, args);
+        return Ok(());
+    }
+
+    if strip_command(&sm.text, &format!("{command_word} status")).is_some() {
+        status_command(worksapce, channel).await;
+        return Ok(());
+    }
+
+    if strip_command(&sm.text, &format!("{command_word} usage")).is_some() {
+        usage_command(worksapce, channel);
+        return Ok(());
+    }
+
+    if let Some(args) = strip_command(&sm.text, &format!("{command_word} stats")) {
+        stats_command(worksapce, channel, args);
+        return Ok(());
+    }
+
+    if strip_command(&sm.text, &format!("{command_word} selfcheck")).is_some() {
+        deps.sink
+            .send(worksapce, channel, &selfcheck_report().await);
+        return Ok(());
+    }
+
+    if let Some(args) = strip_command(&sm.text, &format!("{command_word} history")) {
+        history_command(worksapce, channel, args);
+        return Ok(());
+    }
+
+    if let Some(args) = strip_command(&sm.text, &format!("{command_word} config")) {
+        config_command(worksapce, channel, &sm.user, args);
+        return Ok(());
+    }
+
+    if let Some(args) = strip_command(&sm.text, &format!("{command_word} timezone")) {
+        timezone_command(worksapce, channel, &sm.user, args);
+        return Ok(());
+    }
+
+    if let Some(args) = strip_command(&sm.text, &format!("{command_word} optout")) {
+        optout_command(worksapce, channel, &sm.user, args);
+        return Ok(());
+    }
+
+    if let Some(args) = strip_command(&sm.text, &format!("{command_word} route")) {
+        route_command(worksapce, channel, &sm.user, args);
+        return Ok(());
+    }
+
+    if let Some(args) = strip_command(&sm.text, &format!("{command_word} subscribe")) {
+        subscribe_command(worksapce, channel, &sm.user, args);
+        return Ok(());
+    }
+
+    if let Some(args) = strip_command(&sm.text, &format!("{command_word} unsubscribe")) {
+        unsubscribe_command(worksapce, channel, &sm.user, args);
+        return Ok(());
+    }
+
+    if let Some(args) = strip_command(&sm.text, &format!("{command_word} snooze")) {
+        snooze_command(worksapce, channel, &sm.user, args);
+        return Ok(());
+    }
+
+    if let Some(args) = strip_command(&sm.text, &format!("{command_word} unsnooze")) {
+        unsnooze_command(worksapce, channel, &sm.user, args);
+        return Ok(());
+    }
+
+    if let Some(args) = strip_command(&sm.text, &format!("{command_word} sla")) {
+        sla_command(worksapce, channel, &sm.user, args);
+        return Ok(());
+    }
+
+    if let Some(args) = strip_command(&sm.text, &format!("{command_word} user")) {
+        user_command(worksapce, channel, &sm.user, args);
+        return Ok(());
+    }
+
+    if let Some(args) = strip_command(&sm.text, &format!("{command_word} watch")) {
+        watch_command(worksapce, channel, &sm.user, args);
+        return Ok(());
+    }
+
+    if let Some(args) = strip_command(&sm.text, &format!("{command_word} unwatch")) {
+        unwatch_command(worksapce, channel, &sm.user, args);
+        return Ok(());
+    }
+
+    if let Some(args) = strip_command(&sm.text, &format!("{command_word} close")) {
+        if !is_channel_admin(&sm.user) {
+            return not_allowed(deps, worksapce, channel, &sm.user);
+        }
+        close_command(worksapce, channel, args).await;
+        return Ok(());
+    }
+
+    if let Some(args) = strip_command(&sm.text, &format!("{command_word} rotation")) {
+        rotation_command(worksapce, channel, &sm.user, args);
+        return Ok(());
+    }
+
+    if let Some(args) = strip_command(&sm.text, &format!("{command_word} escalate")) {
+        if !may_run_expensive(&sm.user) {
+            return not_allowed(deps, worksapce, channel, &sm.user);
+        }
+        escalate_command(worksapce, channel, args).await?;
+        return Ok(());
+    }
+
+    if let Some(args) = strip_command(&sm.text, &format!("{command_word} publish")) {
+        if !may_run_expensive(&sm.user) {
+            return not_allowed(deps, worksapce, channel, &sm.user);
+        }
+        publish_command(worksapce, channel, args).await?;
+        return Ok(());
+    }
+
+    let (text, type_filter) = extract_type_filter(&sm.text);
+    let (text, label_filter) = extract_label_filter(&text);
+    let (text, requested_output) = extract_output(&text);
+    let (text, dry) = extract_flag(&text, "dry-run");
+    let (text, requested_limit) = extract_limit(&text);
+
+    let re = Regex::new(r"^(\s*\w+(?: \w+)?)(.*?)( \d+)?\s*$").unwrap();
+    let Some(cap) = re.captures(&text) else {
+        return match text.trim_start().starts_with(&trigger_word) {
+            true => Err(Error::Usage(format!(
+                "Usage: {trigger_word} <github_owner>/<github_repo> [n]"
+            ))),
+            false => Ok(()),
+        };
+    };
+
+    let triggered = match cap.get(1) {
+        Some(trigger) => trigger.as_str().trim().contains(&trigger_word),
+        None => false,
+    };
+
+    if !triggered {
+        return Ok(());
+    }
+
+    let settings = resolved_settings(worksapce, channel);
+    let default_days = settings.days.unwrap_or(7);
+    let limit = issue_limit(requested_limit, &settings);
+    let _n_days = match cap.get(3) {
+        Some(n) => n.as_str().trim().parse::<i64>().unwrap_or(default_days),
+        None => default_days,
+    };
+
+    if let Some(owner_repo_str) = cap.get(2) {
+        let owner_repo_str = match owner_repo_str.as_str().trim() {
+            "" => settings.default_repo.clone().unwrap_or_default(),
+            named => match linear_team(named) {
+                Some(team) => format!("{LINEAR_OWNER}/{team}"),
+                None => named.to_string(),
+            },
+        };
+        let owner_repo = owner_repo_str
+            .split("/")
+            .map(|s| s.to_string())
+            .collect::<Vec<String>>();
+
+        let owner = owner_repo
+            .first()
+            .unwrap_or(&"flows-network".to_string())
+            .to_string();
+        let repo = owner_repo
+            .get(1)
+            .unwrap_or(&"haiku-platform".to_string())
+            .to_string();
+
+        if let Some(refusal) = repo_refusal(&owner, &repo) {
+            deps.sink.send(worksapce, channel, &refusal);
+            return Ok(());
+        }
+        if dry && owner == LINEAR_OWNER {
+            return Err(Error::Usage(
+                "dry-run only queries GitHub, it is not available for Linear teams".to_string(),
+            ));
+        }
+        if dry {
+            return dry_run(
+                worksapce,
+                channel,
+                &owner,
+                &repo,
+                _n_days,
+                &type_filter,
+                &label_filter,
+                limit,
+            )
+            .await;
+        }
+        if !may_run_expensive(&sm.user) {
+            return not_allowed(deps, worksapce, channel, &sm.user);
+        }
+        let remaining = quota_remaining(worksapce, &sm.user, deps.clock.now());
+        if remaining == Some(0) {
+            deps.sink.send(
+                worksapce,
+                channel,
+                &format!(
+                    "<@{}> you have used all {} summaries of your daily quota, it renews at midnight UTC.",
+                    sm.user,
+                    user_daily_quota()
+                ),
+            );
+            return Ok(());
+        }
+        let limit = remaining.map_or(limit, |remaining| limit.min(remaining));
+
+        let key = inflight_key(
+            worksapce,
+            channel,
+            &owner,
+            &repo,
+            _n_days,
+            &type_filter,
+            &label_filter,
+            requested_output,
+            limit,
+        );
+        let inflight = store::load::<InflightRun>(&key)
+            .filter(|run| deps.clock.now() - run.started_at < Duration::minutes(INFLIGHT_MINUTES));
+        let run = match inflight {
+            // Most likely the platform retrying the message: the run is picked
+            // up again and only posts the summaries it has not delivered yet.
+            Some(inflight) if inflight.user == sm.user && !inflight.run.is_empty() => inflight.run,
+            Some(mut inflight) => {
+                inflight.waiting.push(sm.user.clone());
+                store::save(&key, &inflight);
+                deps.sink.send(
+                    worksapce,
+                    channel,
+                    &format!("The same digest of {owner}/{repo} is already being generated, its summaries will be posted here shortly."),
+                );
+                return Ok(());
+            }
+            None => {
+                let now = deps.clock.now();
+                if let Some(wait) = cooldown_wait(worksapce, channel, &sm.user, now) {
+                    deps.sink.send(
+                        worksapce,
+                        channel,
+                        &format!(
+                            "<@{}> digests were requested here too recently, please try again in {}.",
+                            sm.user,
+                            format_minutes((wait.num_seconds() + 59) / 60)
+                        ),
+                    );
+                    return Ok(());
+                }
+                start_cooldown(worksapce, channel, &sm.user, now);
+                let run = run_id();
+                store::save(
+                    &key,
+                    &InflightRun {
+                        started_at: deps.clock.now(),
+                        waiting: Vec::new(),
+                        run: run.clone(),
+                        user: sm.user.clone(),
+                    },
+                );
+                run
+            }
+        };
+        let summarized = match enter_run_queue(worksapce, channel, &run, true).await {
+            true => {
+                let summarized = summarize(
+                    deps,
+                    &run,
+                    worksapce,
+                    channel,
+                    &owner,
+                    &repo,
+                    _n_days,
+                    &type_filter,
+                    &label_filter,
+                    requested_output,
+                    limit,
+                    &sm.text,
+                )
+                .await;
+                leave_run_queue(worksapce, channel, &run);
+                if let Ok(posted) = summarized {
+                    let now = deps.clock.now();
+                    record_usage(worksapce, channel, &sm.user, posted, now);
+                    if let Some(remaining) = quota_remaining(worksapce, &sm.user, now) {
+                        deps.sink.send(
+                            worksapce,
+                            channel,
+                            &format!(
+                                "<@{}> {remaining} of your {} daily summaries are left.",
+                                sm.user,
+                                user_daily_quota()
+                            ),
+                        );
+                    }
+                }
+                summarized.map(|_posted| ())
+            }
+            false => {
+                deps.sink.send(
+                    worksapce,
+                    channel,
+                    &format!("Your digest of {owner}/{repo} waited too long for the runs before it and was dropped, please try again later."),
+                );
+                Ok(())
+            }
+        };
+
+        let waiting = store::load::<InflightRun>(&key)
+            .map(|run| run.waiting)
+            .unwrap_or_default();
+        store::remove(&key);
+        if !waiting.is_empty() {
+            let mentions = waiting
+                .iter()
+                .map(|user| format!("<@{user}>"))
+                .collect::<Vec<String>>()
+                .join(", ");
+            deps.sink.send(
+                worksapce,
+                channel,
+                &format!("{mentions}: the digest of {owner}/{repo} you asked for is above."),
+            );
+        }
+        summarized?;
+    }
+    Ok(())
+}
+
+/// Answers a user who may not run the command. slack-flows can only post to
+/// the whole channel, so the answer mentions the user instead of being shown
+/// to them alone.
+pub(crate) fn not_allowed<I: IssueSource, S: Summarizer>(
+    deps: &Deps<'_, I, S>,
+    workspace: &str,
+    channel: &str,
+    user: &str,
+) -> Result<()> {
+    deps.sink.send(
+        workspace,
+        channel,
+        &format!("<@{user}> you're not allowed to run this command."),
+    );
+    Ok(())
+}
+
+/// Minutes after which a run is dropped from the queue of its channel, so a
+/// run that was killed does not block the channel.
+pub(crate) const RUN_QUEUE_STALE_MINUTES: i64 = 30;
+
+/// Seconds between checks whether a queued run is next.
+pub(crate) const RUN_QUEUE_POLL_SECS: u64 = 5;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct QueuedRun {
+    pub(crate) id: String,
+    pub(crate) queued_at: DateTime<Utc>,
+}
+
+pub(crate) fn run_queue_key(workspace: &str, channel: &str) -> String {
+    format!("run_queue:{workspace}/{channel}")
+}
+
+/// The runs of the channel in the order they were queued, the first one
+/// being the run in progress.
+pub(crate) fn load_run_queue(workspace: &str, channel: &str) -> Vec<QueuedRun> {
+    let stale = Utc::now() - Duration::minutes(RUN_QUEUE_STALE_MINUTES);
+    let mut queue =
+        store::load::<Vec<QueuedRun>>(&run_queue_key(workspace, channel)).unwrap_or_default();
+    queue.retain(|run| run.queued_at > stale);
+    queue
+}
+
+/// Queues the run in the channel and waits until the runs before it are
+/// done, so that the output of runs in the same channel does not interleave.
+/// With `notify`, the channel is told when the run has to wait. Returns
+/// false, leaving the queue, when the run waited for longer than
+/// `run_deadline_secs`.
+pub(crate) async fn enter_run_queue(
+    workspace: &str,
+    channel: &str,
+    run_id: &str,
+    notify: bool,
+) -> bool {
+    let key = run_queue_key(workspace, channel);
+    let mut queue = load_run_queue(workspace, channel);
+    queue.push(QueuedRun {
+        id: run_id.to_string(),
+        queued_at: Utc::now(),
+    });
+    store::save(&key, &queue);
+
+    if queue.len() > 1 && notify {
+        send_message(
+            workspace,
+            channel,
+            format!(
+                "A run is already in progress in this channel, queued yours ({} ahead of it).",
+                queue.len() - 1
+            ),
+        );
+    }
+
+    let deadline = Instant::now() + StdDuration::from_secs(run_deadline_secs());
+    loop {
+        let queue = load_run_queue(workspace, channel);
+        match queue.first() {
+            Some(first) if first.id != run_id => {}
+            // the first run, or dropped from the queue as stale
+            _ => return true,
+        }
+        if Instant::now() >= deadline {
+            leave_run_queue(workspace, channel, run_id);
+            return false;
+        }
+        tokio::time::sleep(StdDuration::from_secs(RUN_QUEUE_POLL_SECS)).await;
+    }
+}
+
+pub(crate) fn leave_run_queue(workspace: &str, channel: &str, run_id: &str) {
+    let mut queue = load_run_queue(workspace, channel);
+    queue.retain(|run| run.id != run_id);
+    store::save(&run_queue_key(workspace, channel), &queue);
+}
+
+pub(crate) fn cooldown_key(workspace: &str, channel: &str, user: Option<&str>) -> String {
+    match user {
+        Some(user) => format!("cooldown:{workspace}/{channel}:{user}"),
+        None => format!("cooldown:{workspace}/{channel}"),
+    }
+}
+
+/// How long the user still has to wait before requesting a digest in the
+/// channel, after the cooldowns of the channel and of the user since their
+/// last digests.
+pub(crate) fn cooldown_wait(
+    workspace: &str,
+    channel: &str,
+    user: &str,
+    now: DateTime<Utc>,
+) -> Option<Duration> {
+    [
+        (cooldown_key(workspace, channel, None), cooldown_minutes()),
+        (
+            cooldown_key(workspace, channel, Some(user)),
+            user_cooldown_minutes(),
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(key, minutes)| {
+        let started = store::load::<DateTime<Utc>>(&key)?;
+        Some(started + Duration::minutes(minutes) - now)
+    })
+    .filter(|wait| *wait > Duration::zero())
+    .max()
+}
+
+/// Records that a digest of the user starts now, for the cooldowns.
+pub(crate) fn start_cooldown(workspace: &str, channel: &str, user: &str, now: DateTime<Utc>) {
+    let to_record = [
+        (cooldown_key(workspace, channel, None), cooldown_minutes()),
+        (
+            cooldown_key(workspace, channel, Some(user)),
+            user_cooldown_minutes(),
+        ),
+    ];
+    for (key, minutes) in to_record {
+        if minutes > 0 {
+            store::save_for(&key, &now, minutes * 60);
+        }
+    }
+}
+
+/// Minutes after which a digest no longer counts as in flight, so a run that
+/// was killed does not hold back identical requests for good.
+pub(crate) const INFLIGHT_MINUTES: i64 = 15;
+
+/// A digest that is being generated, and the users who asked for the same
+/// digest in the meantime. The store has no atomic updates, so two requests
+/// arriving at the very same moment can still both run.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct InflightRun {
+    pub(crate) started_at: DateTime<Utc>,
+    pub(crate) waiting: Vec<String>,
+    /// The run generating the digest, and the user who asked for it.
+    #[serde(default)]
+    pub(crate) run: String,
+    #[serde(default)]
+    pub(crate) user: String,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn inflight_key(
+    workspace: &str,
+    channel: &str,
+    owner: &str,
+    repo: &str,
+    n_days: i64,
+    type_filter: &[IssueType],
+    label_filter: &[String],
+    output: Option<OutputMode>,
+    limit: usize,
+) -> String {
+    let types = type_filter
+        .iter()
+        .map(|t| t.as_str())
+        .collect::<Vec<_>>()
+        .join(",");
+    let labels = label_filter.join(",").to_lowercase();
+    let output = output.map_or("", |o| o.as_str());
+    format!(
+        "inflight:{workspace}/{channel}:{}/{}:{n_days}:{types}:{limit}:{labels}:{output}",
+        owner.to_lowercase(),
+        repo.to_lowercase()
+    )
+}
+
+pub(crate) fn usage_key(workspace: &str, day: NaiveDate) -> String {
+    format!("usage:{workspace}:{day}")
+}
+
+/// The summaries each user of the workspace requested on the day, by the
+/// channel they were requested in.
+pub(crate) fn load_usage(
+    workspace: &str,
+    day: NaiveDate,
+) -> BTreeMap<String, BTreeMap<String, usize>> {
+    store::load(&usage_key(workspace, day)).unwrap_or_default()
+}
+
+/// Counts the summaries posted for the user in the channel today. The
+/// counts are kept for a couple of days, which `flows usage` reads from.
+pub(crate) fn record_usage(
+    workspace: &str,
+    channel: &str,
+    user: &str,
+    summaries: usize,
+    now: DateTime<Utc>,
+) {
+    if summaries == 0 {
+        return;
+    }
+    let key = usage_key(workspace, now.date_naive());
+    let mut usage = load_usage(workspace, now.date_naive());
+    *usage
+        .entry(channel.to_string())
+        .or_default()
+        .entry(user.to_string())
+        .or_default() += summaries;
+    store::save_for(&key, &usage, 2 * 24 * 60 * 60);
+}
+
+/// The summaries the user can still request today in the workspace, across
+/// its channels, `None` without a quota.
+pub(crate) fn quota_remaining(workspace: &str, user: &str, now: DateTime<Utc>) -> Option<usize> {
+    let quota = user_daily_quota();
+    if quota == 0 {
+        return None;
+    }
+    let used = load_usage(workspace, now.date_naive())
+        .values()
+        .filter_map(|users| users.get(user))
+        .sum::<usize>();
+    Some(quota.saturating_sub(used))
+}
+
+/// Lists the summaries requested in the channel today.
+pub(crate) fn usage_command(workspace: &str, channel: &str) {
+    let usage = load_usage(workspace, Utc::now().date_naive())
+        .remove(channel)
+        .unwrap_or_default();
+    let mut users = usage.into_iter().collect::<Vec<(String, usize)>>();
+    users.sort_by_key(|(_, n)| std::cmp::Reverse(*n));
+    let quota = match user_daily_quota() {
+        0 => "no daily quota".to_string(),
+        quota => format!("a daily quota of {quota}"),
+    };
+    let reply = match users.is_empty() {
+        true => format!("No summaries were requested in this channel today ({quota})."),
+        false => format!(
+            "Summaries requested in this channel today ({quota}):\n{}",
+            users
+                .iter()
+                .map(|(user, n)| format!("- <@{user}>: {n}"))
+                .collect::<Vec<String>>()
+                .join("\n")
+        ),
+    };
+    send_message(workspace, channel, reply);
+}
+
+/// Reports the metrics of the runs of the last days, 7 unless given.
+pub(crate) fn stats_command(workspace: &str, channel: &str, args: &str) {
+    let days = args
+        .split_whitespace()
+        .find_map(|arg| arg.parse::<i64>().ok())
+        .unwrap_or(7);
+    send_message(workspace, channel, stats_report(days, Utc::now()));
+}
+
+/// Runs the digest and returns the number of summaries it posted.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn summarize<I: IssueSource, S: Summarizer>(
+    deps: &Deps<'_, I, S>,
+    run: &str,
+    workspace: &str,
+    channel: &str,
+    owner: &str,
+    repo: &str,
+    n_days: i64,
+    type_filter: &[IssueType],
+    label_filter: &[String],
+    output: Option<OutputMode>,
+    limit: usize,
+    request_text: &str,
+) -> Result<usize> {
+    let now = deps.clock.now();
+    // A resumed run searches with the query it started with, so the issues
+    // do not shift under it.
+    let resumed = load_run_cursor(workspace, channel).filter(|cursor| cursor.run == run);
+    let query = match &resumed {
+        Some(cursor) => cursor.query.clone(),
+        None => digest_query(owner, repo, n_days, label_filter, now)?,
+    };
+    // a resumed run keeps the output it was asked for
+    let output = resumed.as_ref().map_or(output, |cursor| cursor.output);
+    let mut cursor = resumed.unwrap_or_else(|| RunCursor {
+        run: run.to_string(),
+        owner: owner.to_string(),
+        repo: repo.to_string(),
+        n_days,
+        type_filter: type_filter.to_vec(),
+        output,
+        limit,
+        query: query.clone(),
+        delivered: Vec::new(),
+        updated_at: now,
+    });
+    cursor.save(workspace, channel);
+
+    let settings = resolved_settings(workspace, channel);
+    let language = settings.language.as_deref();
+    let output = output.or(settings.output).unwrap_or_default();
+
+    let search_started = Instant::now();
+    let searched = deps.issues.search(Some((workspace, channel)), &query).await;
+    time_stage("search", search_started);
+    let (posted, outcome) = match searched {
+        Ok(issues) => {
+            // the digests of a week add up, each issue counted once
+            let week = now.format("%G-W%V").to_string();
+            let mut stats = load_weekly_stats(owner, repo)
+                .into_iter()
+                .find(|w| w.week == week)
+                .unwrap_or(WeeklyStats {
+                    week,
+                    ..WeeklyStats::default()
+                });
+            let searched = issues.len();
+            count_by(Counter::IssuesFound, searched as u64);
+            let summarize_started = Instant::now();
+            let issues = issues
+                .into_iter()
+                .filter(|issue| !is_opted_out(&issue.user.login))
+                .collect::<Vec<_>>();
+            let opted_out = searched - issues.len();
+            // a resumed run continues after the issues it already delivered,
+            // which are not classified or summarized again
+            let (delivered, issues): (Vec<_>, Vec<_>) = issues
+                .into_iter()
+                .partition(|issue| cursor.delivered.contains(&issue.number));
+            let already_delivered = delivered.len();
+            // Issues are classified and summarized a few at a time, in order, so
+            // they are still posted in the order of the search results.
+            let classified = stream::iter(issues)
+                .map(|issue| async move {
+                    let labels = issue
+                        .labels
+                        .iter()
+                        .map(|lab| lab.name.clone())
+                        .collect::<Vec<String>>();
+                    let issue_type = classify_issue(
+                        issue.number,
+                        &issue.title,
+                        issue.body.as_deref().unwrap_or(""),
+                        &labels,
+                    )
+                    .await;
+                    (issue, labels, issue_type)
+                })
+                .buffered(SUMMARY_CONCURRENCY)
+                .filter(|(_, _, issue_type)| {
+                    future::ready(type_filter.is_empty() || type_filter.contains(issue_type))
+                })
+                .take(limit.saturating_sub(already_delivered))
+                .collect::<Vec<_>>()
+                .await;
+
+            cursor.save(workspace, channel);
+            let reached_limit = classified.len() + already_delivered >= limit;
+            let total = classified.len();
+            // Issues that fail, take too long or come after the run deadline
+            // are reported at the end, so the rest of the digest is still
+            // posted.
+            let deadline = Instant::now() + StdDuration::from_secs(run_deadline_secs());
+            let mut summaries = stream::iter(classified)
+                .map(|(issue, labels, issue_type)| async move {
+                    let issue_number = issue.number;
+                    if Instant::now() >= deadline {
+                        let timeout = Error::Timeout {
+                            stage: "The digest",
+                            secs: run_deadline_secs(),
+                        };
+                        return (issue_number, Err(timeout));
+                    }
+                    let started = Instant::now();
+                    let secs = issue_timeout_secs();
+                    let summarized = tokio::time::timeout(StdDuration::from_secs(secs), async {
+                        let issue_title = issue.title.clone();
+                        let issue_url = issue.html_url.to_string();
+                        let summary = match output {
+                            OutputMode::Full => {
+                                cached_summary(deps, owner, repo, issue, issue_type, language)
+                                    .await?
+                            }
+                            OutputMode::Brief => {
+                                let body = issue.body.as_deref().unwrap_or("");
+                                let sentence =
+                                    get_one_line_summary(issue_number, &issue.title, body).await;
+                                format!(
+                                    "#{issue_number} {}: {sentence}\n{}",
+                                    issue.title, issue.html_url
+                                )
+                            }
+                        };
+                        let summary = moderate(issue_number, &issue_url, summary).await;
+                        let sentiment = get_sentiment(issue_number, &summary).await;
+                        Ok::<_, Error>((
+                            issue_title,
+                            issue_url,
+                            labels,
+                            issue_type,
+                            summary,
+                            sentiment,
+                        ))
+                    })
+                    .await
+                    .unwrap_or(Err(Error::Timeout {
+                        stage: "Summarizing the issue",
+                        secs,
+                    }));
+                    match &summarized {
+                        Ok(_) => Event::info("summary")
+                            .issue(issue_number)
+                            .took(started)
+                            .log("summarized"),
+                        Err(e) => Event::warn("summary")
+                            .issue(issue_number)
+                            .took(started)
+                            .err(e)
+                            .log("skipped"),
+                    }
+                    (issue_number, summarized)
+                })
+                .buffered(SUMMARY_CONCURRENCY);
+
+            let mut batch = MessageBatch::new(deps.sink, workspace, channel);
+            let mut posted = 0;
+            let mut failed = Vec::new();
+            let mut archived = Vec::new();
+            let mut notes = Vec::new();
+            let mut priorities = BTreeMap::<String, usize>::new();
+            let priority_labels = priority_labels();
+            while let Some((issue_number, summarized)) = summaries.next().await {
+                match summarized {
+                    Ok((issue_title, issue_url, labels, issue_type, summary, sentiment)) => {
+                        stats.record(issue_number, &issue_title, &labels, issue_type, &sentiment);
+                        let priority = labels
+                            .iter()
+                            .find(|l| priority_labels.contains(&l.to_lowercase()))
+                            .map_or("no priority".to_string(), |l| l.clone());
+                        *priorities.entry(priority).or_default() += 1;
+                        record_history(owner, repo, issue_number, &issue_title, &summary);
+                        archived.push(summary.clone());
+                        notes.push(VaultNote {
+                            number: issue_number,
+                            title: issue_title.clone(),
+                            labels: labels.clone(),
+                            url: issue_url,
+                            summary: summary.clone(),
+                        });
+                        if is_delivered(run, issue_number) {
+                            Event::info("delivery")
+                                .issue(issue_number)
+                                .log("already delivered");
+                        } else {
+                            batch.push_summary(issue_number, summary);
+                            posted += 1;
+                        }
+                        record_sent(&mut batch, &mut cursor, workspace, channel);
+                    }
+                    Err(e) => failed.push(format!("#{issue_number} ({e})")),
+                }
+            }
+            time_stage("summarize", summarize_started);
+            count_by(Counter::IssuesSkipped, (failed.len() + opted_out) as u64);
+            if !failed.is_empty() {
+                // the reasons go to the ops channel, when there is one
+                let failures = match report_digest_failures(owner, repo, channel, &failed) {
+                    true => failed
+                        .iter()
+                        .map(|failure| failure.split(' ').next().unwrap_or(failure))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    false => failed.join(", "),
+                };
+                batch.push(format!(
+                    ":warning: {} of {total} issues could not be summarized: {failures}",
+                    failed.len()
+                ));
+            }
+
+            if opted_out > 0 {
+                batch.push(format!(
+                    "{opted_out} issues were left out, their authors opted out of AI processing."
+                ));
+            }
+
+            if reached_limit {
+                let hint = match limit < hard_max() {
+                    true => format!(", ask for up to {} with `limit:<n>`", hard_max()),
+                    false => ", the most a digest covers".to_string(),
+                };
+                batch.push(format!(
+                    "Only the first {limit} matching issues were summarized{hint}."
+                ));
+            }
+            if !archived.is_empty() {
+                match archive_digest(owner, repo, now, &archived).await {
+                    Some(Ok(url)) => batch.push(format!("The digest was archived to {url}")),
+                    Some(Err(e)) => {
+                        batch.push(format!(":warning: The digest could not be archived: {e}"))
+                    }
+                    None => {}
+                }
+                match export_digest_to_notion(owner, repo, now, &archived, &priorities) {
+                    Some(Ok(url)) => batch.push(format!("The digest was added to Notion: {url}")),
+                    Some(Err(e)) => batch.push(format!(
+                        ":warning: The digest could not be added to Notion: {e}"
+                    )),
+                    None => {}
+                }
+                // only digests of a week or more go to the rolling page and the
+                // discussions
+                if n_days >= 7 {
+                    match publish_digest_to_confluence(owner, repo, now, &archived) {
+                        Some(Ok(url)) => {
+                            batch.push(format!("The digest was published to Confluence: {url}"))
+                        }
+                        Some(Err(e)) => batch.push(format!(
+                            ":warning: The digest could not be published to Confluence: {e}"
+                        )),
+                        None => {}
+                    }
+                    match post_digest_discussion(owner, repo, now, &archived).await {
+                        Some(Ok(url)) => {
+                            batch.push(format!("The digest was posted as a discussion: {url}"))
+                        }
+                        Some(Err(e)) => batch.push(format!(
+                            ":warning: The digest could not be posted as a discussion: {e}"
+                        )),
+                        None => {}
+                    }
+                }
+                match export_digest_to_site(owner, repo, now, &archived).await {
+                    Some(Ok(url)) => {
+                        batch.push(format!("The digest was added to the pulse site: {url}"))
+                    }
+                    Some(Err(e)) => batch.push(format!(
+                        ":warning: The digest could not be added to the pulse site: {e}"
+                    )),
+                    None => {}
+                }
+                match export_notes_to_vault(owner, repo, &notes).await {
+                    Some(Ok(url)) => batch.push(format!(
+                        "{} notes were written to the vault: {url}",
+                        notes.len()
+                    )),
+                    Some(Err(e)) => batch.push(format!(
+                        ":warning: The notes could not be written to the vault: {e}"
+                    )),
+                    None => {}
+                }
+                record_feed_entry(
+                    &format!("Digest of {owner}/{repo}, {}", now.format("%Y-%m-%d")),
+                    &format!("https://github.com/{owner}/{repo}/issues"),
+                    &archived.join("\n\n"),
+                );
+                if let Some(Err(e)) = publish_feed().await {
+                    batch.push(format!(":warning: The feed could not be updated: {e}"));
+                }
+            }
+            batch.flush();
+            record_sent(&mut batch, &mut cursor, workspace, channel);
+
+            // the counts of the last seven days as of the latest digest
+            (stats.opened, stats.closed) = deps
+                .issues
+                .activity(owner, repo, (now - Duration::days(7)).date_naive())
+                .await;
+            save_weekly_stats(owner, repo, stats);
+            let outcome = match failed.len() {
+                0 => format!("posted {posted} summaries"),
+                n => format!("posted {posted} summaries, {n} failed"),
+            };
+            (posted, outcome)
+        }
+        Err(error) if is_rate_limited(&error) => {
+            deps.sink
+                .send(workspace, channel, &rate_limited_message().await);
+            (0, "GitHub rate limit exceeded".to_string())
+        }
+        Err(error) if is_timed_out(&error) => {
+            deps.sink.send(
+                workspace,
+                channel,
+                "GitHub did not respond to the search in time, please try again later.",
+            );
+            (0, "the GitHub search timed out".to_string())
+        }
+        Err(_error) => {
+            deps.sink.send(
+                workspace,
+                channel,
+                &format!(
+                    r#"Please double check if there are errors in the owner and repo names provided in your message:
+{request_text}
+if yes, please correct the spelling and resend your instruction."#
+                ),
+            );
+            (0, "the GitHub search failed".to_string())
+        }
+    };
+    store::remove(&run_cursor_key(workspace, channel));
+    store::save(
+        &last_run_key(workspace, channel),
+        &LastRun {
+            finished_at: deps.clock.now(),
+            owner: owner.to_string(),
+            repo: repo.to_string(),
+            outcome,
+        },
+    );
+    Ok(posted)
+}
+
+/// Posts the health of the channel for its admins: the settings and
+/// features in effect, its schedules and subscriptions, its last digest, the
+/// summary cache hit rate and the GitHub rate limits left.
+pub(crate) async fn status_command(workspace: &str, channel: &str) {
+    let tz = channel_timezone(workspace, channel);
+    let mut lines = vec![
+        format!("*Status of #{channel}*"),
+        resolved_settings(workspace, channel).describe(),
+    ];
+
+    let features = [
+        Feature::GithubEvents,
+        Feature::AutoLabel,
+        Feature::AutoPublish,
+        Feature::ScheduledDigests,
+    ]
+    .into_iter()
+    .map(|f| {
+        let state = if feature_enabled(f) { "on" } else { "off" };
+        format!("{} {state}", f.as_str())
+    })
+    .collect::<Vec<String>>();
+    lines.push(format!("features: {}", features.join(", ")));
+
+    let schedules = load_schedules(workspace, channel)
+        .iter()
+        .map(|s| format!("- {}", s.describe(tz)))
+        .chain(
+            load_subscriptions(workspace, channel)
+                .iter()
+                .map(|s| format!("- subscription: {}", s.describe(tz))),
+        )
+        .collect::<Vec<String>>();
+    match schedules.is_empty() {
+        true => lines.push("schedules: none".to_string()),
+        false => lines.push(format!("schedules:\n{}", schedules.join("\n"))),
+    }
+
+    match store::load::<LastRun>(&last_run_key(workspace, channel)) {
+        Some(run) => lines.push(format!(
+            "last digest: {}/{} at {}, {}",
+            run.owner,
+            run.repo,
+            run.finished_at
+                .with_timezone(&tz)
+                .format("%Y-%m-%d %H:%M %Z"),
+            run.outcome
+        )),
+        None => lines.push("last digest: none yet".to_string()),
+    }
+
+    if let Some(url) = published_feed_url() {
+        lines.push(format!("Atom feed: {url}"));
+    }
+
+    let cache = store::load::<CacheStats>(CACHE_STATS_KEY).unwrap_or_default();
+    match cache.hit_rate() {
+        Some(rate) => lines.push(format!(
+            "summary cache: {:.0}% answered without summarizing the whole issue ({} hits, {} updates, {} misses)",
+            rate * 100.0,
+            cache.hits,
+            cache.updates,
+            cache.misses
+        )),
+        None => lines.push("summary cache: not used yet".to_string()),
+    }
+
+    match rate_limit_summary().await {
+        Some(limits) => lines.push(format!("GitHub rate limits: {}", limits.join(", "))),
+        None => lines.push("GitHub rate limits: could not be read".to_string()),
+    }
+
+    send_message(workspace, channel, lines.join("\n"));
+}
+
+/// The last digest of a channel, for `flows status`.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct LastRun {
+    pub(crate) finished_at: DateTime<Utc>,
+    pub(crate) owner: String,
+    pub(crate) repo: String,
+    pub(crate) outcome: String,
+}
+
+pub(crate) fn last_run_key(workspace: &str, channel: &str) -> String {
+    format!("last_run:{workspace}/{channel}")
+}
+
+/// Where a digest is, saved while it runs and removed once it is done, so a
+/// digest that was killed half way can be resumed with the same run and skip
+/// the summaries it has already posted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct RunCursor {
+    pub(crate) run: String,
+    pub(crate) owner: String,
+    pub(crate) repo: String,
+    pub(crate) n_days: i64,
+    pub(crate) type_filter: Vec<IssueType>,
+    /// The output the digest was asked for, over the channel's.
+    #[serde(default)]
+    pub(crate) output: Option<OutputMode>,
+    pub(crate) limit: usize,
+    pub(crate) query: String,
+    pub(crate) delivered: Vec<u64>,
+    pub(crate) updated_at: DateTime<Utc>,
+}
+
+impl RunCursor {
+    fn save(&mut self, workspace: &str, channel: &str) {
+        self.updated_at = Utc::now();
+        store::save_for(
+            &run_cursor_key(workspace, channel),
+            self,
+            DELIVERY_MARKER_SECS,
+        );
+    }
+
+    /// A running digest saves its cursor at least once per issue, so a cursor
+    /// that has not been saved for longer than an issue may take belongs to a
+    /// digest that was killed.
+    pub(crate) fn is_interrupted(&self, now: DateTime<Utc>) -> bool {
+        now - self.updated_at > Duration::seconds(issue_timeout_secs() as i64)
+    }
+}
+
+/// Records the summaries the batch sent as delivered, in their markers and
+/// in the cursor, and saves the cursor, which also tells that the digest is
+/// still running.
+fn record_sent(batch: &mut MessageBatch, cursor: &mut RunCursor, workspace: &str, channel: &str) {
+    for issue_number in batch.take_sent() {
+        mark_delivered(&cursor.run, issue_number);
+        cursor.delivered.push(issue_number);
+    }
+    cursor.save(workspace, channel);
+}
+
+pub(crate) fn run_cursor_key(workspace: &str, channel: &str) -> String {
+    format!("run_cursor:{workspace}/{channel}")
+}
+
+/// The cursor of the digest of the channel, which runs one at a time. The
+/// cursor expires with the delivery markers it relies on.
+pub(crate) fn load_run_cursor(workspace: &str, channel: &str) -> Option<RunCursor> {
+    store::load(&run_cursor_key(workspace, channel))
+}
+
+/// Continues the interrupted digest of the channel with its run, if there is
+/// one. Returns false when there was nothing to resume.
+pub(crate) async fn resume_run<I: IssueSource, S: Summarizer>(
+    deps: &Deps<'_, I, S>,
+    workspace: &str,
+    channel: &str,
+) -> Result<bool> {
+    let Some(cursor) = load_run_cursor(workspace, channel)
+        .filter(|cursor| cursor.is_interrupted(deps.clock.now()))
+    else {
+        return Ok(false);
+    };
+    if !enter_run_queue(workspace, channel, &cursor.run, false).await {
+        deps.sink.send(
+            workspace,
+            channel,
+            "The interrupted digest could not be resumed, the runs before it took too long.",
+        );
+        return Ok(true);
+    }
+    deps.sink.send(
+        workspace,
+        channel,
+        &format!(
+            "Resuming the interrupted digest of {}/{}, {} of its summaries were already posted.",
+            cursor.owner,
+            cursor.repo,
+            cursor.delivered.len()
+        ),
+    );
+    let summarized = summarize(
+        deps,
+        &cursor.run,
+        workspace,
+        channel,
+        &cursor.owner,
+        &cursor.repo,
+        cursor.n_days,
+        &cursor.type_filter,
+        &[],
+        cursor.output,
+        cursor.limit,
+        &format!("resume {}/{}", cursor.owner, cursor.repo),
+    )
+    .await;
+    leave_run_queue(workspace, channel, &cursor.run);
+    summarized.map(|_posted| true)
+}
+
+pub(crate) fn strip_command<'a>(text: &'a str, command: &str) -> Option<&'a str> {
+    let rest = text.trim_start().strip_prefix(command)?;
+    match rest.chars().next() {
+        None => Some(rest),
+        Some(c) if c.is_whitespace() => Some(rest.trim()),
+        Some(_) => None,
+    }
+}
+
+/// The search for the open issues of the repository that were active in the
+/// last `n_days` days.
+pub(crate) fn digest_query(
+    owner: &str,
+    repo: &str,
+    n_days: i64,
+    label_filter: &[String],
+    now: DateTime<Utc>,
+) -> Result<String> {
+    let n_days_ago_str = Duration::try_days(n_days)
+        .and_then(|days| now.checked_sub_signed(days))
+        .ok_or(Error::DaysOutOfRange(n_days))?
+        .format("%Y-%m-%d");
+
+    let mut query = format!("repo:{owner}/{repo} is:issue state:open updated:>{n_days_ago_str}");
+    // GitHub matches issues with any of the labels of one `label:` term
+    if !label_filter.is_empty() {
+        let labels = label_filter
+            .iter()
+            .map(|label| format!("\"{label}\""))
+            .collect::<Vec<_>>()
+            .join(",");
+        query.push_str(&format!(" label:{labels}"));
+    }
+    Ok(query)
+}
+
+/// Posts what a digest would process and cost: the issues it would
+/// summarize with their comments, tokens and completions. Only GitHub is
+/// queried, the model is not asked at all. Issues whose type the labels do
+/// not tell are listed even with a type filter, since telling their type
+/// takes the model.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn dry_run(
+    workspace: &str,
+    channel: &str,
+    owner: &str,
+    repo: &str,
+    n_days: i64,
+    type_filter: &[IssueType],
+    label_filter: &[String],
+    limit: usize,
+) -> Result<()> {
+    let octocrab = get_octo(&Default);
+    let query = digest_query(owner, repo, n_days, label_filter, Utc::now())?;
+    let pages = match github_request(Some((workspace, channel)), || async {
+        octocrab
+            .search()
+            .issues_and_pull_requests(&query)
+            .send()
+            .await
+    })
+    .await
+    {
+        Ok(pages) => pages,
+        Err(error) if is_rate_limited(&error) => {
+            send_message(workspace, channel, rate_limited_message().await);
+            return Ok(());
+        }
+        Err(_error) => {
+            send_message(
+                workspace,
+                channel,
+                format!(
+                    "{owner}/{repo} could not be searched, please check the owner and repo names."
+                ),
+            );
+            return Ok(());
+        }
+    };
+
+    let mut lines = Vec::new();
+    let (mut comments, mut tokens, mut completions, mut cost) = (0, 0, 0, 0.0);
+    let candidates = pages
+        .items
+        .into_iter()
+        .map(|issue| {
+            let labels = issue
+                .labels
+                .iter()
+                .map(|lab| lab.name.clone())
+                .collect::<Vec<String>>();
+            let issue_type = IssueType::from_labels(&labels);
+            (issue, issue_type)
+        })
+        .filter(|(_, issue_type)| match issue_type {
+            Some(issue_type) => type_filter.is_empty() || type_filter.contains(issue_type),
+            None => true,
+        })
+        .take(limit);
+    for (issue, issue_type) in candidates {
+        let plan = plan_summary(owner, repo, &issue, issue_type).await?;
+        lines.push(format!(
+            "• #{} {}: {} of {} comments, {} tokens in {} parts, {} completions",
+            issue.number,
+            issue.title,
+            plan.comments_read,
+            issue.comments,
+            plan.tokens,
+            plan.parts,
+            plan.completions
+        ));
+        comments += plan.comments_read;
+        tokens += plan.tokens;
+        completions += plan.completions;
+        cost += plan.cost();
+    }
+
+    let reply = match lines.is_empty() {
+        true => format!("Dry run: no issues of {owner}/{repo} were active in the last {n_days} days."),
+        false => format!(
+            "Dry run for {owner}/{repo} (last {n_days} days), nothing was summarized:\n{}\nIn total {} issues, {comments} comments, about {tokens} tokens and {completions} completions, an estimated ${cost:.3}.",
+            lines.join("\n"),
+            lines.len()
+        ),
+    };
+    send_message(workspace, channel, reply);
+    Ok(())
+}
+
+/// Removes the word `flag` from the text, telling whether it was there.
+pub(crate) fn extract_flag(text: &str, flag: &str) -> (String, bool) {
+    let mut found = false;
+    let rest = text
+        .split(' ')
+        .filter(|word| match *word == flag {
+            true => {
+                found = true;
+                false
+            }
+            false => true,
+        })
+        .collect::<Vec<&str>>()
+        .join(" ");
+
+    (rest, found)
+}
+
+/// Removes a `limit:<n>` word from the text, returning the limit it asks for.
+pub(crate) fn extract_limit(text: &str) -> (String, Option<usize>) {
+    let mut limit = None;
+    let mut rest = Vec::new();
+    for word in text.split(' ') {
+        match word.strip_prefix("limit:").map(|n| n.parse::<usize>()) {
+            Some(Ok(n)) if n > 0 => limit = Some(n),
+            _ => rest.push(word),
+        }
+    }
+
+    (rest.join(" "), limit)
+}
+
+/// Removes `label:<labels>` words from the text, returning the comma
+/// separated labels they ask for. Labels with spaces are quoted, as in
+/// `label:bug,"good first issue"`.
+pub(crate) fn extract_label_filter(text: &str) -> (String, Vec<String>) {
+    let term =
+        Regex::new(r#"(^|\s)label:((?:"[^"]*"|[^\s,"]+)(?:,(?:"[^"]*"|[^\s,"]+))*)"#).unwrap();
+    let label = Regex::new(r#""([^"]*)"|([^\s,"]+)"#).unwrap();
+    let mut filter = Vec::new();
+    for cap in term.captures_iter(text) {
+        filter.extend(
+            label
+                .captures_iter(&cap[2])
+                .filter_map(|l| l.get(1).or(l.get(2)))
+                .map(|l| l.as_str().trim().to_string())
+                .filter(|l| !l.is_empty()),
+        );
+    }
+
+    (term.replace_all(text, "$1").to_string(), filter)
+}
+
+/// Removes an `output:<mode>` word from the text, returning the output it
+/// asks for over the channel's.
+pub(crate) fn extract_output(text: &str) -> (String, Option<OutputMode>) {
+    let mut output = None;
+    let mut rest = Vec::new();
+    for word in text.split(' ') {
+        match word.strip_prefix("output:").map(OutputMode::parse) {
+            Some(Some(mode)) => output = Some(mode),
+            _ => rest.push(word),
+        }
+    }
+
+    (rest.join(" "), output)
+}
+
+pub(crate) fn extract_type_filter(text: &str) -> (String, Vec<IssueType>) {
+    let mut filter = Vec::new();
+    let mut rest = Vec::new();
+    for word in text.split(' ') {
+        match word.strip_prefix("type:") {
+            Some(types) => filter.extend(types.split(',').filter_map(IssueType::parse)),
+            None => rest.push(word),
+        }
+    }
+
+    (rest.join(" "), filter)
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct WeeklyStats {
+    pub(crate) week: String,
+    pub(crate) issues: u32,
+    pub(crate) labels: BTreeMap<String, u32>,
+    #[serde(default)]
+    pub(crate) types: BTreeMap<String, u32>,
+    pub(crate) sentiment: BTreeMap<String, u32>,
+    pub(crate) opened: u64,
+    pub(crate) closed: u64,
+    pub(crate) titles: Vec<String>,
+    /// The issues recorded, so those of several digests of the week are
+    /// counted once.
+    #[serde(default)]
+    pub(crate) numbers: Vec<u64>,
+}
+
+impl WeeklyStats {
+    pub(crate) fn record(
+        &mut self,
+        number: u64,
+        title: &str,
+        labels: &[String],
+        issue_type: IssueType,
+        sentiment: &str,
+    ) {
+        if self.numbers.contains(&number) {
+            return;
+        }
+        self.numbers.push(number);
+        self.issues += 1;
+        for label in labels {
+            *self.labels.entry(label.to_string()).or_insert(0) += 1;
+        }
+        *self
+            .types
+            .entry(issue_type.as_str().to_string())
+            .or_insert(0) += 1;
+        *self.sentiment.entry(sentiment.to_string()).or_insert(0) += 1;
+        self.titles.push(title.to_string());
+    }
+
+    pub(crate) fn describe(&self) -> String {
+        let join_counts = |map: &BTreeMap<String, u32>| {
+            map.iter()
+                .map(|(k, v)| format!("{k}: {v}"))
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+
+        format!(
+            "Week {}: {} active issues, {} opened, {} closed; labels [{}]; types [{}]; sentiment [{}]; titles: {}",
+            self.week,
+            self.issues,
+            self.opened,
+            self.closed,
+            join_counts(&self.labels),
+            join_counts(&self.types),
+            join_counts(&self.sentiment),
+            self.titles.join(" | ")
+        )
+    }
+}
+
+pub(crate) fn trends_key(owner: &str, repo: &str) -> String {
+    format!("trends:{owner}/{repo}")
+}
+
+pub(crate) fn load_weekly_stats(owner: &str, repo: &str) -> Vec<WeeklyStats> {
+    store::load(&trends_key(owner, repo)).unwrap_or_default()
+}
+
+pub(crate) fn save_weekly_stats(owner: &str, repo: &str, stats: WeeklyStats) {
+    let mut history = load_weekly_stats(owner, repo);
+    history.retain(|w| w.week != stats.week);
+    history.push(stats);
+    history.sort_by(|a, b| a.week.cmp(&b.week));
+    if history.len() > TREND_WEEKS_KEPT {
+        history.drain(0..history.len() - TREND_WEEKS_KEPT);
+    }
+
+    store::save(&trends_key(owner, repo), &history);
+}
+
+pub(crate) async fn trends(workspace: &str, channel: &str, args: &str) {
+    let mut parts = args.split_whitespace();
+    let Some((owner, repo)) = parts.next().and_then(|s| s.split_once('/')) else {
+        send_message(
+            workspace,
+            channel,
+            "Usage: flows trends <github_owner>/<github_repo> [n_weeks]".to_string(),
+        );
+        return;
+    };
+    if let Some(refusal) = repo_refusal(owner, repo) {
+        send_message(workspace, channel, refusal);
+        return;
+    }
+    let n_weeks = parts
+        .next()
+        .and_then(|n| n.parse::<usize>().ok())
+        .unwrap_or(4)
+        .clamp(2, TREND_WEEKS_KEPT);
+
+    let history = load_weekly_stats(owner, repo);
+    if history.len() < 2 {
+        send_message(
+            workspace,
+            channel,
+            format!("Not enough history for {owner}/{repo} yet. Trends become available after digests have been generated in at least two different weeks."),
+        );
+        return;
+    }
+
+    let recent = &history[history.len().saturating_sub(n_weeks)..];
+    let weeks = recent
+        .iter()
+        .map(|w| w.describe())
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    let chat_id = format!("Trends#{owner}/{repo}");
+    let co = ChatOptions {
+        model: ChatModel::GPT35Turbo,
+        restart: true,
+        system_prompt: Some("As an AI co-owner of a GitHub repository, you track how issue activity changes from week to week and explain the trends to the maintainers."),
+    };
+    let question = format!("Here are the weekly issue digest statistics for the repository {owner}/{repo}, oldest first:\n{weeks}\nCompare the most recent week against the previous weeks. Narrate the notable trends in a few short sentences, quantifying changes with percentages where possible (for example, 'bug reports up 40%, mostly about the 3.2 release') and pointing out recurring topics from the issue titles.");
+
+    match chat_completion(&chat_id, &question, &co).await {
+        Ok(r) => {
+            send_message(
+                workspace,
+                channel,
+                format!("Issue Trends for {owner}/{repo}:\n{}", r.choice),
+            );
+        }
+        Err(e) => {
+            Event::warn("trends")
+                .err(&e)
+                .log("the trends could not be narrated");
+            send_message(
+                workspace,
+                channel,
+                format!(":warning: The trends of {owner}/{repo} could not be narrated: {e}"),
+            );
+        }
+    }
+}
+
+pub(crate) fn schedule_command(workspace: &str, channel: &str, user: &str, args: &str) {
+    let mut schedules = load_schedules(workspace, channel);
+    let tz = channel_timezone(workspace, channel);
+
+    let reply = match args.split_whitespace().collect::<Vec<&str>>().as_slice() {
+        ["list"] | [] => match schedules.is_empty() {
+            true => "There are no scheduled digests for this channel.".to_string(),
+            false => schedules
+                .iter()
+                .enumerate()
+                .map(|(i, s)| format!("{}. {}", i + 1, s.describe(tz)))
+                .collect::<Vec<String>>()
+                .join("\n"),
+        },
+        _ if !is_channel_admin(user) => {
+            "Only the channel admins listed in `channel_admins` can change the schedules.".to_string()
+        }
+        ["remove", n] => match n.parse::<usize>() {
+            Ok(n) if n >= 1 && n <= schedules.len() => {
+                let removed = schedules.remove(n - 1);
+                save_schedules(workspace, channel, &schedules);
+                format!("Removed the scheduled digest {}.", removed.describe(tz))
+            }
+            _ => format!("There is no scheduled digest number {n}, see `flows schedule list`."),
+        },
+        _ => match Schedule::parse(args) {
+            Ok(schedule) => match repo_refusal(&schedule.owner, &schedule.repo) {
+                Some(refusal) => refusal,
+                None => {
+                    let reply = format!("Scheduled a digest {}.", schedule.describe(tz));
+                    schedules.push(schedule);
+                    save_schedules(workspace, channel, &schedules);
+                    reply
+                }
+            },
+            Err(e) => format!(
+                "Could not create the schedule: {e}.\nUsage: flows schedule weekly <weekday> <HH:00> <github_owner>/<github_repo> [n], flows schedule daily <HH:00> <github_owner>/<github_repo> [n], flows schedule list, flows schedule remove <number>"
+            ),
+        },
+    };
+
+    send_message(workspace, channel, reply);
+}
+
+/// Parses `[<github_owner>/<github_repo>]#<number>`. When the repository is
+/// omitted, the `repo` setting of the channel is used, or else the
+/// `default_repo` environment variable.
+pub(crate) fn parse_issue_ref(
+    workspace: &str,
+    channel: &str,
+    args: &str,
+) -> Option<(String, String, u64)> {
+    let args = args.split_whitespace().collect::<String>();
+    let (owner_repo, number) = args.split_once('#')?;
+    let number = number.parse::<u64>().ok()?;
+    let owner_repo = match owner_repo.is_empty() {
+        true => resolved_settings(workspace, channel)
+            .default_repo
+            .or_else(|| config::var("default_repo"))?,
+        false => owner_repo.to_string(),
+    };
+    let (owner, repo) = owner_repo.split_once('/')?;
+    if owner.is_empty() || repo.is_empty() {
+        return None;
+    }
+    Some((owner.to_string(), repo.to_string(), number))
+}
+
+pub(crate) async fn publish_command(workspace: &str, channel: &str, args: &str) -> Result<()> {
+    let Some((owner, repo, issue_number)) = parse_issue_ref(workspace, channel, args) else {
+        send_message(
+            workspace,
+            channel,
+            "Usage: flows publish <github_owner>/<github_repo>#<issue_number>, or flows publish #<issue_number> when a default repository is set".to_string(),
+        );
+        return Ok(());
+    };
+
+    if let Some(refusal) = repo_refusal(&owner, &repo) {
+        send_message(workspace, channel, refusal);
+        return Ok(());
+    }
+    if publish_mode(&owner, &repo).is_none() {
+        send_message(
+            workspace,
+            channel,
+            format!("{owner}/{repo} has not opted in to publishing summaries on GitHub. Add it to `publish_repos` to enable this."),
+        );
+        return Ok(());
+    }
+
+    let octocrab = get_octo(&Default);
+    let issue = match github_request(Some((workspace, channel)), || async {
+        octocrab.issues(&owner, &repo).get(issue_number).await
+    })
+    .await
+    {
+        Ok(issue) => issue,
+        Err(error) if is_rate_limited(&error) => {
+            send_message(workspace, channel, rate_limited_message().await);
+            return Ok(());
+        }
+        Err(_e) => {
+            send_message(
+                workspace,
+                channel,
+                format!("Could not find issue {owner}/{repo}#{issue_number}, please double check the repository and issue number."),
+            );
+            return Ok(());
+        }
+    };
+
+    let labels = issue
+        .labels
+        .iter()
+        .map(|lab| lab.name.clone())
+        .collect::<Vec<String>>();
+    let issue_type = classify_issue(
+        issue.number,
+        &issue.title,
+        issue.body.as_deref().unwrap_or(""),
+        &labels,
+    )
+    .await;
+    let url = issue.html_url.to_string();
+    // comments on GitHub are read by everyone, so they stay in English
+    let summary = get_summary(&Deps::live(), &owner, &repo, issue, issue_type, None).await?;
+    let summary = moderate(issue_number, &url, summary).await;
+
+    let reply = match publish_summary(&owner, &repo, issue_number, &summary).await {
+        Ok(url) => format!("Published the summary of {owner}/{repo}#{issue_number}: {url}"),
+        Err(e) => format!("Could not publish the summary of {owner}/{repo}#{issue_number}: {e}"),
+    };
+    send_message(workspace, channel, reply);
+    Ok(())
+}
+
+/// Creates a Jira ticket from a GitHub issue, given as
+/// `<issue> to jira:<PROJECT>`, with its summary as the description.
+pub(crate) async fn escalate_command(workspace: &str, channel: &str, args: &str) -> Result<()> {
+    let usage = "Usage: flows escalate <github_owner>/<github_repo>#<issue_number> to jira:<PROJECT>, or flows escalate #<issue_number> to jira:<PROJECT> when a default repository is set";
+    let parsed = args.split_once(" to ").and_then(|(issue, project)| {
+        let project = project.trim();
+        let project = project
+            .get(..5)
+            .filter(|prefix| prefix.eq_ignore_ascii_case("jira:"))
+            .map(|_| project[5..].trim())?;
+        let (owner, repo, issue_number) = parse_issue_ref(workspace, channel, issue)?;
+        (!project.is_empty()).then(|| (owner, repo, issue_number, project.to_uppercase()))
+    });
+    let Some((owner, repo, issue_number, project)) = parsed else {
+        send_message(workspace, channel, usage.to_string());
+        return Ok(());
+    };
+    if let Some(refusal) = repo_refusal(&owner, &repo) {
+        send_message(workspace, channel, refusal);
+        return Ok(());
+    }
+
+    let octocrab = get_octo(&Default);
+    let issue = match github_request(Some((workspace, channel)), || async {
+        octocrab.issues(&owner, &repo).get(issue_number).await
+    })
+    .await
+    {
+        Ok(issue) => issue,
+        Err(error) if is_rate_limited(&error) => {
+            send_message(workspace, channel, rate_limited_message().await);
+            return Ok(());
+        }
+        Err(_e) => {
+            send_message(
+                workspace,
+                channel,
+                format!("Could not find issue {owner}/{repo}#{issue_number}, please double check the repository and issue number."),
+            );
+            return Ok(());
+        }
+    };
+
+    let labels = issue
+        .labels
+        .iter()
+        .map(|lab| lab.name.clone())
+        .collect::<Vec<String>>();
+    let issue_type = classify_issue(
+        issue.number,
+        &issue.title,
+        issue.body.as_deref().unwrap_or(""),
+        &labels,
+    )
+    .await;
+    let (title, url) = (issue.title.clone(), issue.html_url.to_string());
+    let summary = cached_summary(&Deps::live(), &owner, &repo, issue, issue_type, None).await?;
+    let summary = moderate(issue_number, &url, summary).await;
+
+    let issue_ref = format!("{owner}/{repo}#{issue_number}");
+    let reply = match create_jira_ticket(&project, &title, &summary, &labels, &issue_ref, &url) {
+        Ok((key, ticket_url)) => format!("Escalated {issue_ref} to {key}: {ticket_url}"),
+        Err(e) => format!(":warning: Could not escalate {issue_ref} to Jira: {e}"),
+    };
+    send_message(workspace, channel, reply);
+    Ok(())
+}
+
+pub(crate) fn subscribe_command(workspace: &str, channel: &str, user: &str, args: &str) {
+    let mut subscriptions = load_subscriptions(workspace, channel);
+    let tz = channel_timezone(workspace, channel);
+
+    let reply = match args.split_whitespace().collect::<Vec<&str>>().as_slice() {
+        ["list"] | [] => match subscriptions.is_empty() {
+            true => "This channel has no subscriptions.".to_string(),
+            false => subscriptions
+                .iter()
+                .map(|s| format!("- {}", s.describe(tz)))
+                .collect::<Vec<String>>()
+                .join("\n"),
+        },
+        _ if !is_channel_admin(user) => {
+            "Only the channel admins listed in `channel_admins` can change the subscriptions.".to_string()
+        }
+        [owner_repo, frequency] => match (owner_repo.split_once('/'), Frequency::parse(frequency)) {
+            (Some((owner, repo)), Some(frequency)) if !owner.is_empty() && !repo.is_empty() => {
+                if let Some(refusal) = repo_refusal(owner, repo) {
+                    send_message(workspace, channel, refusal);
+                    return;
+                }
+                subscriptions.retain(|s| {
+                    !(s.owner.eq_ignore_ascii_case(owner) && s.repo.eq_ignore_ascii_case(repo))
+                });
+                let subscription = Subscription {
+                    owner: owner.to_string(),
+                    repo: repo.to_string(),
+                    frequency,
+                    last_run: "".to_string(),
+                };
+                let reply = format!("Subscribed this channel to {}. New issues and busy threads are posted here as well once the flow has been redeployed.", subscription.describe(tz));
+                subscriptions.push(subscription);
+                save_subscriptions(workspace, channel, &subscriptions);
+                reply
+            }
+            _ => "Usage: flows subscribe <github_owner>/<github_repo> <daily|weekly>, flows subscribe list".to_string(),
+        },
+        _ => "Usage: flows subscribe <github_owner>/<github_repo> <daily|weekly>, flows subscribe list".to_string(),
+    };
+
+    send_message(workspace, channel, reply);
+}
+
+/// The channel name of a `#channel` argument. Slack sends channels picked
+/// from its autocompletion as `<#C024BE7LH|channel>`.
+pub(crate) fn channel_argument(arg: &str) -> Option<String> {
+    let arg = arg.trim();
+    let name = match arg.strip_prefix("<#").and_then(|a| a.strip_suffix('>')) {
+        // Discord mentions a channel by its id alone
+        Some(mention) => mention.split_once('|').map_or(mention, |(_, name)| name),
+        None => arg.trim_start_matches('#'),
+    };
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// The text of a Discord message as the commands expect it: Discord's
+/// nickname mentions `<@!id>` become plain user mentions `<@id>`.
+pub(crate) fn discord_command_text(content: &str) -> String {
+    content.replace("<@!", "<@")
+}
+
+pub(crate) fn route_command(workspace: &str, channel: &str, user: &str, args: &str) {
+    let usage = "Usage: flows route add <github_owner>/<github_repo> #<slack_channel> (or gchat:<space>, zulip:<stream>), flows route remove <github_owner>/<github_repo>, flows route list";
+    let mut routes = load_routes(workspace);
+
+    let reply = match args.split_whitespace().collect::<Vec<&str>>().as_slice() {
+        ["list"] | [] => {
+            let listed = routes
+                .iter()
+                .map(|r| format!("- {}/{} -> {}", r.owner, r.repo, r.destination()))
+                .chain(
+                    issue_routes(workspace, &home_channel().1)
+                        .into_iter()
+                        .filter(|r| !routes.iter().any(|s| s.is_for(&r.owner, &r.repo)))
+                        .map(|r| {
+                            format!(
+                                "- {}/{} -> {} (issue_routes)",
+                                r.owner,
+                                r.repo,
+                                r.destination()
+                            )
+                        }),
+                )
+                .collect::<Vec<String>>();
+            match listed.is_empty() {
+                true => {
+                    "No GitHub repositories are routed to channels of this workspace.".to_string()
+                }
+                false => format!("GitHub events are posted to:\n{}", listed.join("\n")),
+            }
+        }
+        ["add" | "remove", ..] if !is_channel_admin(user) => {
+            "Only the channel admins listed in `channel_admins` can change the routes.".to_string()
+        }
+        ["add", owner_repo, target] => match (owner_repo.split_once('/'), channel_argument(target))
+        {
+            (Some((owner, repo)), Some(target)) if !owner.is_empty() && !repo.is_empty() => {
+                if let Some(refusal) = repo_refusal(owner, repo) {
+                    send_message(workspace, channel, refusal);
+                    return;
+                }
+                let (route_workspace, route_channel) =
+                    route_destination(workspace, &target, owner, repo);
+                if is_google_chat(&route_workspace)
+                    && !google_chat_spaces().contains_key(&route_channel)
+                {
+                    send_message(
+                        workspace,
+                        channel,
+                        format!(
+                            "{route_channel} is not one of the spaces in `google_chat_spaces`."
+                        ),
+                    );
+                    return;
+                }
+                let route = IssueRoute {
+                    workspace: route_workspace,
+                    owner: owner.to_string(),
+                    repo: repo.to_string(),
+                    channel: route_channel,
+                };
+                let destination = route.destination();
+                routes.retain(|r| !r.is_for(owner, repo));
+                routes.push(route);
+                save_routes(workspace, &routes);
+                format!("New issues, busy threads and releases of {owner}/{repo} are posted to {destination} once the flow has been redeployed.")
+            }
+            _ => usage.to_string(),
+        },
+        ["remove", owner_repo] => match owner_repo.split_once('/') {
+            Some((owner, repo)) => {
+                let before = routes.len();
+                routes.retain(|r| !r.is_for(owner, repo));
+                match routes.len() < before {
+                    true => {
+                        save_routes(workspace, &routes);
+                        format!("Removed the route of {owner}/{repo}, it takes effect once the flow has been redeployed.")
+                    }
+                    false => format!("{owner}/{repo} has no route added with `flows route`."),
+                }
+            }
+            None => usage.to_string(),
+        },
+        _ => usage.to_string(),
+    };
+
+    send_message(workspace, channel, reply);
+}
+
+pub(crate) fn optout_command(workspace: &str, channel: &str, user: &str, args: &str) {
+    let usage = "Usage: flows optout add <github_login>, flows optout remove <github_login>, flows optout list";
+    let mut logins = load_opt_outs();
+
+    let reply = match args.split_whitespace().collect::<Vec<&str>>().as_slice() {
+        ["list"] | [] => match logins.is_empty() {
+            true => "No GitHub users opted out of AI processing with `flows optout`.".to_string(),
+            false => format!(
+                "GitHub users who opted out of AI processing: {}",
+                logins.join(", ")
+            ),
+        },
+        ["add" | "remove", ..] if !is_channel_admin(user) => {
+            "Only the channel admins listed in `channel_admins` can change the opt-outs."
+                .to_string()
+        }
+        ["add", login] => {
+            let login = login.trim_start_matches('@').to_lowercase();
+            if !logins.contains(&login) {
+                logins.push(login.clone());
+                save_opt_outs(&logins);
+            }
+            format!("Issues opened by {login} are no longer summarized and their comments are left out of summaries.")
+        }
+        ["remove", login] => {
+            let login = login.trim_start_matches('@').to_lowercase();
+            let before = logins.len();
+            logins.retain(|listed| *listed != login);
+            match logins.len() < before {
+                true => {
+                    save_opt_outs(&logins);
+                    format!("{login} is summarized again.")
+                }
+                false => format!("{login} did not opt out with `flows optout`."),
+            }
+        }
+        _ => usage.to_string(),
+    };
+
+    send_message(workspace, channel, reply);
+}
+
+pub(crate) fn unsubscribe_command(workspace: &str, channel: &str, user: &str, args: &str) {
+    let mut subscriptions = load_subscriptions(workspace, channel);
+
+    let reply = match args.trim().split_once('/') {
+        _ if !is_channel_admin(user) => {
+            "Only the channel admins listed in `channel_admins` can change the subscriptions."
+                .to_string()
+        }
+        Some((owner, repo)) => {
+            let before = subscriptions.len();
+            subscriptions.retain(|s| {
+                !(s.owner.eq_ignore_ascii_case(owner) && s.repo.eq_ignore_ascii_case(repo))
+            });
+            match subscriptions.len() < before {
+                true => {
+                    save_subscriptions(workspace, channel, &subscriptions);
+                    format!("Unsubscribed this channel from {owner}/{repo}.")
+                }
+                false => format!("This channel is not subscribed to {owner}/{repo}."),
+            }
+        }
+        None => "Usage: flows unsubscribe <github_owner>/<github_repo>".to_string(),
+    };
+
+    send_message(workspace, channel, reply);
+}
+
+/// The longest snooze or SLA, in days.
+pub(crate) const MAX_DURATION_DAYS: i64 = 366;
+
+/// Parses durations such as `30m`, `48h`, `2d` or `1w`, of up to a year.
+pub(crate) fn parse_duration(s: &str) -> Result<Duration> {
+    let s = s.trim();
+    let invalid = || {
+        Error::Usage(format!(
+            "`{s}` is not a duration such as 30m, 48h, 2d or 1w"
+        ))
+    };
+    let split = s.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+    let (n, unit) = s.split_at(split);
+    let n = n
+        .parse::<i64>()
+        .ok()
+        .filter(|n| *n > 0)
+        .ok_or_else(invalid)?;
+    let duration = match unit {
+        "m" => Duration::try_minutes(n),
+        "h" => Duration::try_hours(n),
+        "d" => Duration::try_days(n),
+        "w" => Duration::try_weeks(n),
+        _ => return Err(invalid()),
+    };
+    duration
+        .filter(|d| d.num_days() <= MAX_DURATION_DAYS)
+        .ok_or_else(|| Error::Usage(format!("`{s}` is longer than a year")))
+}
+
+pub(crate) fn snooze_command(workspace: &str, channel: &str, user: &str, args: &str) {
+    let mut snoozes = load_snoozes(workspace);
+
+    let reply = match args.split_whitespace().collect::<Vec<&str>>().as_slice() {
+        ["list"] | [] => match snoozes.is_empty() {
+            true => "No repositories are snoozed.".to_string(),
+            false => snoozes
+                .iter()
+                .map(|s| {
+                    format!(
+                        "- {}/{} until {}",
+                        s.owner,
+                        s.repo,
+                        s.until.format("%Y-%m-%d %H:%M UTC")
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join("\n"),
+        },
+        _ if !is_channel_admin(user) => {
+            "Only the channel admins listed in `channel_admins` can snooze and unsnooze repositories.".to_string()
+        }
+        [owner_repo, arg] => match (owner_repo.split_once('/'), parse_duration(arg)) {
+            (Some((owner, repo)), Ok(duration)) if !owner.is_empty() && !repo.is_empty() => {
+                let Some(until) = Utc::now().checked_add_signed(duration) else {
+                    send_message(workspace, channel, format!("`{arg}` is too long to snooze."));
+                    return;
+                };
+                snoozes.retain(|s| !s.matches(owner, repo));
+                snoozes.push(Snooze {
+                    owner: owner.to_string(),
+                    repo: repo.to_string(),
+                    until,
+                    channel: channel.to_string(),
+                });
+                save_snoozes(workspace, &snoozes);
+                format!(
+                    "Snoozed {owner}/{repo} until {}. Subscriptions are kept and resume automatically.",
+                    until.format("%Y-%m-%d %H:%M UTC")
+                )
+            }
+            (_, Err(e)) => format!("{e}. Usage: flows snooze <github_owner>/<github_repo> <duration such as 48h or 2d>, flows snooze list"),
+            _ => "Usage: flows snooze <github_owner>/<github_repo> <duration such as 48h or 2d>, flows snooze list".to_string(),
+        },
+        _ => "Usage: flows snooze <github_owner>/<github_repo> <duration such as 48h or 2d>, flows snooze list".to_string(),
+    };
+
+    send_message(workspace, channel, reply);
+}
+
+pub(crate) fn unsnooze_command(workspace: &str, channel: &str, user: &str, args: &str) {
+    let mut snoozes = load_snoozes(workspace);
+
+    let reply = match args.trim().split_once('/') {
+        _ if !is_channel_admin(user) => {
+            "Only the channel admins listed in `channel_admins` can snooze and unsnooze repositories."
+                .to_string()
+        }
+        Some((owner, repo)) => {
+            let before = snoozes.len();
+            snoozes.retain(|s| !s.matches(owner, repo));
+            match snoozes.len() < before {
+                true => {
+                    save_snoozes(workspace, &snoozes);
+                    format!("{owner}/{repo} is no longer snoozed.")
+                }
+                false => format!("{owner}/{repo} is not snoozed."),
+            }
+        }
+        None => "Usage: flows unsnooze <github_owner>/<github_repo>".to_string(),
+    };
+
+    send_message(workspace, channel, reply);
+}
+
+/// Posts the n most recent entries of the audit log (default: 20), only
+/// those by a Slack user or on a repository when one is given.
+pub(crate) fn audit_log_command(workspace: &str, channel: &str, args: &str) {
+    let mut n = 20;
+    let mut filter = None;
+    for arg in args.split_whitespace() {
+        match arg.parse::<usize>() {
+            Ok(count) => n = count.clamp(1, AUDIT_LOG_KEPT),
+            Err(_) => filter = Some(arg),
+        }
+    }
+
+    let log = load_audit_log();
+    let entries = log
+        .iter()
+        .rev()
+        .filter(|entry| filter.is_none_or(|filter| entry.matches(filter)))
+        .take(n)
+        .collect::<Vec<_>>();
+    if entries.is_empty() {
+        send_message(
+            workspace,
+            channel,
+            "The audit log has no matching entries.".to_string(),
+        );
+        return;
+    }
+
+    let mut batch = MessageBatch::new(&Sinks, workspace, channel);
+    batch.push(format!(
+        "The {} most recent audit log entries:",
+        entries.len()
+    ));
+    for entry in entries {
+        batch.push(entry.describe());
+    }
+}
+
+pub(crate) fn history_command(workspace: &str, channel: &str, args: &str) {
+    let mut parts = args.split_whitespace();
+    let Some((owner, repo)) = parts.next().and_then(|s| s.split_once('/')) else {
+        send_message(
+            workspace,
+            channel,
+            "Usage: flows history <github_owner>/<github_repo> [n]".to_string(),
+        );
+        return;
+    };
+    let n = parts
+        .next()
+        .and_then(|n| n.parse::<usize>().ok())
+        .unwrap_or(5)
+        .clamp(1, HISTORY_KEPT);
+
+    let history = load_history(owner, repo);
+    if history.is_empty() {
+        send_message(
+            workspace,
+            channel,
+            format!("No summaries of {owner}/{repo} have been posted yet."),
+        );
+        return;
+    }
+
+    let mut batch = MessageBatch::new(&Sinks, workspace, channel);
+    batch.push(format!(
+        "The {} most recent summaries of {owner}/{repo}:",
+        n.min(history.len())
+    ));
+    for entry in history.iter().rev().take(n) {
+        batch.push(format!(
+            "#{} {} (summarized {}):\n{}",
+            entry.issue_number,
+            entry.title,
+            entry.summarized_at.format("%Y-%m-%d %H:%M UTC"),
+            entry.text
+        ));
+    }
+}
+
+/// Reads the configuration file and the stored settings again and posts how
+/// the settings in effect changed since the last reload in the channel.
+pub(crate) fn reload_command(workspace: &str, channel: &str, user: &str) {
+    if !is_channel_admin(user) {
+        send_message(
+            workspace,
+            channel,
+            "Only the channel admins listed in `channel_admins` can reload the configuration."
+                .to_string(),
+        );
+        return;
+    }
+    let file = reload_config_file();
+    let key = settings_snapshot_key(workspace, channel);
+    let after = effective_settings(workspace, channel);
+    let reply = match store::load::<BTreeMap<String, String>>(&key) {
+        None => format!(
+            "Reloaded the configuration, {} settings are in effect. Changes are reported from the next reload on.",
+            after.len()
+        ),
+        Some(before) => match settings_diff(&before, &after).as_slice() {
+            [] => "Reloaded the configuration, no setting changed.".to_string(),
+            changes => format!(
+                "Reloaded the configuration, the settings changed:\n{}",
+                changes.join("\n")
+            ),
+        },
+    };
+    store::save(&key, &after);
+    match &file.error {
+        Some(e) => send_message(workspace, channel, format!("{reply}\n:warning: {e}")),
+        None => send_message(workspace, channel, reply),
+    }
+}
+
+/// Shows the settings of the channel to everyone, but only lets the users in
+/// `channel_admins` change them.
+pub(crate) fn config_command(workspace: &str, channel: &str, user: &str, args: &str) {
+    let usage = format!(
+        "Usage: flows config [org] get, flows config [org] set <name>=<value> ..., flows config [org] reset [<name> ...], where the names are {}",
+        ChannelSettings::NAMES.join(", ")
+    );
+    // `org` edits the settings of the workspace, which every channel of it
+    // falls back to.
+    let (org, args) = match strip_command(args, "org") {
+        Some(rest) => (true, rest),
+        None => (false, args),
+    };
+    let scope = if org {
+        "this workspace"
+    } else {
+        "this channel"
+    };
+    let mut settings = match org {
+        true => load_org_settings(workspace),
+        false => load_channel_settings(workspace, channel),
+    };
+    let save = |settings: &ChannelSettings| match org {
+        true => save_org_settings(workspace, settings),
+        false => save_channel_settings(workspace, channel, settings),
+    };
+    // what the channel gets, with the layers below the edited one
+    let effective = || match org {
+        true => load_org_settings(workspace)
+            .layered_over(deployment_settings())
+            .describe(),
+        false => resolved_settings(workspace, channel).describe(),
+    };
+
+    let reply = match args.split_whitespace().collect::<Vec<&str>>().as_slice() {
+        ["get"] | [] => format!("Settings of {scope}:\n{}", effective()),
+        ["set" | "reset", ..] if !is_channel_admin(user) => {
+            format!("Only the channel admins listed in `channel_admins` can change the settings of {scope}.")
+        }
+        ["reset"] => {
+            match org {
+                true => remove_org_settings(workspace),
+                false => remove_channel_settings(workspace, channel),
+            }
+            format!("Reset the settings of {scope}:\n{}", effective())
+        }
+        ["reset", names @ ..] => match names
+            .iter()
+            .try_for_each(|n| settings.set(&format!("{n}=")))
+        {
+            Ok(()) => {
+                save(&settings);
+                format!("Updated the settings of {scope}:\n{}", effective())
+            }
+            Err(e) => format!("The settings were not changed, {e}."),
+        },
+        ["set", assignments @ ..] if !assignments.is_empty() => {
+            match assignments.iter().try_for_each(|a| settings.set(a)) {
+                Ok(()) => {
+                    save(&settings);
+                    format!("Updated the settings of {scope}:\n{}", effective())
+                }
+                Err(e) => format!("The settings were not changed, {e}."),
+            }
+        }
+        _ => usage,
+    };
+
+    send_message(workspace, channel, reply);
+}
+
+pub(crate) fn timezone_command(workspace: &str, channel: &str, user: &str, args: &str) {
+    let reply = match args.trim() {
+        "" => format!(
+            "Schedules of this channel use the {} timezone.",
+            channel_timezone(workspace, channel)
+        ),
+        _ if !is_channel_admin(user) => {
+            "Only the channel admins listed in `channel_admins` can change the timezone.".to_string()
+        }
+        name => match name.parse::<Tz>() {
+            Ok(tz) => {
+                store::save(&timezone_key(workspace, channel), &tz.name());
+                format!("Schedules of this channel now use the {tz} timezone, including daylight saving time changes.")
+            }
+            Err(_e) => format!("Unknown timezone `{name}`, please use an IANA name such as Europe/Berlin or America/New_York."),
+        },
+    };
+
+    send_message(workspace, channel, reply);
+}
+
+pub(crate) fn sla_command(workspace: &str, channel: &str, user: &str, args: &str) {
+    let mut slas = load_slas(workspace, channel);
+    let usage = "Usage: flows sla <github_owner>/<github_repo> <label or *> <duration such as 48h>, flows sla list, flows sla remove <github_owner>/<github_repo> <label or *>";
+
+    let reply = match args.split_whitespace().collect::<Vec<&str>>().as_slice() {
+        ["list"] | [] => match slas.is_empty() {
+            true => "No SLAs are defined for this channel.".to_string(),
+            false => slas
+                .iter()
+                .map(|sla| format!("- {}", sla.describe()))
+                .collect::<Vec<String>>()
+                .join("\n"),
+        },
+        _ if !is_channel_admin(user) => {
+            "Only the channel admins listed in `channel_admins` can change the SLAs.".to_string()
+        }
+        ["remove", owner_repo, label] => match owner_repo.split_once('/') {
+            Some((owner, repo)) => {
+                let before = slas.len();
+                slas.retain(|sla| !sla.matches(owner, repo, label));
+                match slas.len() < before {
+                    true => {
+                        save_slas(workspace, channel, &slas);
+                        format!("Removed the SLA for `{label}` issues of {owner}/{repo}.")
+                    }
+                    false => format!("There is no SLA for `{label}` issues of {owner}/{repo}."),
+                }
+            }
+            None => usage.to_string(),
+        },
+        [owner_repo, label, duration] => {
+            match (owner_repo.split_once('/'), parse_duration(duration)) {
+                (Some((owner, repo)), Ok(within)) if !owner.is_empty() && !repo.is_empty() => {
+                    if let Some(refusal) = repo_refusal(owner, repo) {
+                        send_message(workspace, channel, refusal);
+                        return;
+                    }
+                    slas.retain(|sla| !sla.matches(owner, repo, label));
+                    let sla = Sla {
+                        owner: owner.to_string(),
+                        repo: repo.to_string(),
+                        label: label.to_string(),
+                        within: within.num_minutes(),
+                        alerted: Vec::new(),
+                    };
+                    let reply = format!("SLA set, {}.", sla.describe());
+                    slas.push(sla);
+                    save_slas(workspace, channel, &slas);
+                    reply
+                }
+                (_, Err(e)) => format!("{e}. {usage}"),
+                _ => usage.to_string(),
+            }
+        }
+        _ => usage.to_string(),
+    };
+
+    send_message(workspace, channel, reply);
+}
+
+pub(crate) fn user_command(workspace: &str, channel: &str, sender: &str, args: &str) {
+    let mut users = load_slack_users(workspace);
+    let usage = "Usage: flows user <github_login> <@slack_user>, flows user list, flows user remove <github_login>";
+
+    let reply = match args.split_whitespace().collect::<Vec<&str>>().as_slice() {
+        ["list"] | [] => match users.is_empty() {
+            true => "No GitHub logins are mapped to Slack users.".to_string(),
+            false => users
+                .iter()
+                .map(|(login, user)| format!("- {login} → {user}"))
+                .collect::<Vec<String>>()
+                .join("\n"),
+        },
+        _ if !is_channel_admin(sender) => {
+            "Only the channel admins listed in `channel_admins` can map GitHub logins to Slack users.".to_string()
+        }
+        ["remove", login] => match users.remove(&login.to_lowercase()) {
+            Some(_) => {
+                save_slack_users(workspace, &users);
+                format!("{login} is no longer mapped to a Slack user.")
+            }
+            None => format!("{login} is not mapped to a Slack user."),
+        },
+        // Slack sends mentions as <@U012AB3CD>
+        [login, user] if user.starts_with("<@") && user.ends_with('>') => {
+            users.insert(login.to_lowercase(), user.to_string());
+            save_slack_users(workspace, &users);
+            format!("GitHub user {login} is now mentioned as {user}.")
+        }
+        _ => usage.to_string(),
+    };
+
+    send_message(workspace, channel, reply);
+}
+
+pub(crate) fn watch_command(workspace: &str, channel: &str, user: &str, args: &str) {
+    let mut keywords = load_watchlist(workspace, channel);
+    let keyword = args.trim();
+
+    let reply = match keyword {
+        "" | "list" => match keywords.is_empty() {
+            true => "No keywords are watched in this channel.".to_string(),
+            false => keywords
+                .iter()
+                .map(|k| format!("- {k}"))
+                .collect::<Vec<String>>()
+                .join("\n"),
+        },
+        _ if !is_channel_admin(user) => {
+            "Only the channel admins listed in `channel_admins` can change the watched keywords."
+                .to_string()
+        }
+        keyword if keywords.iter().any(|k| k.eq_ignore_ascii_case(keyword)) => {
+            format!("\"{keyword}\" is already watched in this channel.")
+        }
+        keyword => {
+            keywords.push(keyword.to_string());
+            save_watchlist(workspace, channel, &keywords);
+            format!(
+                "New issues and comments mentioning \"{keyword}\" are now posted to this channel."
+            )
+        }
+    };
+
+    send_message(workspace, channel, reply);
+}
+
+pub(crate) fn unwatch_command(workspace: &str, channel: &str, user: &str, args: &str) {
+    let mut keywords = load_watchlist(workspace, channel);
+    let keyword = args.trim();
+
+    let reply = match keyword {
+        "" => "Usage: flows unwatch <keyword>".to_string(),
+        _ if !is_channel_admin(user) => {
+            "Only the channel admins listed in `channel_admins` can change the watched keywords."
+                .to_string()
+        }
+        keyword => {
+            let before = keywords.len();
+            keywords.retain(|k| !k.eq_ignore_ascii_case(keyword));
+            match keywords.len() < before {
+                true => {
+                    save_watchlist(workspace, channel, &keywords);
+                    format!("\"{keyword}\" is no longer watched in this channel.")
+                }
+                false => format!("\"{keyword}\" is not watched in this channel."),
+            }
+        }
+    };
+
+    send_message(workspace, channel, reply);
+}
+
+pub(crate) async fn close_command(workspace: &str, channel: &str, args: &str) {
+    let Some((owner, repo, issue_number)) = parse_issue_ref(workspace, channel, args) else {
+        send_message(
+            workspace,
+            channel,
+            "Usage: flows close <github_owner>/<github_repo>#<issue_number>".to_string(),
+        );
+        return;
+    };
+
+    let allowed = env_repos("close_candidate_repos")
+        .iter()
+        .any(|(o, r)| o.eq_ignore_ascii_case(&owner) && r.eq_ignore_ascii_case(&repo));
+    if !allowed {
+        send_message(
+            workspace,
+            channel,
+            format!("Issues of {owner}/{repo} can't be closed from Slack. Add it to `close_candidate_repos` to enable this."),
+        );
+        return;
+    }
+
+    let octocrab = get_octo(&Default);
+    let closed = github_request(Some((workspace, channel)), || async {
+        octocrab
+            .issues(&owner, &repo)
+            .update(issue_number)
+            .state(IssueState::Closed)
+            .send()
+            .await
+    })
+    .await;
+    let reply = match closed {
+        Ok(issue) => format!("Closed {owner}/{repo}#{issue_number}: {}", issue.html_url),
+        Err(github_flows::octocrab::Error::GitHub { source, .. }) => format!(
+            "GitHub refused to close {owner}/{repo}#{issue_number} ({}). Please make sure the connected GitHub account is allowed to close issues in {owner}/{repo}.",
+            source.message
+        ),
+        Err(_e) => format!("Could not close {owner}/{repo}#{issue_number}, the request to GitHub failed. Please try again later."),
+    };
+    send_message(workspace, channel, reply);
+}
+
+pub(crate) fn rotation_command(workspace: &str, channel: &str, user: &str, args: &str) {
+    let mut rotations = load_rotations(workspace, channel);
+    let usage = "Usage: flows rotation <github_owner>/<github_repo> <github_login>,<github_login>,... [assign], flows rotation list, flows rotation remove <github_owner>/<github_repo>";
+    let today = Utc::now()
+        .with_timezone(&channel_timezone(workspace, channel))
+        .date_naive();
+
+    let reply = match args.split_whitespace().collect::<Vec<&str>>().as_slice() {
+        ["list"] | [] => match rotations.is_empty() {
+            true => "No triage rotations are set up in this channel.".to_string(),
+            false => rotations
+                .iter()
+                .map(|r| {
+                    format!(
+                        "- {}/{}: {} (on duty today: {}){}",
+                        r.owner,
+                        r.repo,
+                        r.members.join(", "),
+                        r.on_duty(today).map(String::as_str).unwrap_or(""),
+                        if r.assign { ", assigned on GitHub" } else { "" }
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join("\n"),
+        },
+        ["remove", owner_repo] => match owner_repo.split_once('/') {
+            Some((owner, repo)) => {
+                let before = rotations.len();
+                rotations.retain(|r| !r.matches(owner, repo));
+                match rotations.len() < before {
+                    true => {
+                        save_rotations(workspace, channel, &rotations);
+                        format!("Removed the triage rotation of {owner}/{repo}.")
+                    }
+                    false => format!("{owner}/{repo} has no triage rotation in this channel."),
+                }
+            }
+            None => usage.to_string(),
+        },
+        [_, _, options @ ..] if !options.is_empty() && !is_channel_admin(user) => {
+            "Only the channel admins listed in `channel_admins` can have issues assigned on GitHub."
+                .to_string()
+        }
+        [owner_repo, members, options @ ..] if options.iter().all(|o| *o == "assign") => {
+            let members = members
+                .split(',')
+                .map(|m| m.trim().trim_start_matches('@').to_string())
+                .filter(|m| !m.is_empty())
+                .collect::<Vec<String>>();
+            match owner_repo.split_once('/') {
+                Some((owner, repo))
+                    if !owner.is_empty() && !repo.is_empty() && !members.is_empty() =>
+                {
+                    rotations.retain(|r| !r.matches(owner, repo));
+                    let rotation = Rotation {
+                        owner: owner.to_string(),
+                        repo: repo.to_string(),
+                        members,
+                        assign: !options.is_empty(),
+                    };
+                    let reply = format!(
+                        "Triage rotation of {owner}/{repo} set to {}. {} is on duty today.",
+                        rotation.members.join(", "),
+                        rotation.on_duty(today).map(String::as_str).unwrap_or("")
+                    );
+                    rotations.push(rotation);
+                    save_rotations(workspace, channel, &rotations);
+                    reply
+                }
+                _ => usage.to_string(),
+            }
+        }
+        _ => usage.to_string(),
+    };
+
+    send_message(workspace, channel, reply);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deps::Clock;
+    use crate::fakes::FixedClock;
+
+    fn clock() -> FixedClock {
+        FixedClock("2024-04-20T12:00:00Z".parse().unwrap())
+    }
+
+    #[test]
+    fn digests_search_the_issues_active_in_the_window() {
+        let query = digest_query("octocat", "Hello-World", 7, &[], clock().now()).unwrap();
+        assert_eq!(
+            query,
+            "repo:octocat/Hello-World is:issue state:open updated:>2024-04-13"
+        );
+
+        let labels = ["bug".to_string(), "good first issue".to_string()];
+        let query = digest_query("octocat", "Hello-World", 1, &labels, clock().now()).unwrap();
+        assert_eq!(
+            query,
+            "repo:octocat/Hello-World is:issue state:open updated:>2024-04-19 label:\"bug\",\"good first issue\""
+        );
+    }
+
+    #[test]
+    fn windows_beyond_the_calendar_are_refused() {
+        let query = digest_query("octocat", "Hello-World", i64::MAX, &[], clock().now());
+        assert!(matches!(query, Err(Error::DaysOutOfRange(i64::MAX))));
+    }
+
+    #[test]
+    fn the_limit_is_taken_from_the_text() {
+        assert_eq!(
+            extract_limit("octocat/Hello-World limit:5 2"),
+            ("octocat/Hello-World 2".to_string(), Some(5))
+        );
+        assert_eq!(
+            extract_limit("octocat/Hello-World limit:0 limit:x"),
+            ("octocat/Hello-World limit:0 limit:x".to_string(), None)
+        );
+    }
+
+    #[test]
+    fn labels_are_taken_from_the_text() {
+        let (rest, labels) =
+            extract_label_filter(r#"octocat/Hello-World label:bug,"good first issue" 7"#);
+        assert_eq!(rest, "octocat/Hello-World  7");
+        assert_eq!(labels, ["bug", "good first issue"]);
+
+        let (rest, labels) = extract_label_filter("label:docs octocat/Hello-World label:ci");
+        assert_eq!(rest, " octocat/Hello-World ");
+        assert_eq!(labels, ["docs", "ci"]);
+
+        let (rest, labels) = extract_label_filter("octocat/Hello-World nolabel:bug");
+        assert_eq!(rest, "octocat/Hello-World nolabel:bug");
+        assert!(labels.is_empty());
+    }
+
+    #[test]
+    fn durations_are_parsed() {
+        assert_eq!(parse_duration("30m").ok(), Some(Duration::minutes(30)));
+        assert_eq!(parse_duration(" 48h ").ok(), Some(Duration::hours(48)));
+        assert_eq!(parse_duration("2d").ok(), Some(Duration::days(2)));
+        assert_eq!(parse_duration("1w").ok(), Some(Duration::weeks(1)));
+        for invalid in ["0d", "d", "5", "3y", "-2d", "2 d"] {
+            assert!(parse_duration(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn durations_are_at_most_a_year() {
+        assert_eq!(parse_duration("52w").ok(), Some(Duration::weeks(52)));
+        assert_eq!(parse_duration("366d").ok(), Some(Duration::days(366)));
+        for too_long in ["367d", "53w", "99999999999999w", "99999999999999999m"] {
+            assert!(
+                matches!(parse_duration(too_long), Err(Error::Usage(_))),
+                "{too_long}"
+            );
+        }
+    }
+}
@@ -0,0 +1,1663 @@
+//! Configuration read from the environment and per-channel settings.
+
+use crate::logging::Event;
+use crate::store;
+use chrono::Duration;
+use chrono_tz::Tz;
+use discord_flows::Bot;
+use github_flows::octocrab::models::issues::Issue;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, env, sync::RwLock};
+
+/// The configuration file of the repository, compiled into the flow. It is
+/// used unless the `summarizer_config` environment variable gives the path
+/// of another one.
+const BUNDLED_CONFIG: &str = include_str!("../summarizer.toml");
+
+/// The contents of a `summarizer.toml` file.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct ConfigFile {
+    /// Digests posted to the flow's channel, written like the arguments of
+    /// `flows schedule`, e.g. `weekly mon 09:00 WasmEdge/WasmEdge 7`.
+    #[serde(default)]
+    pub(crate) schedules: Vec<String>,
+    /// Prompts replacing the built-in ones, by name.
+    #[serde(default)]
+    pub(crate) prompts: BTreeMap<String, String>,
+    /// Prompt profiles by name, attached to repositories by `repo_profiles`.
+    #[serde(default)]
+    pub(crate) profiles: BTreeMap<String, PromptProfile>,
+    /// Every other key is a setting, named like its environment variable.
+    #[serde(flatten)]
+    pub(crate) settings: BTreeMap<String, toml::Value>,
+    /// Why the file could not be read, in which case it is left empty.
+    #[serde(skip)]
+    pub(crate) error: Option<String>,
+}
+
+static CONFIG_FILE: RwLock<Option<&'static ConfigFile>> = RwLock::new(None);
+
+/// The configuration file, read once per instance of the flow or until it is
+/// reloaded.
+pub(crate) fn config_file() -> &'static ConfigFile {
+    if let Some(file) = *CONFIG_FILE.read().unwrap_or_else(|e| e.into_inner()) {
+        return file;
+    }
+    let mut file = CONFIG_FILE.write().unwrap_or_else(|e| e.into_inner());
+    file.get_or_insert_with(|| Box::leak(Box::new(read_config_file())))
+}
+
+/// Reads the configuration file again. The replaced file is leaked, since
+/// settings borrowed from it may still be in use, which is fine for the few
+/// reloads an instance sees.
+pub(crate) fn reload_config_file() -> &'static ConfigFile {
+    let file: &'static ConfigFile = Box::leak(Box::new(read_config_file()));
+    *CONFIG_FILE.write().unwrap_or_else(|e| e.into_inner()) = Some(file);
+    file
+}
+
+fn read_config_file() -> ConfigFile {
+    let text = match env::var("summarizer_config") {
+        Ok(path) => match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) => {
+                return ConfigFile {
+                    error: Some(format!("{path} could not be read: {e}")),
+                    ..ConfigFile::default()
+                }
+            }
+        },
+        Err(_) => BUNDLED_CONFIG.to_string(),
+    };
+    toml::from_str(&text).unwrap_or_else(|e| ConfigFile {
+        error: Some(format!("the configuration file is not valid: {e}")),
+        ..ConfigFile::default()
+    })
+}
+
+/// Whether the value of the setting must not be posted to Slack: keys,
+/// tokens and passwords, and every URL, since the incoming webhooks of
+/// `teams_channels`, `google_chat_spaces`, `mattermost_webhook` and
+/// `outbound_webhook` are credentials themselves.
+fn is_secret(name: &str, value: &str) -> bool {
+    let name = name.to_lowercase();
+    ["key", "token", "secret", "password", "webhook", "url"]
+        .iter()
+        .any(|word| name.contains(word))
+        || value.contains("://")
+}
+
+/// The settings of the flow whose values may be posted to Slack.
+const SHOWN_SETTINGS: &[&str] = &[
+    "allowed_repos",
+    "archive_repo",
+    "atom_feed",
+    "auto_label_repos",
+    "channel_defaults",
+    "channel_languages",
+    "chat_platform",
+    "close_candidate_days",
+    "close_candidate_repos",
+    "comment_threshold",
+    "confluence_page",
+    "cooldown_minutes",
+    "default_limit",
+    "default_repo",
+    "discord_channels",
+    "discussion_category",
+    "discussion_repo",
+    "features",
+    "github_timeout_secs",
+    "hard_max",
+    "heat_velocity",
+    "heat_window_minutes",
+    "heated_alert_channel",
+    "issue_routes",
+    "issue_timeout_secs",
+    "jira_issue_type",
+    "leaderboard_repos",
+    "log_level",
+    "matrix_rooms",
+    "mattermost_channel",
+    "max_comments_fetched",
+    "max_completions_per_issue",
+    "max_map_chunks",
+    "milestone_countdown_days",
+    "moderation",
+    "notion_database",
+    "openai_timeout_secs",
+    "ops_channel",
+    "page_keywords",
+    "page_labels",
+    "postmortem_min_comments",
+    "priority_labels",
+    "publish_repos",
+    "repo_orgs",
+    "repo_profiles",
+    "run_deadline_secs",
+    "s3_archive_bucket",
+    "s3_archive_prefix",
+    "s3_archive_region",
+    "s3_archive_retention_days",
+    "site_branch",
+    "site_format",
+    "site_repo",
+    "slack_channel",
+    "slack_channels",
+    "slack_workspace",
+    "stale_pr_days",
+    "stale_pr_hour",
+    "stale_pr_repos",
+    "subscription_hour",
+    "summarizer_config",
+    "telegram_chats",
+    "trigger_word",
+    "user_cooldown_minutes",
+    "user_daily_quota",
+    "vault_branch",
+    "vault_repo",
+];
+
+/// The settings of the flow that are credentials, addresses or lists of
+/// people, which are only shown as set.
+const MASKED_SETTINGS: &[&str] = &[
+    "allowed_users",
+    "channel_admins",
+    "confluence_token",
+    "confluence_user",
+    "discord_token",
+    "google_chat_spaces",
+    "jira_token",
+    "jira_url",
+    "jira_user",
+    "linear_api_key",
+    "matrix_access_token",
+    "matrix_homeserver",
+    "matrix_hs_token",
+    "matrix_user_id",
+    "mattermost_webhook",
+    "mattermost_webhook_token",
+    "metrics_push_token",
+    "metrics_push_url",
+    "notion_token",
+    "opsgenie_api_key",
+    "opsgenie_api_url",
+    "opted_out_authors",
+    "outbound_webhook",
+    "outbound_webhook_secret",
+    "pagerduty_routing_key",
+    "s3_access_key_id",
+    "s3_archive_endpoint",
+    "s3_secret_access_key",
+    "slack_bot_token",
+    "slack_signing_secret",
+    "teams_channels",
+    "teams_webhook_secret",
+    "telegram_token",
+    "telegram_webhook_secret",
+    "trigger_token",
+    "zulip_api_key",
+    "zulip_email",
+    "zulip_site",
+];
+
+/// How a setting of the configuration file or the environment is shown:
+/// the known settings that are not secret with their value, other known
+/// ones as set, and unknown ones not at all.
+fn shown_setting(name: &str, value: &str) -> Option<String> {
+    let shown = SHOWN_SETTINGS.contains(&name);
+    if shown && !is_secret(name, value) {
+        Some(value.to_string())
+    } else if shown || MASKED_SETTINGS.contains(&name) {
+        Some("(set)".to_string())
+    } else {
+        None
+    }
+}
+
+/// Every known setting in effect for the channel by name: those of the
+/// configuration file with the environment variables overriding them, and
+/// the stored settings of the channel as `channel.<name>`. Secrets are
+/// masked, and variables the flow does not read are left out.
+pub(crate) fn effective_settings(workspace: &str, channel: &str) -> BTreeMap<String, String> {
+    let file = config_file();
+    let mut settings = file
+        .settings
+        .iter()
+        .map(|(name, value)| (name.clone(), setting_text(value)))
+        .collect::<BTreeMap<String, String>>();
+    settings.extend(env::vars());
+    let mut settings = settings
+        .into_iter()
+        .filter_map(|(name, value)| Some((name.clone(), shown_setting(&name, &value)?)))
+        .collect::<BTreeMap<String, String>>();
+    if !file.schedules.is_empty() {
+        settings.insert("schedules".to_string(), file.schedules.join("; "));
+    }
+    for (name, prompt) in &file.prompts {
+        settings.insert(format!("prompts.{name}"), prompt.clone());
+    }
+    for (name, profile) in &file.profiles {
+        if let Some(system) = &profile.system {
+            settings.insert(format!("profiles.{name}.system"), system.clone());
+        }
+        if let Some(style) = &profile.style {
+            settings.insert(format!("profiles.{name}.style"), style.clone());
+        }
+    }
+    for line in resolved_settings(workspace, channel).describe().lines() {
+        if let Some((name, value)) = line.split_once(": ") {
+            settings.insert(format!("channel.{name}"), value.to_string());
+        }
+    }
+    settings
+}
+
+pub(crate) fn settings_snapshot_key(workspace: &str, channel: &str) -> String {
+    format!("settings_snapshot:{workspace}/{channel}")
+}
+
+/// The lines telling how the settings changed, `+` for added, `-` for
+/// removed and `~` for changed ones.
+pub(crate) fn settings_diff(
+    before: &BTreeMap<String, String>,
+    after: &BTreeMap<String, String>,
+) -> Vec<String> {
+    let mut lines = Vec::new();
+    for (name, value) in after {
+        match before.get(name) {
+            None => lines.push(format!("+ {name} = {value}")),
+            Some(old) if old != value => lines.push(format!("~ {name}: {old} -> {value}")),
+            Some(_) => {}
+        }
+    }
+    for name in before.keys().filter(|name| !after.contains_key(*name)) {
+        lines.push(format!("- {name}"));
+    }
+    lines
+}
+
+/// A system prompt and style notes that replace the built-in summary
+/// instructions for the repositories the profile is attached to.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub(crate) struct PromptProfile {
+    /// Replaces the `summary` prompt when set.
+    #[serde(default)]
+    pub(crate) system: Option<String>,
+    /// Appended to the system prompt, e.g. what the summary should stress.
+    #[serde(default)]
+    pub(crate) style: Option<String>,
+}
+
+/// The prompt profile of the repository, named in the `repo_profiles`
+/// environment variable, a comma separated list of
+/// `<github_owner>/<github_repo>=<profile>` entries where `*` as the
+/// repository matches every repository of the owner. An exact entry wins
+/// over a wildcard one.
+pub(crate) fn prompt_profile(owner: &str, repo: &str) -> Option<PromptProfile> {
+    let entries = var("repo_profiles").unwrap_or_default();
+    let mut wildcard = None;
+    let mut exact = None;
+    for entry in entries.split(',') {
+        let Some((owner_repo, name)) = entry.split_once('=') else {
+            continue;
+        };
+        match owner_repo.trim().split_once('/') {
+            Some((o, "*")) if o.eq_ignore_ascii_case(owner) => wildcard = Some(name.trim()),
+            Some((o, r)) if o.eq_ignore_ascii_case(owner) && r.eq_ignore_ascii_case(repo) => {
+                exact = Some(name.trim())
+            }
+            _ => {}
+        }
+    }
+    let name = exact.or(wildcard)?;
+    let profile = config_file().profiles.get(name).cloned();
+    if profile.is_none() {
+        Event::warn("config").log(&format!(
+            "{owner}/{repo} uses the prompt profile `{name}`, which is not in the configuration file"
+        ));
+    }
+    profile
+}
+
+/// Reads a setting from the environment variable of its name, which
+/// overrides the configuration file.
+pub(crate) fn var(name: &str) -> Option<String> {
+    if let Ok(value) = env::var(name) {
+        return Some(value);
+    }
+    config_file().settings.get(name).map(setting_text)
+}
+
+/// Settings of the file are turned into the syntax of their environment
+/// variables: arrays into comma separated lists, and tables into comma
+/// separated `<key>=<value>` entries.
+fn setting_text(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s.clone(),
+        toml::Value::Array(items) => items
+            .iter()
+            .map(setting_text)
+            .collect::<Vec<String>>()
+            .join(","),
+        toml::Value::Table(entries) => entries
+            .iter()
+            .map(|(key, value)| format!("{key}={}", setting_text(value)))
+            .collect::<Vec<String>>()
+            .join(","),
+        other => other.to_string(),
+    }
+}
+
+/// The prompt of the configuration file with the name, or the built-in one.
+pub(crate) fn prompt<'a>(name: &str, default: &'a str) -> &'a str {
+    config_file()
+        .prompts
+        .get(name)
+        .map(String::as_str)
+        .unwrap_or(default)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct IssueRoute {
+    pub(crate) workspace: String,
+    pub(crate) owner: String,
+    pub(crate) repo: String,
+    pub(crate) channel: String,
+}
+
+impl IssueRoute {
+    pub(crate) fn matches(&self, issue: &Issue) -> bool {
+        let mut segments = issue
+            .repository_url
+            .path_segments()
+            .map(|s| s.collect::<Vec<&str>>())
+            .unwrap_or_default()
+            .into_iter()
+            .rev();
+        let repo = segments.next().unwrap_or("");
+        let owner = segments.next().unwrap_or("");
+        self.matches_repository(owner, repo)
+    }
+
+    pub(crate) fn matches_repository(&self, owner: &str, repo: &str) -> bool {
+        owner.eq_ignore_ascii_case(&self.owner) && repo.eq_ignore_ascii_case(&self.repo)
+    }
+}
+
+/// Reads the `issue_routes` environment variable, a comma separated list of
+/// `<github_owner>/<github_repo>=<slack_channel>` entries. The channel part
+/// may be omitted to post to the default channel. The channels are in the
+/// workspace of the flow, or Google Chat spaces given as `gchat:<name>` and
+/// Zulip streams given as `zulip:<stream>`.
+pub(crate) fn issue_routes(workspace: &str, default_channel: &str) -> Vec<IssueRoute> {
+    var("issue_routes")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|entry| {
+            let (owner_repo, channel) = match entry.split_once('=') {
+                Some((owner_repo, channel)) => (owner_repo.trim(), channel.trim()),
+                None => (entry.trim(), default_channel),
+            };
+            let (owner, repo) = owner_repo.split_once('/')?;
+            if owner.is_empty() || repo.is_empty() {
+                return None;
+            }
+            let (workspace, channel) = route_destination(workspace, channel, owner, repo);
+            Some(IssueRoute {
+                workspace,
+                owner: owner.to_string(),
+                repo: repo.to_string(),
+                channel,
+            })
+        })
+        .collect()
+}
+
+pub(crate) fn routes_key(workspace: &str) -> String {
+    format!("routes:{workspace}")
+}
+
+/// The routes of the workspace added with `flows route`. They take
+/// precedence over `issue_routes` for the same repository.
+pub(crate) fn load_routes(workspace: &str) -> Vec<IssueRoute> {
+    store::load(&routes_key(workspace)).unwrap_or_default()
+}
+
+pub(crate) fn save_routes(workspace: &str, routes: &[IssueRoute]) {
+    store::save(&routes_key(workspace), routes);
+}
+
+impl IssueRoute {
+    pub(crate) fn is_for(&self, owner: &str, repo: &str) -> bool {
+        self.owner.eq_ignore_ascii_case(owner) && self.repo.eq_ignore_ascii_case(repo)
+    }
+
+    /// Where the route posts to, as shown by `flows route list`.
+    pub(crate) fn destination(&self) -> String {
+        if is_google_chat(&self.workspace) {
+            return format!("{GOOGLE_CHAT_PREFIX}{}", self.channel);
+        }
+        if is_zulip(&self.workspace) {
+            return format!("{ZULIP_PREFIX}{}", zulip_stream(&self.channel).0);
+        }
+        format!("#{}", self.channel)
+    }
+}
+
+/// The Slack workspace of the channel the flow listens to, read from the
+/// `slack_workspace` environment variable.
+pub(crate) fn slack_workspace() -> String {
+    var("slack_workspace").unwrap_or("secondstate".to_string())
+}
+
+/// The channel the flow listens to for commands, read from the
+/// `slack_channel` environment variable.
+pub(crate) fn slack_channel() -> String {
+    var("slack_channel").unwrap_or("test-flow".to_string())
+}
+
+/// The chat platform the flow listens to for commands besides Discord,
+/// Teams, Telegram and Matrix, read from the `chat_platform` environment variable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ChatPlatform {
+    Slack,
+    /// A self-hosted Mattermost server, through its webhooks.
+    Mattermost,
+}
+
+pub(crate) fn chat_platform() -> ChatPlatform {
+    match var("chat_platform")
+        .map(|p| p.trim().to_lowercase())
+        .as_deref()
+    {
+        Some("mattermost") => ChatPlatform::Mattermost,
+        _ => ChatPlatform::Slack,
+    }
+}
+
+/// The workspace the Mattermost channels are served under, by their names.
+pub(crate) const MATTERMOST_WORKSPACE: &str = "mattermost";
+
+pub(crate) fn is_mattermost(workspace: &str) -> bool {
+    workspace == MATTERMOST_WORKSPACE
+}
+
+/// The channel operational failures are reported to, `ops_channel` as
+/// `<slack_workspace>/<slack_channel>` or as a channel of the home
+/// workspace.
+pub(crate) fn ops_channel() -> Option<(String, String)> {
+    let channel = var("ops_channel")?;
+    let channel = channel.trim().trim_start_matches('#');
+    match channel.split_once('/') {
+        Some((workspace, channel)) => Some((workspace.to_string(), channel.to_string())),
+        None if !channel.is_empty() => Some((home_channel().0, channel.to_string())),
+        None => None,
+    }
+}
+
+/// The `(workspace, channel)` the flow listens to for commands: the Slack
+/// channel, or with Mattermost the channel named by the
+/// `mattermost_channel` environment variable.
+pub(crate) fn home_channel() -> (String, String) {
+    match chat_platform() {
+        ChatPlatform::Slack => (slack_workspace(), slack_channel()),
+        ChatPlatform::Mattermost => (
+            MATTERMOST_WORKSPACE.to_string(),
+            var("mattermost_channel").unwrap_or("town-square".to_string()),
+        ),
+    }
+}
+
+/// The `(workspace, channel)` pairs the flow serves: the channel it listens
+/// to, followed by the entries of the `slack_channels` environment variable,
+/// a comma separated list of `<slack_workspace>/<slack_channel>` entries
+/// where `mattermost/<channel>` is a Mattermost channel, and the Discord,
+/// Teams and Telegram channels and Matrix rooms. Every channel has its own settings,
+/// schedules and subscriptions.
+pub(crate) fn served_channels() -> Vec<(String, String)> {
+    let mut channels = vec![home_channel()];
+    for entry in var("slack_channels").unwrap_or_default().split(',') {
+        let Some((workspace, channel)) = entry.trim().split_once('/') else {
+            continue;
+        };
+        let served = (
+            workspace.trim().to_string(),
+            channel.trim().trim_start_matches('#').to_string(),
+        );
+        if !served.0.is_empty() && !served.1.is_empty() && !channels.contains(&served) {
+            channels.push(served);
+        }
+    }
+    for channel in discord_channels() {
+        channels.push((DISCORD_WORKSPACE.to_string(), channel));
+    }
+    for channel in teams_channels().into_keys() {
+        channels.push((TEAMS_WORKSPACE.to_string(), channel));
+    }
+    for chat in telegram_chats() {
+        channels.push((TELEGRAM_WORKSPACE.to_string(), chat));
+    }
+    for room in matrix_rooms() {
+        channels.push((MATRIX_WORKSPACE.to_string(), room));
+    }
+    channels
+}
+
+/// The workspace the Discord channels are served under. Discord channels
+/// are addressed by their ids within it.
+pub(crate) const DISCORD_WORKSPACE: &str = "discord";
+
+pub(crate) fn is_discord(workspace: &str) -> bool {
+    workspace == DISCORD_WORKSPACE
+}
+
+/// Whether the workspace is a Slack workspace, rather than one the channels
+/// of another platform are served under.
+pub(crate) fn is_slack(workspace: &str) -> bool {
+    !is_discord(workspace)
+        && !is_teams(workspace)
+        && !is_telegram(workspace)
+        && !is_matrix(workspace)
+        && !is_mattermost(workspace)
+        && !is_google_chat(workspace)
+        && !is_zulip(workspace)
+}
+
+/// The Discord channels the flow listens to for commands, read from the
+/// `discord_channels` environment variable, a comma separated list of
+/// channel ids. Empty unless the flow serves Discord.
+pub(crate) fn discord_channels() -> Vec<String> {
+    let mut channels = Vec::new();
+    for channel in var("discord_channels").unwrap_or_default().split(',') {
+        let channel = channel.trim().to_string();
+        if !channel.is_empty() && !channels.contains(&channel) {
+            channels.push(channel);
+        }
+    }
+    channels
+}
+
+/// The workspace the Teams channels are served under, by the names they are
+/// given in `teams_channels`.
+pub(crate) const TEAMS_WORKSPACE: &str = "teams";
+
+pub(crate) fn is_teams(workspace: &str) -> bool {
+    workspace == TEAMS_WORKSPACE
+}
+
+/// The Teams channels the flow serves, by name, with the URL of the
+/// incoming webhook that posts to each. Read from the `teams_channels`
+/// environment variable, a comma separated list of `<name>=<webhook_url>`
+/// entries.
+pub(crate) fn teams_channels() -> BTreeMap<String, String> {
+    var("teams_channels")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|entry| entry.split_once('='))
+        .map(|(name, url)| (name.trim().to_string(), url.trim().to_string()))
+        .filter(|(name, url)| !name.is_empty() && !url.is_empty())
+        .collect()
+}
+
+/// The workspace the Google Chat spaces are posted to under, by the names
+/// they are given in `google_chat_spaces`.
+pub(crate) const GOOGLE_CHAT_WORKSPACE: &str = "google-chat";
+
+/// Routes to Google Chat spaces give `gchat:<name>` as their channel.
+pub(crate) const GOOGLE_CHAT_PREFIX: &str = "gchat:";
+
+pub(crate) fn is_google_chat(workspace: &str) -> bool {
+    workspace == GOOGLE_CHAT_WORKSPACE
+}
+
+/// The Google Chat spaces routes can post to, by name, with the URL of the
+/// incoming webhook of each. Read from the `google_chat_spaces`
+/// environment variable, a comma separated list of `<name>=<webhook_url>`
+/// entries.
+pub(crate) fn google_chat_spaces() -> BTreeMap<String, String> {
+    var("google_chat_spaces")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|entry| entry.split_once('='))
+        .map(|(name, url)| (name.trim().to_string(), url.trim().to_string()))
+        .filter(|(name, url)| !name.is_empty() && !url.is_empty())
+        .collect()
+}
+
+/// The workspace the Zulip streams are posted to under. Its channels are
+/// `<stream>#<owner>/<repo>`, so the messages of a repository get topics
+/// of their own.
+pub(crate) const ZULIP_WORKSPACE: &str = "zulip";
+
+/// Routes to Zulip streams give `zulip:<stream>` as their channel.
+pub(crate) const ZULIP_PREFIX: &str = "zulip:";
+
+pub(crate) fn is_zulip(workspace: &str) -> bool {
+    workspace == ZULIP_WORKSPACE
+}
+
+/// The stream of a Zulip channel, with the repository its topics are
+/// named after.
+pub(crate) fn zulip_stream(channel: &str) -> (&str, Option<&str>) {
+    match channel.rsplit_once('#') {
+        Some((stream, repo)) => (stream, Some(repo)),
+        None => (channel, None),
+    }
+}
+
+/// The workspace and channel a route's destination is posted to: a
+/// Google Chat space for `gchat:<name>`, a Zulip stream for
+/// `zulip:<stream>`, otherwise the channel of the workspace.
+pub(crate) fn route_destination(
+    workspace: &str,
+    channel: &str,
+    owner: &str,
+    repo: &str,
+) -> (String, String) {
+    if let Some(space) = channel.strip_prefix(GOOGLE_CHAT_PREFIX) {
+        return (GOOGLE_CHAT_WORKSPACE.to_string(), space.trim().to_string());
+    }
+    if let Some(stream) = channel.strip_prefix(ZULIP_PREFIX) {
+        return (
+            ZULIP_WORKSPACE.to_string(),
+            format!("{}#{owner}/{repo}", stream.trim()),
+        );
+    }
+    (
+        workspace.to_string(),
+        channel.trim_start_matches('#').to_string(),
+    )
+}
+
+/// The workspace the Telegram chats are served under, by their chat ids.
+pub(crate) const TELEGRAM_WORKSPACE: &str = "telegram";
+
+pub(crate) fn is_telegram(workspace: &str) -> bool {
+    workspace == TELEGRAM_WORKSPACE
+}
+
+/// The Telegram chats the bot serves, read from the `telegram_chats`
+/// environment variable, a comma separated list of chat ids. Messages of
+/// other chats are ignored.
+pub(crate) fn telegram_chats() -> Vec<String> {
+    let mut chats = Vec::new();
+    for chat in var("telegram_chats").unwrap_or_default().split(',') {
+        let chat = chat.trim().to_string();
+        if !chat.is_empty() && !chats.contains(&chat) {
+            chats.push(chat);
+        }
+    }
+    chats
+}
+
+/// The workspace the Matrix rooms are served under, by their room ids.
+pub(crate) const MATRIX_WORKSPACE: &str = "matrix";
+
+pub(crate) fn is_matrix(workspace: &str) -> bool {
+    workspace == MATRIX_WORKSPACE
+}
+
+/// The Matrix rooms the bot serves, read from the `matrix_rooms`
+/// environment variable, a comma separated list of room ids such as
+/// `!abcdefgh:matrix.org`. Messages of other rooms are ignored.
+pub(crate) fn matrix_rooms() -> Vec<String> {
+    let mut rooms = Vec::new();
+    for room in var("matrix_rooms").unwrap_or_default().split(',') {
+        let room = room.trim().to_string();
+        if !room.is_empty() && !rooms.contains(&room) {
+            rooms.push(room);
+        }
+    }
+    rooms
+}
+
+/// The homeserver the Matrix bot is on, read from the `matrix_homeserver`
+/// environment variable, e.g. `https://matrix.org`.
+pub(crate) fn matrix_homeserver() -> Option<String> {
+    var("matrix_homeserver")
+        .map(|url| url.trim().trim_end_matches('/').to_string())
+        .filter(|url| !url.is_empty())
+}
+
+/// The Discord bot the flow talks through: the one given by the
+/// `discord_token` environment variable, or else the bot connected to the
+/// flow on flows.network.
+pub(crate) fn discord_bot() -> Bot {
+    var("discord_token").into()
+}
+
+/// Issues a digest summarizes when neither the request nor the channel sets
+/// a limit, read from the `default_limit` environment variable.
+pub(crate) fn default_limit() -> usize {
+    env_count("default_limit", 10)
+}
+
+/// Issues a digest summarizes at most, whatever the request or the channel
+/// asks for, read from the `hard_max` environment variable.
+pub(crate) fn hard_max() -> usize {
+    env_count("hard_max", 20)
+}
+
+/// The number of issues a digest summarizes: the limit of the request, else
+/// that of the channel, else `default_limit`, and never more than `hard_max`.
+pub(crate) fn issue_limit(requested: Option<usize>, settings: &ChannelSettings) -> usize {
+    requested
+        .or(settings.limit)
+        .unwrap_or_else(default_limit)
+        .min(hard_max())
+}
+
+/// Minutes after a digest before the channel can request the next one, read
+/// from the `cooldown_minutes` environment variable. Off by default.
+pub(crate) fn cooldown_minutes() -> i64 {
+    var("cooldown_minutes")
+        .and_then(|n| n.parse::<i64>().ok())
+        .filter(|n| *n >= 0)
+        .unwrap_or(0)
+}
+
+/// The labels that mark an issue as a priority, lower case, read from the
+/// comma separated `priority_labels` environment variable.
+pub(crate) fn priority_labels() -> Vec<String> {
+    var("priority_labels")
+        .unwrap_or("P0,P1,critical,priority: high".to_string())
+        .split(',')
+        .map(|l| l.trim().to_lowercase())
+        .filter(|l| !l.is_empty())
+        .collect()
+}
+
+/// The labels that page the on-call for a new issue, lower case, read from
+/// the comma separated `page_labels` environment variable.
+pub(crate) fn page_labels() -> Vec<String> {
+    var("page_labels")
+        .unwrap_or("P0".to_string())
+        .split(',')
+        .map(|l| l.trim().to_lowercase())
+        .filter(|l| !l.is_empty())
+        .collect()
+}
+
+/// The phrases that page the on-call when a new issue mentions them, lower
+/// case, read from the comma separated `page_keywords` environment variable.
+pub(crate) fn page_keywords() -> Vec<String> {
+    var("page_keywords")
+        .unwrap_or("data loss".to_string())
+        .split(',')
+        .map(|k| k.trim().to_lowercase())
+        .filter(|k| !k.is_empty())
+        .collect()
+}
+
+/// The Notion database digests are added to as pages, read from the
+/// `notion_database` environment variable. Off when not set.
+pub(crate) fn notion_database() -> Option<String> {
+    var("notion_database")
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+}
+
+/// The Confluence page weekly digests are published to, read from the
+/// `confluence_page` environment variable as the URL of the page, e.g.
+/// `https://acme.atlassian.net/wiki/spaces/ENG/pages/123456/Issue+Digest`.
+/// Returns the base URL of the REST API, the id of the page and its URL.
+pub(crate) fn confluence_page() -> Option<(String, String, String)> {
+    let url = var("confluence_page")?.trim().to_string();
+    let id = match url.split_once("pageId=") {
+        Some((_, query)) => query.split('&').next()?,
+        None => url.split("/pages/").nth(1)?.split('/').next()?,
+    };
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let base = match url.find("/wiki/") {
+        Some(end) => &url[..end + "/wiki".len()],
+        None => {
+            let host_end = url
+                .find("://")
+                .and_then(|start| url[start + 3..].find('/').map(|end| start + 3 + end))
+                .unwrap_or(url.len());
+            &url[..host_end]
+        }
+    };
+    Some((base.to_string(), id.to_string(), url.clone()))
+}
+
+/// A repository with a directory in it, read from an environment variable
+/// such as `site_repo` as `<owner>/<repo>` with an optional directory after
+/// it, `default_dir` when there is none.
+fn env_repo_path(name: &str, default_dir: &str) -> Option<(String, String, String)> {
+    let value = var(name)?;
+    let mut parts = value.trim().splitn(3, '/');
+    let owner = parts.next().filter(|s| !s.is_empty())?;
+    let repo = parts.next().filter(|s| !s.is_empty())?;
+    let dir = parts.next().unwrap_or(default_dir).trim_matches('/');
+    Some((owner.to_string(), repo.to_string(), dir.to_string()))
+}
+
+/// A branch read from an environment variable such as `site_branch`.
+fn env_branch(name: &str) -> Option<String> {
+    var(name)
+        .map(|branch| branch.trim().to_string())
+        .filter(|branch| !branch.is_empty())
+}
+
+/// The repository digests are archived to as Markdown files, read from the
+/// `archive_repo` environment variable as `<owner>/<repo>` with an optional
+/// directory after it, e.g. `acme/reports/digests`. Off when not set.
+pub(crate) fn archive_repo() -> Option<(String, String, String)> {
+    env_repo_path("archive_repo", "digests")
+}
+
+/// The repository the project pulse site is written to, read from the
+/// `site_repo` environment variable as `<owner>/<repo>` with an optional
+/// directory of the site after it, e.g. `acme/pulse/site`. Off when not set.
+pub(crate) fn site_repo() -> Option<(String, String, String)> {
+    env_repo_path("site_repo", "")
+}
+
+/// The branch of the `site_repo` the pages are committed to, read from the
+/// `site_branch` environment variable. The default branch when not set.
+pub(crate) fn site_branch() -> Option<String> {
+    env_branch("site_branch")
+}
+
+/// The repository of the Markdown vault the notes of the summarized issues
+/// are written to, read from the `vault_repo` environment variable as
+/// `<owner>/<repo>` with an optional directory of the notes after it, e.g.
+/// `acme/research-vault/issues`. Off when not set.
+pub(crate) fn vault_repo() -> Option<(String, String, String)> {
+    env_repo_path("vault_repo", "")
+}
+
+/// The branch of the `vault_repo` the notes are committed to, read from the
+/// `vault_branch` environment variable. The default branch when not set.
+pub(crate) fn vault_branch() -> Option<String> {
+    env_branch("vault_branch")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SiteFormat {
+    MdBook,
+    Hugo,
+}
+
+/// The static site generator the pages are written for, read from the
+/// `site_format` environment variable (`mdbook` or `hugo`). mdBook by
+/// default.
+pub(crate) fn site_format() -> SiteFormat {
+    match var("site_format").map(|format| format.trim().to_lowercase()) {
+        Some(format) if format == "hugo" => SiteFormat::Hugo,
+        _ => SiteFormat::MdBook,
+    }
+}
+
+/// The Discussions category weekly digests are posted to, read from the
+/// `discussion_category` environment variable by its name, with the
+/// repository of the `discussion_repo` environment variable as
+/// `<owner>/<repo>`. Without a `discussion_repo`, digests are posted to the
+/// repository they cover. Off when not set.
+pub(crate) fn discussion_category() -> Option<(Option<(String, String)>, String)> {
+    let category = var("discussion_category")?.trim().to_string();
+    if category.is_empty() {
+        return None;
+    }
+    let repo = var("discussion_repo").and_then(|value| {
+        let (owner, repo) = value.trim().split_once('/')?;
+        Some((owner.to_string(), repo.to_string()))
+    });
+    Some((repo, category))
+}
+
+/// The S3-compatible bucket the raw runs are archived to.
+pub(crate) struct S3Archive {
+    pub(crate) endpoint: String,
+    pub(crate) region: String,
+    pub(crate) bucket: String,
+    pub(crate) prefix: String,
+    pub(crate) access_key: String,
+    pub(crate) secret_key: String,
+    pub(crate) retention_days: i64,
+}
+
+/// The archive of the raw runs, read from the `s3_archive_bucket`,
+/// `s3_archive_endpoint` (default: AWS S3 of the region),
+/// `s3_archive_region` (default: us-east-1), `s3_archive_prefix` (default:
+/// `runs`), `s3_access_key_id`, `s3_secret_access_key` and
+/// `s3_archive_retention_days` (default: 90) environment variables. Off when
+/// no bucket is set.
+pub(crate) fn s3_archive() -> Option<Result<S3Archive, String>> {
+    let bucket = var("s3_archive_bucket")?.trim().to_string();
+    let (Some(access_key), Some(secret_key)) =
+        (var("s3_access_key_id"), var("s3_secret_access_key"))
+    else {
+        return Some(Err(
+            "s3_access_key_id and s3_secret_access_key have to be set".to_string(),
+        ));
+    };
+    let region = var("s3_archive_region").unwrap_or("us-east-1".to_string());
+    let region = region.trim();
+    let endpoint = var("s3_archive_endpoint")
+        .unwrap_or(format!("https://s3.{region}.amazonaws.com"))
+        .trim()
+        .trim_end_matches('/')
+        .to_string();
+    Some(Ok(S3Archive {
+        endpoint,
+        region: region.to_string(),
+        bucket,
+        prefix: var("s3_archive_prefix")
+            .unwrap_or("runs".to_string())
+            .trim()
+            .trim_matches('/')
+            .to_string(),
+        access_key: access_key.trim().to_string(),
+        secret_key: secret_key.trim().to_string(),
+        retention_days: env_count("s3_archive_retention_days", 90) as i64,
+    }))
+}
+
+/// Minutes after a digest before the same user can request the next one,
+/// read from the `user_cooldown_minutes` environment variable.
+pub(crate) fn user_cooldown_minutes() -> i64 {
+    var("user_cooldown_minutes")
+        .and_then(|n| n.parse::<i64>().ok())
+        .filter(|n| *n >= 0)
+        .unwrap_or(10)
+}
+
+/// Whether summaries go through the content filter before they are posted,
+/// read from the `moderation` environment variable (`on` or `off`). Off by
+/// default.
+pub(crate) fn moderation_enabled() -> bool {
+    var("moderation").is_some_and(|value| {
+        matches!(
+            value.trim().to_lowercase().as_str(),
+            "on" | "true" | "yes" | "1"
+        )
+    })
+}
+
+/// Whether digests and issue summaries are published as an Atom feed, read
+/// from the `atom_feed` environment variable (`on` or `off`). Off by
+/// default.
+pub(crate) fn atom_feed_enabled() -> bool {
+    var("atom_feed").is_some_and(|value| {
+        matches!(
+            value.trim().to_lowercase().as_str(),
+            "on" | "true" | "yes" | "1"
+        )
+    })
+}
+
+/// Summaries a Slack user may request per day (UTC), read from the
+/// `user_daily_quota` environment variable. Unlimited when 0 or not set.
+pub(crate) fn user_daily_quota() -> usize {
+    var("user_daily_quota")
+        .and_then(|n| n.parse::<usize>().ok())
+        .unwrap_or(0)
+}
+
+/// Number of new comments after which an issue is summarized again,
+/// read from the `comment_threshold` environment variable.
+pub(crate) fn comment_threshold() -> u64 {
+    env_count("comment_threshold", 10) as u64
+}
+
+/// The most detailed level that is logged, read from the `log_level`
+/// environment variable (`error`, `warn`, `info`, `debug` or `trace`).
+pub(crate) fn log_level() -> log::LevelFilter {
+    var("log_level")
+        .and_then(|l| l.parse::<log::LevelFilter>().ok())
+        .unwrap_or(log::LevelFilter::Warn)
+}
+
+fn env_secs(name: &str, default: u64) -> u64 {
+    var(name)
+        .and_then(|n| n.trim().parse::<u64>().ok())
+        .filter(|n| *n > 0)
+        .unwrap_or(default)
+}
+
+fn env_count(name: &str, default: usize) -> usize {
+    var(name)
+        .and_then(|n| n.trim().parse::<usize>().ok())
+        .filter(|n| *n > 0)
+        .unwrap_or(default)
+}
+
+/// Comments of an issue that are fetched at most for its summary, read from
+/// the `max_comments_fetched` environment variable.
+pub(crate) fn max_comments_fetched() -> usize {
+    env_count("max_comments_fetched", 2000)
+}
+
+/// Chunks of a long discussion that are summarized at most, read from the
+/// `max_map_chunks` environment variable.
+pub(crate) fn max_map_chunks() -> usize {
+    env_count("max_map_chunks", 8)
+}
+
+/// Chat completions requested at most for the summary of an issue, read from
+/// the `max_completions_per_issue` environment variable. Long discussions
+/// need at least two, one chunk and the combined summary.
+pub(crate) fn max_completions_per_issue() -> usize {
+    env_count("max_completions_per_issue", 12).max(2)
+}
+
+/// Seconds a GitHub request may take before it is given up, read from the
+/// `github_timeout_secs` environment variable.
+pub(crate) fn github_timeout_secs() -> u64 {
+    env_secs("github_timeout_secs", 30)
+}
+
+/// Seconds an OpenAI request, including its retries, may take before it is
+/// given up, read from the `openai_timeout_secs` environment variable.
+pub(crate) fn openai_timeout_secs() -> u64 {
+    env_secs("openai_timeout_secs", 180)
+}
+
+/// Seconds summarizing a single issue may take before it is skipped, read
+/// from the `issue_timeout_secs` environment variable.
+pub(crate) fn issue_timeout_secs() -> u64 {
+    env_secs("issue_timeout_secs", 300)
+}
+
+/// Seconds after which a digest stops summarizing further issues, read from
+/// the `run_deadline_secs` environment variable.
+pub(crate) fn run_deadline_secs() -> u64 {
+    env_secs("run_deadline_secs", 900)
+}
+
+/// Number of comments within the heat window that makes a thread a heated
+/// candidate, read from the `heat_velocity` environment variable.
+pub(crate) fn heat_velocity() -> usize {
+    env_count("heat_velocity", 5)
+}
+
+/// Length of the window comment velocity is measured over, read from the
+/// `heat_window_minutes` environment variable.
+pub(crate) fn heat_window() -> Duration {
+    Duration::minutes(env_count("heat_window_minutes", 60) as i64)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PublishMode {
+    Manual,
+    Auto,
+}
+
+/// Looks up the repository in the `publish_repos` environment variable, a
+/// comma separated list of `<github_owner>/<github_repo>` entries that have
+/// opted in to summaries being posted as issue comments. Entries suffixed
+/// with `:auto` also get the summaries of GitHub event triggered runs.
+pub(crate) fn publish_mode(owner: &str, repo: &str) -> Option<PublishMode> {
+    var("publish_repos")
+        .unwrap_or_default()
+        .split(',')
+        .find_map(|entry| {
+            let (owner_repo, mode) = match entry.trim().split_once(':') {
+                Some((owner_repo, "auto")) => (owner_repo, PublishMode::Auto),
+                Some((owner_repo, _)) => (owner_repo, PublishMode::Manual),
+                None => (entry.trim(), PublishMode::Manual),
+            };
+            owner_repo
+                .eq_ignore_ascii_case(&format!("{owner}/{repo}"))
+                .then_some(mode)
+        })
+}
+
+/// The repositories listed in an environment variable such as
+/// `stale_pr_repos`, a comma separated list of `<github_owner>/<github_repo>`
+/// entries.
+pub(crate) fn env_repos(name: &str) -> Vec<(String, String)> {
+    var(name)
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|entry| entry.trim().split_once('/'))
+        .filter(|(owner, repo)| !owner.is_empty() && !repo.is_empty())
+        .map(|(owner, repo)| (owner.to_string(), repo.to_string()))
+        .collect()
+}
+
+/// Whether the repository is listed in the `auto_label_repos` environment
+/// variable, a comma separated list of `<github_owner>/<github_repo>` entries.
+pub(crate) fn auto_label_enabled(owner: &str, repo: &str) -> bool {
+    var("auto_label_repos")
+        .unwrap_or_default()
+        .split(',')
+        .any(|entry| {
+            entry
+                .trim()
+                .eq_ignore_ascii_case(&format!("{owner}/{repo}"))
+        })
+}
+
+/// Hour (in the channel's timezone) subscription digests are posted at, read from the
+/// `subscription_hour` environment variable. Weekly digests go out on Mondays.
+pub(crate) fn subscription_hour() -> u32 {
+    var("subscription_hour")
+        .and_then(|n| n.parse::<u32>().ok())
+        .filter(|n| *n < 24)
+        .unwrap_or(9)
+}
+
+pub(crate) fn timezone_key(workspace: &str, channel: &str) -> String {
+    format!("timezone:{workspace}/{channel}")
+}
+
+/// The IANA timezone schedules of the channel are interpreted in, UTC unless
+/// set with the timezone command.
+pub(crate) fn channel_timezone(workspace: &str, channel: &str) -> Tz {
+    store::load::<String>(&timezone_key(workspace, channel))
+        .and_then(|s| s.parse::<Tz>().ok())
+        .unwrap_or(Tz::UTC)
+}
+
+/// Major behaviors of the flow that admins can switch off per deployment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Feature {
+    /// Listening to GitHub events for new issues, comments and releases.
+    GithubEvents,
+    /// Applying the confident label suggestions in `auto_label_repos`.
+    AutoLabel,
+    /// Commenting summaries on issues in the `:auto` `publish_repos`.
+    AutoPublish,
+    /// Digests of schedules and subscriptions posted by the hourly tick.
+    ScheduledDigests,
+}
+
+impl Feature {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Feature::GithubEvents => "github_events",
+            Feature::AutoLabel => "auto_label",
+            Feature::AutoPublish => "auto_publish",
+            Feature::ScheduledDigests => "scheduled_digests",
+        }
+    }
+}
+
+/// Whether the feature is on, read from the `features` environment variable,
+/// a comma separated list of `<feature>=on|off` entries, or the `[features]`
+/// table of the configuration file. Features are on unless switched off.
+pub(crate) fn feature_enabled(feature: Feature) -> bool {
+    !var("features")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|entry| entry.split_once('='))
+        .any(|(name, value)| {
+            name.trim().eq_ignore_ascii_case(feature.as_str())
+                && matches!(
+                    value.trim().to_lowercase().as_str(),
+                    "off" | "false" | "no" | "0"
+                )
+        })
+}
+
+/// How the summaries of a digest are posted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum OutputMode {
+    /// The full summary of every issue.
+    #[default]
+    Full,
+    /// A single sentence per issue.
+    Brief,
+}
+
+/// Settings of a channel that its digests consult on every run. Unset
+/// settings fall back to the defaults.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct ChannelSettings {
+    /// The `<github_owner>/<github_repo>` digested when the command names
+    /// no repository.
+    pub(crate) default_repo: Option<String>,
+    pub(crate) days: Option<i64>,
+    /// Issues summarized per digest.
+    pub(crate) limit: Option<usize>,
+    /// The language summaries are written in, e.g. `German`.
+    pub(crate) language: Option<String>,
+    pub(crate) output: Option<OutputMode>,
+}
+
+impl OutputMode {
+    pub(crate) fn parse(s: &str) -> Option<OutputMode> {
+        match s {
+            "full" => Some(OutputMode::Full),
+            "brief" => Some(OutputMode::Brief),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            OutputMode::Full => "full",
+            OutputMode::Brief => "brief",
+        }
+    }
+}
+
+impl ChannelSettings {
+    /// The names settings are set by, as in `days=14`.
+    pub(crate) const NAMES: [&'static str; 5] = ["repo", "days", "limit", "language", "output"];
+
+    /// Sets the setting given as `<name>=<value>`. An empty value unsets it.
+    pub(crate) fn set(&mut self, assignment: &str) -> Result<(), String> {
+        let (name, value) = assignment
+            .split_once('=')
+            .map(|(n, v)| (n.trim(), v.trim()))
+            .ok_or(format!("`{assignment}` should be given as <name>=<value>"))?;
+        let unset = value.is_empty();
+        match name {
+            "repo" => {
+                self.default_repo = match value.split_once('/') {
+                    _ if unset => None,
+                    Some((owner, repo)) if !owner.is_empty() && !repo.is_empty() => {
+                        Some(value.to_string())
+                    }
+                    _ => {
+                        return Err(
+                            "the repository must be given as <github_owner>/<github_repo>"
+                                .to_string(),
+                        )
+                    }
+                }
+            }
+            "days" => {
+                self.days = match value.parse::<i64>() {
+                    _ if unset => None,
+                    Ok(days) if days > 0 => Some(days),
+                    _ => return Err("the number of days must be a positive number".to_string()),
+                }
+            }
+            "limit" => {
+                self.limit = match value.parse::<usize>() {
+                    _ if unset => None,
+                    Ok(limit) if limit > 0 => Some(limit),
+                    _ => return Err("the limit must be a positive number of issues".to_string()),
+                }
+            }
+            "language" => self.language = (!unset).then(|| value.to_string()),
+            "output" => {
+                self.output = match OutputMode::parse(value) {
+                    _ if unset => None,
+                    Some(output) => Some(output),
+                    None => return Err("the output must be `full` or `brief`".to_string()),
+                }
+            }
+            _ => {
+                return Err(format!(
+                    "unknown setting `{name}`, the settings are {}",
+                    ChannelSettings::NAMES.join(", ")
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// The settings with the unset ones taken from `base`.
+    pub(crate) fn layered_over(self, base: ChannelSettings) -> ChannelSettings {
+        ChannelSettings {
+            default_repo: self.default_repo.or(base.default_repo),
+            days: self.days.or(base.days),
+            limit: self.limit.or(base.limit),
+            language: self.language.or(base.language),
+            output: self.output.or(base.output),
+        }
+    }
+
+    /// One line per setting, with the default of unset ones.
+    pub(crate) fn describe(&self) -> String {
+        let or_default = |value: Option<String>, default: &str| match value {
+            Some(value) => value,
+            None => format!("{default} (default)"),
+        };
+        [
+            format!("repo: {}", or_default(self.default_repo.clone(), "none")),
+            format!(
+                "days: {}",
+                or_default(self.days.map(|d| d.to_string()), "7")
+            ),
+            format!(
+                "limit: {}",
+                or_default(
+                    self.limit.map(|l| l.to_string()),
+                    &default_limit().to_string()
+                )
+            ),
+            format!("language: {}", or_default(self.language.clone(), "English")),
+            format!(
+                "output: {}",
+                or_default(self.output.map(|o| o.as_str().to_string()), "full")
+            ),
+        ]
+        .join("\n")
+    }
+}
+
+/// Whether the Slack user is listed in the `channel_admins` environment
+/// variable, a comma separated list of Slack user ids such as `U024BE7LH`.
+pub(crate) fn is_channel_admin(user: &str) -> bool {
+    var("channel_admins")
+        .unwrap_or_default()
+        .split(',')
+        .any(|admin| admin.trim() == user)
+}
+
+/// Whether the repository matches one of the comma separated
+/// `<github_owner>/<github_repo>` entries of the setting, where `*` as the
+/// repository matches every repository of the owner.
+fn repo_listed(name: &str, owner: &str, repo: &str) -> bool {
+    var(name)
+        .unwrap_or_default()
+        .split(',')
+        .any(|entry| match entry.trim().split_once('/') {
+            Some((o, "*")) => o.eq_ignore_ascii_case(owner),
+            Some((o, r)) => o.eq_ignore_ascii_case(owner) && r.eq_ignore_ascii_case(repo),
+            None => false,
+        })
+}
+
+/// Why the bot refuses to work on the repository, if it does: it is listed
+/// in `denied_repos`, or `allowed_repos` is set and does not list it.
+pub(crate) fn repo_refusal(owner: &str, repo: &str) -> Option<String> {
+    if repo_listed("denied_repos", owner, repo) {
+        return Some(format!(
+            "{owner}/{repo} is excluded from summaries by the admins of the bot."
+        ));
+    }
+    let restricted = var("allowed_repos").is_some_and(|repos| !repos.trim().is_empty());
+    if restricted && !repo_listed("allowed_repos", owner, repo) {
+        return Some(format!(
+            "{owner}/{repo} is not one of the repositories the bot is allowed to summarize."
+        ));
+    }
+    None
+}
+
+/// The GitHub organizations or users whose repositories the slash command
+/// and the compose modal offer, read from the `repo_orgs` environment
+/// variable, a comma separated list. Defaults to the owners of
+/// `allowed_repos` and `default_repo`.
+pub(crate) fn repo_orgs() -> Vec<String> {
+    let listed = var("repo_orgs").unwrap_or_default();
+    let owners = match listed.trim().is_empty() {
+        true => format!(
+            "{},{}",
+            var("allowed_repos").unwrap_or_default(),
+            var("default_repo").unwrap_or_default()
+        ),
+        false => listed,
+    };
+    let mut orgs = Vec::<String>::new();
+    for entry in owners.split(',') {
+        let org = entry.split('/').next().unwrap_or("").trim();
+        if !org.is_empty() && !orgs.iter().any(|o| o.eq_ignore_ascii_case(org)) {
+            orgs.push(org.to_string());
+        }
+    }
+    orgs
+}
+
+/// Whether the Slack user may run commands that ask the model, read from the
+/// `allowed_users` environment variable, a comma separated list of Slack user
+/// ids. Everyone may when it is not set.
+pub(crate) fn may_run_expensive(user: &str) -> bool {
+    let allowed = var("allowed_users").unwrap_or_default();
+    allowed.trim().is_empty() || allowed.split(',').any(|id| id.trim() == user)
+}
+
+/// The language the summaries posted to the channel are written in, English
+/// when none of the layers of its settings gives one.
+pub(crate) fn channel_language(workspace: &str, channel: &str) -> Option<String> {
+    resolved_settings(workspace, channel).language
+}
+
+/// The settings in effect for the channel, each taken from the first layer
+/// that sets it: the channel's own settings, its entry in
+/// `channel_languages`, the settings of its workspace and the
+/// `channel_defaults` of the deployment.
+pub(crate) fn resolved_settings(workspace: &str, channel: &str) -> ChannelSettings {
+    let language = ChannelSettings {
+        language: listed_channel_language(workspace, channel),
+        ..ChannelSettings::default()
+    };
+    load_channel_settings(workspace, channel)
+        .layered_over(language)
+        .layered_over(load_org_settings(workspace))
+        .layered_over(deployment_settings())
+}
+
+/// The defaults of every channel, read from the `channel_defaults`
+/// environment variable, comma separated `<name>=<value>` entries with the
+/// names of `flows config`. Invalid entries are logged and skipped.
+pub(crate) fn deployment_settings() -> ChannelSettings {
+    let mut settings = ChannelSettings::default();
+    for entry in var("channel_defaults").unwrap_or_default().split(',') {
+        if entry.trim().is_empty() {
+            continue;
+        }
+        if let Err(e) = settings.set(entry) {
+            Event::warn("config").log(&format!("`channel_defaults` entry skipped, {e}"));
+        }
+    }
+    settings
+}
+
+/// The entry of the channel in the `channel_languages` environment variable,
+/// a comma separated list of `<slack_workspace>/<slack_channel>=<language>`
+/// entries where the workspace may be left out.
+fn listed_channel_language(workspace: &str, channel: &str) -> Option<String> {
+    var("channel_languages")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|entry| entry.split_once('='))
+        .find(|(target, _)| {
+            let target = target.trim().trim_start_matches('#');
+            let target = target.replace("/#", "/");
+            target.eq_ignore_ascii_case(channel)
+                || target.eq_ignore_ascii_case(&format!("{workspace}/{channel}"))
+        })
+        .map(|(_, language)| language.trim().to_string())
+        .filter(|language| !language.is_empty())
+}
+
+pub(crate) const OPT_OUTS_KEY: &str = "opt_outs";
+
+/// GitHub logins added with `flows optout`, lower case.
+pub(crate) fn load_opt_outs() -> Vec<String> {
+    store::load(OPT_OUTS_KEY).unwrap_or_default()
+}
+
+pub(crate) fn save_opt_outs(logins: &[String]) {
+    store::save(OPT_OUTS_KEY, logins);
+}
+
+/// Whether the GitHub user opted out of AI processing, either with
+/// `flows optout` or in the `opted_out_authors` environment variable, a comma
+/// separated list of GitHub logins. Their issues are not summarized and
+/// their comments are left out of summaries.
+pub(crate) fn is_opted_out(login: &str) -> bool {
+    var("opted_out_authors")
+        .unwrap_or_default()
+        .split(',')
+        .any(|listed| listed.trim().eq_ignore_ascii_case(login))
+        || load_opt_outs()
+            .iter()
+            .any(|listed| listed.eq_ignore_ascii_case(login))
+}
+
+pub(crate) fn channel_settings_key(workspace: &str, channel: &str) -> String {
+    format!("channel_settings:{workspace}/{channel}")
+}
+
+/// Settings shared by the channels of a workspace, which the settings of
+/// each channel override.
+pub(crate) fn org_settings_key(workspace: &str) -> String {
+    format!("org_settings:{workspace}")
+}
+
+pub(crate) fn load_org_settings(workspace: &str) -> ChannelSettings {
+    store::load(&org_settings_key(workspace)).unwrap_or_default()
+}
+
+pub(crate) fn save_org_settings(workspace: &str, settings: &ChannelSettings) {
+    store::save(&org_settings_key(workspace), settings);
+}
+
+pub(crate) fn remove_org_settings(workspace: &str) {
+    store::remove(&org_settings_key(workspace));
+}
+
+pub(crate) fn load_channel_settings(workspace: &str, channel: &str) -> ChannelSettings {
+    store::load(&channel_settings_key(workspace, channel)).unwrap_or_default()
+}
+
+pub(crate) fn save_channel_settings(workspace: &str, channel: &str, settings: &ChannelSettings) {
+    store::save(&channel_settings_key(workspace, channel), settings);
+}
+
+pub(crate) fn remove_channel_settings(workspace: &str, channel: &str) {
+    store::remove(&channel_settings_key(workspace, channel));
+}
+
+pub(crate) fn slack_users_key(workspace: &str) -> String {
+    format!("slack_users:{workspace}")
+}
+
+/// The Slack users of the workspace, keyed by their lowercased GitHub login.
+pub(crate) fn load_slack_users(workspace: &str) -> BTreeMap<String, String> {
+    store::load(&slack_users_key(workspace)).unwrap_or_default()
+}
+
+pub(crate) fn save_slack_users(workspace: &str, users: &BTreeMap<String, String>) {
+    store::save(&slack_users_key(workspace), users);
+}
+
+/// Days a pull request can wait for a review before its reviewers are nudged,
+/// read from the `stale_pr_days` environment variable.
+pub(crate) fn stale_pr_days() -> i64 {
+    env_count("stale_pr_days", 3) as i64
+}
+
+/// Hour (in the channel's timezone) the stale pull request nudges are posted
+/// at, read from the `stale_pr_hour` environment variable.
+pub(crate) fn stale_pr_hour() -> u32 {
+    var("stale_pr_hour")
+        .and_then(|n| n.parse::<u32>().ok())
+        .filter(|n| *n < 24)
+        .unwrap_or(20)
+}
+
+/// Days before a milestone's due date its countdown report starts, read from
+/// the `milestone_countdown_days` environment variable.
+pub(crate) fn milestone_countdown_days() -> i64 {
+    var("milestone_countdown_days")
+        .and_then(|n| n.parse::<i64>().ok())
+        .filter(|n| *n >= 0)
+        .unwrap_or(7)
+}
+
+/// Days an issue author can leave a maintainer's reply unanswered before the
+/// issue is considered for closing, read from the `close_candidate_days`
+/// environment variable.
+pub(crate) fn close_candidate_days() -> i64 {
+    env_count("close_candidate_days", 14) as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_known_settings_are_shown_and_credentials_are_masked() {
+        assert_eq!(
+            shown_setting("default_repo", "octocat/Hello-World").as_deref(),
+            Some("octocat/Hello-World")
+        );
+        assert_eq!(
+            shown_setting("zulip_email", "bot@example.com").as_deref(),
+            Some("(set)")
+        );
+        assert_eq!(
+            shown_setting("channel_admins", "U012AB3CD,U045EF6GH").as_deref(),
+            Some("(set)")
+        );
+        assert_eq!(
+            shown_setting("discord_channels", "https://discord.com/api/webhooks/1/abc").as_deref(),
+            Some("(set)")
+        );
+        assert_eq!(shown_setting("home", "/root"), None);
+        assert_eq!(shown_setting("aws_session_credentials", "abc"), None);
+    }
+
+    #[test]
+    fn settings_are_set_and_unset() {
+        let mut settings = ChannelSettings::default();
+        settings.set("repo=octocat/Hello-World").unwrap();
+        settings.set(" days = 14 ").unwrap();
+        settings.set("limit=5").unwrap();
+        settings.set("language=German").unwrap();
+        settings.set("output=brief").unwrap();
+        assert_eq!(
+            settings.default_repo.as_deref(),
+            Some("octocat/Hello-World")
+        );
+        assert_eq!(settings.days, Some(14));
+        assert_eq!(settings.limit, Some(5));
+        assert_eq!(settings.language.as_deref(), Some("German"));
+        assert!(matches!(settings.output, Some(OutputMode::Brief)));
+
+        for name in ChannelSettings::NAMES {
+            settings.set(&format!("{name}=")).unwrap();
+        }
+        assert!(settings.default_repo.is_none());
+        assert!(settings.days.is_none());
+        assert!(settings.limit.is_none());
+        assert!(settings.language.is_none());
+        assert!(settings.output.is_none());
+    }
+
+    #[test]
+    fn invalid_settings_are_refused() {
+        let mut settings = ChannelSettings::default();
+        for assignment in [
+            "days",
+            "repo=octocat",
+            "repo=/Hello-World",
+            "days=0",
+            "days=a week",
+            "limit=-1",
+            "output=verbose",
+        ] {
+            assert!(settings.set(assignment).is_err(), "{assignment}");
+        }
+        assert_eq!(
+            settings.set("colour=blue"),
+            Err(
+                "unknown setting `colour`, the settings are repo, days, limit, language, output"
+                    .to_string()
+            )
+        );
+        assert!(settings.days.is_none());
+    }
+
+    #[test]
+    fn settings_diffs_tell_what_changed() {
+        let settings = |entries: &[(&str, &str)]| {
+            entries
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect::<BTreeMap<String, String>>()
+        };
+        let before = settings(&[("days", "7"), ("limit", "10"), ("language", "German")]);
+        let after = settings(&[("days", "14"), ("limit", "10"), ("output", "brief")]);
+        assert_eq!(
+            settings_diff(&before, &after),
+            ["~ days: 7 -> 14", "+ output = brief", "- language"]
+        );
+        assert!(settings_diff(&after, &after).is_empty());
+    }
+}
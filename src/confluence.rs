@@ -0,0 +1,113 @@
+//! A rolling Confluence page that weekly digests are published to.
+
+use crate::audit::audit;
+use crate::config::{self, confluence_page};
+use crate::http::request_json;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use http_req::request::Method;
+use serde_json::json;
+
+/// Digests kept on the page, newest first.
+pub(crate) const CONFLUENCE_DIGESTS_KEPT: usize = 12;
+
+/// Every digest starts with a heading of this form, which is where the page
+/// is cut to the kept digests.
+const DIGEST_HEADING: &str = "<h2>Digest of ";
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// The digest in Confluence's storage format.
+fn digest_section(owner: &str, repo: &str, now: DateTime<Utc>, summaries: &[String]) -> String {
+    let mut section = format!(
+        "{DIGEST_HEADING}{owner}/{repo}, {}</h2>",
+        now.format("%Y-%m-%d")
+    );
+    for summary in summaries {
+        let lines = summary
+            .lines()
+            .map(escape_html)
+            .collect::<Vec<_>>()
+            .join("<br/>");
+        section.push_str(&format!("<p>{lines}</p>"));
+    }
+    section
+}
+
+/// Adds the digest above the others on the `confluence_page`, keeping the
+/// latest `CONFLUENCE_DIGESTS_KEPT` digests on it. Confluence only takes an
+/// update of the page's current version, so the page is read first.
+/// Returns the URL of the page.
+pub(crate) fn publish_digest_to_confluence(
+    owner: &str,
+    repo: &str,
+    now: DateTime<Utc>,
+    summaries: &[String],
+) -> Option<Result<String, String>> {
+    let (base, page_id, url) = confluence_page()?;
+    let (Some(user), Some(token)) = (
+        config::var("confluence_user"),
+        config::var("confluence_token"),
+    ) else {
+        return Some(Err(
+            "confluence_user and confluence_token have to be set".to_string()
+        ));
+    };
+    let authorization = format!(
+        "Basic {}",
+        STANDARD.encode(format!("{}:{}", user.trim(), token.trim()))
+    );
+    let headers = [
+        ("Authorization", authorization.as_str()),
+        ("Accept", "application/json"),
+    ];
+    let content_url = format!("{base}/rest/api/content/{page_id}");
+
+    let published = request_json(
+        Method::GET,
+        &format!("{content_url}?expand=body.storage,version"),
+        &headers,
+        None,
+    )
+    .and_then(|page| {
+        let version = page["version"]["number"].as_u64().unwrap_or(1);
+        let title = page["title"].as_str().unwrap_or("Issue Digest").to_string();
+        let existing = page["body"]["storage"]["value"].as_str().unwrap_or("");
+        // whatever is above the first digest, like an introduction, stays
+        let mut parts = existing.split(DIGEST_HEADING);
+        let mut body = parts.next().unwrap_or("").to_string();
+        body.push_str(&digest_section(owner, repo, now, summaries));
+        for kept in parts.take(CONFLUENCE_DIGESTS_KEPT - 1) {
+            body.push_str(DIGEST_HEADING);
+            body.push_str(kept);
+        }
+        let update = json!({
+            "id": page_id,
+            "type": "page",
+            "title": title,
+            "version": { "number": version + 1 },
+            "body": { "storage": { "value": body, "representation": "storage" } },
+        });
+        request_json(Method::PUT, &content_url, &headers, Some(&update))
+    })
+    .map(|_| url.clone())
+    .map_err(|e| {
+        format!("Confluence refused the update ({e}). Please make sure confluence_user can edit the page.")
+    });
+    let outcome = match &published {
+        Ok(url) => format!("updated {url}"),
+        Err(e) => format!("failed: {e}"),
+    };
+    audit(
+        "flow",
+        &format!("published the digest of {owner}/{repo} to Confluence"),
+        Some((owner, repo)),
+        &outcome,
+        false,
+    );
+    Some(published)
+}
@@ -0,0 +1,177 @@
+//! The services a digest runs against, passed in so it can be driven with fakes.
+
+use crate::github::{count_issues, github_request};
+use crate::linear::{is_linear_query, LinearIssues, LINEAR_OWNER};
+use crate::sink::{Sink, Sinks};
+use crate::summarizer::{select_comments, OpenAiSummarizer, Summarizer};
+use chrono::{DateTime, NaiveDate, Utc};
+use github_flows::{get_octo, octocrab::models::issues::Issue, GithubLogin::Default};
+
+/// Tells the time, so the day windows of the digests can be tested.
+pub(crate) trait Clock {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+pub(crate) struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Where the issues and their discussions come from.
+pub(crate) trait IssueSource {
+    /// The issues found by a GitHub search query. When `notify` is given, the
+    /// channel is told about rate limits.
+    async fn search(
+        &self,
+        notify: Option<(&str, &str)>,
+        query: &str,
+    ) -> Result<Vec<Issue>, github_flows::octocrab::Error>;
+
+    /// The issues of the repository opened and closed after the day, for the
+    /// weekly trends.
+    async fn activity(&self, owner: &str, repo: &str, since: NaiveDate) -> (u64, u64);
+
+    /// The comments of the issue that its summary reads, oldest first, as
+    /// `(commenter, comment)` pairs.
+    async fn comments(&self, owner: &str, repo: &str, issue: &Issue) -> Vec<(String, String)>;
+
+    /// The comments of the issue posted or edited after `since`, oldest
+    /// first, as `(commenter, comment)` pairs. `None` when they could not be
+    /// read.
+    async fn comments_since(
+        &self,
+        owner: &str,
+        repo: &str,
+        issue: &Issue,
+        since: DateTime<Utc>,
+    ) -> Option<Vec<(String, String)>>;
+}
+
+pub(crate) struct GithubIssues;
+
+impl IssueSource for GithubIssues {
+    async fn search(
+        &self,
+        notify: Option<(&str, &str)>,
+        query: &str,
+    ) -> Result<Vec<Issue>, github_flows::octocrab::Error> {
+        let octocrab = get_octo(&Default);
+        github_request(notify, || async {
+            octocrab
+                .search()
+                .issues_and_pull_requests(query)
+                .send()
+                .await
+        })
+        .await
+        .map(|page| page.items)
+    }
+
+    async fn activity(&self, owner: &str, repo: &str, since: NaiveDate) -> (u64, u64) {
+        let since = since.format("%Y-%m-%d");
+        let opened = count_issues(&format!("repo:{owner}/{repo} is:issue created:>{since}")).await;
+        let closed = count_issues(&format!("repo:{owner}/{repo} is:issue closed:>{since}")).await;
+        (opened, closed)
+    }
+
+    async fn comments(&self, owner: &str, repo: &str, issue: &Issue) -> Vec<(String, String)> {
+        select_comments(owner, repo, issue.number, issue.comments as usize).await
+    }
+
+    async fn comments_since(
+        &self,
+        owner: &str,
+        repo: &str,
+        issue: &Issue,
+        since: DateTime<Utc>,
+    ) -> Option<Vec<(String, String)>> {
+        let octocrab = get_octo(&Default);
+        let page = github_request(None, || async {
+            octocrab
+                .issues(owner, repo)
+                .list_comments(issue.number)
+                .since(since)
+                .per_page(100u8)
+                .send()
+                .await
+        })
+        .await
+        .ok()?;
+        // `since` also returns comments that were edited after it
+        Some(
+            page.items
+                .into_iter()
+                .filter(|c| c.created_at > since || c.updated_at.is_some_and(|u| u > since))
+                .map(|c| (c.user.login, c.body.unwrap_or_default()))
+                .collect(),
+        )
+    }
+}
+
+/// The issues of GitHub repositories, and of the Linear teams given as
+/// repositories of the `linear` owner.
+pub(crate) struct LiveIssues;
+
+impl IssueSource for LiveIssues {
+    async fn search(
+        &self,
+        notify: Option<(&str, &str)>,
+        query: &str,
+    ) -> Result<Vec<Issue>, github_flows::octocrab::Error> {
+        match is_linear_query(query) {
+            true => LinearIssues.search(notify, query).await,
+            false => GithubIssues.search(notify, query).await,
+        }
+    }
+
+    async fn activity(&self, owner: &str, repo: &str, since: NaiveDate) -> (u64, u64) {
+        match owner == LINEAR_OWNER {
+            true => LinearIssues.activity(owner, repo, since).await,
+            false => GithubIssues.activity(owner, repo, since).await,
+        }
+    }
+
+    async fn comments(&self, owner: &str, repo: &str, issue: &Issue) -> Vec<(String, String)> {
+        match owner == LINEAR_OWNER {
+            true => LinearIssues.comments(owner, repo, issue).await,
+            false => GithubIssues.comments(owner, repo, issue).await,
+        }
+    }
+
+    async fn comments_since(
+        &self,
+        owner: &str,
+        repo: &str,
+        issue: &Issue,
+        since: DateTime<Utc>,
+    ) -> Option<Vec<(String, String)>> {
+        match owner == LINEAR_OWNER {
+            true => LinearIssues.comments_since(owner, repo, issue, since).await,
+            false => GithubIssues.comments_since(owner, repo, issue, since).await,
+        }
+    }
+}
+
+/// The issue source and the summarizer are type parameters since their
+/// async methods can't be called through trait objects.
+pub(crate) struct Deps<'a, I: IssueSource = LiveIssues, S: Summarizer = OpenAiSummarizer> {
+    pub(crate) issues: &'a I,
+    pub(crate) summarizer: &'a S,
+    pub(crate) sink: &'a dyn Sink,
+    pub(crate) clock: &'a dyn Clock,
+}
+
+impl Deps<'static> {
+    /// GitHub and Linear, OpenAI, the configured sinks and the system clock.
+    pub(crate) fn live() -> Deps<'static> {
+        Deps {
+            issues: &LiveIssues,
+            summarizer: &OpenAiSummarizer,
+            sink: &Sinks,
+            clock: &SystemClock,
+        }
+    }
+}
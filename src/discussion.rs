@@ -0,0 +1,123 @@
+//! Weekly digests posted to a GitHub Discussions category, for the
+//! contributors who aren't in the chat.
+
+use crate::audit::audit;
+use crate::config::discussion_category;
+use crate::linear::LINEAR_OWNER;
+use crate::render::markdown;
+use chrono::{DateTime, Utc};
+use github_flows::{get_octo, GithubLogin::Default};
+use serde_json::{json, Value};
+
+/// Sends a query to GitHub's GraphQL API, which answers errors with a 200
+/// and an `errors` list.
+async fn graphql(query: &str, variables: Value) -> Result<Value, String> {
+    let octocrab = get_octo(&Default);
+    let answer = octocrab
+        .post::<_, Value>(
+            "graphql",
+            Some(&json!({ "query": query, "variables": variables })),
+        )
+        .await
+        .map_err(|_e| "the request to GitHub failed.".to_string())?;
+    match answer["errors"][0]["message"].as_str() {
+        Some(message) => Err(message.to_string()),
+        None => Ok(answer["data"].clone()),
+    }
+}
+
+/// Posts the digest as a new discussion in the `discussion_category` of the
+/// repository, or of the `discussion_repo` when it is set. Returns the URL
+/// of the discussion.
+pub(crate) async fn post_digest_discussion(
+    owner: &str,
+    repo: &str,
+    now: DateTime<Utc>,
+    summaries: &[String],
+) -> Option<Result<String, String>> {
+    let (discussion_repo, category) = discussion_category()?;
+    // a Linear team has no repository to discuss it in
+    let (target_owner, target_repo) = match discussion_repo {
+        Some(target) => target,
+        None if owner == LINEAR_OWNER => return None,
+        None => (owner.to_string(), repo.to_string()),
+    };
+
+    let found = graphql(
+        "query($owner: String!, $name: String!) {
+            repository(owner: $owner, name: $name) {
+                id
+                hasDiscussionsEnabled
+                discussionCategories(first: 100) { nodes { id name slug } }
+            }
+        }",
+        json!({ "owner": target_owner, "name": target_repo }),
+    )
+    .await
+    .and_then(|data| {
+        let repository = &data["repository"];
+        if repository["hasDiscussionsEnabled"] != true {
+            return Err(format!(
+                "{target_owner}/{target_repo} does not have discussions enabled"
+            ));
+        }
+        let category_id = repository["discussionCategories"]["nodes"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|node| {
+                node["name"]
+                    .as_str()
+                    .is_some_and(|name| name.eq_ignore_ascii_case(&category))
+                    || node["slug"] == category.to_lowercase()
+            })
+            .and_then(|node| node["id"].as_str())
+            .ok_or(format!(
+                "{target_owner}/{target_repo} has no discussion category {category}"
+            ))?;
+        let repository_id = repository["id"].as_str().unwrap_or_default();
+        Ok((repository_id.to_string(), category_id.to_string()))
+    });
+
+    let posted = match found {
+        Ok((repository_id, category_id)) => {
+            let body = summaries
+                .iter()
+                .map(|summary| markdown(summary))
+                .collect::<Vec<_>>()
+                .join("\n\n---\n\n");
+            graphql(
+                "mutation($input: CreateDiscussionInput!) {
+                    createDiscussion(input: $input) { discussion { url } }
+                }",
+                json!({ "input": {
+                    "repositoryId": repository_id,
+                    "categoryId": category_id,
+                    "title": format!("Weekly digest of {owner}/{repo}, {}", now.format("%Y-%m-%d")),
+                    "body": format!("{body}\n\n_Summarized from the issues active in the last week._"),
+                } }),
+            )
+            .await
+            .and_then(|data| {
+                data["createDiscussion"]["discussion"]["url"]
+                    .as_str()
+                    .map(str::to_string)
+                    .ok_or("GitHub did not answer with the discussion".to_string())
+            })
+        }
+        Err(e) => Err(e),
+    };
+
+    let outcome = match &posted {
+        Ok(url) => format!("posted {url}"),
+        Err(e) => format!("failed: {e}"),
+    };
+    audit(
+        "flow",
+        &format!("posted the digest of {owner}/{repo} as a discussion"),
+        Some((&target_owner, &target_repo)),
+        &outcome,
+        false,
+    );
+    Some(posted)
+}
@@ -0,0 +1,205 @@
+//! Fakes of the services a digest runs against, fed with recorded GitHub issues.
+
+use crate::deps::{Clock, IssueSource};
+use crate::sink::Sink;
+use crate::summarizer::{tokenizer, IssueContext, Prompts, Summarizer, Summary};
+use crate::{Error, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use github_flows::octocrab::models::issues::{Comment, Issue};
+use serde::Deserialize;
+use std::cell::RefCell;
+
+/// An issue with a short discussion that is summarized in one request.
+pub(crate) const SMALL_ISSUE: &str = include_str!("../fixtures/issue_small.json");
+/// An issue whose comments are packed into several chunks.
+pub(crate) const LARGE_ISSUE: &str = include_str!("../fixtures/issue_large.json");
+/// An issue with a pasted log that is longer than a chunk on its own.
+pub(crate) const CHUNKED_ISSUE: &str = include_str!("../fixtures/issue_chunked.json");
+
+/// An issue and its comments as the GitHub API returned them.
+#[derive(Deserialize)]
+pub(crate) struct FixtureIssues {
+    pub(crate) issue: Issue,
+    pub(crate) comments: Vec<Comment>,
+}
+
+impl FixtureIssues {
+    pub(crate) fn load(fixture: &str) -> FixtureIssues {
+        serde_json::from_str(fixture).expect("the fixture is a recorded issue")
+    }
+
+    /// The context a summary of the issue reads, like `issue_context` but
+    /// without the opt-outs and prompt profiles, which live in the store.
+    pub(crate) fn context(&self) -> IssueContext {
+        let issue = &self.issue;
+        IssueContext {
+            number: issue.number,
+            title: issue.title.clone(),
+            author: issue.user.login.clone(),
+            author_role: issue.author_association.clone(),
+            labels: issue
+                .labels
+                .iter()
+                .map(|lab| lab.name.clone())
+                .collect::<Vec<String>>()
+                .join(", "),
+            body: issue.body.clone().unwrap_or_default(),
+            comments: futures::executor::block_on(self.comments("octocat", "Hello-World", issue)),
+            language: None,
+            profile: None,
+        }
+    }
+}
+
+impl IssueSource for FixtureIssues {
+    async fn search(
+        &self,
+        _notify: Option<(&str, &str)>,
+        _query: &str,
+    ) -> Result<Vec<Issue>, github_flows::octocrab::Error> {
+        Ok(vec![self.issue.clone()])
+    }
+
+    async fn activity(&self, _owner: &str, _repo: &str, since: NaiveDate) -> (u64, u64) {
+        let opened = self.issue.created_at.date_naive() > since;
+        let closed = self.issue.closed_at.is_some_and(|c| c.date_naive() > since);
+        (opened as u64, closed as u64)
+    }
+
+    async fn comments(&self, _owner: &str, _repo: &str, _issue: &Issue) -> Vec<(String, String)> {
+        self.comments
+            .iter()
+            .map(|c| (c.user.login.clone(), c.body.clone().unwrap_or_default()))
+            .collect()
+    }
+
+    async fn comments_since(
+        &self,
+        _owner: &str,
+        _repo: &str,
+        _issue: &Issue,
+        since: DateTime<Utc>,
+    ) -> Option<Vec<(String, String)>> {
+        Some(
+            self.comments
+                .iter()
+                .filter(|c| c.created_at > since)
+                .map(|c| (c.user.login.clone(), c.body.clone().unwrap_or_default()))
+                .collect(),
+        )
+    }
+}
+
+/// A clock stopped at the given time.
+pub(crate) struct FixedClock(pub(crate) DateTime<Utc>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+/// A message a sink was given, which is all a platform renders it from:
+/// threads and blocks are not posted.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SentMessage {
+    pub(crate) workspace: String,
+    pub(crate) channel: String,
+    pub(crate) text: String,
+}
+
+/// Keeps the messages instead of delivering them, in the order they were
+/// sent, progress and error messages included.
+#[derive(Default)]
+pub(crate) struct RecordingSink {
+    pub(crate) messages: RefCell<Vec<SentMessage>>,
+}
+
+impl RecordingSink {
+    /// The texts of the messages sent to the channel.
+    pub(crate) fn texts(&self, channel: &str) -> Vec<String> {
+        self.messages
+            .borrow()
+            .iter()
+            .filter(|m| m.channel == channel)
+            .map(|m| m.text.clone())
+            .collect()
+    }
+}
+
+impl Sink for RecordingSink {
+    fn send(&self, workspace: &str, channel: &str, text: &str) {
+        self.messages.borrow_mut().push(SentMessage {
+            workspace: workspace.to_string(),
+            channel: channel.to_string(),
+            text: text.to_string(),
+        });
+    }
+}
+
+/// Builds the prompts like the OpenAI summarizer but keeps them instead of
+/// sending them, answering every chunk with a canned interim summary.
+#[derive(Default)]
+pub(crate) struct MockSummarizer {
+    pub(crate) prompts: RefCell<Vec<String>>,
+}
+
+impl Summarizer for MockSummarizer {
+    async fn summarize(&self, ctx: IssueContext) -> Result<Summary> {
+        let mut summary = Summary::default();
+        let mut prompts = self.prompts.borrow_mut();
+        match ctx.prompts(tokenizer()?)? {
+            Prompts::Single(prompt) => prompts.push(prompt),
+            Prompts::MapReduce {
+                map_prompts,
+                left_out_parts,
+            } => {
+                let mut map_out = String::new();
+                summary.left_out_parts = left_out_parts;
+                for prompt in map_prompts {
+                    summary.parts += 1;
+                    map_out.push_str(&format!("Interim summary of part {}. ", summary.parts));
+                    prompts.push(prompt);
+                }
+                prompts.push(ctx.reduce_prompt(map_out.trim_end()));
+            }
+        }
+        summary.text = format!("{} is being discussed.\nConfidence: 85", ctx.title);
+        Ok(summary)
+    }
+}
+
+/// Fails every summary, like a model that is down.
+pub(crate) struct FailingSummarizer;
+
+impl Summarizer for FailingSummarizer {
+    async fn summarize(&self, _ctx: IssueContext) -> Result<Summary> {
+        Err(Error::Model("the model is overloaded".to_string()))
+    }
+}
+
+/// Compares the output with its snapshot in `snapshots/`, so changes of the
+/// prompts and messages show up in review. With `UPDATE_SNAPSHOTS` set the
+/// snapshot is written instead.
+macro_rules! assert_snapshot {
+    ($name:literal, $actual:expr) => {
+        $crate::fakes::compare_snapshot(
+            $name,
+            include_str!(concat!("../snapshots/", $name, ".snap")),
+            &$actual,
+        )
+    };
+}
+pub(crate) use assert_snapshot;
+
+pub(crate) fn compare_snapshot(name: &str, stored: &str, actual: &str) {
+    if std::env::var("UPDATE_SNAPSHOTS").is_ok() {
+        let path = format!("{}/snapshots/{name}.snap", env!("CARGO_MANIFEST_DIR"));
+        std::fs::write(&path, actual).expect("the snapshot is written");
+        return;
+    }
+    assert_eq!(
+        actual, stored,
+        "the output differs from snapshots/{name}.snap, run the tests with UPDATE_SNAPSHOTS=1 to accept it"
+    );
+}
@@ -0,0 +1,148 @@
+//! An Atom feed of the digests and issue summaries, published to a gist.
+
+use crate::config::atom_feed_enabled;
+use crate::logging::Event;
+use crate::store;
+use chrono::{DateTime, Utc};
+use github_flows::{get_octo, GithubLogin::Default};
+use serde::{Deserialize, Serialize};
+
+pub(crate) const FEED_KEY: &str = "feed:entries";
+pub(crate) const FEED_GIST_KEY: &str = "feed:gist";
+pub(crate) const FEED_FILE: &str = "summaries.atom";
+
+/// Entries kept in the feed, the newest.
+pub(crate) const FEED_KEPT: usize = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct FeedEntry {
+    pub(crate) id: String,
+    pub(crate) title: String,
+    pub(crate) link: String,
+    pub(crate) content: String,
+    pub(crate) updated: DateTime<Utc>,
+}
+
+/// The gist the feed is published to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct FeedGist {
+    pub(crate) id: String,
+    pub(crate) feed_url: String,
+}
+
+pub(crate) fn load_feed() -> Vec<FeedEntry> {
+    store::load(FEED_KEY).unwrap_or_default()
+}
+
+/// Adds an entry to the feed, dropping the oldest beyond `FEED_KEPT`. Does
+/// nothing unless the feed is on.
+pub(crate) fn record_feed_entry(title: &str, link: &str, content: &str) {
+    if !atom_feed_enabled() {
+        return;
+    }
+    let now = Utc::now();
+    let mut entries = load_feed();
+    entries.push(FeedEntry {
+        id: format!(
+            "urn:summarizer:{}:{}",
+            now.timestamp_millis(),
+            entries.len()
+        ),
+        title: title.to_string(),
+        link: link.to_string(),
+        content: content.to_string(),
+        updated: now,
+    });
+    let excess = entries.len().saturating_sub(FEED_KEPT);
+    entries.drain(..excess);
+    store::save(FEED_KEY, &entries);
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The feed as an Atom document, newest entries first.
+pub(crate) fn render_feed(entries: &[FeedEntry]) -> String {
+    let updated = entries
+        .last()
+        .map_or(Utc::now(), |entry| entry.updated)
+        .to_rfc3339();
+    let mut feed = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<feed xmlns=\"http://www.w3.org/2005/Atom\">\n  <title>GitHub issue summaries</title>\n  <id>urn:summarizer:feed</id>\n  <updated>{updated}</updated>\n  <author><name>Issue summarizer</name></author>\n"
+    );
+    for entry in entries.iter().rev() {
+        feed.push_str(&format!(
+            "  <entry>\n    <title>{}</title>\n    <id>{}</id>\n    <link href=\"{}\"/>\n    <updated>{}</updated>\n    <content type=\"text\">{}</content>\n  </entry>\n",
+            escape_xml(&entry.title),
+            escape_xml(&entry.id),
+            escape_xml(&entry.link),
+            entry.updated.to_rfc3339(),
+            escape_xml(&entry.content)
+        ));
+    }
+    feed.push_str("</feed>\n");
+    feed
+}
+
+/// The URL feed readers subscribe to, which always serves the latest
+/// revision of the gist.
+fn feed_url(gist_url: &str) -> String {
+    format!("{}/raw/{FEED_FILE}", gist_url.trim_end_matches('/'))
+}
+
+/// Writes the feed to its gist, creating a secret gist of the connected
+/// GitHub account the first time. Returns the URL of the feed, `None` when
+/// the feed is off.
+pub(crate) async fn publish_feed() -> Option<Result<String, String>> {
+    if !atom_feed_enabled() {
+        return None;
+    }
+    let content = render_feed(&load_feed());
+    let octocrab = get_octo(&Default);
+    let published = match store::load::<FeedGist>(FEED_GIST_KEY) {
+        Some(FeedGist { id, .. }) => octocrab
+            .gists()
+            .update(&id)
+            .file(FEED_FILE)
+            .with_content(content)
+            .send()
+            .await
+            .map_err(|_e| format!("the gist {id} of the feed could not be updated")),
+        None => octocrab
+            .gists()
+            .create()
+            .description("Atom feed of the GitHub issue summaries")
+            .public(false)
+            .file(FEED_FILE, content)
+            .send()
+            .await
+            .map_err(|_e| {
+                "the gist of the feed could not be created, the GitHub token needs the `gist` scope"
+                    .to_string()
+            })
+            .inspect(|gist| {
+                let feed_url = feed_url(gist.html_url.as_str());
+                store::save(
+                    FEED_GIST_KEY,
+                    &FeedGist {
+                        id: gist.id.clone(),
+                        feed_url,
+                    },
+                );
+            }),
+    };
+    let published = published.map(|gist| feed_url(gist.html_url.as_str()));
+    if let Err(e) = &published {
+        Event::warn("feed").log(e);
+    }
+    Some(published)
+}
+
+/// The URL of the published feed, once it was published.
+pub(crate) fn published_feed_url() -> Option<String> {
+    store::load::<FeedGist>(FEED_GIST_KEY).map(|gist| gist.feed_url)
+}
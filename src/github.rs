@@ -0,0 +1,1119 @@
+//! GitHub events and the GitHub API requests shared by the features.
+
+use crate::audit::audit;
+use crate::config::{
+    self, archive_repo, auto_label_enabled, channel_language, close_candidate_days,
+    comment_threshold, feature_enabled, github_timeout_secs, heat_velocity, heat_window,
+    is_opted_out, priority_labels, publish_mode, Feature, IssueRoute, PublishMode,
+};
+use crate::deps::{Deps, LiveIssues};
+use crate::feed::{publish_feed, record_feed_entry};
+use crate::logging::Event;
+use crate::metrics::{count, time_stage, Counter};
+use crate::ops::{report_ops, OpsFailure};
+use crate::paging::{page_on_call, page_reason};
+use crate::schedule::is_snoozed;
+use crate::sink::send_message;
+use crate::summarizer::{
+    assess_heat, classify_issue, get_postmortem, get_release_announcement, get_release_fixes,
+    get_summary, moderate, record_history, suggest_labels,
+};
+use crate::vault::{export_notes_to_vault, VaultNote};
+use crate::{store, Result};
+use chrono::{DateTime, Duration, Utc};
+use github_flows::{
+    get_octo,
+    octocrab::models::{
+        events::payload::{IssueCommentEventAction, IssuesEventAction},
+        issues::{Issue, IssueStateReason},
+        pulls::PullRequest,
+    },
+    EventPayload,
+    GithubLogin::Default,
+};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use snafu::GenerateImplicitData;
+
+pub(crate) const GITHUB_RETRIES: u32 = 3;
+
+pub(crate) const SECONDARY_RATE_LIMIT_BACKOFF_SECS: u64 = 30;
+
+pub(crate) const MAX_RATE_LIMIT_WAIT_SECS: u64 = 120;
+
+pub(crate) const HEAT_SCORE_THRESHOLD: u8 = 70;
+
+pub(crate) const HEAT_ALERT_COOLDOWN_HOURS: i64 = 24;
+
+pub(crate) const AUTO_LABEL_CONFIDENCE: u8 = 85;
+
+pub(crate) const LABEL_AUDIT_KEPT: usize = 500;
+
+pub(crate) async fn github_event_handler(
+    workspace: &str,
+    route: &IssueRoute,
+    payload: EventPayload,
+) -> Result<()> {
+    if is_snoozed(workspace, &route.owner, &route.repo) {
+        return Ok(());
+    }
+    let author = match &payload {
+        EventPayload::IssuesEvent(event) => Some(&event.issue.user.login),
+        EventPayload::IssueCommentEvent(event) => Some(&event.issue.user.login),
+        _ => None,
+    };
+    if author.is_some_and(|author| is_opted_out(author)) {
+        Event::info("github_event").log("skipped, the author of the issue opted out");
+        return Ok(());
+    }
+
+    // Every registered listener sees every delivered event, so only the
+    // listener of the repository the issue belongs to handles it.
+    match payload {
+        EventPayload::IssuesEvent(event)
+            if event.action == IssuesEventAction::Opened && route.matches(&event.issue) =>
+        {
+            let text = format!(
+                "{}\n{}",
+                event.issue.title,
+                event.issue.body.as_deref().unwrap_or("")
+            );
+            check_watchlists(workspace, route, &event.issue, &text, "new issue").await?;
+
+            let issue_number = event.issue.number;
+            let labeling = triage_labels(route, &event.issue).await;
+            let paging = page_reason(&event.issue).map(|reason| (reason, event.issue.clone()));
+            let summary = routed_issue_summary(route, event.issue).await?;
+            let paged =
+                paging.and_then(|(reason, issue)| page_on_call(route, &issue, &reason, &summary));
+            send_message(
+                workspace,
+                &route.channel,
+                format!(
+                    "New issue opened in {}/{}:\n{summary}{labeling}{}",
+                    route.owner,
+                    route.repo,
+                    paged.unwrap_or_default()
+                ),
+            );
+            auto_publish(workspace, route, issue_number, &summary).await;
+        }
+        EventPayload::IssuesEvent(event)
+            if event.action == IssuesEventAction::Closed
+                && event.issue.state_reason != Some(IssueStateReason::NotPlanned)
+                && needs_postmortem(&event.issue)
+                && route.matches(&event.issue) =>
+        {
+            let postmortem =
+                get_postmortem(&LiveIssues, &route.owner, &route.repo, &event.issue).await;
+            send_message(
+                workspace,
+                &route.channel,
+                format!(
+                    ":white_check_mark: Issue #{} in {}/{} was closed: {}\n{postmortem}\n{}",
+                    event.issue.number,
+                    route.owner,
+                    route.repo,
+                    event.issue.title,
+                    event.issue.html_url
+                ),
+            );
+        }
+        EventPayload::IssueCommentEvent(event)
+            if event.action == IssueCommentEventAction::Created
+                && event.issue.pull_request.is_none()
+                && route.matches(&event.issue) =>
+        {
+            check_heat(workspace, route, &event.issue, event.comment.created_at).await?;
+            check_watchlists(
+                workspace,
+                route,
+                &event.issue,
+                event.comment.body.as_deref().unwrap_or(""),
+                &format!("new comment by {} on issue", event.comment.user.login),
+            )
+            .await?;
+
+            let new_comments = increment_comment_counter(route, event.issue.number);
+            if new_comments >= comment_threshold() {
+                reset_comment_counter(route, event.issue.number);
+                let issue_number = event.issue.number;
+                let summary = routed_issue_summary(route, event.issue).await?;
+                send_message(
+                    workspace,
+                    &route.channel,
+                    format!(
+                        "Issue #{issue_number} in {}/{} has {new_comments} new comments since its last summary:\n{summary}",
+                        route.owner, route.repo
+                    ),
+                );
+                auto_publish(workspace, route, issue_number, &summary).await;
+            }
+        }
+        // octocrab has no typed payload for release events
+        EventPayload::UnknownEvent(event)
+            if event["action"] == "published"
+                && event["release"].is_object()
+                && event["repository"]["full_name"]
+                    .as_str()
+                    .and_then(|n| n.split_once('/'))
+                    .is_some_and(|(owner, repo)| route.matches_repository(owner, repo)) =>
+        {
+            let announcement =
+                get_release_announcement(&route.owner, &route.repo, &event["release"]).await;
+            send_message(workspace, &route.channel, announcement);
+
+            if let Some(fixes) =
+                get_release_fixes(&route.owner, &route.repo, &event["release"]).await
+            {
+                send_message(workspace, &route.channel, fixes);
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+pub(crate) async fn routed_issue_summary(route: &IssueRoute, issue: Issue) -> Result<String> {
+    let labels = issue
+        .labels
+        .iter()
+        .map(|lab| lab.name.clone())
+        .collect::<Vec<String>>();
+    let issue_type = classify_issue(
+        issue.number,
+        &issue.title,
+        issue.body.as_deref().unwrap_or(""),
+        &labels,
+    )
+    .await;
+    let (issue_number, title, url) = (issue.number, issue.title.clone(), issue.html_url.clone());
+    let language = channel_language(&route.workspace, &route.channel);
+    let summary = get_summary(
+        &Deps::live(),
+        &route.owner,
+        &route.repo,
+        issue,
+        issue_type,
+        language.as_deref(),
+    )
+    .await?;
+    let summary = moderate(issue_number, url.as_str(), summary).await;
+    record_history(&route.owner, &route.repo, issue_number, &title, &summary);
+    record_feed_entry(
+        &format!("{}/{}#{issue_number}: {title}", route.owner, route.repo),
+        url.as_str(),
+        &summary,
+    );
+    publish_feed().await;
+    let note = VaultNote {
+        number: issue_number,
+        title,
+        labels,
+        url: url.to_string(),
+        summary: summary.clone(),
+    };
+    if let Some(Err(e)) = export_notes_to_vault(&route.owner, &route.repo, &[note]).await {
+        Event::warn("vault")
+            .issue(issue_number)
+            .err(e)
+            .log("the note could not be written");
+    }
+    Ok(summary)
+}
+
+/// Whether GitHub refused the request because a rate limit was exceeded.
+pub(crate) fn is_rate_limited(error: &github_flows::octocrab::Error) -> bool {
+    match error {
+        github_flows::octocrab::Error::GitHub { source, .. } => {
+            source.message.to_lowercase().contains("rate limit")
+        }
+        _ => false,
+    }
+}
+
+/// When the exhausted GitHub rate limit resets. octocrab doesn't expose the
+/// rate limit headers of its responses, so they are read from `rate_limit`.
+pub(crate) async fn rate_limit_reset() -> Option<DateTime<Utc>> {
+    let octocrab = get_octo(&Default);
+    let limits = octocrab.ratelimit().get().await.ok()?;
+    [limits.resources.core, limits.resources.search]
+        .iter()
+        .filter(|rate| rate.remaining == 0)
+        .filter_map(|rate| DateTime::from_timestamp(rate.reset as i64, 0))
+        .max()
+}
+
+/// The remaining requests of the core and search rate limits of the
+/// connected GitHub account, one line each.
+pub(crate) async fn rate_limit_summary() -> Option<Vec<String>> {
+    let octocrab = get_octo(&Default);
+    let limits = octocrab.ratelimit().get().await.ok()?;
+    let rates = [
+        ("core", limits.resources.core),
+        ("search", limits.resources.search),
+    ];
+    Some(
+        rates
+            .into_iter()
+            .map(|(name, rate)| {
+                let reset = DateTime::from_timestamp(rate.reset as i64, 0)
+                    .map(|reset| reset.format(" until %H:%M UTC").to_string())
+                    .unwrap_or_default();
+                format!("{name}: {} of {} left{reset}", rate.remaining, rate.limit)
+            })
+            .collect(),
+    )
+}
+
+/// GitHub did not answer a request within `github_timeout_secs`.
+#[derive(Debug, thiserror::Error)]
+#[error("GitHub did not respond within {0}s")]
+pub(crate) struct GithubTimeout(u64);
+
+pub(crate) fn is_timed_out(error: &github_flows::octocrab::Error) -> bool {
+    match error {
+        github_flows::octocrab::Error::Other { source, .. } => source.is::<GithubTimeout>(),
+        _ => false,
+    }
+}
+
+/// Runs a GitHub request, waiting out rate limits and retrying it. Secondary
+/// rate limits are backed off exponentially; an exhausted rate limit is
+/// waited for until it resets, unless that is too far away. When `notify`
+/// is given, the channel is told about the wait. Each attempt is given up
+/// after `github_timeout_secs`.
+pub(crate) async fn github_request<T, F, Fut>(
+    notify: Option<(&str, &str)>,
+    mut request: F,
+) -> Result<T, github_flows::octocrab::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T, github_flows::octocrab::Error>>,
+{
+    let mut attempt = 0;
+    loop {
+        let secs = github_timeout_secs();
+        let started = std::time::Instant::now();
+        count(Counter::GithubCalls);
+        let response =
+            match tokio::time::timeout(std::time::Duration::from_secs(secs), request()).await {
+                Ok(response) => response,
+                Err(_elapsed) => Err(github_flows::octocrab::Error::Other {
+                    source: Box::new(GithubTimeout(secs)),
+                    backtrace: snafu::Backtrace::generate(),
+                }),
+            };
+        time_stage("github", started);
+        let error = match response {
+            Err(e) if is_rate_limited(&e) && attempt < GITHUB_RETRIES => e,
+            Err(e) => {
+                Event::warn("github").err(&e).log("the request failed");
+                report_github_failure(&e);
+                return Err(e);
+            }
+            result => return result,
+        };
+
+        let secondary = match &error {
+            github_flows::octocrab::Error::GitHub { source, .. } => {
+                source.message.to_lowercase().contains("secondary")
+            }
+            _ => false,
+        };
+        let wait = match secondary {
+            true => SECONDARY_RATE_LIMIT_BACKOFF_SECS << attempt,
+            false => match rate_limit_reset().await {
+                Some(reset) => (reset - Utc::now()).num_seconds().max(0) as u64 + 1,
+                None => SECONDARY_RATE_LIMIT_BACKOFF_SECS,
+            },
+        };
+        if wait > MAX_RATE_LIMIT_WAIT_SECS {
+            Event::warn("github")
+                .err(&error)
+                .log(&format!("rate limited for {wait}s, giving up"));
+            report_ops(
+                OpsFailure::GithubRateLimit,
+                &format!("A request gave up, the limit resets in {wait}s: {error}"),
+            );
+            return Err(error);
+        }
+        Event::info("github").log(&format!("rate limited, retrying in {wait}s"));
+
+        if let Some((workspace, channel)) = notify {
+            send_message(
+                workspace,
+                channel,
+                format!("GitHub rate limited the request, retrying in {wait}s…"),
+            );
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(wait)).await;
+        attempt += 1;
+    }
+}
+
+/// GitHub refused the token of the flow.
+fn is_bad_credentials(error: &github_flows::octocrab::Error) -> bool {
+    match error {
+        github_flows::octocrab::Error::GitHub { source, .. } => {
+            let message = source.message.to_lowercase();
+            message.contains("bad credentials") || message.contains("requires authentication")
+        }
+        _ => false,
+    }
+}
+
+/// Reports a failed request to the ops channel when only an operator can
+/// fix it.
+fn report_github_failure(error: &github_flows::octocrab::Error) {
+    if is_bad_credentials(error) {
+        report_ops(OpsFailure::GithubAuth, &format!("GitHub answered: {error}"));
+    } else if is_rate_limited(error) {
+        report_ops(
+            OpsFailure::GithubRateLimit,
+            &format!("A request gave up after {GITHUB_RETRIES} retries: {error}"),
+        );
+    }
+}
+
+/// Tells the channel when the GitHub rate limit allows trying again.
+pub(crate) async fn rate_limited_message() -> String {
+    match rate_limit_reset().await {
+        Some(reset) => format!(
+            "GitHub's rate limit has been reached. Please try again after {}.",
+            reset.format("%H:%M UTC")
+        ),
+        None => {
+            "GitHub's rate limit has been reached. Please try again in a few minutes.".to_string()
+        }
+    }
+}
+
+pub(crate) async fn count_issues(query: &str) -> u64 {
+    let octocrab = get_octo(&Default);
+    match github_request(None, || async {
+        octocrab
+            .search()
+            .issues_and_pull_requests(query)
+            .per_page(1u8)
+            .send()
+            .await
+    })
+    .await
+    {
+        Ok(page) => page.total_count.unwrap_or(0),
+        Err(_e) => 0,
+    }
+}
+
+pub(crate) fn comment_counter_key(route: &IssueRoute, issue_number: u64) -> String {
+    format!(
+        "comments_since_summary:{}/{}#{issue_number}",
+        route.owner, route.repo
+    )
+}
+
+pub(crate) fn increment_comment_counter(route: &IssueRoute, issue_number: u64) -> u64 {
+    let key = comment_counter_key(route, issue_number);
+    let count = store::load::<u64>(&key).unwrap_or(0) + 1;
+    store::save(&key, &count);
+    count
+}
+
+pub(crate) fn reset_comment_counter(route: &IssueRoute, issue_number: u64) {
+    store::save(&comment_counter_key(route, issue_number), &0u64);
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct HeatTracker {
+    pub(crate) comment_times: Vec<i64>,
+    pub(crate) last_alert: Option<i64>,
+}
+
+pub(crate) fn heat_key(route: &IssueRoute, issue_number: u64) -> String {
+    format!("heat:{}/{}#{issue_number}", route.owner, route.repo)
+}
+
+pub(crate) async fn check_heat(
+    workspace: &str,
+    route: &IssueRoute,
+    issue: &Issue,
+    commented_at: DateTime<Utc>,
+) -> Result<()> {
+    let key = heat_key(route, issue.number);
+    let now = Utc::now();
+    let window_start = now - heat_window();
+
+    let mut tracker = store::load::<HeatTracker>(&key).unwrap_or_default();
+    tracker.comment_times.push(commented_at.timestamp());
+    tracker
+        .comment_times
+        .retain(|t| *t > window_start.timestamp());
+
+    let velocity = tracker.comment_times.len();
+    let recently_alerted = tracker
+        .last_alert
+        .is_some_and(|t| now.timestamp() - t < HEAT_ALERT_COOLDOWN_HOURS * 3600);
+
+    let mut alert = None;
+    if velocity >= heat_velocity() && !recently_alerted {
+        if let Some(heat) = assess_heat(&LiveIssues, route, issue, window_start).await {
+            if heat.score >= HEAT_SCORE_THRESHOLD {
+                tracker.last_alert = Some(now.timestamp());
+                alert = Some(heat);
+            }
+        }
+    }
+
+    store::save(&key, &tracker);
+
+    if let Some(heat) = alert {
+        let channel = config::var("heated_alert_channel").unwrap_or(route.channel.clone());
+        let summary = routed_issue_summary(route, issue.clone()).await?;
+        send_message(
+            workspace,
+            &channel,
+            format!(
+                ":fire: Issue #{} in {}/{} is heating up: {velocity} comments in the last {} minutes.\nWhy it's heated: {}\n{summary}",
+                issue.number,
+                route.owner,
+                route.repo,
+                heat_window().num_minutes(),
+                heat.reason
+            ),
+        );
+    }
+    Ok(())
+}
+
+/// Posts the summary as a comment on the issue and returns the comment's URL.
+pub(crate) async fn publish_summary(
+    owner: &str,
+    repo: &str,
+    issue_number: u64,
+    summary: &str,
+) -> Result<String, String> {
+    let body = format!("> :robot: **AI-generated summary.** This comment was written automatically by a bot from the discussion above and may contain mistakes.\n\n{summary}");
+
+    let octocrab = get_octo(&Default);
+    match octocrab
+        .issues(owner, repo)
+        .create_comment(issue_number, body)
+        .await
+    {
+        Ok(comment) => Ok(comment.html_url.to_string()),
+        Err(github_flows::octocrab::Error::GitHub { source, .. }) => Err(format!(
+            "GitHub refused the comment ({}). Please make sure the connected GitHub account is allowed to comment on issues in {owner}/{repo}.",
+            source.message
+        )),
+        Err(_e) => Err("the request to GitHub failed, please try again later.".to_string()),
+    }
+}
+
+/// Commits the digest of a repository to the archive repository as a dated
+/// Markdown file, so it outlives the retention of the Slack channel. Returns
+/// the URL of the file.
+pub(crate) async fn archive_digest(
+    owner: &str,
+    repo: &str,
+    now: DateTime<Utc>,
+    summaries: &[String],
+) -> Option<Result<String, String>> {
+    let (archive_owner, archive_repo, dir) = archive_repo()?;
+    let action = format!("archived the digest of {owner}/{repo}");
+    let path = format!("{dir}/{owner}/{repo}/{}.md", now.format("%Y-%m-%d-%H%M"))
+        .trim_start_matches('/')
+        .to_string();
+    let content = format!(
+        "# Digest of {owner}/{repo}, {}\n\n{}\n",
+        now.format("%Y-%m-%d %H:%M UTC"),
+        summaries.join("\n\n---\n\n")
+    );
+
+    let octocrab = get_octo(&Default);
+    let archived = match octocrab
+        .repos(&archive_owner, &archive_repo)
+        .create_file(&path, format!("Archive the digest of {owner}/{repo}"), content)
+        .send()
+        .await
+    {
+        Ok(update) => Ok(update
+            .content
+            .html_url
+            .unwrap_or_else(|| format!("{archive_owner}/{archive_repo}/{path}"))),
+        Err(github_flows::octocrab::Error::GitHub { source, .. }) => Err(format!(
+            "GitHub refused the file ({}). Please make sure the connected GitHub account can push to {archive_owner}/{archive_repo}.",
+            source.message
+        )),
+        Err(_e) => Err("the request to GitHub failed.".to_string()),
+    };
+    let outcome = match &archived {
+        Ok(url) => format!("committed {url}"),
+        Err(e) => format!("failed: {e}"),
+    };
+    audit(
+        "flow",
+        &action,
+        Some((&archive_owner, &archive_repo)),
+        &outcome,
+        false,
+    );
+    Some(archived)
+}
+
+/// Reads a file of a repository, on the branch or the default branch, with
+/// its sha that an update needs. `None` when the file doesn't exist.
+pub(crate) async fn read_repo_file(
+    owner: &str,
+    repo: &str,
+    path: &str,
+    branch: Option<&str>,
+) -> Option<(String, String)> {
+    let octocrab = get_octo(&Default);
+    let repos = octocrab.repos(owner, repo);
+    let mut request = repos.get_content().path(path);
+    if let Some(branch) = branch {
+        request = request.r#ref(branch);
+    }
+    let file = request.send().await.ok()?.items.into_iter().next()?;
+    Some((file.decoded_content()?, file.sha))
+}
+
+/// Checks that the connected GitHub account answers authenticated requests.
+/// Returns the scopes of its token, or `None` when GitHub does not report
+/// them, as for fine-grained tokens and GitHub Apps.
+pub(crate) async fn check_github_token() -> Result<Option<Vec<String>>, String> {
+    let octocrab = get_octo(&Default);
+    let url = octocrab
+        .absolute_url("user")
+        .map_err(|_e| "the GitHub API address of the flow is invalid.".to_string())?;
+    match octocrab._get(url, None::<&()>).await {
+        Ok(response) if response.status().is_success() => Ok(response
+            .headers()
+            .get("x-oauth-scopes")
+            .and_then(|scopes| scopes.to_str().ok())
+            .map(|scopes| {
+                scopes
+                    .split(',')
+                    .map(|scope| scope.trim().to_string())
+                    .filter(|scope| !scope.is_empty())
+                    .collect()
+            })),
+        Ok(response) => Err(format!(
+            "GitHub answered {} to an authenticated request, please connect a GitHub account to the flow.",
+            response.status()
+        )),
+        Err(_e) => Err("GitHub could not be reached.".to_string()),
+    }
+}
+
+pub(crate) async fn auto_publish(
+    workspace: &str,
+    route: &IssueRoute,
+    issue_number: u64,
+    summary: &str,
+) {
+    if !feature_enabled(Feature::AutoPublish)
+        || publish_mode(&route.owner, &route.repo) != Some(PublishMode::Auto)
+    {
+        return;
+    }
+
+    let published = publish_summary(&route.owner, &route.repo, issue_number, summary).await;
+    let outcome = match &published {
+        Ok(url) => format!("posted {url}"),
+        Err(e) => format!("failed: {e}"),
+    };
+    audit(
+        "flow",
+        &format!("commented the summary on #{issue_number}"),
+        Some((&route.owner, &route.repo)),
+        &outcome,
+        false,
+    );
+    if let Err(e) = published {
+        send_message(
+            workspace,
+            &route.channel,
+            format!(
+                "Could not publish the summary of {}/{}#{issue_number}: {e}",
+                route.owner, route.repo
+            ),
+        );
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct LabelSuggestion {
+    pub(crate) name: String,
+    pub(crate) confidence: u8,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct LabelAuditEntry {
+    pub(crate) issue_number: u64,
+    pub(crate) label: String,
+    pub(crate) confidence: u8,
+    pub(crate) applied_at: DateTime<Utc>,
+}
+
+/// Suggests labels for a new issue and applies the confident ones when the
+/// repository has opted in. Returns the lines to append to the summary.
+pub(crate) async fn triage_labels(route: &IssueRoute, issue: &Issue) -> String {
+    let suggestions = suggest_labels(&route.owner, &route.repo, issue).await;
+    if suggestions.is_empty() {
+        return "".to_string();
+    }
+
+    let mut lines = format!(
+        "\nSuggested labels: {}",
+        suggestions
+            .iter()
+            .map(|s| format!("{} ({}%)", s.name, s.confidence))
+            .collect::<Vec<String>>()
+            .join(", ")
+    );
+
+    if !feature_enabled(Feature::AutoLabel) || !auto_label_enabled(&route.owner, &route.repo) {
+        return lines;
+    }
+
+    let confident = suggestions
+        .iter()
+        .filter(|s| s.confidence >= AUTO_LABEL_CONFIDENCE)
+        .collect::<Vec<&LabelSuggestion>>();
+    if confident.is_empty() {
+        return lines;
+    }
+
+    let names = confident
+        .iter()
+        .map(|s| s.name.clone())
+        .collect::<Vec<String>>();
+    let octocrab = get_octo(&Default);
+    match octocrab
+        .issues(&route.owner, &route.repo)
+        .add_labels(issue.number, &names)
+        .await
+    {
+        Ok(_) => {
+            record_label_audit(&route.owner, &route.repo, issue.number, &confident);
+            audit(
+                "flow",
+                &format!("labeled #{} with {}", issue.number, names.join(", ")),
+                Some((&route.owner, &route.repo)),
+                "applied",
+                false,
+            );
+            lines.push_str(&format!("\nApplied labels: {}", names.join(", ")));
+        }
+        Err(e) => {
+            Event::warn("labels")
+                .issue(issue.number)
+                .err(&e)
+                .log("the labels could not be applied");
+            audit(
+                "flow",
+                &format!("labeled #{} with {}", issue.number, names.join(", ")),
+                Some((&route.owner, &route.repo)),
+                "refused by GitHub",
+                false,
+            );
+            lines.push_str("\nCould not apply the suggested labels, please make sure the connected GitHub account can label issues in this repository.");
+        }
+    }
+    lines
+}
+
+pub(crate) fn label_audit_key(owner: &str, repo: &str) -> String {
+    format!("label_audit:{owner}/{repo}")
+}
+
+pub(crate) fn record_label_audit(
+    owner: &str,
+    repo: &str,
+    issue_number: u64,
+    applied: &[&LabelSuggestion],
+) {
+    let key = label_audit_key(owner, repo);
+    let mut audit = store::load::<Vec<LabelAuditEntry>>(&key).unwrap_or_default();
+    let now = Utc::now();
+    audit.extend(applied.iter().map(|s| LabelAuditEntry {
+        issue_number,
+        label: s.name.clone(),
+        confidence: s.confidence,
+        applied_at: now,
+    }));
+    if audit.len() > LABEL_AUDIT_KEPT {
+        audit.drain(0..audit.len() - LABEL_AUDIT_KEPT);
+    }
+
+    store::save(&key, &audit);
+}
+
+/// Closed issues get a resolution summary when they had at least
+/// `postmortem_min_comments` comments (default: 20) or carry one of the
+/// `priority_labels`.
+pub(crate) fn needs_postmortem(issue: &Issue) -> bool {
+    let min_comments = config::var("postmortem_min_comments")
+        .and_then(|n| n.parse::<u32>().ok())
+        .unwrap_or(20);
+    let priority_labels = priority_labels();
+
+    issue.comments >= min_comments
+        || issue
+            .labels
+            .iter()
+            .any(|lab| priority_labels.contains(&lab.name.to_lowercase()))
+}
+
+/// Collects the pull requests and commits the issue's timeline links to.
+pub(crate) async fn resolution_references(
+    owner: &str,
+    repo: &str,
+    issue_number: u64,
+) -> Vec<String> {
+    let octocrab = get_octo(&Default);
+    let route = format!("repos/{owner}/{repo}/issues/{issue_number}/timeline");
+    let events = match octocrab
+        .get::<Vec<serde_json::Value>, _, _>(&route, Some(&[("per_page", "100")]))
+        .await
+    {
+        Ok(events) => events,
+        Err(e) => {
+            Event::warn("timeline")
+                .issue(issue_number)
+                .err(&e)
+                .log("the timeline could not be read");
+            return Vec::new();
+        }
+    };
+
+    let mut references = Vec::new();
+    for event in events {
+        let reference = match event["event"].as_str() {
+            Some("cross-referenced") if event["source"]["issue"]["pull_request"].is_object() => {
+                let pr = &event["source"]["issue"];
+                format!(
+                    "PR #{} {} ({})",
+                    pr["number"],
+                    pr["title"].as_str().unwrap_or(""),
+                    pr["html_url"].as_str().unwrap_or("")
+                )
+            }
+            Some("closed") | Some("referenced") if event["commit_id"].is_string() => {
+                let sha = event["commit_id"].as_str().unwrap_or("");
+                format!(
+                    "commit {} ({})",
+                    &sha[..sha.len().min(7)],
+                    event["commit_url"].as_str().unwrap_or("")
+                )
+            }
+            _ => continue,
+        };
+        if !references.contains(&reference) {
+            references.push(reference);
+        }
+    }
+    references.truncate(10);
+    references
+}
+
+/// Merged pull requests since the previous release, most discussed first.
+/// When the release before the given one was published.
+pub(crate) async fn previous_release_date(
+    owner: &str,
+    repo: &str,
+    release_id: u64,
+) -> Option<DateTime<Utc>> {
+    let octocrab = get_octo(&Default);
+    let releases = github_request(None, || async {
+        octocrab
+            .get::<Vec<serde_json::Value>, _, _>(
+                format!("repos/{owner}/{repo}/releases"),
+                Some(&[("per_page", "10")]),
+            )
+            .await
+    })
+    .await
+    .unwrap_or_default();
+    releases
+        .iter()
+        .filter(|r| r["id"].as_u64() != Some(release_id) && !r["draft"].as_bool().unwrap_or(false))
+        .filter_map(|r| r["published_at"].as_str())
+        .filter_map(|d| d.parse::<DateTime<Utc>>().ok())
+        .max()
+}
+
+pub(crate) async fn notable_pull_requests(owner: &str, repo: &str, release_id: u64) -> Vec<String> {
+    let octocrab = get_octo(&Default);
+    let previous = previous_release_date(owner, repo, release_id)
+        .await
+        .unwrap_or(Utc::now() - Duration::days(30));
+
+    let query = format!(
+        "repo:{owner}/{repo} is:pr is:merged merged:>{}",
+        previous.format("%Y-%m-%dT%H:%M:%SZ")
+    );
+    match octocrab
+        .search()
+        .issues_and_pull_requests(&query)
+        .sort("comments")
+        .order("desc")
+        .per_page(10u8)
+        .send()
+        .await
+    {
+        Ok(page) => page
+            .items
+            .into_iter()
+            .map(|pr| format!("#{} {} by {}", pr.number, pr.title, pr.user.login))
+            .collect(),
+        Err(e) => {
+            Event::warn("pull_requests")
+                .err(&e)
+                .log("the merged pull requests could not be read");
+            Vec::new()
+        }
+    }
+}
+
+/// Whether a maintainer of the repository has commented on the issue.
+pub(crate) const COMMENTS_PER_PAGE: usize = 100;
+
+/// A comment of an issue discussion.
+pub(crate) struct IssueComment {
+    pub(crate) author: String,
+    pub(crate) body: String,
+    pub(crate) maintainer: bool,
+    pub(crate) reactions: u64,
+}
+
+pub(crate) fn is_maintainer(author_association: Option<&str>) -> bool {
+    matches!(
+        author_association,
+        Some("OWNER") | Some("MEMBER") | Some("COLLABORATOR")
+    )
+}
+
+/// Fetches one page of the issue's comments, oldest first. Pages start at 1.
+pub(crate) async fn comments_page(
+    owner: &str,
+    repo: &str,
+    issue_number: u64,
+    page: usize,
+) -> Result<Vec<IssueComment>, github_flows::octocrab::Error> {
+    let octocrab = get_octo(&Default);
+    // octocrab's comment model has no author_association
+    let route = format!("repos/{owner}/{repo}/issues/{issue_number}/comments");
+    let per_page = COMMENTS_PER_PAGE.to_string();
+    let page = page.to_string();
+    let comments = github_request(None, || async {
+        octocrab
+            .get::<Vec<serde_json::Value>, _, _>(
+                &route,
+                Some(&[("per_page", per_page.as_str()), ("page", page.as_str())]),
+            )
+            .await
+    })
+    .await?;
+
+    Ok(comments
+        .iter()
+        .map(|c| IssueComment {
+            author: c["user"]["login"].as_str().unwrap_or_default().to_string(),
+            body: c["body"].as_str().unwrap_or_default().to_string(),
+            maintainer: is_maintainer(c["author_association"].as_str()),
+            reactions: c["reactions"]["total_count"].as_u64().unwrap_or(0),
+        })
+        .collect())
+}
+
+pub(crate) async fn has_maintainer_reply(owner: &str, repo: &str, issue_number: u64) -> bool {
+    let octocrab = get_octo(&Default);
+    // octocrab's comment model has no author_association
+    let route = format!("repos/{owner}/{repo}/issues/{issue_number}/comments");
+    match github_request(None, || async {
+        octocrab
+            .get::<Vec<serde_json::Value>, _, _>(&route, Some(&[("per_page", "100")]))
+            .await
+    })
+    .await
+    {
+        Ok(comments) => comments
+            .iter()
+            .any(|c| is_maintainer(c["author_association"].as_str())),
+        // an unreadable thread is checked again on the next tick
+        Err(_e) => true,
+    }
+}
+
+/// When the pull request started waiting for its requested reviewers: its
+/// latest review, or its creation if it was never reviewed.
+pub(crate) async fn awaiting_review_since(
+    owner: &str,
+    repo: &str,
+    pull: &PullRequest,
+) -> Option<DateTime<Utc>> {
+    let octocrab = get_octo(&Default);
+    let reviews = github_request(None, || async {
+        octocrab.pulls(owner, repo).list_reviews(pull.number).await
+    })
+    .await
+    .ok()?;
+
+    reviews
+        .items
+        .iter()
+        .filter_map(|review| review.submitted_at)
+        .max()
+        .or(pull.created_at)
+}
+
+pub(crate) fn watchlist_key(workspace: &str, channel: &str) -> String {
+    format!("watchlist:{workspace}/{channel}")
+}
+
+/// The channels of the workspace with a watchlist, so GitHub events can be
+/// checked against all of them.
+pub(crate) fn watchlist_channels_key(workspace: &str) -> String {
+    format!("watchlist_channels:{workspace}")
+}
+
+pub(crate) fn load_watchlist(workspace: &str, channel: &str) -> Vec<String> {
+    store::load(&watchlist_key(workspace, channel)).unwrap_or_default()
+}
+
+pub(crate) fn save_watchlist(workspace: &str, channel: &str, keywords: &[String]) {
+    store::save(&watchlist_key(workspace, channel), keywords);
+
+    let mut channels = load_watchlist_channels(workspace);
+    channels.retain(|c| c != channel);
+    if !keywords.is_empty() {
+        channels.push(channel.to_string());
+    }
+    store::save(&watchlist_channels_key(workspace), &channels);
+}
+
+pub(crate) fn load_watchlist_channels(workspace: &str) -> Vec<String> {
+    store::load(&watchlist_channels_key(workspace)).unwrap_or_default()
+}
+
+/// Whether the keyword appears in the text as a whole word or phrase,
+/// ignoring case.
+pub(crate) fn keyword_matches(keyword: &str, text: &str) -> bool {
+    let pattern = format!(r"(?i)(^|\W){}($|\W)", regex::escape(keyword));
+    Regex::new(&pattern).is_ok_and(|re| re.is_match(text))
+}
+
+/// Alerts every channel whose watchlist matches the text of a new issue or
+/// comment. The issue is only summarized once, however many channels match.
+pub(crate) async fn check_watchlists(
+    workspace: &str,
+    route: &IssueRoute,
+    issue: &Issue,
+    text: &str,
+    source: &str,
+) -> Result<()> {
+    let mut alerts = Vec::new();
+    for channel in load_watchlist_channels(workspace) {
+        let matched = load_watchlist(workspace, &channel)
+            .into_iter()
+            .filter(|keyword| keyword_matches(keyword, text))
+            .map(|keyword| format!("\"{keyword}\""))
+            .collect::<Vec<String>>();
+        if !matched.is_empty() {
+            alerts.push((channel, matched.join(", ")));
+        }
+    }
+    if alerts.is_empty() {
+        return Ok(());
+    }
+
+    let summary = routed_issue_summary(route, issue.clone()).await?;
+    for (channel, matched) in alerts {
+        send_message(
+            workspace,
+            &channel,
+            format!(
+                ":mag: Watchlist match for {matched} in a {source} #{} in {}/{}: {}\n{summary}",
+                issue.number, route.owner, route.repo, issue.title
+            ),
+        );
+    }
+    Ok(())
+}
+
+/// The hints that an issue may already be resolved, from its last comment and
+/// its linked pull requests, along with that last comment.
+pub(crate) async fn resolution_signals(
+    owner: &str,
+    repo: &str,
+    issue: &Issue,
+) -> (Vec<String>, String) {
+    let octocrab = get_octo(&Default);
+    let mut signals = Vec::new();
+
+    // octocrab's comment model has no author_association
+    let route = format!("repos/{owner}/{repo}/issues/{}/comments", issue.number);
+    let last_page = issue.comments.div_ceil(100).max(1).to_string();
+    let comments = github_request(None, || async {
+        octocrab
+            .get::<Vec<serde_json::Value>, _, _>(
+                &route,
+                Some(&[("per_page", "100"), ("page", last_page.as_str())]),
+            )
+            .await
+    })
+    .await
+    .unwrap_or_default();
+    let last_comment = comments.last().cloned().unwrap_or_default();
+    let last_body = last_comment["body"].as_str().unwrap_or("").to_string();
+
+    let fixed =
+        Regex::new(r"(?i)\b(fixed|resolved|addressed|solved)\s+(in|by|with|via)\b").unwrap();
+    if fixed.is_match(&last_body) {
+        signals.push("the last comment says it was fixed".to_string());
+    }
+
+    let from_maintainer = matches!(
+        last_comment["author_association"].as_str(),
+        Some("OWNER") | Some("MEMBER") | Some("COLLABORATOR")
+    );
+    let commented_at = last_comment["created_at"]
+        .as_str()
+        .and_then(|d| DateTime::parse_from_rfc3339(d).ok());
+    if let Some(commented_at) = commented_at {
+        let silent_days = (Utc::now() - commented_at.with_timezone(&Utc)).num_days();
+        if from_maintainer
+            && last_comment["user"]["login"].as_str() != Some(issue.user.login.as_str())
+            && silent_days >= close_candidate_days()
+        {
+            signals.push(format!(
+                "the author has not replied to a maintainer for {silent_days} days"
+            ));
+        }
+    }
+
+    let route = format!("repos/{owner}/{repo}/issues/{}/timeline", issue.number);
+    let events = octocrab
+        .get::<Vec<serde_json::Value>, _, _>(&route, Some(&[("per_page", "100")]))
+        .await
+        .unwrap_or_default();
+    for event in events {
+        let pr = &event["source"]["issue"];
+        if event["event"] == "cross-referenced" && pr["pull_request"]["merged_at"].is_string() {
+            signals.push(format!("linked PR #{} was merged", pr["number"]));
+        }
+    }
+
+    let last_comment = match last_body.is_empty() {
+        true => String::new(),
+        false => format!(
+            "{} commented last: {}",
+            last_comment["user"]["login"].as_str().unwrap_or(""),
+            last_body
+        ),
+    };
+    (signals, last_comment)
+}
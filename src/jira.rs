@@ -0,0 +1,80 @@
+//! Jira tickets escalated from GitHub issues, through Jira's REST API.
+
+use crate::config;
+use crate::http::request_json;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use http_req::request::Method;
+use serde_json::json;
+
+/// The Jira site, read from the `jira_url` environment variable, with the
+/// value of the `Authorization` header for `jira_user` and `jira_token`.
+fn jira_site() -> Result<(String, String), String> {
+    let (Some(url), Some(user), Some(token)) = (
+        config::var("jira_url"),
+        config::var("jira_user"),
+        config::var("jira_token"),
+    ) else {
+        return Err("jira_url, jira_user and jira_token have to be set".to_string());
+    };
+    let authorization = format!(
+        "Basic {}",
+        STANDARD.encode(format!("{}:{}", user.trim(), token.trim()))
+    );
+    Ok((url.trim().trim_end_matches('/').to_string(), authorization))
+}
+
+/// Creates a ticket of the `jira_issue_type` (default: Task) in the
+/// project, with the summary of the GitHub issue as its description and a
+/// link back to the issue. Labels become Jira labels, which can't contain
+/// spaces. Returns the key of the ticket and its URL.
+pub(crate) fn create_jira_ticket(
+    project: &str,
+    title: &str,
+    summary: &str,
+    labels: &[String],
+    issue_ref: &str,
+    issue_url: &str,
+) -> Result<(String, String), String> {
+    let (site, authorization) = jira_site()?;
+    let headers = [
+        ("Authorization", authorization.as_str()),
+        ("Accept", "application/json"),
+    ];
+    let issue_type = config::var("jira_issue_type").unwrap_or("Task".to_string());
+    let labels = labels
+        .iter()
+        .map(|label| label.split_whitespace().collect::<Vec<_>>().join("-"))
+        .filter(|label| !label.is_empty())
+        .collect::<Vec<_>>();
+    let ticket = json!({
+        "fields": {
+            "project": { "key": project },
+            "issuetype": { "name": issue_type.trim() },
+            "summary": format!("{issue_ref}: {title}"),
+            "description": format!("{summary}\n\nEscalated from GitHub: {issue_url}"),
+            "labels": labels,
+        }
+    });
+    let created = request_json(
+        Method::POST,
+        &format!("{site}/rest/api/2/issue"),
+        &headers,
+        Some(&ticket),
+    )
+    .map_err(|e| format!("Jira refused the ticket ({e})"))?;
+    let key = created["key"]
+        .as_str()
+        .ok_or("Jira did not answer with the key of the ticket".to_string())?
+        .to_string();
+
+    // the link shows with the ticket's links, the description has it anyway
+    let link = json!({ "object": { "url": issue_url, "title": issue_ref } });
+    let _ = request_json(
+        Method::POST,
+        &format!("{site}/rest/api/2/issue/{key}/remotelink"),
+        &headers,
+        Some(&link),
+    );
+    let url = format!("{site}/browse/{key}");
+    Ok((key, url))
+}
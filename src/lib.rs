@@ -1,207 +1,269 @@
-use chrono::{Duration, Utc};
-use dotenv::dotenv;
-use github_flows::{get_octo, octocrab::models::issues::Issue, GithubLogin::Default};
-use openai_flows::{
-    chat::{ChatModel, ChatOptions},
-    OpenAIFlows,
+mod app_home;
+mod audit;
+mod backup;
+mod command;
+mod config;
+mod confluence;
+mod deps;
+mod discussion;
+#[cfg(test)]
+mod fakes;
+mod feed;
+mod github;
+mod http;
+mod jira;
+mod linear;
+mod logging;
+mod matrix;
+mod mattermost;
+mod metrics;
+mod modal;
+mod notion;
+mod ops;
+mod paging;
+mod render;
+mod run_archive;
+mod schedule;
+mod shortcut;
+mod sink;
+mod site;
+mod slash;
+mod store;
+mod summarizer;
+mod teams;
+mod telegram;
+mod trigger;
+mod vault;
+
+use crate::app_home::{app_home_enabled, handle_slack_request, is_slack_request};
+use crate::command::{discord_command_text, handler};
+use crate::config::{
+    chat_platform, discord_bot, discord_channels, feature_enabled, home_channel, issue_routes,
+    load_routes, matrix_rooms, served_channels, teams_channels, telegram_chats, ChatPlatform,
+    Feature, IssueRoute, DISCORD_WORKSPACE,
 };
-use regex::Regex;
-use slack_flows::{listen_to_channel, send_message_to_channel, SlackMessage};
-use std::env;
-use tiktoken_rs::cl100k_base;
+use crate::github::github_event_handler;
+use crate::logging::{start_run, Event};
+use crate::matrix::{handle_matrix_request, is_matrix_request};
+use crate::mattermost::{handle_mattermost_request, mattermost_request};
+use crate::metrics::record_run_metrics;
+use crate::run_archive::archive_run;
+use crate::schedule::{load_subscriptions, schedule_tick};
+use crate::sink::send_message;
+use crate::teams::handle_teams_request;
+use crate::telegram::{handle_telegram_request, is_telegram_request};
+use crate::trigger::{handle_trigger_request, is_trigger_request, trigger_enabled};
+use discord_flows::model::Message;
+use dotenv::dotenv;
+use github_flows::{listen_to_event, EventPayload, GithubLogin::Default};
+use schedule_flows::schedule_cron_job;
+use slack_flows::{listen_to_channel, SlackMessage};
+use std::time::Instant;
+use webhook_flows::request_received;
+
+/// Errors that end the handling of a Slack message, GitHub event or schedule
+/// tick. They are reported to the channel instead of killing the flow.
+#[derive(Debug, thiserror::Error)]
+enum Error {
+    #[error("{0}")]
+    Usage(String),
+    #[error("{0} days is too far back to search for issues")]
+    DaysOutOfRange(i64),
+    #[error("the discussion could not be tokenized: {0}")]
+    Tokenizer(String),
+    #[error("the model could not summarize the issue: {0}")]
+    Model(String),
+    #[error("the issue was opened by {0}, who opted out of AI processing")]
+    OptedOut(String),
+    #[error("{stage} did not finish within {secs}s")]
+    Timeout { stage: &'static str, secs: u64 },
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+const SCHEDULE_TICK: &str = "summarizer_schedule_tick";
+
+/// Archives the raw inputs and outputs of the run that ends and adds it to
+/// the metrics.
+fn finish_run() {
+    archive_run();
+    record_run_metrics();
+}
 
 #[no_mangle]
 pub fn run() {
     dotenv().ok();
 
-    let slack_workspace = env::var("slack_workspace").unwrap_or("secondstate".to_string());
-    let slack_channel = env::var("slack_channel").unwrap_or("test-flow".to_string());
+    let (workspace, channel) = home_channel();
+
+    if chat_platform() == ChatPlatform::Slack {
+        listen_to_channel(&workspace, &channel, |sm| {
+            start_run("slack");
+            let started = Instant::now();
+            match handler(&workspace, &channel, sm) {
+                Ok(()) => Event::info("command").took(started).log("handled"),
+                Err(e) => {
+                    Event::error("command").took(started).err(&e).log("failed");
+                    send_message(&workspace, &channel, format!(":warning: {e}"));
+                }
+            }
+            finish_run();
+        });
+    }
 
-    listen_to_channel(&slack_workspace, &slack_channel, |sm| {
-        handler(&slack_workspace, &slack_channel, sm);
-    });
-}
+    if !discord_channels().is_empty() {
+        listen_to_discord();
+    }
 
-#[no_mangle]
-#[tokio::main(flavor = "current_thread")]
-async fn handler(worksapce: &str, channel: &str, sm: SlackMessage) {
-    let trigger_word = env::var("trigger_word").unwrap_or("flows summarize".to_string());
-    let octocrab = get_octo(&Default);
-    let re = Regex::new(r"^(\s*\w+(?: \w+)?)(.*)( \d+)").unwrap();
-    let cap = re.captures(&sm.text).unwrap();
-
-    let triggered = match cap.get(1) {
-        Some(trigger) => trigger.as_str().trim().contains(&trigger_word),
-        None => false,
-    };
+    if !teams_channels().is_empty()
+        || !telegram_chats().is_empty()
+        || !matrix_rooms().is_empty()
+        || chat_platform() == ChatPlatform::Mattermost
+        || app_home_enabled()
+        || trigger_enabled()
+    {
+        listen_to_webhook();
+    }
 
-    if !triggered {
-        return;
+    if feature_enabled(Feature::GithubEvents) {
+        listen_to_github(&workspace, &channel);
     }
 
-    let _n_days = match cap.get(3) {
-        Some(n) => n.as_str().trim().parse::<i64>().unwrap_or(7),
-        None => 7,
-    };
-    let n_days_ago_str = Utc::now()
-        .checked_sub_signed(Duration::days(_n_days))
-        .unwrap()
-        .format("%Y-%m-%d");
-
-    if let Some(owner_repo_str) = cap.get(2) {
-        let owner_repo = owner_repo_str
-            .as_str()
-            .trim()
-            .split("/")
-            .map(|s| s.to_string())
-            .collect::<Vec<String>>();
-
-        let owner = owner_repo.get(0).unwrap_or(&"flows-network".to_string()).to_string();
-        let repo = owner_repo.get(1).unwrap_or(&"haiku-platform".to_string()).to_string();
-
-        let query = format!("repo:{owner}/{repo} is:issue state:open updated:>{n_days_ago_str}");
-
-        match octocrab
-            .search()
-            .issues_and_pull_requests(&query)
-            .send()
-            .await
-        {
-            Ok(pages) => {
-                let mut count = 10;
-                for issue in pages {
-                    count -= 1;
-                    let summary = get_summary(&owner, &repo, issue).await;
-                    send_message_to_channel(&worksapce, &channel, summary.to_string());
-
-                    if count <= 0 {
-                        send_message_to_channel(
-                                &worksapce,
-                                &channel,
-                                "You've reached your limit of 10 issues. Please wait 10 minutes before running the command again.".to_string(),
-                            );
-                        break;
-                    }
-                }
-            }
-            Err(_error) => {
-                if triggered {
-                    let _text = sm.text.clone();
-                    send_message_to_channel(
-                        &worksapce,
+    // Schedules are managed from Slack at runtime, so a single hourly tick is
+    // registered and the due schedules are looked up in the store on each tick.
+    schedule_cron_job("0 * * * *".to_string(), SCHEDULE_TICK.to_string(), |body| {
+        if body == SCHEDULE_TICK.as_bytes() {
+            start_run("schedule");
+            for (workspace, channel) in served_channels() {
+                let started = Instant::now();
+                if let Err(e) = schedule_tick(&workspace, &channel) {
+                    Event::error("schedule").took(started).err(&e).log("failed");
+                    send_message(
+                        &workspace,
                         &channel,
-                        format!(
-                            r#"Please double check if there are errors in the owner and repo names provided in your message:
-{_text}
-if yes, please correct the spelling and resend your instruction."#
-                        ),
+                        format!(":warning: The scheduled run failed: {e}"),
                     );
-                    return;
                 }
             }
-        };
-    }
+            finish_run();
+        }
+    });
 }
 
-async fn get_summary(owner: &str, repo: &str, issue: Issue) -> String {
-    let mut openai = OpenAIFlows::new();
-    openai.set_retry_times(3);
-
-    let octocrab = get_octo(&Default);
-    let issues_handle = octocrab.issues(owner, repo);
-
-    let issue_creator_name = issue.user.login;
-    let mut issue_creator_role = "".to_string();
-    issue_creator_role = issue.author_association;
-    let issue_number = issue.number;
-    let issue_title = issue.title;
-    let issue_body = issue.body.unwrap_or("".to_string());
-    let issue_url = issue.html_url;
-    let labels = issue
-        .labels
-        .into_iter()
-        .map(|lab| lab.name)
-        .collect::<Vec<String>>()
-        .join(", ");
-
-    let bpe = cl100k_base().unwrap();
-
-    let mut feed_tokens_map = Vec::new();
-
-    let issue_creator_input = format!("User '{issue_creator_name}', who holds the role of '{issue_creator_role}', has submitted an issue titled '{issue_title}', labeled as '{labels}', with the following post: '{issue_body}'.");
-
-    let mut tokens = bpe.encode_ordinary(&issue_creator_input);
-    feed_tokens_map.append(&mut tokens);
+/// The Discord listener only sends synchronous requests, so it is driven
+/// without a runtime, since `handler` starts its own.
+fn listen_to_discord() {
+    futures::executor::block_on(discord_flows::listen_to_event(discord_bot(), |msg| {
+        handle_discord_message(msg);
+        async {}
+    }));
+}
 
-    match issues_handle.list_comments(issue_number).send().await {
-        Ok(pages) => {
-            for comment in pages.items {
-                let comment_body = comment.body.unwrap_or("".to_string());
-                let commenter = comment.user.login;
-                let commenter_input = format!("{commenter} commented: {comment_body}");
-                let mut tokens = bpe.encode_ordinary(&commenter_input);
-                feed_tokens_map.append(&mut tokens);
-            }
+/// Commands from Teams, Telegram, Matrix and Mattermost, the events of the
+/// Slack app's App Home and the triggers of external systems arrive through
+/// the flow's webhook, which is driven without a runtime like the Discord
+/// listener.
+fn listen_to_webhook() {
+    futures::executor::block_on(request_received(|headers, query, body| {
+        if is_slack_request(&headers) {
+            handle_slack_request(headers, body);
+        } else if is_trigger_request(&headers) {
+            handle_trigger_request(headers, body);
+        } else if is_matrix_request(&headers, &query) {
+            handle_matrix_request(body);
+        } else if is_telegram_request(&headers) {
+            handle_telegram_request(headers, body);
+        } else if let Some(request) = mattermost_request(&body) {
+            handle_mattermost_request(request);
+        } else {
+            handle_teams_request(headers, query, body);
         }
+        finish_run();
+        async {}
+    }));
+}
 
-        Err(_e) => {}
+/// Handles a message of a served Discord channel like a Slack message.
+fn handle_discord_message(msg: Message) {
+    let channel = msg.channel_id.to_string();
+    if msg.author.bot || !discord_channels().contains(&channel) {
+        return;
     }
-
-    let chat_id = format!("Issue#{issue_number}");
-    let system = &format!("As an AI co-owner of a GitHub repository, you are responsible for conducting a comprehensive analysis of GitHub issues. Your analytic focus encompasses distinct elements, including the issue's title, associated labels, body text, the identity of the issue's creator, their role, and the nature of the comments on the issue. Utilizing these data points, your task is to generate a succinct, context-aware summary of the issue.");
-
-    let co = ChatOptions {
-        model: ChatModel::GPT35Turbo,
-        restart: true,
-        system_prompt: Some(system),
+    start_run("discord");
+    let sm = SlackMessage {
+        event_type: "message".to_string(),
+        channel: channel.clone(),
+        user: msg.author.id.to_string(),
+        text: discord_command_text(&msg.content),
+        channel_type: "channel".to_string(),
     };
-
-    let total_tokens_count = feed_tokens_map.len();
-    let mut _summary = "".to_string();
-
-    if total_tokens_count > 2800 {
-        let mut token_vec = feed_tokens_map;
-        let mut map_out = "".to_string();
-
-        while !token_vec.is_empty() {
-            let drain_to = std::cmp::min(token_vec.len(), 2800);
-            let token_chunk = token_vec.drain(0..drain_to).collect::<Vec<_>>();
-
-            let text_chunk = bpe.decode(token_chunk).unwrap();
-
-            let map_question = format!("Given the issue titled '{issue_title}' and a particular segment of body or comment text '{text_chunk}', focus on extracting the central arguments, proposed solutions, and instances of agreement or conflict among the participants. Generate an interim summary capturing the essential information in this section. This will be used later to form a comprehensive summary of the entire discussion.");
-
-            match openai.chat_completion(&chat_id, &map_question, &co).await {
-                Ok(r) => {
-                    map_out.push_str(&r.choice);
-                }
-                Err(_e) => {}
-            }
+    let started = Instant::now();
+    match handler(DISCORD_WORKSPACE, &channel, sm) {
+        Ok(()) => Event::info("command").took(started).log("handled"),
+        Err(e) => {
+            Event::error("command").took(started).err(&e).log("failed");
+            send_message(DISCORD_WORKSPACE, &channel, format!(":warning: {e}"));
         }
+    }
+    finish_run();
+}
 
-        let reduce_question = format!("User '{issue_creator_name}', in the role of '{issue_creator_role}', has filed an issue titled '{issue_title}', labeled as '{labels}'. The key information you've extracted from the issue's body text and comments in segmented form are: {map_out}. Concentrate on the principal arguments, suggested solutions, and areas of consensus or disagreement among the participants. From these elements, generate a concise summary of the entire issue to inform the next course of action.");
-
-        match openai
-            .chat_completion(&chat_id, &reduce_question, &co)
-            .await
-        {
-            Ok(r) => {
-                _summary = r.choice;
-            }
-            Err(_e) => {}
+#[no_mangle]
+#[tokio::main(flavor = "current_thread")]
+async fn listen_to_github(workspace: &str, default_channel: &str) {
+    // Each repository is routed to one channel: a route added with
+    // `flows route` first, then `issue_routes`, then a subscription.
+    let mut routes: Vec<IssueRoute> = Vec::new();
+    let mut add = |route: IssueRoute| {
+        if !routes.iter().any(|r| r.is_for(&route.owner, &route.repo)) {
+            routes.push(route);
         }
-    } else {
-        let issue_body = bpe.decode(feed_tokens_map).unwrap();
-
-        let question = format!("{issue_body}, concentrate on the principal arguments, suggested solutions, and areas of consensus or disagreement among the participants. From these elements, generate a concise summary of the entire issue to inform the next course of action.");
-
-        match openai.chat_completion(&chat_id, &question, &co).await {
-            Ok(r) => {
-                _summary = r.choice;
-            }
-            Err(_e) => {}
+    };
+    let served = served_channels();
+    let mut workspaces = served.iter().map(|(ws, _)| ws.clone()).collect::<Vec<_>>();
+    workspaces.sort();
+    workspaces.dedup();
+    for served_workspace in &workspaces {
+        load_routes(served_workspace).into_iter().for_each(&mut add);
+    }
+    issue_routes(workspace, default_channel)
+        .into_iter()
+        .for_each(&mut add);
+    for (served_workspace, channel) in served {
+        for subscription in load_subscriptions(&served_workspace, &channel) {
+            add(IssueRoute {
+                workspace: served_workspace.clone(),
+                owner: subscription.owner,
+                repo: subscription.repo,
+                channel: channel.clone(),
+            });
         }
     }
 
-    format!("Issue Summary:\n{}\n{}", _summary, issue_url)
+    for route in routes {
+        listen_to_event(
+            &Default,
+            &route.owner,
+            &route.repo,
+            vec!["issues", "issue_comment", "release"],
+            |payload| handle_github_event(&route, payload),
+        )
+        .await;
+    }
+}
+
+async fn handle_github_event(route: &IssueRoute, payload: EventPayload) {
+    start_run("github");
+    if let Err(e) = github_event_handler(&route.workspace, route, payload).await {
+        Event::error("github_event").err(&e).log("failed");
+        send_message(
+            &route.workspace,
+            &route.channel,
+            format!(
+                ":warning: Handling a GitHub event of {}/{} failed: {e}",
+                route.owner, route.repo
+            ),
+        );
+    }
+    finish_run();
 }
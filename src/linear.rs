@@ -0,0 +1,266 @@
+//! Linear teams as a source of issues, through Linear's GraphQL API.
+
+use crate::config;
+use crate::deps::IssueSource;
+use crate::http::request_json;
+use chrono::{DateTime, NaiveDate, Utc};
+use github_flows::octocrab::{self, models::issues::Issue};
+use http_req::request::Method;
+use serde_json::{json, Value};
+use snafu::GenerateImplicitData;
+
+/// The owner Linear teams are given under where a repository is expected,
+/// as `linear/<TEAM>` or `linear:<TEAM>`.
+pub(crate) const LINEAR_OWNER: &str = "linear";
+
+const LINEAR_API: &str = "https://api.linear.app/graphql";
+
+/// The most issues one digest or count reads from Linear.
+const LINEAR_PAGE: usize = 250;
+
+#[derive(Debug, thiserror::Error)]
+#[error("Linear refused the request: {0}")]
+pub(crate) struct LinearError(String);
+
+fn linear_error(message: String) -> octocrab::Error {
+    octocrab::Error::Other {
+        source: Box::new(LinearError(message)),
+        backtrace: snafu::Backtrace::generate(),
+    }
+}
+
+/// The team key of `linear:<TEAM>`.
+pub(crate) fn linear_team(name: &str) -> Option<&str> {
+    name.trim()
+        .strip_prefix("linear:")
+        .filter(|team| !team.is_empty())
+}
+
+/// Whether the GitHub search query is for a Linear team.
+pub(crate) fn is_linear_query(query: &str) -> bool {
+    query
+        .split_whitespace()
+        .any(|term| term.starts_with(&format!("repo:{LINEAR_OWNER}/")))
+}
+
+/// Translates the GitHub search queries of the digests into an issue filter
+/// of Linear: the team, open issues, and the `created`, `updated` and
+/// `closed` dates.
+fn linear_filter(query: &str) -> Value {
+    let mut filter = serde_json::Map::new();
+    for term in query.split_whitespace() {
+        let (name, value) = term.split_once(':').unwrap_or((term, ""));
+        let after = value.trim_start_matches(['>', '=']);
+        match name {
+            "repo" => {
+                let team = value.split_once('/').map_or(value, |(_, team)| team);
+                filter.insert("team".into(), json!({ "key": { "eq": team } }));
+            }
+            "state" if value == "open" => {
+                filter.insert(
+                    "state".into(),
+                    json!({ "type": { "nin": ["completed", "canceled"] } }),
+                );
+            }
+            "created" => {
+                filter.insert("createdAt".into(), json!({ "gt": after }));
+            }
+            "updated" => {
+                filter.insert("updatedAt".into(), json!({ "gt": after }));
+            }
+            "closed" => {
+                filter.insert("completedAt".into(), json!({ "gt": after }));
+            }
+            _ => {}
+        }
+    }
+    Value::Object(filter)
+}
+
+fn graphql(query: &str, variables: Value) -> Result<Value, String> {
+    let key = config::var("linear_api_key").ok_or("linear_api_key is not set".to_string())?;
+    let answer = request_json(
+        Method::POST,
+        LINEAR_API,
+        &[("Authorization", key.trim())],
+        Some(&json!({ "query": query, "variables": variables })),
+    )?;
+    match answer["errors"][0]["message"].as_str() {
+        Some(message) => Err(message.to_string()),
+        None => Ok(answer["data"].clone()),
+    }
+}
+
+/// A Linear issue in the shape of a GitHub issue, so the digest handles it
+/// like any other. Its number is the number within the team.
+fn as_issue(node: &Value) -> Option<Issue> {
+    let url = node["url"].as_str()?;
+    let login = node["creator"]["name"].as_str().unwrap_or(LINEAR_OWNER);
+    let closed = matches!(
+        node["state"]["type"].as_str(),
+        Some("completed" | "canceled")
+    );
+    let labels = node["labels"]["nodes"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .enumerate()
+        .map(|(i, label)| {
+            json!({
+                "id": i + 1,
+                "node_id": "",
+                "url": url,
+                "name": label["name"].as_str().unwrap_or(""),
+                "color": label["color"].as_str().unwrap_or("").trim_start_matches('#'),
+                "default": false,
+            })
+        })
+        .collect::<Vec<Value>>();
+    let user = json!({
+        "login": login,
+        "id": 0,
+        "node_id": "",
+        "avatar_url": url,
+        "gravatar_id": "",
+        "url": url,
+        "html_url": url,
+        "followers_url": url,
+        "following_url": url,
+        "gists_url": url,
+        "starred_url": url,
+        "subscriptions_url": url,
+        "organizations_url": url,
+        "repos_url": url,
+        "events_url": url,
+        "received_events_url": url,
+        "type": "User",
+        "site_admin": false,
+    });
+    let issue = json!({
+        "id": node["number"].as_f64()? as u64,
+        "node_id": node["identifier"],
+        "url": url,
+        "repository_url": url,
+        "labels_url": url,
+        "comments_url": url,
+        "events_url": url,
+        "html_url": url,
+        "number": node["number"].as_f64()? as u64,
+        "state": if closed { "closed" } else { "open" },
+        "state_reason": null,
+        "title": node["title"],
+        "body": node["description"],
+        "user": user,
+        "labels": labels,
+        "assignees": [],
+        "author_association": "NONE",
+        "locked": false,
+        "comments": node["comments"]["nodes"].as_array().map_or(0, |c| c.len()),
+        "closed_at": node["completedAt"],
+        "created_at": node["createdAt"],
+        "updated_at": node["updatedAt"],
+    });
+    serde_json::from_value(issue).ok()
+}
+
+/// Counts up to `LINEAR_PAGE` issues of the filter, Linear has no total
+/// count.
+fn count_filtered(filter: Value) -> u64 {
+    graphql(
+        "query($filter: IssueFilter, $first: Int) { issues(filter: $filter, first: $first) { nodes { id } } }",
+        json!({ "filter": filter, "first": LINEAR_PAGE }),
+    )
+    .ok()
+    .and_then(|data| data["issues"]["nodes"].as_array().map(|n| n.len() as u64))
+    .unwrap_or(0)
+}
+
+/// The issues of Linear teams. The queries are the GitHub search queries of
+/// the digests, translated into Linear's filters.
+pub(crate) struct LinearIssues;
+
+impl IssueSource for LinearIssues {
+    async fn search(
+        &self,
+        _notify: Option<(&str, &str)>,
+        query: &str,
+    ) -> Result<Vec<Issue>, octocrab::Error> {
+        let data = graphql(
+            "query($filter: IssueFilter, $first: Int) { issues(filter: $filter, first: $first, orderBy: updatedAt) { nodes { identifier number title description url createdAt updatedAt completedAt state { type } creator { name } labels { nodes { name color } } comments { nodes { id } } } } }",
+            json!({ "filter": linear_filter(query), "first": LINEAR_PAGE }),
+        )
+        .map_err(linear_error)?;
+        Ok(data["issues"]["nodes"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(as_issue)
+            .collect())
+    }
+
+    async fn activity(&self, _owner: &str, team: &str, since: NaiveDate) -> (u64, u64) {
+        let team = json!({ "key": { "eq": team } });
+        let since = since.to_string();
+        (
+            count_filtered(json!({ "team": team, "createdAt": { "gt": since } })),
+            count_filtered(json!({ "team": team, "completedAt": { "gt": since } })),
+        )
+    }
+
+    async fn comments(&self, _owner: &str, team: &str, issue: &Issue) -> Vec<(String, String)> {
+        issue_comments(team, issue.number)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(_, commenter, body)| (commenter, body))
+            .collect()
+    }
+
+    async fn comments_since(
+        &self,
+        _owner: &str,
+        team: &str,
+        issue: &Issue,
+        since: DateTime<Utc>,
+    ) -> Option<Vec<(String, String)>> {
+        Some(
+            issue_comments(team, issue.number)?
+                .into_iter()
+                .filter(|(at, _, _)| at.is_some_and(|at| at > since))
+                .map(|(_, commenter, body)| (commenter, body))
+                .collect(),
+        )
+    }
+}
+
+/// A comment of a Linear issue as `(posted at, commenter, comment)`.
+type LinearComment = (Option<DateTime<Utc>>, String, String);
+
+/// The comments of the Linear issue, oldest first.
+fn issue_comments(team: &str, number: u64) -> Option<Vec<LinearComment>> {
+    let identifier = format!("{team}-{number}");
+    let data = graphql(
+        "query($id: String!) { issue(id: $id) { comments(first: 250) { nodes { body createdAt user { name } } } } }",
+        json!({ "id": identifier }),
+    )
+    .ok()?;
+    let mut comments = data["issue"]["comments"]["nodes"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|comment| {
+            (
+                comment["createdAt"]
+                    .as_str()
+                    .and_then(|at| DateTime::parse_from_rfc3339(at).ok())
+                    .map(|at| at.with_timezone(&Utc)),
+                comment["user"]["name"]
+                    .as_str()
+                    .unwrap_or("someone")
+                    .to_string(),
+                comment["body"].as_str().unwrap_or("").to_string(),
+            )
+        })
+        .collect::<Vec<_>>();
+    comments.sort();
+    Some(comments)
+}
@@ -0,0 +1,160 @@
+//! Structured log events, written through the flows logger.
+
+use crate::config::{config_file, log_level};
+use log::Level;
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, Once,
+    },
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+static LOGGER: Once = Once::new();
+
+/// The run that the events belong to, set when a Slack message, GitHub event
+/// or schedule tick starts being handled.
+static RUN_ID: Mutex<String> = Mutex::new(String::new());
+
+/// The entry point of the current run and when it started, until the run
+/// is ended.
+static RUN_STARTED: Mutex<Option<(String, Instant)>> = Mutex::new(None);
+
+/// Tokens sent to and received from the model since they were last taken,
+/// estimated with the tokenizer since openai-flows does not report usage.
+static RUN_TOKENS: AtomicUsize = AtomicUsize::new(0);
+
+/// Errors logged during the run.
+static RUN_ERRORS: AtomicUsize = AtomicUsize::new(0);
+
+pub(crate) fn add_tokens(tokens: usize) {
+    RUN_TOKENS.fetch_add(tokens, Ordering::Relaxed);
+}
+
+/// The tokens used since the run started or they were last taken.
+pub(crate) fn take_tokens() -> usize {
+    RUN_TOKENS.swap(0, Ordering::Relaxed)
+}
+
+/// Starts a new run of `entry` and returns its id. The logger is set up on
+/// the first run, at the level read from `log_level`.
+pub(crate) fn start_run(entry: &str) -> String {
+    LOGGER.call_once(|| {
+        std::env::set_var("RUST_LOG", log_level().as_str());
+        flowsnet_platform_sdk::logger::init();
+    });
+    if let Some(e) = &config_file().error {
+        Event::error("config")
+            .err(e)
+            .log("using the environment only");
+    }
+
+    RUN_TOKENS.store(0, Ordering::Relaxed);
+    RUN_ERRORS.store(0, Ordering::Relaxed);
+    if let Ok(mut started) = RUN_STARTED.lock() {
+        *started = Some((entry.to_string(), Instant::now()));
+    }
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    let id = format!("{entry}-{millis:x}");
+    if let Ok(mut run_id) = RUN_ID.lock() {
+        *run_id = id.clone();
+    }
+    id
+}
+
+/// How a run went, for its metrics.
+pub(crate) struct RunEnd {
+    pub(crate) entry: String,
+    pub(crate) secs: f64,
+    pub(crate) errors: usize,
+}
+
+/// Ends the current run. `None` when no run was started since the last one
+/// ended.
+pub(crate) fn end_run() -> Option<RunEnd> {
+    let (entry, started) = RUN_STARTED.lock().ok()?.take()?;
+    Some(RunEnd {
+        entry,
+        secs: started.elapsed().as_secs_f64(),
+        errors: RUN_ERRORS.swap(0, Ordering::Relaxed),
+    })
+}
+
+/// The id of the current run.
+pub(crate) fn run_id() -> String {
+    RUN_ID.lock().map(|id| id.clone()).unwrap_or_default()
+}
+
+/// A log event with the context it happened in.
+pub(crate) struct Event {
+    level: Level,
+    stage: &'static str,
+    issue: Option<u64>,
+    duration_ms: Option<u128>,
+    error: Option<String>,
+}
+
+impl Event {
+    pub(crate) fn new(level: Level, stage: &'static str) -> Event {
+        Event {
+            level,
+            stage,
+            issue: None,
+            duration_ms: None,
+            error: None,
+        }
+    }
+
+    pub(crate) fn debug(stage: &'static str) -> Event {
+        Event::new(Level::Debug, stage)
+    }
+
+    pub(crate) fn info(stage: &'static str) -> Event {
+        Event::new(Level::Info, stage)
+    }
+
+    pub(crate) fn warn(stage: &'static str) -> Event {
+        Event::new(Level::Warn, stage)
+    }
+
+    pub(crate) fn error(stage: &'static str) -> Event {
+        Event::new(Level::Error, stage)
+    }
+
+    pub(crate) fn issue(mut self, issue_number: u64) -> Event {
+        self.issue = Some(issue_number);
+        self
+    }
+
+    /// Records the time passed since `started`.
+    pub(crate) fn took(mut self, started: Instant) -> Event {
+        self.duration_ms = Some(started.elapsed().as_millis());
+        self
+    }
+
+    pub(crate) fn err(mut self, error: impl std::fmt::Display) -> Event {
+        self.error = Some(error.to_string());
+        self
+    }
+
+    pub(crate) fn log(self, message: &str) {
+        if self.level == Level::Error {
+            RUN_ERRORS.fetch_add(1, Ordering::Relaxed);
+        }
+        if self.level > log::max_level() {
+            return;
+        }
+        let event = serde_json::json!({
+            "run": run_id(),
+            "stage": self.stage,
+            "issue": self.issue,
+            "duration_ms": self.duration_ms,
+            "error": self.error,
+            "message": message,
+        });
+        log::log!(self.level, "{event}");
+    }
+}
@@ -0,0 +1,149 @@
+//! Commands from Matrix rooms, received as the transactions the homeserver
+//! pushes to the flow as an application service.
+
+use crate::command::handler;
+use crate::config::{self, matrix_homeserver, matrix_rooms, MATRIX_WORKSPACE};
+use crate::http::request_json;
+use crate::logging::{start_run, Event};
+use crate::sink::send_message;
+use http_req::request::Method;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use slack_flows::SlackMessage;
+use std::collections::HashMap;
+use std::time::Instant;
+use webhook_flows::send_response;
+
+#[derive(Debug, Deserialize)]
+struct Transaction {
+    #[serde(default)]
+    events: Vec<RoomEvent>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RoomEvent {
+    #[serde(rename = "type")]
+    kind: String,
+    room_id: Option<String>,
+    sender: String,
+    state_key: Option<String>,
+    #[serde(default)]
+    content: Value,
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+/// Whether the request is a transaction of the homeserver: it carries the
+/// `matrix_hs_token` of the registration, as a bearer token or, with older
+/// homeservers, as the `access_token` query parameter.
+pub(crate) fn is_matrix_request(
+    headers: &[(String, String)],
+    query: &HashMap<String, Value>,
+) -> bool {
+    let Some(token) = config::var("matrix_hs_token").filter(|t| !t.trim().is_empty()) else {
+        return false;
+    };
+    let token = token.trim();
+    header(headers, "authorization") == Some(format!("Bearer {token}").as_str())
+        || query.get("access_token").and_then(Value::as_str) == Some(token)
+}
+
+/// The command of a message, without the quote of the message it replies
+/// to that clients put before a reply.
+fn command_text(content: &Value) -> String {
+    let body = content["body"].as_str().unwrap_or("");
+    match content["m.relates_to"]["m.in_reply_to"].is_object() {
+        true => body
+            .lines()
+            .skip_while(|line| line.starts_with("> "))
+            .collect::<Vec<_>>()
+            .join("\n")
+            .trim()
+            .to_string(),
+        false => body.to_string(),
+    }
+}
+
+/// Joins the room the bot was invited to.
+fn join(room: &str) {
+    let (Some(homeserver), Some(token)) = (matrix_homeserver(), config::var("matrix_access_token"))
+    else {
+        return;
+    };
+    let url = format!(
+        "{homeserver}/_matrix/client/v3/rooms/{}/join",
+        form_urlencoded::byte_serialize(room.as_bytes()).collect::<String>()
+    );
+    let authorization = format!("Bearer {}", token.trim());
+    if let Err(e) = request_json(
+        Method::POST,
+        &url,
+        &[("Authorization", authorization.as_str())],
+        Some(&json!({})),
+    ) {
+        Event::warn("matrix")
+            .err(e)
+            .log(&format!("the invite to {room} could not be accepted"));
+    }
+}
+
+/// Handles a transaction of the homeserver: the messages of the rooms of
+/// `matrix_rooms` are handled like Slack messages, and invites of the bot
+/// of `matrix_user_id` to them are accepted. Other rooms are ignored.
+pub(crate) fn handle_matrix_request(body: Vec<u8>) {
+    start_run("matrix");
+    // the homeserver resends a transaction until it is acknowledged
+    send_response(
+        200,
+        vec![("Content-Type".to_string(), "application/json".to_string())],
+        b"{}".to_vec(),
+    );
+    let transaction = match serde_json::from_slice::<Transaction>(&body) {
+        Ok(transaction) => transaction,
+        Err(e) => {
+            Event::warn("matrix").err(e).log("not a Matrix transaction");
+            return;
+        }
+    };
+    let bot = config::var("matrix_user_id").unwrap_or_default();
+    let rooms = matrix_rooms();
+    for event in transaction.events {
+        let Some(room) = event.room_id.filter(|room| rooms.contains(room)) else {
+            continue;
+        };
+        if event.sender == bot.trim() {
+            continue;
+        }
+        match event.kind.as_str() {
+            "m.room.member"
+                if event.state_key.as_deref() == Some(bot.trim())
+                    && event.content["membership"] == "invite" =>
+            {
+                join(&room);
+            }
+            "m.room.message" if event.content["msgtype"] == "m.text" => {
+                let sm = SlackMessage {
+                    event_type: "message".to_string(),
+                    channel: room.clone(),
+                    user: event.sender.clone(),
+                    text: command_text(&event.content),
+                    channel_type: "channel".to_string(),
+                };
+                let started = Instant::now();
+                match handler(MATRIX_WORKSPACE, &room, sm) {
+                    Ok(()) => Event::info("command").took(started).log("handled"),
+                    Err(e) => {
+                        Event::error("command").took(started).err(&e).log("failed");
+                        send_message(MATRIX_WORKSPACE, &room, format!(":warning: {e}"));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
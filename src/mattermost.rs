@@ -0,0 +1,63 @@
+//! Commands from Mattermost channels, received through an outgoing webhook.
+
+use crate::command::handler;
+use crate::config::{self, MATTERMOST_WORKSPACE};
+use crate::logging::{start_run, Event};
+use crate::sink::send_message;
+use serde::Deserialize;
+use slack_flows::SlackMessage;
+use std::time::Instant;
+use webhook_flows::send_response;
+
+/// The JSON an outgoing webhook posts, which is shaped like Slack's.
+#[derive(Debug, Deserialize)]
+pub(crate) struct MattermostRequest {
+    token: String,
+    channel_name: String,
+    user_id: String,
+    #[serde(default)]
+    text: String,
+}
+
+/// Reads the request of an outgoing webhook, which has to be set up to send
+/// `application/json`. Other webhook requests, like those of Teams, don't
+/// parse as one.
+pub(crate) fn mattermost_request(body: &[u8]) -> Option<MattermostRequest> {
+    serde_json::from_slice(body).ok()
+}
+
+/// Handles the request of the outgoing webhook like a Slack message. The
+/// token of the request has to be the one Mattermost gave the webhook, read
+/// from the `mattermost_webhook_token` environment variable. The channel
+/// has to be one the flow serves.
+pub(crate) fn handle_mattermost_request(request: MattermostRequest) {
+    start_run("mattermost");
+    let token = config::var("mattermost_webhook_token").unwrap_or_default();
+    if token.trim().is_empty() || request.token != token.trim() {
+        Event::warn("mattermost").log("refused a request without the webhook's token");
+        send_response(401, vec![], vec![]);
+        return;
+    }
+    send_response(200, vec![], vec![]);
+
+    let channel = request.channel_name;
+    let served = (MATTERMOST_WORKSPACE.to_string(), channel.clone());
+    if !config::served_channels().contains(&served) {
+        return;
+    }
+    let sm = SlackMessage {
+        event_type: "message".to_string(),
+        channel: channel.clone(),
+        user: request.user_id,
+        text: request.text,
+        channel_type: "channel".to_string(),
+    };
+    let started = Instant::now();
+    match handler(MATTERMOST_WORKSPACE, &channel, sm) {
+        Ok(()) => Event::info("command").took(started).log("handled"),
+        Err(e) => {
+            Event::error("command").took(started).err(&e).log("failed");
+            send_message(MATTERMOST_WORKSPACE, &channel, format!(":warning: {e}"));
+        }
+    }
+}
@@ -0,0 +1,489 @@
+//! Metrics of the runs: what each run did and how long its stages took,
+//! added up in daily aggregates for `flows stats` and in totals pushed in
+//! the Prometheus text format to a Pushgateway or any webhook that takes
+//! them.
+
+use crate::config;
+use crate::http::request_text;
+use crate::logging::{end_run, run_id, Event, RunEnd};
+use crate::store;
+use crate::summarizer::{CacheStats, CACHE_STATS_KEY};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use http_req::request::Method;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+pub(crate) const METRICS_KEY: &str = "metrics:totals";
+
+/// The latest runs, newest last.
+pub(crate) const RECENT_RUNS_KEY: &str = "metrics:recent_runs";
+
+/// Runs kept in `RECENT_RUNS_KEY`.
+const RECENT_RUNS_KEPT: usize = 20;
+
+/// Days the daily aggregates are kept, the most `flows stats` goes back.
+pub(crate) const METRICS_DAYS_KEPT: i64 = 30;
+
+/// The upper bounds of the buckets of the run durations, in seconds.
+const RUN_SECS_BUCKETS: [f64; 7] = [1.0, 5.0, 15.0, 60.0, 300.0, 900.0, 1800.0];
+
+/// What is counted during a run.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Counter {
+    /// Issues a digest's search found.
+    IssuesFound,
+    /// Issues summarized by the model.
+    Summaries,
+    /// Issues the model could not summarize.
+    SummaryFailures,
+    /// Issues a digest left out: those it could not summarize in time or at
+    /// all, and those of people who opted out.
+    IssuesSkipped,
+    GithubCalls,
+    OpenaiCalls,
+    /// Tokens sent to the model, estimated with the tokenizer since
+    /// openai-flows does not report usage.
+    TokensIn,
+    /// Tokens the model answered with, estimated like `TokensIn`.
+    TokensOut,
+}
+
+/// The time spent in a stage, such as the GitHub search or the requests to
+/// the model, over the times it ran.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub(crate) struct StageTime {
+    pub(crate) count: u64,
+    pub(crate) secs: f64,
+}
+
+/// The counts of a run, or of the runs of an aggregate.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub(crate) struct RunCounts {
+    #[serde(default)]
+    pub(crate) issues_found: u64,
+    #[serde(default)]
+    pub(crate) summaries: u64,
+    #[serde(default)]
+    pub(crate) summary_failures: u64,
+    #[serde(default)]
+    pub(crate) issues_skipped: u64,
+    #[serde(default)]
+    pub(crate) github_calls: u64,
+    #[serde(default)]
+    pub(crate) openai_calls: u64,
+    #[serde(default)]
+    pub(crate) tokens_in: u64,
+    #[serde(default)]
+    pub(crate) tokens_out: u64,
+    #[serde(default)]
+    pub(crate) stages: BTreeMap<String, StageTime>,
+}
+
+impl RunCounts {
+    fn counter(&mut self, counter: Counter) -> &mut u64 {
+        match counter {
+            Counter::IssuesFound => &mut self.issues_found,
+            Counter::Summaries => &mut self.summaries,
+            Counter::SummaryFailures => &mut self.summary_failures,
+            Counter::IssuesSkipped => &mut self.issues_skipped,
+            Counter::GithubCalls => &mut self.github_calls,
+            Counter::OpenaiCalls => &mut self.openai_calls,
+            Counter::TokensIn => &mut self.tokens_in,
+            Counter::TokensOut => &mut self.tokens_out,
+        }
+    }
+
+    fn add(&mut self, other: &RunCounts) {
+        self.issues_found += other.issues_found;
+        self.summaries += other.summaries;
+        self.summary_failures += other.summary_failures;
+        self.issues_skipped += other.issues_skipped;
+        self.github_calls += other.github_calls;
+        self.openai_calls += other.openai_calls;
+        self.tokens_in += other.tokens_in;
+        self.tokens_out += other.tokens_out;
+        for (stage, time) in &other.stages {
+            let total = self.stages.entry(stage.clone()).or_default();
+            total.count += time.count;
+            total.secs += time.secs;
+        }
+    }
+}
+
+/// The counts of the current run.
+static RUN_COUNTS: Mutex<Option<RunCounts>> = Mutex::new(None);
+
+fn with_run_counts(update: impl FnOnce(&mut RunCounts)) {
+    if let Ok(mut counts) = RUN_COUNTS.lock() {
+        update(counts.get_or_insert_with(RunCounts::default));
+    }
+}
+
+pub(crate) fn count(counter: Counter) {
+    count_by(counter, 1);
+}
+
+pub(crate) fn count_by(counter: Counter, n: u64) {
+    with_run_counts(|counts| *counts.counter(counter) += n);
+}
+
+/// Adds the time since `started` to the stage.
+pub(crate) fn time_stage(stage: &str, started: Instant) {
+    let secs = started.elapsed().as_secs_f64();
+    with_run_counts(|counts| {
+        let time = counts.stages.entry(stage.to_string()).or_default();
+        time.count += 1;
+        time.secs += secs;
+    });
+}
+
+/// The runs of an entry point, such as `slack` or `schedule`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct EntryTotals {
+    pub(crate) runs: u64,
+    /// Runs that logged an error.
+    pub(crate) failed: u64,
+    pub(crate) secs_sum: f64,
+    /// The runs of each of `RUN_SECS_BUCKETS` that took at most its bound.
+    pub(crate) secs_buckets: Vec<u64>,
+}
+
+/// The runs added up, since the metrics were first written or over a day.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct MetricsTotals {
+    pub(crate) entries: BTreeMap<String, EntryTotals>,
+    #[serde(flatten)]
+    pub(crate) counts: RunCounts,
+}
+
+impl MetricsTotals {
+    fn add_run(&mut self, run: &RunEnd, counts: &RunCounts) {
+        let entry = self.entries.entry(run.entry.clone()).or_default();
+        entry.runs += 1;
+        if run.errors > 0 {
+            entry.failed += 1;
+        }
+        entry.secs_sum += run.secs;
+        entry.secs_buckets.resize(RUN_SECS_BUCKETS.len(), 0);
+        for (bucket, bound) in entry.secs_buckets.iter_mut().zip(RUN_SECS_BUCKETS) {
+            if run.secs <= bound {
+                *bucket += 1;
+            }
+        }
+        self.counts.add(counts);
+    }
+}
+
+/// A run as `flows stats` lists it.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct RunRecord {
+    pub(crate) run: String,
+    pub(crate) entry: String,
+    pub(crate) finished_at: DateTime<Utc>,
+    pub(crate) secs: f64,
+    pub(crate) errors: usize,
+    pub(crate) counts: RunCounts,
+}
+
+pub(crate) fn daily_metrics_key(day: NaiveDate) -> String {
+    format!("metrics:day:{day}")
+}
+
+/// Adds the run that ends to the totals, to the aggregate of the day and to
+/// the recent runs, and pushes the totals to the `metrics_push_url`, when
+/// it is set.
+pub(crate) fn record_run_metrics() {
+    let Some(run) = end_run() else {
+        return;
+    };
+    let counts = RUN_COUNTS
+        .lock()
+        .ok()
+        .and_then(|mut counts| counts.take())
+        .unwrap_or_default();
+    let now = Utc::now();
+
+    let mut totals = store::load::<MetricsTotals>(METRICS_KEY).unwrap_or_default();
+    totals.add_run(&run, &counts);
+    store::save(METRICS_KEY, &totals);
+
+    let day_key = daily_metrics_key(now.date_naive());
+    let mut day = store::load::<MetricsTotals>(&day_key).unwrap_or_default();
+    day.add_run(&run, &counts);
+    store::save_for(&day_key, &day, (METRICS_DAYS_KEPT + 1) * 24 * 60 * 60);
+
+    let mut recent = store::load::<Vec<RunRecord>>(RECENT_RUNS_KEY).unwrap_or_default();
+    recent.push(RunRecord {
+        run: run_id(),
+        entry: run.entry.clone(),
+        finished_at: now,
+        secs: run.secs,
+        errors: run.errors,
+        counts,
+    });
+    let excess = recent.len().saturating_sub(RECENT_RUNS_KEPT);
+    recent.drain(..excess);
+    store::save(RECENT_RUNS_KEY, &recent);
+
+    if let Some(url) = config::var("metrics_push_url") {
+        push_metrics(url.trim(), &render_metrics(&totals));
+    }
+}
+
+/// The daily aggregates of the last days, today included, added up.
+pub(crate) fn load_recent_metrics(days: i64, now: DateTime<Utc>) -> MetricsTotals {
+    let mut totals = MetricsTotals::default();
+    for back in 0..days.clamp(1, METRICS_DAYS_KEPT) {
+        let day = (now - Duration::days(back)).date_naive();
+        let Some(daily) = store::load::<MetricsTotals>(&daily_metrics_key(day)) else {
+            continue;
+        };
+        for (entry, runs) in daily.entries {
+            let total = totals.entries.entry(entry).or_default();
+            total.runs += runs.runs;
+            total.failed += runs.failed;
+            total.secs_sum += runs.secs_sum;
+            total.secs_buckets.resize(RUN_SECS_BUCKETS.len(), 0);
+            for (bucket, n) in total.secs_buckets.iter_mut().zip(runs.secs_buckets) {
+                *bucket += n;
+            }
+        }
+        totals.counts.add(&daily.counts);
+    }
+    totals
+}
+
+/// The report of `flows stats`: the runs of the last days by entry point,
+/// what they did, where their time went, and the latest runs.
+pub(crate) fn stats_report(days: i64, now: DateTime<Utc>) -> String {
+    let days = days.clamp(1, METRICS_DAYS_KEPT);
+    let totals = load_recent_metrics(days, now);
+    let mut lines = vec![format!("*Stats of the last {days} days*")];
+    if totals.entries.is_empty() {
+        lines.push("No runs were recorded.".to_string());
+        return lines.join("\n");
+    }
+    for (entry, runs) in &totals.entries {
+        lines.push(format!(
+            "- {entry}: {} runs, {} failed, {:.1}s on average",
+            runs.runs,
+            runs.failed,
+            runs.secs_sum / runs.runs.max(1) as f64
+        ));
+    }
+    let c = &totals.counts;
+    lines.push(format!(
+        "Issues: {} found, {} summarized, {} could not be summarized, {} skipped",
+        c.issues_found, c.summaries, c.summary_failures, c.issues_skipped
+    ));
+    lines.push(format!(
+        "Calls: {} to GitHub, {} to OpenAI, {} tokens in and {} out (estimated)",
+        c.github_calls, c.openai_calls, c.tokens_in, c.tokens_out
+    ));
+    if !c.stages.is_empty() {
+        let mut stages = c.stages.iter().collect::<Vec<_>>();
+        stages.sort_by(|a, b| b.1.secs.total_cmp(&a.1.secs));
+        lines.push(format!(
+            "Time by stage: {}",
+            stages
+                .iter()
+                .map(|(stage, time)| format!(
+                    "{stage} {:.0}s ({}x, {:.1}s each)",
+                    time.secs,
+                    time.count,
+                    time.secs / time.count.max(1) as f64
+                ))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+
+    let recent = store::load::<Vec<RunRecord>>(RECENT_RUNS_KEY).unwrap_or_default();
+    if !recent.is_empty() {
+        lines.push("Latest runs:".to_string());
+        for run in recent.iter().rev().take(5) {
+            lines.push(format!(
+                "- {} {} at {}, {:.1}s{}: {} summarized, {} GitHub and {} OpenAI calls",
+                run.entry,
+                run.run,
+                run.finished_at.format("%Y-%m-%d %H:%M UTC"),
+                run.secs,
+                match run.errors {
+                    0 => String::new(),
+                    n => format!(", {n} errors"),
+                },
+                run.counts.summaries,
+                run.counts.github_calls,
+                run.counts.openai_calls
+            ));
+        }
+    }
+    lines.join("\n")
+}
+
+fn metric(text: &mut String, name: &str, kind: &str, help: &str) {
+    text.push_str(&format!("# HELP {name} {help}\n# TYPE {name} {kind}\n"));
+}
+
+fn counter(text: &mut String, name: &str, help: &str, value: u64) {
+    metric(text, name, "counter", help);
+    text.push_str(&format!("{name} {value}\n"));
+}
+
+/// The totals, and the hit ratio of the summary cache, in the Prometheus
+/// text exposition format.
+pub(crate) fn render_metrics(totals: &MetricsTotals) -> String {
+    let mut text = String::new();
+    metric(
+        &mut text,
+        "summarizer_runs_total",
+        "counter",
+        "Runs handled, by entry point.",
+    );
+    for (entry, t) in &totals.entries {
+        text.push_str(&format!(
+            "summarizer_runs_total{{entry=\"{entry}\"}} {}\n",
+            t.runs
+        ));
+    }
+    metric(
+        &mut text,
+        "summarizer_run_failures_total",
+        "counter",
+        "Runs that logged an error, by entry point.",
+    );
+    for (entry, t) in &totals.entries {
+        text.push_str(&format!(
+            "summarizer_run_failures_total{{entry=\"{entry}\"}} {}\n",
+            t.failed
+        ));
+    }
+    metric(
+        &mut text,
+        "summarizer_run_duration_seconds",
+        "histogram",
+        "How long runs took, by entry point.",
+    );
+    for (entry, t) in &totals.entries {
+        for (bound, runs) in RUN_SECS_BUCKETS.iter().zip(&t.secs_buckets) {
+            text.push_str(&format!(
+                "summarizer_run_duration_seconds_bucket{{entry=\"{entry}\",le=\"{bound}\"}} {runs}\n"
+            ));
+        }
+        text.push_str(&format!(
+            "summarizer_run_duration_seconds_bucket{{entry=\"{entry}\",le=\"+Inf\"}} {}\nsummarizer_run_duration_seconds_sum{{entry=\"{entry}\"}} {}\nsummarizer_run_duration_seconds_count{{entry=\"{entry}\"}} {}\n",
+            t.runs, t.secs_sum, t.runs
+        ));
+    }
+
+    let c = &totals.counts;
+    counter(
+        &mut text,
+        "summarizer_issues_found_total",
+        "Issues the searches of the digests found.",
+        c.issues_found,
+    );
+    counter(
+        &mut text,
+        "summarizer_summaries_total",
+        "Issues summarized by the model.",
+        c.summaries,
+    );
+    counter(
+        &mut text,
+        "summarizer_summary_failures_total",
+        "Issues the model could not summarize.",
+        c.summary_failures,
+    );
+    counter(
+        &mut text,
+        "summarizer_issues_skipped_total",
+        "Issues digests left out.",
+        c.issues_skipped,
+    );
+    counter(
+        &mut text,
+        "summarizer_github_calls_total",
+        "Requests to the GitHub API.",
+        c.github_calls,
+    );
+    counter(
+        &mut text,
+        "summarizer_openai_calls_total",
+        "Requests to the model.",
+        c.openai_calls,
+    );
+    metric(
+        &mut text,
+        "summarizer_tokens_total",
+        "counter",
+        "Tokens sent to and received from the model, estimated.",
+    );
+    text.push_str(&format!(
+        "summarizer_tokens_total{{direction=\"in\"}} {}\nsummarizer_tokens_total{{direction=\"out\"}} {}\n",
+        c.tokens_in, c.tokens_out
+    ));
+    metric(
+        &mut text,
+        "summarizer_stage_seconds_total",
+        "counter",
+        "Time spent in each stage of the runs.",
+    );
+    for (stage, time) in &c.stages {
+        text.push_str(&format!(
+            "summarizer_stage_seconds_total{{stage=\"{stage}\"}} {}\n",
+            time.secs
+        ));
+    }
+
+    let cache = store::load::<CacheStats>(CACHE_STATS_KEY).unwrap_or_default();
+    metric(
+        &mut text,
+        "summarizer_summary_cache_total",
+        "counter",
+        "Summaries asked of the cache, by how they were answered.",
+    );
+    for (result, n) in [
+        ("hit", cache.hits),
+        ("update", cache.updates),
+        ("miss", cache.misses),
+    ] {
+        text.push_str(&format!(
+            "summarizer_summary_cache_total{{result=\"{result}\"}} {n}\n"
+        ));
+    }
+    if let Some(rate) = cache.hit_rate() {
+        metric(
+            &mut text,
+            "summarizer_summary_cache_hit_ratio",
+            "gauge",
+            "Share of the summaries that did not need the whole issue summarized.",
+        );
+        text.push_str(&format!("summarizer_summary_cache_hit_ratio {rate}\n"));
+    }
+    text
+}
+
+/// Pushes the metrics, with the `metrics_push_token` as bearer token when
+/// it is set. A Pushgateway takes them at `<gateway>/metrics/job/<job>`.
+fn push_metrics(url: &str, text: &str) {
+    let authorization =
+        config::var("metrics_push_token").map(|token| format!("Bearer {}", token.trim()));
+    let headers = authorization
+        .as_deref()
+        .map(|authorization| vec![("Authorization", authorization)])
+        .unwrap_or_default();
+    if let Err(e) = request_text(
+        Method::POST,
+        url,
+        &headers,
+        "text/plain; version=0.0.4",
+        text,
+    ) {
+        Event::warn("metrics")
+            .err(e)
+            .log("the metrics could not be pushed");
+    }
+}
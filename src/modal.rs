@@ -0,0 +1,169 @@
+//! The modal of the compose shortcut, which builds the digest command from
+//! form fields for those who'd rather not learn its syntax.
+
+use crate::app_home::{slack_api, slack_channel, trigger_word};
+use crate::config::{self, is_slack, served_channels, OutputMode};
+use crate::linear::linear_team;
+use crate::logging::Event;
+use serde_json::{json, Value};
+
+/// The callback id of the shortcut and of its modal.
+pub(crate) const COMPOSE_CALLBACK: &str = "compose_digest";
+
+fn plain(text: &str) -> Value {
+    json!({ "type": "plain_text", "text": text })
+}
+
+fn input(block_id: &str, label: &str, element: Value, optional: bool) -> Value {
+    json!({
+        "type": "input",
+        "block_id": block_id,
+        "label": plain(label),
+        "element": element,
+        "optional": optional,
+    })
+}
+
+fn option(value: &str, label: &str) -> Value {
+    json!({ "text": plain(label), "value": value })
+}
+
+/// Opens the modal, with the channel the shortcut was used in selected when
+/// it is a served one.
+pub(crate) fn open_compose_modal(trigger_id: &str, channel: Option<&str>) {
+    let channels = served_channels()
+        .into_iter()
+        .filter(|(ws, _)| is_slack(ws))
+        .take(100)
+        .map(|(_, channel)| option(&channel, &format!("#{channel}")))
+        .collect::<Vec<Value>>();
+    let mut channel_menu = json!({
+        "type": "static_select",
+        "action_id": "value",
+        "options": channels,
+    });
+    if let Some(selected) = channel
+        .and_then(slack_channel)
+        .map(|(_, channel)| option(&channel, &format!("#{channel}")))
+    {
+        channel_menu["initial_option"] = selected;
+    }
+
+    // the options are loaded from the repositories of `repo_orgs` as the
+    // field is typed in
+    let mut repo = json!({
+        "type": "external_select",
+        "action_id": "value",
+        "min_query_length": 1,
+        "placeholder": plain("WasmEdge/WasmEdge or linear:ENG"),
+    });
+    if let Some(default_repo) = config::var("default_repo") {
+        repo["initial_option"] = option(default_repo.trim(), default_repo.trim());
+    }
+    let outputs = [OutputMode::Full, OutputMode::Brief]
+        .map(|mode| option(mode.as_str(), mode.as_str()))
+        .to_vec();
+
+    let view = json!({
+        "trigger_id": trigger_id,
+        "view": {
+            "type": "modal",
+            "callback_id": COMPOSE_CALLBACK,
+            "title": plain("Summarize issues"),
+            "submit": plain("Summarize"),
+            "close": plain("Cancel"),
+            "blocks": [
+                input("channel", "Post to", channel_menu, false),
+                input("repo", "Repository", repo, false),
+                input("days", "Issues active in the last days", json!({
+                    "type": "number_input",
+                    "action_id": "value",
+                    "is_decimal_allowed": false,
+                    "min_value": "1",
+                    "initial_value": "7",
+                }), false),
+                input("labels", "Only issues with one of the labels", json!({
+                    "type": "plain_text_input",
+                    "action_id": "value",
+                    "placeholder": plain("bug, good first issue"),
+                }), true),
+                input("limit", "At most this many issues", json!({
+                    "type": "number_input",
+                    "action_id": "value",
+                    "is_decimal_allowed": false,
+                    "min_value": "1",
+                }), true),
+                input("output", "Output", json!({
+                    "type": "static_select",
+                    "action_id": "value",
+                    "placeholder": plain("the channel's"),
+                    "options": outputs,
+                }), true),
+            ],
+        },
+    });
+    if let Err(e) = slack_api("views.open", &view) {
+        Event::error("app-home")
+            .err(e)
+            .log("the compose modal could not be opened");
+    }
+}
+
+/// The value of a field of the submitted modal.
+fn field<'a>(values: &'a Value, block_id: &str) -> Option<&'a str> {
+    let element = &values[block_id]["value"];
+    element["value"]
+        .as_str()
+        .or_else(|| element["selected_option"]["value"].as_str())
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+/// The channel and the digest command of the submitted modal, as typed in
+/// the channel, or the errors of the fields by their block ids.
+pub(crate) fn compose_command(values: &Value) -> Result<(String, String, String), Value> {
+    let mut errors = serde_json::Map::new();
+    let channel = field(values, "channel").and_then(slack_channel);
+    if channel.is_none() {
+        errors.insert("channel".into(), json!("Please choose a served channel."));
+    }
+    let repo = field(values, "repo").filter(|repo| {
+        linear_team(repo).is_some()
+            || repo
+                .split_once('/')
+                .is_some_and(|(owner, name)| !owner.is_empty() && !name.is_empty())
+    });
+    if repo.is_none() {
+        errors.insert(
+            "repo".into(),
+            json!("Please give the repository as <github_owner>/<github_repo>."),
+        );
+    }
+    let (Some((workspace, channel)), Some(repo)) = (channel, repo) else {
+        return Err(Value::Object(errors));
+    };
+
+    let days = field(values, "days").unwrap_or("7");
+    let mut command = format!("{} {repo} {days}", trigger_word());
+    if let Some(labels) = field(values, "labels") {
+        let labels = labels
+            .split(',')
+            .map(str::trim)
+            .filter(|label| !label.is_empty())
+            .map(|label| match label.contains(' ') {
+                true => format!("\"{label}\""),
+                false => label.to_string(),
+            })
+            .collect::<Vec<_>>();
+        if !labels.is_empty() {
+            command.push_str(&format!(" label:{}", labels.join(",")));
+        }
+    }
+    if let Some(limit) = field(values, "limit") {
+        command.push_str(&format!(" limit:{limit}"));
+    }
+    if let Some(output) = field(values, "output") {
+        command.push_str(&format!(" output:{output}"));
+    }
+    Ok((workspace, channel, command))
+}
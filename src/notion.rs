@@ -0,0 +1,99 @@
+//! Pages of a Notion database that digests are added to.
+
+use crate::audit::audit;
+use crate::config::{self, notion_database};
+use crate::http::request_json;
+use chrono::{DateTime, Utc};
+use http_req::request::Method;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+const NOTION_VERSION: &str = "2022-06-28";
+
+/// Notion's limits for the text of a rich text object and for the blocks of
+/// a request.
+const TEXT_LIMIT: usize = 2000;
+const BLOCK_LIMIT: usize = 100;
+
+/// A paragraph block, with the text cut into rich text objects Notion
+/// accepts.
+fn paragraph(text: &str) -> Value {
+    let chars = text.chars().collect::<Vec<char>>();
+    let rich_text = chars
+        .chunks(TEXT_LIMIT)
+        .map(|chunk| json!({ "type": "text", "text": { "content": chunk.iter().collect::<String>() } }))
+        .collect::<Vec<Value>>();
+    json!({ "object": "block", "type": "paragraph", "paragraph": { "rich_text": rich_text } })
+}
+
+/// Adds the digest of a repository as a page of the `notion_database`,
+/// with the summaries as its content. The database needs the properties
+/// `Name` (title), `Repository` (select), `Date` (date), `Issues` (number)
+/// and `Priorities` (text), which gets the number of issues by priority
+/// label. Returns the URL of the page.
+pub(crate) fn export_digest_to_notion(
+    owner: &str,
+    repo: &str,
+    now: DateTime<Utc>,
+    summaries: &[String],
+    priorities: &BTreeMap<String, usize>,
+) -> Option<Result<String, String>> {
+    let database = notion_database()?;
+    let Some(token) = config::var("notion_token") else {
+        return Some(Err("notion_token is not set".to_string()));
+    };
+    let breakdown = priorities
+        .iter()
+        .map(|(priority, n)| format!("{priority}: {n}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut children = summaries
+        .iter()
+        .map(|summary| paragraph(summary))
+        .collect::<Vec<Value>>();
+    if children.len() > BLOCK_LIMIT {
+        children.truncate(BLOCK_LIMIT - 1);
+        children.push(paragraph(&format!(
+            "{} more summaries are in the Slack channel.",
+            summaries.len() + 1 - BLOCK_LIMIT
+        )));
+    }
+    let page = json!({
+        "parent": { "database_id": database },
+        "properties": {
+            "Name": { "title": [{ "text": { "content": format!("Digest of {owner}/{repo}, {}", now.format("%Y-%m-%d")) } }] },
+            "Repository": { "select": { "name": format!("{owner}/{repo}") } },
+            "Date": { "date": { "start": now.to_rfc3339() } },
+            "Issues": { "number": summaries.len() },
+            "Priorities": { "rich_text": [{ "text": { "content": breakdown } }] },
+        },
+        "children": children,
+    });
+
+    let authorization = format!("Bearer {}", token.trim());
+    let exported = request_json(
+        Method::POST,
+        "https://api.notion.com/v1/pages",
+        &[
+            ("Authorization", authorization.as_str()),
+            ("Notion-Version", NOTION_VERSION),
+        ],
+        Some(&page),
+    )
+    .map(|page| page["url"].as_str().unwrap_or("Notion").to_string())
+    .map_err(|e| {
+        format!("Notion refused the page ({e}). Please make sure the database is shared with the integration of notion_token and has the documented properties.")
+    });
+    let outcome = match &exported {
+        Ok(url) => format!("added {url}"),
+        Err(e) => format!("failed: {e}"),
+    };
+    audit(
+        "flow",
+        &format!("exported the digest of {owner}/{repo} to Notion"),
+        Some((owner, repo)),
+        &outcome,
+        false,
+    );
+    Some(exported)
+}
@@ -0,0 +1,156 @@
+//! The ops channel, which is told about the failures only an operator can
+//! fix, such as a revoked token, with what to do about them. The channels
+//! of the users are spared their details.
+
+use crate::config::ops_channel;
+use crate::logging::{run_id, Event};
+use crate::sink::send_message;
+use crate::store;
+use std::sync::Mutex;
+
+/// How long a failure is not reported again, so a broken token doesn't
+/// post on every run.
+const OPS_REPORT_COOLDOWN_SECS: i64 = 60 * 60;
+
+/// Failed requests to the model in a run before they are reported.
+const MODEL_FAILURES_REPORTED: usize = 3;
+
+/// The run and its failed requests to the model.
+static MODEL_FAILURES: Mutex<Option<(String, usize)>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum OpsFailure {
+    GithubAuth,
+    GithubRateLimit,
+    OpenaiAuth,
+    OpenaiQuota,
+    ModelFailures,
+    SlackAuth,
+}
+
+impl OpsFailure {
+    fn as_str(&self) -> &'static str {
+        match self {
+            OpsFailure::GithubAuth => "github-auth",
+            OpsFailure::GithubRateLimit => "github-rate-limit",
+            OpsFailure::OpenaiAuth => "openai-auth",
+            OpsFailure::OpenaiQuota => "openai-quota",
+            OpsFailure::ModelFailures => "model-failures",
+            OpsFailure::SlackAuth => "slack-auth",
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            OpsFailure::GithubAuth => "GitHub refused the credentials of the flow",
+            OpsFailure::GithubRateLimit => "GitHub's rate limit is used up",
+            OpsFailure::OpenaiAuth => "OpenAI refused the API key of the flow",
+            OpsFailure::OpenaiQuota => "The OpenAI account is out of quota",
+            OpsFailure::ModelFailures => "Requests to the model keep failing",
+            OpsFailure::SlackAuth => "Slack refused the bot token",
+        }
+    }
+
+    fn remediation(&self) -> &'static str {
+        match self {
+            OpsFailure::GithubAuth => {
+                "Reconnect the GitHub account of the flow on flows.network, or check that its token was not revoked or expired."
+            }
+            OpsFailure::GithubRateLimit => {
+                "Wait for the limit to reset, and spread out the schedules or connect an account with a higher limit if it keeps happening."
+            }
+            OpsFailure::OpenaiAuth => {
+                "Reconnect the OpenAI integration of the flow with a valid API key, `flows selfcheck` tells when it works again."
+            }
+            OpsFailure::OpenaiQuota => {
+                "Check the billing and usage limits of the OpenAI account."
+            }
+            OpsFailure::ModelFailures => {
+                "Check status.openai.com, and raise `openai_timeout_secs` if the requests time out."
+            }
+            OpsFailure::SlackAuth => {
+                "Reinstall the Slack app and update `slack_bot_token`, the token was revoked or the app uninstalled."
+            }
+        }
+    }
+}
+
+fn reported_key(failure: OpsFailure) -> String {
+    format!("ops:reported:{}", failure.as_str())
+}
+
+/// Reports the failure to the ops channel, unless it was reported within
+/// `OPS_REPORT_COOLDOWN_SECS`. Returns whether the ops channel is set, and
+/// so whether the users can be spared the details.
+pub(crate) fn report_ops(failure: OpsFailure, detail: &str) -> bool {
+    let Some((workspace, channel)) = ops_channel() else {
+        return false;
+    };
+    let key = reported_key(failure);
+    if store::load::<String>(&key).is_some() {
+        return true;
+    }
+    // saved before posting, a failure of the post itself is not reported again
+    store::save_for(&key, &run_id(), OPS_REPORT_COOLDOWN_SECS);
+    Event::info("ops").log(&format!("reporting {} to #{channel}", failure.as_str()));
+    send_message(
+        &workspace,
+        &channel,
+        format!(
+            ":rotating_light: *{}*\nrun: `{}`\n{detail}\nWhat to do: {}",
+            failure.title(),
+            run_id(),
+            failure.remediation()
+        ),
+    );
+    true
+}
+
+/// Tells the ops channel why issues of a digest could not be summarized,
+/// as `#<issue_number> (<reason>)` entries. Returns whether the ops channel
+/// is set, and so whether the digest can list the issues without reasons.
+pub(crate) fn report_digest_failures(
+    owner: &str,
+    repo: &str,
+    channel: &str,
+    failed: &[String],
+) -> bool {
+    let Some((ops_workspace, ops_channel)) = ops_channel() else {
+        return false;
+    };
+    send_message(
+        &ops_workspace,
+        &ops_channel,
+        format!(
+            ":warning: {} issues of the {owner}/{repo} digest in #{channel} could not be summarized (run `{}`): {}",
+            failed.len(),
+            run_id(),
+            failed.join(", ")
+        ),
+    );
+    true
+}
+
+/// Counts a request to the model that failed after its retries, reporting
+/// them once `MODEL_FAILURES_REPORTED` failed in the run.
+pub(crate) fn model_failed(detail: &str) {
+    let run = run_id();
+    let failures = match MODEL_FAILURES.lock() {
+        Ok(mut failures) => {
+            let (failed_run, n) = failures.get_or_insert_with(|| (run.clone(), 0));
+            if *failed_run != run {
+                *failed_run = run;
+                *n = 0;
+            }
+            *n += 1;
+            *n
+        }
+        Err(_) => return,
+    };
+    if failures == MODEL_FAILURES_REPORTED {
+        report_ops(
+            OpsFailure::ModelFailures,
+            &format!("{failures} requests failed in the run, the last one with: {detail}"),
+        );
+    }
+}
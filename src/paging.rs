@@ -0,0 +1,177 @@
+//! PagerDuty and Opsgenie alerts for new issues that need someone now.
+
+use crate::audit::audit;
+use crate::config::{self, page_keywords, page_labels, IssueRoute};
+use crate::http::request_json;
+use github_flows::octocrab::models::issues::Issue;
+use http_req::request::Method;
+use regex::Regex;
+use serde_json::json;
+
+/// PagerDuty's limit for the summary of an event.
+const PAGERDUTY_SUMMARY_LIMIT: usize = 1024;
+
+/// Opsgenie's limits for the message and the description of an alert.
+const OPSGENIE_MESSAGE_LIMIT: usize = 130;
+const OPSGENIE_DESCRIPTION_LIMIT: usize = 15000;
+
+fn truncate(text: &str, limit: usize) -> String {
+    match text.char_indices().nth(limit) {
+        Some((end, _)) => text[..end].to_string(),
+        None => text.to_string(),
+    }
+}
+
+/// Why the issue pages the on-call: a label of `page_labels`, a phrase of
+/// `page_keywords` in its title or body, or a security advisory it
+/// mentions by its GHSA or CVE id. `None` when it doesn't.
+pub(crate) fn page_reason(issue: &Issue) -> Option<String> {
+    let labels = issue
+        .labels
+        .iter()
+        .map(|lab| lab.name.to_lowercase())
+        .collect::<Vec<String>>();
+    if let Some(label) = page_labels()
+        .into_iter()
+        .find(|label| labels.contains(label))
+    {
+        return Some(format!("labeled `{label}`"));
+    }
+    let text = format!("{}\n{}", issue.title, issue.body.as_deref().unwrap_or(""));
+    let lowered = text.to_lowercase();
+    if let Some(keyword) = page_keywords()
+        .into_iter()
+        .find(|keyword| lowered.contains(keyword))
+    {
+        return Some(format!("mentions \"{keyword}\""));
+    }
+    let advisory =
+        Regex::new(r"(?i)\b(GHSA(?:-[23456789cfghjmpqrvwx]{4}){3}|CVE-\d{4}-\d{4,})\b").unwrap();
+    advisory
+        .captures(&text)
+        .map(|cap| format!("mentions the security advisory {}", cap[1].to_uppercase()))
+}
+
+/// Triggers a critical PagerDuty event through the Events API v2 of the
+/// service whose integration key is `pagerduty_routing_key`.
+fn trigger_pagerduty(
+    routing_key: &str,
+    issue_ref: &str,
+    issue: &Issue,
+    reason: &str,
+    summary: &str,
+) -> Result<(), String> {
+    let event = json!({
+        "routing_key": routing_key,
+        "event_action": "trigger",
+        // a redelivered webhook adds to the incident of the issue
+        "dedup_key": issue_ref,
+        "payload": {
+            "summary": truncate(&format!("{issue_ref}: {}", issue.title), PAGERDUTY_SUMMARY_LIMIT),
+            "source": issue_ref.split('#').next().unwrap_or(issue_ref),
+            "severity": "critical",
+            "custom_details": { "reason": reason, "summary": summary },
+        },
+        "links": [{ "href": issue.html_url.as_str(), "text": issue_ref }],
+    });
+    request_json(
+        Method::POST,
+        "https://events.pagerduty.com/v2/enqueue",
+        &[],
+        Some(&event),
+    )
+    .map(|_| ())
+    .map_err(|e| format!("PagerDuty refused the event ({e})"))
+}
+
+/// Creates a P1 Opsgenie alert with the API key of `opsgenie_api_key`, on
+/// `opsgenie_api_url` (default: https://api.opsgenie.com, the EU instance is
+/// https://api.eu.opsgenie.com).
+fn create_opsgenie_alert(
+    api_key: &str,
+    issue_ref: &str,
+    issue: &Issue,
+    reason: &str,
+    summary: &str,
+) -> Result<(), String> {
+    let site = config::var("opsgenie_api_url")
+        .unwrap_or("https://api.opsgenie.com".to_string())
+        .trim()
+        .trim_end_matches('/')
+        .to_string();
+    let authorization = format!("GenieKey {api_key}");
+    let alert = json!({
+        "message": truncate(&format!("{issue_ref}: {}", issue.title), OPSGENIE_MESSAGE_LIMIT),
+        // Opsgenie keeps one open alert per alias
+        "alias": issue_ref,
+        "description": truncate(
+            &format!("{summary}\n\n{}", issue.html_url),
+            OPSGENIE_DESCRIPTION_LIMIT,
+        ),
+        "details": { "reason": reason, "url": issue.html_url.as_str() },
+        "source": "GitHub issue summarizer",
+        "priority": "P1",
+    });
+    request_json(
+        Method::POST,
+        &format!("{site}/v2/alerts"),
+        &[("Authorization", authorization.as_str())],
+        Some(&alert),
+    )
+    .map(|_| ())
+    .map_err(|e| format!("Opsgenie refused the alert ({e})"))
+}
+
+/// Pages the on-call through PagerDuty and Opsgenie, those of the two that
+/// are set up, with the summary of the issue. Returns the line posted below
+/// the summary in the channel, `None` when neither is set up. The page is
+/// recorded in the audit log.
+pub(crate) fn page_on_call(
+    route: &IssueRoute,
+    issue: &Issue,
+    reason: &str,
+    summary: &str,
+) -> Option<String> {
+    let issue_ref = format!("{}/{}#{}", route.owner, route.repo, issue.number);
+    let mut paged = Vec::new();
+    let mut failed = Vec::new();
+    if let Some(key) = config::var("pagerduty_routing_key") {
+        match trigger_pagerduty(key.trim(), &issue_ref, issue, reason, summary) {
+            Ok(()) => paged.push("PagerDuty"),
+            Err(e) => failed.push(e),
+        }
+    }
+    if let Some(key) = config::var("opsgenie_api_key") {
+        match create_opsgenie_alert(key.trim(), &issue_ref, issue, reason, summary) {
+            Ok(()) => paged.push("Opsgenie"),
+            Err(e) => failed.push(e),
+        }
+    }
+    if paged.is_empty() && failed.is_empty() {
+        return None;
+    }
+    let outcome = match failed.is_empty() {
+        true => format!("paged for #{}, it {reason}", issue.number),
+        false => format!("failed for #{}: {}", issue.number, failed.join("; ")),
+    };
+    audit(
+        "flow",
+        "page on-call",
+        Some((&route.owner, &route.repo)),
+        &outcome,
+        false,
+    );
+    let mut line = String::new();
+    if !paged.is_empty() {
+        line.push_str(&format!(
+            "\n:rotating_light: Paged the on-call through {}, the issue {reason}.",
+            paged.join(" and ")
+        ));
+    }
+    for e in failed {
+        line.push_str(&format!(
+            "\n:warning: The on-call could not be paged, the issue {reason}: {e}"
+        ));
+    }
+    Some(line)
+}
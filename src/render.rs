@@ -0,0 +1,464 @@
+//! Rendering of summaries and other values into Slack messages, and of
+//! Slack messages into Discord embeds, Teams and Google Chat cards and
+//! Telegram and Matrix messages.
+
+use crate::summarizer::{Confidence, IssueSummary, ReproInfo};
+use regex::Regex;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+impl IssueSummary {
+    pub(crate) fn render(&self) -> String {
+        format!(
+            "Issue Summary ({}):\n{}\n{}{}{}{}",
+            self.issue_type.as_str(),
+            self.summary,
+            self.repro,
+            self.failures,
+            self.confidence,
+            self.url
+        )
+    }
+}
+
+/// Discord's limit for the description of an embed.
+pub(crate) const DISCORD_EMBED_LIMIT: usize = 4096;
+
+/// The Slack emoji the bot uses, which Discord and Teams don't resolve in
+/// messages posted by bots.
+const EMOJI: [(&str, &str); 8] = [
+    (":warning:", "\u{26a0}\u{fe0f}"),
+    (":white_check_mark:", "\u{2705}"),
+    (":x:", "\u{274c}"),
+    (":tada:", "\u{1f389}"),
+    (":trophy:", "\u{1f3c6}"),
+    (":scissors:", "\u{2702}\u{fe0f}"),
+    (":robot:", "\u{1f916}"),
+    (":package:", "\u{1f4e6}"),
+];
+
+/// Translates Slack's mrkdwn into the markdown of Discord, Teams and the
+/// pulse site: bold, strikethrough, links and emoji.
+pub(crate) fn markdown(text: &str) -> String {
+    let link = Regex::new(r"<(https?://[^|>]+)\|([^>]+)>").unwrap();
+    let bare_link = Regex::new(r"<(https?://[^|>]+)>").unwrap();
+    let bold = Regex::new(r"(^|[\s(])\*([^*\n]+)\*").unwrap();
+    let strike = Regex::new(r"(^|[\s(])~([^~\n]+)~").unwrap();
+
+    let text = link.replace_all(text, "[$2]($1)");
+    let text = bare_link.replace_all(&text, "$1");
+    let text = bold.replace_all(&text, "$1**$2**");
+    let mut text = strike.replace_all(&text, "$1~~$2~~").to_string();
+    for (code, emoji) in EMOJI {
+        text = text.replace(code, emoji);
+    }
+    text
+}
+
+/// Splits off a bold first line, like the headings of status and digest
+/// messages, to be rendered as a title.
+fn heading(text: &str) -> (Option<&str>, &str) {
+    match text.split_once('\n') {
+        Some((first, rest))
+            if first.len() > 2 && first.starts_with('*') && first.ends_with('*') =>
+        {
+            (Some(&first[1..first.len() - 1]), rest)
+        }
+        _ => (None, text),
+    }
+}
+
+/// Renders a message as a Discord embed, with the heading as its title.
+pub(crate) fn discord_embed(text: &str) -> Value {
+    let (title, body) = heading(text);
+    let mut description = markdown(body);
+    if description.len() > DISCORD_EMBED_LIMIT {
+        let mut end = DISCORD_EMBED_LIMIT - 3;
+        while !description.is_char_boundary(end) {
+            end -= 1;
+        }
+        description.truncate(end);
+        description.push_str("...");
+    }
+    match title {
+        Some(title) => json!({ "title": title, "description": description }),
+        None => json!({ "description": description }),
+    }
+}
+
+/// Telegram's limit for the text of a message, in characters.
+pub(crate) const TELEGRAM_MESSAGE_LIMIT: usize = 4096;
+
+/// Escapes the characters MarkdownV2 reserves, given those that are
+/// reserved where the text goes.
+fn escape_markdown_v2(text: &str, reserved: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if reserved.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Translates Slack's mrkdwn into Telegram's MarkdownV2: bold,
+/// strikethrough, links and emoji are kept, and every other reserved
+/// character is escaped.
+pub(crate) fn telegram_markdown(text: &str) -> String {
+    const RESERVED: &str = "_*[]()~`>#+-=|{}.!\\";
+    let token = Regex::new(
+        r"<(https?://[^|>]+)(?:\|([^>]+))?>|(^|[\s(])\*([^*\n]+)\*|(^|[\s(])~([^~\n]+)~",
+    )
+    .unwrap();
+    let mut text = text.to_string();
+    for (code, emoji) in EMOJI {
+        text = text.replace(code, emoji);
+    }
+
+    let mut rendered = String::with_capacity(text.len());
+    let mut last = 0;
+    for cap in token.captures_iter(&text) {
+        let whole = cap.get(0).unwrap();
+        rendered.push_str(&escape_markdown_v2(&text[last..whole.start()], RESERVED));
+        if let Some(url) = cap.get(1) {
+            let label = cap.get(2).map_or(url.as_str(), |l| l.as_str());
+            rendered.push_str(&format!(
+                "[{}]({})",
+                escape_markdown_v2(label, RESERVED),
+                escape_markdown_v2(url.as_str(), ")\\")
+            ));
+        } else if let Some(bold) = cap.get(4) {
+            rendered.push_str(&escape_markdown_v2(&cap[3], RESERVED));
+            rendered.push_str(&format!(
+                "*{}*",
+                escape_markdown_v2(bold.as_str(), RESERVED)
+            ));
+        } else if let Some(strike) = cap.get(6) {
+            rendered.push_str(&escape_markdown_v2(&cap[5], RESERVED));
+            rendered.push_str(&format!(
+                "~{}~",
+                escape_markdown_v2(strike.as_str(), RESERVED)
+            ));
+        }
+        last = whole.end();
+    }
+    rendered.push_str(&escape_markdown_v2(&text[last..], RESERVED));
+    rendered
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Translates Slack's mrkdwn into HTML: bold, strikethrough, which HTML
+/// clients differ on the tag of, links and emoji are kept, anything else is
+/// escaped.
+fn html(text: &str, strike_tag: &str) -> String {
+    let token = Regex::new(
+        r"<(https?://[^|>]+)(?:\|([^>]+))?>|(^|[\s(])\*([^*\n]+)\*|(^|[\s(])~([^~\n]+)~",
+    )
+    .unwrap();
+    let mut text = text.to_string();
+    for (code, emoji) in EMOJI {
+        text = text.replace(code, emoji);
+    }
+
+    let mut rendered = String::with_capacity(text.len());
+    let mut last = 0;
+    for cap in token.captures_iter(&text) {
+        let whole = cap.get(0).unwrap();
+        rendered.push_str(&escape_html(&text[last..whole.start()]));
+        if let Some(url) = cap.get(1) {
+            let label = cap.get(2).map_or(url.as_str(), |l| l.as_str());
+            rendered.push_str(&format!(
+                "<a href=\"{}\">{}</a>",
+                escape_html(url.as_str()).replace('"', "&quot;"),
+                escape_html(label)
+            ));
+        } else if let Some(bold) = cap.get(4) {
+            rendered.push_str(&escape_html(&cap[3]));
+            rendered.push_str(&format!("<b>{}</b>", escape_html(bold.as_str())));
+        } else if let Some(strike) = cap.get(6) {
+            rendered.push_str(&escape_html(&cap[5]));
+            rendered.push_str(&format!(
+                "<{strike_tag}>{}</{strike_tag}>",
+                escape_html(strike.as_str())
+            ));
+        }
+        last = whole.end();
+    }
+    rendered.push_str(&escape_html(&text[last..]));
+    rendered
+}
+
+/// Renders a message as the card of a Google Chat message, with the
+/// heading as the title of the card and every paragraph, like the
+/// summaries of a digest, as a section of its own.
+pub(crate) fn google_chat_card(text: &str) -> Value {
+    let (title, body) = heading(text);
+    let sections = body
+        .split("\n\n")
+        .filter(|paragraph| !paragraph.trim().is_empty())
+        .map(|paragraph| {
+            let lines = paragraph
+                .lines()
+                .map(|line| html(line, "s"))
+                .collect::<Vec<_>>()
+                .join("<br>");
+            json!({ "widgets": [{ "textParagraph": { "text": lines } }] })
+        })
+        .collect::<Vec<Value>>();
+    let mut card = json!({ "sections": sections });
+    if let Some(title) = title {
+        card["header"] = json!({ "title": title });
+    }
+    json!({ "cardsV2": [{ "cardId": "summary", "card": card }] })
+}
+
+/// Renders a message as the HTML `formatted_body` of a Matrix message, with
+/// the tags of the Matrix spec's HTML subset.
+pub(crate) fn matrix_html(text: &str) -> String {
+    text.lines()
+        .map(|line| html(line, "del"))
+        .collect::<Vec<_>>()
+        .join("<br/>")
+}
+
+/// Renders a message as the Adaptive Card of a Teams message, with the
+/// heading as its title. Every line is a text block, and the paragraphs,
+/// like the summaries of a digest, are separated by a line.
+pub(crate) fn adaptive_card(text: &str) -> Value {
+    let (title, body) = heading(text);
+    let mut blocks = Vec::new();
+    if let Some(title) = title {
+        blocks.push(json!({
+            "type": "TextBlock",
+            "text": title,
+            "size": "Medium",
+            "weight": "Bolder",
+            "wrap": true,
+        }));
+    }
+    for (i, paragraph) in body.split("\n\n").enumerate() {
+        for (j, line) in paragraph
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .enumerate()
+        {
+            blocks.push(json!({
+                "type": "TextBlock",
+                "text": markdown(line),
+                "wrap": true,
+                "spacing": if j == 0 { "Medium" } else { "None" },
+                "separator": i > 0 && j == 0,
+            }));
+        }
+    }
+    json!({
+        "type": "message",
+        "attachments": [{
+            "contentType": "application/vnd.microsoft.card.adaptive",
+            "content": {
+                "$schema": "http://adaptivecards.io/schemas/adaptive-card.json",
+                "type": "AdaptiveCard",
+                "version": "1.4",
+                "msteams": { "width": "Full" },
+                "body": blocks,
+            },
+        }],
+    })
+}
+
+pub(crate) fn format_minutes(minutes: i64) -> String {
+    match minutes {
+        m if m % (60 * 24) == 0 => format!("{}d", m / (60 * 24)),
+        m if m % 60 == 0 => format!("{}h", m / 60),
+        m => format!("{m}m"),
+    }
+}
+
+/// Mentions the Slack user mapped to the GitHub login, or names the login.
+pub(crate) fn mention(users: &BTreeMap<String, String>, login: &str) -> String {
+    match users.get(&login.to_lowercase()) {
+        Some(user) => user.clone(),
+        None => format!("{login} (on GitHub)"),
+    }
+}
+
+impl Confidence {
+    pub(crate) fn render(&self) -> String {
+        let mut warning = format!(":warning: Low confidence ({}/100)", self.score);
+        if !self.reasons.is_empty() {
+            warning.push_str(&format!(": {}", self.reasons.join("; ")));
+        }
+        warning.push_str(". Please check the issue itself for the details.");
+        warning
+    }
+}
+
+impl ReproInfo {
+    pub(crate) fn render(&self) -> String {
+        let mut block = format!(
+            "Environment:\n- OS: {}\n- Version: {}\n- Reproduction steps:",
+            self.os.as_deref().unwrap_or("not provided"),
+            self.version.as_deref().unwrap_or("not provided")
+        );
+        if self.steps.is_empty() {
+            block.push_str(" not provided");
+        }
+        for (i, step) in self.steps.iter().enumerate() {
+            block.push_str(&format!("\n  {}. {step}", i + 1));
+        }
+
+        let missing = self.missing();
+        if !missing.is_empty() {
+            block.push_str(&format!(
+                "\n:warning: Missing repro: {}",
+                missing.join(", ")
+            ));
+        }
+        block
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fakes::{
+        assert_snapshot, FixtureIssues, MockSummarizer, RecordingSink, CHUNKED_ISSUE, LARGE_ISSUE,
+        SMALL_ISSUE,
+    };
+    use crate::sink::MessageBatch;
+    use crate::summarizer::{take_confidence, IssueType, Summarizer};
+
+    /// The summary of the recorded issue as a digest posts it, with
+    /// `failed_parts` of its discussion not summarized.
+    fn issue_summary(
+        fixture: &str,
+        issue_type: IssueType,
+        repro: Option<ReproInfo>,
+        failed_parts: usize,
+    ) -> String {
+        let fixture = FixtureIssues::load(fixture);
+        let summarizer = MockSummarizer::default();
+        let mut summary =
+            futures::executor::block_on(summarizer.summarize(fixture.context())).unwrap();
+        let parts = summary.parts.max(1);
+        let confidence = Confidence::assess(
+            take_confidence(&mut summary.text),
+            summary.text.is_empty(),
+            (failed_parts, parts),
+            (fixture.comments.len(), fixture.issue.comments as usize),
+        );
+        IssueSummary {
+            issue_type,
+            summary: summary.text,
+            repro: repro.map_or(String::new(), |r| format!("{}\n", r.render())),
+            failures: match failed_parts {
+                0 => String::new(),
+                n => format!(
+                    ":warning: {n} of {parts} parts of the discussion could not be summarized: {}\n",
+                    (1..=n)
+                        .map(|part| format!("part {part} (timed out)"))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            },
+            confidence: match confidence.is_low() {
+                true => format!("{}\n", confidence.render()),
+                false => String::new(),
+            },
+            url: fixture.issue.html_url.to_string(),
+        }
+        .render()
+    }
+
+    /// The messages of a digest of the recorded issues, one of which could
+    /// not be summarized.
+    fn digest() -> Vec<String> {
+        let sink = RecordingSink::default();
+        let mut batch = MessageBatch::new(&sink, "octo-team", "issues");
+        let repro = ReproInfo {
+            os: Some("Ubuntu 22.04".to_string()),
+            version: None,
+            steps: vec![
+                "Create an empty config.toml".to_string(),
+                "Start the server".to_string(),
+            ],
+        };
+        batch.push_summary(
+            1347,
+            issue_summary(SMALL_ISSUE, IssueType::Bug, Some(repro), 0),
+        );
+        batch.push_summary(
+            2210,
+            issue_summary(LARGE_ISSUE, IssueType::FeatureRequest, None, 0),
+        );
+        batch.push_summary(3105, issue_summary(CHUNKED_ISSUE, IssueType::Bug, None, 2));
+        batch.push(
+            ":warning: 1 of 4 issues could not be summarized: #3120 (The summary took more than 120 seconds)"
+                .to_string(),
+        );
+        drop(batch);
+        sink.texts("issues")
+    }
+
+    fn messages(rendered: Vec<String>) -> String {
+        rendered
+            .iter()
+            .enumerate()
+            .map(|(i, message)| {
+                format!(
+                    "--- message {} of {} ---\n{message}\n",
+                    i + 1,
+                    rendered.len()
+                )
+            })
+            .collect()
+    }
+
+    fn pretty(value: Value) -> String {
+        serde_json::to_string_pretty(&value).unwrap()
+    }
+
+    #[test]
+    fn digests_on_slack() {
+        assert_snapshot!("digest_slack", messages(digest()));
+    }
+
+    #[test]
+    fn digests_on_discord() {
+        let rendered = digest().iter().map(|m| pretty(discord_embed(m))).collect();
+        assert_snapshot!("digest_discord", messages(rendered));
+    }
+
+    #[test]
+    fn digests_on_teams() {
+        let rendered = digest().iter().map(|m| pretty(adaptive_card(m))).collect();
+        assert_snapshot!("digest_teams", messages(rendered));
+    }
+
+    #[test]
+    fn digests_on_telegram() {
+        let rendered = digest().iter().map(|m| telegram_markdown(m)).collect();
+        assert_snapshot!("digest_telegram", messages(rendered));
+    }
+
+    #[test]
+    fn digests_on_google_chat() {
+        let rendered = digest()
+            .iter()
+            .map(|m| pretty(google_chat_card(m)))
+            .collect();
+        assert_snapshot!("digest_google_chat", messages(rendered));
+    }
+
+    #[test]
+    fn digests_on_matrix() {
+        let rendered = digest().iter().map(|m| matrix_html(m)).collect();
+        assert_snapshot!("digest_matrix", messages(rendered));
+    }
+}
@@ -0,0 +1,173 @@
+//! The raw inputs and outputs of the runs, archived to S3-compatible object
+//! storage for audits, replays and offline evaluation of prompt changes.
+
+use crate::config::{self, s3_archive, S3Archive};
+use crate::http::{request_json, request_text};
+use crate::logging::{run_id, Event};
+use crate::store;
+use chrono::{DateTime, Duration, Utc};
+use hmac_sha256::{Hash, HMAC};
+use http_req::{request::Method, uri::Uri};
+use serde_json::{json, Value};
+use std::sync::Mutex;
+
+/// The objects archived, with when they were, so those past the retention
+/// can be deleted without listing the bucket.
+pub(crate) const RUN_ARCHIVE_KEY: &str = "run_archive:objects";
+
+/// The records of the current run, in the order they were made.
+static RECORDS: Mutex<Vec<Value>> = Mutex::new(Vec::new());
+
+/// Records an input or output of the run, such as the issue read or a
+/// prompt and its answer. Does nothing unless the archive is set up.
+pub(crate) fn record(kind: &str, issue_number: Option<u64>, data: Value) {
+    if config::var("s3_archive_bucket").is_none() {
+        return;
+    }
+    if let Ok(mut records) = RECORDS.lock() {
+        records.push(json!({
+            "kind": kind,
+            "issue": issue_number,
+            "at": Utc::now().to_rfc3339(),
+            "data": data,
+        }));
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect::<String>()
+}
+
+/// Percent-encodes the path of an object as SigV4 expects, keeping the
+/// slashes.
+fn encode_path(path: &str) -> String {
+    path.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// Sends a request for an object of the bucket, addressed path-style so it
+/// works with any S3-compatible storage, signed with Signature Version 4.
+fn s3_request(
+    archive: &S3Archive,
+    method: Method,
+    object: &str,
+    body: Option<&str>,
+) -> Result<(), String> {
+    let path = encode_path(&format!("/{}/{object}", archive.bucket));
+    let url = format!("{}{path}", archive.endpoint);
+    let host = Uri::try_from(url.as_str())
+        .ok()
+        .and_then(|uri| uri.host_header())
+        .ok_or(format!("{} is not a valid endpoint", archive.endpoint))?;
+    let now = Utc::now();
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+    let payload_hash = hex(&Hash::hash(body.unwrap_or("").as_bytes()));
+    let method_name = match method {
+        Method::PUT => "PUT",
+        Method::DELETE => "DELETE",
+        _ => "GET",
+    };
+
+    let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+    let canonical_request = format!(
+        "{method_name}\n{path}\n\nhost:{host}\nx-amz-content-sha256:{payload_hash}\nx-amz-date:{amz_date}\n\n{signed_headers}\n{payload_hash}"
+    );
+    let scope = format!("{date}/{}/s3/aws4_request", archive.region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+        hex(&Hash::hash(canonical_request.as_bytes()))
+    );
+    let key = HMAC::mac(date.as_bytes(), format!("AWS4{}", archive.secret_key));
+    let key = HMAC::mac(archive.region.as_bytes(), key);
+    let key = HMAC::mac(b"s3", key);
+    let key = HMAC::mac(b"aws4_request", key);
+    let signature = hex(&HMAC::mac(string_to_sign.as_bytes(), key));
+    let authorization = format!(
+        "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+        archive.access_key
+    );
+
+    let headers = [
+        ("Authorization", authorization.as_str()),
+        ("x-amz-content-sha256", payload_hash.as_str()),
+        ("x-amz-date", amz_date.as_str()),
+    ];
+    match body {
+        Some(body) => request_text(method, &url, &headers, "application/json", body),
+        None => request_json(method, &url, &headers, None),
+    }
+    .map(|_| ())
+}
+
+/// Uploads the records of the run as one JSON object, named by the day and
+/// the run id under the `s3_archive_prefix`, and deletes the archived
+/// objects older than `s3_archive_retention_days`. A run without records
+/// uploads nothing.
+pub(crate) fn archive_run() {
+    let records = match RECORDS.lock() {
+        Ok(mut records) => std::mem::take(&mut *records),
+        Err(_e) => return,
+    };
+    if records.is_empty() {
+        return;
+    }
+    let archive = match s3_archive() {
+        Some(Ok(archive)) => archive,
+        Some(Err(e)) => {
+            Event::warn("run-archive")
+                .err(e)
+                .log("the run could not be archived");
+            return;
+        }
+        None => return,
+    };
+
+    let now = Utc::now();
+    let run = run_id();
+    let object = format!("{}/{}/{run}.json", archive.prefix, now.format("%Y/%m/%d"))
+        .trim_start_matches('/')
+        .to_string();
+    let entry = run
+        .rsplit_once('-')
+        .map_or(run.as_str(), |(entry, _)| entry);
+    let document = json!({
+        "run": run,
+        "entry": entry,
+        "archived_at": now.to_rfc3339(),
+        "records": records,
+    });
+    if let Err(e) = s3_request(&archive, Method::PUT, &object, Some(&document.to_string())) {
+        Event::warn("run-archive")
+            .err(e)
+            .log("the run could not be archived");
+        return;
+    }
+
+    let mut objects =
+        store::load::<Vec<(String, DateTime<Utc>)>>(RUN_ARCHIVE_KEY).unwrap_or_default();
+    objects.push((object, now));
+    let cutoff = now - Duration::days(archive.retention_days);
+    objects.retain(|(object, archived_at)| {
+        if *archived_at >= cutoff {
+            return true;
+        }
+        // an object that could not be deleted is tried again on the next run
+        match s3_request(&archive, Method::DELETE, object, None) {
+            Ok(()) => false,
+            Err(e) => {
+                Event::warn("run-archive")
+                    .err(e)
+                    .log(&format!("{object} could not be deleted"));
+                true
+            }
+        }
+    });
+    store::save(RUN_ARCHIVE_KEY, &objects);
+}
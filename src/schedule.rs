@@ -0,0 +1,1159 @@
+//! The hourly schedule tick and the recurring jobs it runs.
+
+use crate::audit::audit;
+use crate::command::{enter_run_queue, leave_run_queue, resume_run, summarize};
+use crate::config::{
+    channel_timezone, config_file, env_repos, feature_enabled, home_channel, issue_limit,
+    load_slack_users, milestone_countdown_days, resolved_settings, stale_pr_days, stale_pr_hour,
+    subscription_hour, Feature, IssueRoute,
+};
+use crate::deps::Deps;
+use crate::github::{
+    awaiting_review_since, github_request, has_maintainer_reply, resolution_signals,
+    routed_issue_summary,
+};
+use crate::logging::{run_id, Event};
+use crate::render::{format_minutes, mention};
+use crate::sink::{send_message, MessageBatch, Sinks};
+use crate::summarizer::{assess_close, get_milestone_report, get_one_line_summary, get_pr_summary};
+use crate::{store, Result};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Timelike, Utc, Weekday};
+use chrono_tz::Tz;
+use github_flows::octocrab::models::issues::Issue;
+use github_flows::{get_octo, octocrab::params, GithubLogin::Default};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub(crate) const SLA_LOOKBACK_DAYS: i64 = 30;
+
+pub(crate) const SLA_ALERTS_PER_TICK: usize = 5;
+
+pub(crate) const LEADERBOARD_SIZE: usize = 5;
+
+#[no_mangle]
+#[tokio::main(flavor = "current_thread")]
+pub(crate) async fn schedule_tick(workspace: &str, channel: &str) -> Result<()> {
+    resume_expired_snoozes(workspace);
+    // A digest that was killed by a timeout or a redeploy is finished first.
+    resume_run(&Deps::live(), workspace, channel).await?;
+
+    let tz = channel_timezone(workspace, channel);
+    let slot = ScheduleSlot::current(tz);
+    let mut schedules = load_schedules(workspace, channel);
+    let mut subscriptions = load_subscriptions(workspace, channel);
+
+    // (owner, repo, days, description) of every digest due in this slot
+    let mut due = Vec::new();
+    for schedule in schedules.iter_mut() {
+        if schedule.is_due(&slot) && schedule.last_run != slot.key {
+            schedule.last_run = slot.key.clone();
+            due.push((
+                schedule.owner.clone(),
+                schedule.repo.clone(),
+                schedule.days,
+                schedule.describe(tz),
+            ));
+        }
+    }
+    for (i, schedule) in file_schedules(workspace, channel).iter().enumerate() {
+        let key = format!("file_schedule:{workspace}/{channel}:{i}");
+        if schedule.is_due(&slot) && claim_slot(&key, &slot) {
+            due.push((
+                schedule.owner.clone(),
+                schedule.repo.clone(),
+                schedule.days,
+                schedule.describe(tz),
+            ));
+        }
+    }
+    for subscription in subscriptions.iter_mut() {
+        if subscription.is_due(&slot) && subscription.last_run != slot.key {
+            subscription.last_run = slot.key.clone();
+            due.push((
+                subscription.owner.clone(),
+                subscription.repo.clone(),
+                subscription.frequency.days(),
+                subscription.describe(tz),
+            ));
+        }
+    }
+    // Slots that pass while the digests are switched off are not caught up
+    // on once they are switched on again.
+    if !feature_enabled(Feature::ScheduledDigests) {
+        due.clear();
+    }
+    if !due.is_empty() {
+        save_schedules(workspace, channel, &schedules);
+        save_subscriptions(workspace, channel, &subscriptions);
+    }
+
+    for (owner, repo, days, description) in due {
+        if is_snoozed(workspace, &owner, &repo) {
+            continue;
+        }
+        let run = run_id();
+        if !enter_run_queue(workspace, channel, &run, false).await {
+            send_message(
+                workspace,
+                channel,
+                format!("The scheduled digest for {owner}/{repo} was skipped, the runs before it took too long."),
+            );
+            continue;
+        }
+        send_message(
+            workspace,
+            channel,
+            format!("Scheduled digest for {owner}/{repo} (last {days} days):"),
+        );
+        let summarized = summarize(
+            &Deps::live(),
+            &run,
+            workspace,
+            channel,
+            &owner,
+            &repo,
+            days,
+            &[],
+            &[],
+            None,
+            issue_limit(None, &resolved_settings(workspace, channel)),
+            &description,
+        )
+        .await;
+        leave_run_queue(workspace, channel, &run);
+        let outcome = match &summarized {
+            Ok(posted) => format!("posted {posted} summaries"),
+            Err(e) => format!("failed: {e}"),
+        };
+        audit(
+            "schedule",
+            "scheduled digest",
+            Some((&owner, &repo)),
+            &outcome,
+            true,
+        );
+        // a failed digest does not hold up the other digests and jobs of the tick
+        if let Err(e) = summarized {
+            Event::error("schedule")
+                .err(&e)
+                .log(&format!("the scheduled digest for {owner}/{repo} failed"));
+            send_message(
+                workspace,
+                channel,
+                format!(":warning: The scheduled digest for {owner}/{repo} failed: {e}"),
+            );
+        }
+    }
+
+    check_slas(workspace, channel).await;
+
+    if slot.hours.contains(&stale_pr_hour()) {
+        nudge_stale_prs(workspace, channel, &slot).await;
+    }
+
+    if slot.hours.contains(&subscription_hour()) {
+        milestone_countdowns(workspace, channel, tz, &slot).await;
+        triage_rotations(workspace, channel, tz, &slot).await;
+
+        if slot.weekday == Weekday::Mon {
+            hottest_issues(workspace, channel, &slot).await;
+            close_candidates(workspace, channel, &slot).await;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Schedule {
+    /// `None` runs the digest every day.
+    pub(crate) weekday: Option<Weekday>,
+    pub(crate) hour: u32,
+    pub(crate) owner: String,
+    pub(crate) repo: String,
+    pub(crate) days: i64,
+    #[serde(default)]
+    pub(crate) last_run: String,
+}
+
+impl Schedule {
+    /// Parses `weekly <weekday> <HH:MM> <owner>/<repo> [n]` or
+    /// `daily <HH:MM> <owner>/<repo> [n]`.
+    pub(crate) fn parse(args: &str) -> Result<Schedule, String> {
+        let mut parts = args.split_whitespace();
+        let weekday = match parts.next() {
+            Some("daily") => None,
+            Some("weekly") => Some(
+                parts
+                    .next()
+                    .and_then(|d| d.parse::<Weekday>().ok())
+                    .ok_or("a weekly schedule needs a weekday such as `mon`")?,
+            ),
+            _ => return Err("the frequency must be `daily` or `weekly`".to_string()),
+        };
+        let (hour, minute) = parts
+            .next()
+            .and_then(|t| t.split_once(':'))
+            .and_then(|(h, m)| Some((h.parse::<u32>().ok()?, m.parse::<u32>().ok()?)))
+            .filter(|(h, m)| *h < 24 && *m < 60)
+            .ok_or("the time must be given as HH:MM in the channel's timezone")?;
+        if minute != 0 {
+            return Err("digests run at the top of the hour, please use HH:00".to_string());
+        }
+        let (owner, repo) = parts
+            .next()
+            .and_then(|s| s.split_once('/'))
+            .filter(|(o, r)| !o.is_empty() && !r.is_empty())
+            .ok_or("the repository must be given as <github_owner>/<github_repo>")?;
+        let days = match parts.next() {
+            Some(n) => n
+                .parse::<i64>()
+                .ok()
+                .filter(|n| *n > 0)
+                .ok_or("the number of days must be a positive number")?,
+            None => 7,
+        };
+
+        Ok(Schedule {
+            weekday,
+            hour,
+            owner: owner.to_string(),
+            repo: repo.to_string(),
+            days,
+            last_run: "".to_string(),
+        })
+    }
+
+    pub(crate) fn is_due(&self, slot: &ScheduleSlot) -> bool {
+        slot.hours.contains(&self.hour) && self.weekday.is_none_or(|d| d == slot.weekday)
+    }
+
+    pub(crate) fn describe(&self, tz: Tz) -> String {
+        let when = match self.weekday {
+            Some(d) => format!("weekly on {d}"),
+            None => "daily".to_string(),
+        };
+        format!(
+            "{when} at {:02}:00 {tz}: {}/{} {}",
+            self.hour, self.owner, self.repo, self.days
+        )
+    }
+}
+
+pub(crate) fn schedules_key(workspace: &str, channel: &str) -> String {
+    format!("schedules:{workspace}/{channel}")
+}
+
+pub(crate) fn load_schedules(workspace: &str, channel: &str) -> Vec<Schedule> {
+    store::load(&schedules_key(workspace, channel)).unwrap_or_default()
+}
+
+pub(crate) fn save_schedules(workspace: &str, channel: &str, schedules: &[Schedule]) {
+    store::save(&schedules_key(workspace, channel), schedules);
+}
+
+/// The schedules of the configuration file for the channel. Entries are for
+/// the channel the flow listens to, unless they start with another served
+/// `<slack_workspace>/<slack_channel>`. They are not listed or removed by the
+/// schedule command, and entries that don't parse are logged and left out.
+pub(crate) fn file_schedules(workspace: &str, channel: &str) -> Vec<Schedule> {
+    let listened = home_channel();
+    config_file()
+        .schedules
+        .iter()
+        .filter_map(|entry| {
+            let (target, args) = match entry.trim().split_once(' ') {
+                Some((target, args)) if target.contains('/') => {
+                    let (ws, ch) = target.split_once('/')?;
+                    ((ws, ch.trim_start_matches('#')), args)
+                }
+                _ => ((listened.0.as_str(), listened.1.as_str()), entry.as_str()),
+            };
+            (target == (workspace, channel)).then_some(args)
+        })
+        .filter_map(|args| match Schedule::parse(args) {
+            Ok(schedule) => Some(schedule),
+            Err(e) => {
+                Event::warn("config")
+                    .err(e)
+                    .log(&format!("schedule `{args}` left out"));
+                None
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Frequency {
+    Daily,
+    Weekly,
+}
+
+impl Frequency {
+    pub(crate) fn parse(s: &str) -> Option<Frequency> {
+        match s {
+            "daily" => Some(Frequency::Daily),
+            "weekly" => Some(Frequency::Weekly),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn days(&self) -> i64 {
+        match self {
+            Frequency::Daily => 1,
+            Frequency::Weekly => 7,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Subscription {
+    pub(crate) owner: String,
+    pub(crate) repo: String,
+    pub(crate) frequency: Frequency,
+    #[serde(default)]
+    pub(crate) last_run: String,
+}
+
+impl Subscription {
+    pub(crate) fn is_due(&self, slot: &ScheduleSlot) -> bool {
+        slot.hours.contains(&subscription_hour())
+            && (self.frequency == Frequency::Daily || slot.weekday == Weekday::Mon)
+    }
+
+    pub(crate) fn describe(&self, tz: Tz) -> String {
+        let when = match self.frequency {
+            Frequency::Daily => "daily",
+            Frequency::Weekly => "weekly on Mon",
+        };
+        format!(
+            "{}/{} {when} at {:02}:00 {tz}",
+            self.owner,
+            self.repo,
+            subscription_hour()
+        )
+    }
+}
+
+pub(crate) fn subscriptions_key(workspace: &str, channel: &str) -> String {
+    format!("subscriptions:{workspace}/{channel}")
+}
+
+pub(crate) fn load_subscriptions(workspace: &str, channel: &str) -> Vec<Subscription> {
+    store::load(&subscriptions_key(workspace, channel)).unwrap_or_default()
+}
+
+pub(crate) fn save_subscriptions(workspace: &str, channel: &str, subscriptions: &[Subscription]) {
+    store::save(&subscriptions_key(workspace, channel), subscriptions);
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Snooze {
+    pub(crate) owner: String,
+    pub(crate) repo: String,
+    pub(crate) until: DateTime<Utc>,
+    /// The channel the snooze was requested in, which is told when it ends.
+    pub(crate) channel: String,
+}
+
+impl Snooze {
+    pub(crate) fn matches(&self, owner: &str, repo: &str) -> bool {
+        self.owner.eq_ignore_ascii_case(owner) && self.repo.eq_ignore_ascii_case(repo)
+    }
+}
+
+pub(crate) fn snoozes_key(workspace: &str) -> String {
+    format!("snoozes:{workspace}")
+}
+
+pub(crate) fn load_snoozes(workspace: &str) -> Vec<Snooze> {
+    store::load(&snoozes_key(workspace)).unwrap_or_default()
+}
+
+pub(crate) fn save_snoozes(workspace: &str, snoozes: &[Snooze]) {
+    store::save(&snoozes_key(workspace), snoozes);
+}
+
+pub(crate) fn is_snoozed(workspace: &str, owner: &str, repo: &str) -> bool {
+    let now = Utc::now();
+    load_snoozes(workspace)
+        .iter()
+        .any(|s| s.matches(owner, repo) && s.until > now)
+}
+
+pub(crate) fn resume_expired_snoozes(workspace: &str) {
+    let now = Utc::now();
+    let (expired, active): (Vec<Snooze>, Vec<Snooze>) = load_snoozes(workspace)
+        .into_iter()
+        .partition(|s| s.until <= now);
+    if expired.is_empty() {
+        return;
+    }
+    save_snoozes(workspace, &active);
+
+    for snooze in expired {
+        send_message(
+            workspace,
+            &snooze.channel,
+            format!(
+                "The snooze of {}/{} has ended, its digests and alerts are posted again.",
+                snooze.owner, snooze.repo
+            ),
+        );
+    }
+}
+
+/// Records that the job stored under `key` runs in this slot, returning false
+/// when it already ran in it.
+pub(crate) fn claim_slot(key: &str, slot: &ScheduleSlot) -> bool {
+    if store::load::<String>(key).as_ref() == Some(&slot.key) {
+        return false;
+    }
+    store::save(key, &slot.key);
+    true
+}
+
+/// The local hours an hourly schedule tick covers in a channel's timezone.
+pub(crate) struct ScheduleSlot {
+    pub(crate) weekday: Weekday,
+    /// Usually just the current hour. When a DST change skipped local hours
+    /// since the previous tick, they are included so their schedules still
+    /// run; a repeated hour covers nothing so schedules don't run twice.
+    pub(crate) hours: Vec<u32>,
+    /// Identifies the slot to avoid running a schedule twice in it.
+    pub(crate) key: String,
+}
+
+impl ScheduleSlot {
+    pub(crate) fn current(tz: Tz) -> ScheduleSlot {
+        let now = Utc::now();
+        let local = now.with_timezone(&tz);
+        let previous = (now - Duration::hours(1)).with_timezone(&tz);
+
+        let mut hours = Vec::new();
+        let mut hour = previous.hour();
+        while hour != local.hour() {
+            hour = (hour + 1) % 24;
+            hours.push(hour);
+        }
+
+        ScheduleSlot {
+            weekday: local.weekday(),
+            hours,
+            key: local.format("%Y-%m-%dT%H%z").to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Sla {
+    pub(crate) owner: String,
+    pub(crate) repo: String,
+    /// The label the SLA applies to, `*` for every issue.
+    pub(crate) label: String,
+    /// The time within which a maintainer is expected to reply, in minutes.
+    pub(crate) within: i64,
+    /// Issues already alerted, so a breach is only reported once.
+    #[serde(default)]
+    pub(crate) alerted: Vec<u64>,
+}
+
+impl Sla {
+    pub(crate) fn matches(&self, owner: &str, repo: &str, label: &str) -> bool {
+        self.owner.eq_ignore_ascii_case(owner)
+            && self.repo.eq_ignore_ascii_case(repo)
+            && self.label.eq_ignore_ascii_case(label)
+    }
+
+    pub(crate) fn describe(&self) -> String {
+        let label = match self.label.as_str() {
+            "*" => "every issue".to_string(),
+            label => format!("`{label}` issues"),
+        };
+        format!(
+            "{}/{}: {label} get a first maintainer reply within {}",
+            self.owner,
+            self.repo,
+            format_minutes(self.within)
+        )
+    }
+}
+
+pub(crate) fn slas_key(workspace: &str, channel: &str) -> String {
+    format!("slas:{workspace}/{channel}")
+}
+
+pub(crate) fn load_slas(workspace: &str, channel: &str) -> Vec<Sla> {
+    store::load(&slas_key(workspace, channel)).unwrap_or_default()
+}
+
+pub(crate) fn save_slas(workspace: &str, channel: &str, slas: &[Sla]) {
+    store::save(&slas_key(workspace, channel), slas);
+}
+
+pub(crate) async fn check_slas(workspace: &str, channel: &str) {
+    let mut slas = load_slas(workspace, channel);
+    if slas.is_empty() {
+        return;
+    }
+
+    let octocrab = get_octo(&Default);
+    let now = Utc::now();
+    let mut changed = false;
+
+    let mut batch = MessageBatch::new(&Sinks, workspace, channel);
+    for sla in slas.iter_mut() {
+        if is_snoozed(workspace, &sla.owner, &sla.repo) {
+            continue;
+        }
+
+        let deadline = now - Duration::minutes(sla.within);
+        let oldest = deadline - Duration::days(SLA_LOOKBACK_DAYS);
+        let label_filter = match sla.label.as_str() {
+            "*" => String::new(),
+            label => format!(" label:\"{label}\""),
+        };
+        let query = format!(
+            "repo:{}/{} is:issue is:open{label_filter} created:{}..{}",
+            sla.owner,
+            sla.repo,
+            oldest.format("%Y-%m-%dT%H:%M:%SZ"),
+            deadline.format("%Y-%m-%dT%H:%M:%SZ")
+        );
+        let candidates = match github_request(None, || async {
+            octocrab
+                .search()
+                .issues_and_pull_requests(&query)
+                .per_page(50u8)
+                .send()
+                .await
+        })
+        .await
+        {
+            Ok(page) => page.items,
+            Err(e) => {
+                Event::warn("sla")
+                    .err(&e)
+                    .log(&format!("{}/{} could not be read", sla.owner, sla.repo));
+                continue;
+            }
+        };
+
+        // forget issues that were closed or fell out of the lookback window
+        let candidate_numbers = candidates.iter().map(|i| i.number).collect::<Vec<u64>>();
+        let before = sla.alerted.len();
+        sla.alerted.retain(|n| candidate_numbers.contains(n));
+        changed |= sla.alerted.len() != before;
+
+        let mut breaches = Vec::new();
+        for issue in candidates {
+            if breaches.len() >= SLA_ALERTS_PER_TICK {
+                break;
+            }
+            if sla.alerted.contains(&issue.number) {
+                continue;
+            }
+            if issue.comments > 0 && has_maintainer_reply(&sla.owner, &sla.repo, issue.number).await
+            {
+                continue;
+            }
+            breaches.push(issue);
+        }
+
+        let route = IssueRoute {
+            workspace: workspace.to_string(),
+            owner: sla.owner.clone(),
+            repo: sla.repo.clone(),
+            channel: channel.to_string(),
+        };
+        changed |= alert_breaches(sla, breaches, now, &mut batch, |issue| {
+            routed_issue_summary(&route, issue)
+        })
+        .await;
+    }
+
+    if changed {
+        save_slas(workspace, channel, &slas);
+    }
+}
+
+/// Alerts the breaches of the SLA and records them as alerted. An issue that
+/// could not be summarized is alerted with its title and link, so it is not
+/// alerted again on the next tick. Whether any breach was alerted.
+pub(crate) async fn alert_breaches<F>(
+    sla: &mut Sla,
+    breaches: Vec<Issue>,
+    now: DateTime<Utc>,
+    batch: &mut MessageBatch<'_>,
+    mut summarize: impl FnMut(Issue) -> F,
+) -> bool
+where
+    F: std::future::Future<Output = Result<String>>,
+{
+    let alerted = !breaches.is_empty();
+    for issue in breaches {
+        let issue_number = issue.number;
+        let waiting = (now - issue.created_at).num_hours();
+        let fallback = format!("{}\n{}", issue.title, issue.html_url);
+        let summary = match summarize(issue).await {
+            Ok(summary) => summary,
+            Err(e) => {
+                Event::warn("sla").err(&e).log(&format!(
+                    "{}/{}#{issue_number} could not be summarized",
+                    sla.owner, sla.repo
+                ));
+                fallback
+            }
+        };
+        batch.push(
+            format!(
+                ":alarm_clock: SLA breached: issue #{issue_number} in {}/{} has had no maintainer reply for {waiting}h (SLA: {}).\n{summary}",
+                sla.owner,
+                sla.repo,
+                format_minutes(sla.within)
+            ),
+        );
+        sla.alerted.push(issue_number);
+    }
+    alerted
+}
+
+pub(crate) fn stale_prs_key(workspace: &str, channel: &str) -> String {
+    format!("stale_prs:{workspace}/{channel}")
+}
+
+pub(crate) async fn nudge_stale_prs(workspace: &str, channel: &str, slot: &ScheduleSlot) {
+    if !claim_slot(&stale_prs_key(workspace, channel), slot) {
+        return;
+    }
+
+    let octocrab = get_octo(&Default);
+    let users = load_slack_users(workspace);
+    let now = Utc::now();
+
+    let mut batch = MessageBatch::new(&Sinks, workspace, channel);
+    for (owner, repo) in env_repos("stale_pr_repos") {
+        if is_snoozed(workspace, &owner, &repo) {
+            continue;
+        }
+
+        let pulls = match github_request(None, || async {
+            octocrab
+                .pulls(&owner, &repo)
+                .list()
+                .state(params::State::Open)
+                .per_page(50u8)
+                .send()
+                .await
+        })
+        .await
+        {
+            Ok(page) => page.items,
+            Err(e) => {
+                Event::warn("stale_prs")
+                    .err(&e)
+                    .log(&format!("{owner}/{repo} could not be read"));
+                continue;
+            }
+        };
+
+        for pull in pulls {
+            let reviewers = pull.requested_reviewers.clone().unwrap_or_default();
+            if pull.draft == Some(true) || reviewers.is_empty() {
+                continue;
+            }
+            let Some(since) = awaiting_review_since(&owner, &repo, &pull).await else {
+                continue;
+            };
+            let waiting = (now - since).num_days();
+            if waiting < stale_pr_days() {
+                continue;
+            }
+
+            let summary = get_pr_summary(&pull).await;
+            let mentions = reviewers
+                .iter()
+                .map(|reviewer| mention(&users, &reviewer.login))
+                .collect::<Vec<String>>()
+                .join(", ");
+            batch.push(
+                format!(
+                    ":hourglass: PR #{} in {owner}/{repo} has been waiting {waiting} days for a review: {}\n{summary}\nReviewers: {mentions}\n{}",
+                    pull.number,
+                    pull.title.as_deref().unwrap_or(""),
+                    pull.html_url.as_ref().map(|u| u.to_string()).unwrap_or_default()
+                ),
+            );
+        }
+    }
+}
+
+pub(crate) fn milestones_key(workspace: &str, channel: &str) -> String {
+    format!("milestones:{workspace}/{channel}")
+}
+
+pub(crate) async fn milestone_countdowns(
+    workspace: &str,
+    channel: &str,
+    tz: Tz,
+    slot: &ScheduleSlot,
+) {
+    if !claim_slot(&milestones_key(workspace, channel), slot) {
+        return;
+    }
+
+    let octocrab = get_octo(&Default);
+    let today = Utc::now().with_timezone(&tz).date_naive();
+
+    for subscription in load_subscriptions(workspace, channel) {
+        let (owner, repo) = (&subscription.owner, &subscription.repo);
+        if is_snoozed(workspace, owner, repo) {
+            continue;
+        }
+
+        // octocrab has no milestones API
+        let route = format!("repos/{owner}/{repo}/milestones");
+        let milestones = match github_request(None, || async {
+            octocrab
+                .get::<Vec<serde_json::Value>, _, _>(
+                    &route,
+                    Some(&[("state", "open"), ("sort", "due_on"), ("per_page", "100")]),
+                )
+                .await
+        })
+        .await
+        {
+            Ok(milestones) => milestones,
+            Err(e) => {
+                Event::warn("milestones")
+                    .err(&e)
+                    .log(&format!("{owner}/{repo} could not be read"));
+                continue;
+            }
+        };
+
+        for milestone in milestones {
+            let Some(due_on) = milestone["due_on"]
+                .as_str()
+                .and_then(|d| DateTime::parse_from_rfc3339(d).ok())
+            else {
+                continue;
+            };
+            // GitHub stores the due date at a fixed time of the day in UTC
+            let days_left = (due_on.date_naive() - today).num_days();
+            if !(0..=milestone_countdown_days()).contains(&days_left) {
+                continue;
+            }
+
+            let report = get_milestone_report(owner, repo, &milestone, days_left).await;
+            send_message(workspace, channel, report);
+        }
+    }
+}
+
+pub(crate) fn leaderboard_key(workspace: &str, channel: &str) -> String {
+    format!("leaderboard:{workspace}/{channel}")
+}
+
+pub(crate) fn reactions_key(owner: &str, repo: &str) -> String {
+    format!("reactions:{owner}/{repo}")
+}
+
+pub(crate) struct HotIssue {
+    pub(crate) number: u64,
+    pub(crate) title: String,
+    pub(crate) body: String,
+    pub(crate) html_url: String,
+    pub(crate) comments: usize,
+    pub(crate) reaction_growth: u64,
+    pub(crate) participants: usize,
+}
+
+impl HotIssue {
+    pub(crate) fn score(&self) -> u64 {
+        self.comments as u64 + 2 * self.reaction_growth + 3 * self.participants as u64
+    }
+}
+
+pub(crate) async fn hottest_issues(workspace: &str, channel: &str, slot: &ScheduleSlot) {
+    if !claim_slot(&leaderboard_key(workspace, channel), slot) {
+        return;
+    }
+
+    let octocrab = get_octo(&Default);
+    let week_ago = Utc::now() - Duration::days(7);
+
+    for (owner, repo) in env_repos("leaderboard_repos") {
+        if is_snoozed(workspace, &owner, &repo) {
+            continue;
+        }
+
+        // octocrab's issue model has no reaction counts
+        let query = format!(
+            "repo:{owner}/{repo} is:issue updated:>{}",
+            week_ago.format("%Y-%m-%dT%H:%M:%SZ")
+        );
+        let items = match github_request(None, || async {
+            octocrab
+                .get::<serde_json::Value, _, _>(
+                    "search/issues",
+                    Some(&[
+                        ("q", query.as_str()),
+                        ("sort", "comments"),
+                        ("per_page", "30"),
+                    ]),
+                )
+                .await
+        })
+        .await
+        {
+            Ok(result) => result["items"].as_array().cloned().unwrap_or_default(),
+            Err(e) => {
+                Event::warn("leaderboard")
+                    .err(&e)
+                    .log(&format!("{owner}/{repo} could not be read"));
+                continue;
+            }
+        };
+
+        let previous_reactions =
+            store::load::<BTreeMap<u64, u64>>(&reactions_key(&owner, &repo)).unwrap_or_default();
+        let mut reactions = BTreeMap::new();
+
+        let mut hot = Vec::new();
+        for item in items {
+            let Some(number) = item["number"].as_u64() else {
+                continue;
+            };
+            let total_reactions = item["reactions"]["total_count"].as_u64().unwrap_or(0);
+            reactions.insert(number, total_reactions);
+
+            let comments = match github_request(None, || async {
+                octocrab
+                    .issues(&owner, &repo)
+                    .list_comments(number)
+                    .since(week_ago)
+                    .per_page(100u8)
+                    .send()
+                    .await
+            })
+            .await
+            {
+                Ok(page) => page.items,
+                Err(e) => {
+                    Event::warn("leaderboard")
+                        .issue(number)
+                        .err(&e)
+                        .log("the comments could not be read");
+                    Vec::new()
+                }
+            };
+            let mut participants = comments
+                .iter()
+                .map(|c| c.user.login.to_lowercase())
+                .collect::<Vec<String>>();
+            participants.sort();
+            participants.dedup();
+
+            hot.push(HotIssue {
+                number,
+                title: item["title"].as_str().unwrap_or("").to_string(),
+                body: item["body"].as_str().unwrap_or("").to_string(),
+                html_url: item["html_url"].as_str().unwrap_or("").to_string(),
+                comments: comments.len(),
+                reaction_growth: total_reactions
+                    .saturating_sub(previous_reactions.get(&number).copied().unwrap_or(0)),
+                participants: participants.len(),
+            });
+        }
+
+        store::save(&reactions_key(&owner, &repo), &reactions);
+
+        hot.sort_by_key(|issue| std::cmp::Reverse(issue.score()));
+        hot.truncate(LEADERBOARD_SIZE);
+        if hot.first().is_none_or(|issue| issue.score() == 0) {
+            continue;
+        }
+
+        let mut leaderboard = vec![format!(
+            ":trophy: Hottest issues of the week in {owner}/{repo}:"
+        )];
+        for (rank, issue) in hot.iter().enumerate() {
+            let summary = get_one_line_summary(issue.number, &issue.title, &issue.body).await;
+            leaderboard.push(format!(
+                "{}. #{} {} ({} comments, +{} reactions, {} participants)\n{summary}\n{}",
+                rank + 1,
+                issue.number,
+                issue.title,
+                issue.comments,
+                issue.reaction_growth,
+                issue.participants,
+                issue.html_url
+            ));
+        }
+        send_message(workspace, channel, leaderboard.join("\n"));
+    }
+}
+
+pub(crate) fn close_candidates_key(workspace: &str, channel: &str) -> String {
+    format!("close_candidates:{workspace}/{channel}")
+}
+
+pub(crate) async fn close_candidates(workspace: &str, channel: &str, slot: &ScheduleSlot) {
+    if !claim_slot(&close_candidates_key(workspace, channel), slot) {
+        return;
+    }
+
+    let octocrab = get_octo(&Default);
+
+    for (owner, repo) in env_repos("close_candidate_repos") {
+        if is_snoozed(workspace, &owner, &repo) {
+            continue;
+        }
+
+        let issues = match github_request(None, || async {
+            octocrab
+                .search()
+                .issues_and_pull_requests(&format!(
+                    "repo:{owner}/{repo} is:issue is:open comments:>0"
+                ))
+                .sort("updated")
+                .order("asc")
+                .per_page(30u8)
+                .send()
+                .await
+        })
+        .await
+        {
+            Ok(page) => page.items,
+            Err(e) => {
+                Event::warn("close_candidates")
+                    .err(&e)
+                    .log(&format!("{owner}/{repo} could not be read"));
+                continue;
+            }
+        };
+
+        let mut candidates = Vec::new();
+        for issue in issues {
+            let (signals, last_comment) = resolution_signals(&owner, &repo, &issue).await;
+            if signals.is_empty() {
+                continue;
+            }
+            if let Some(assessment) = assess_close(&issue, &signals, &last_comment).await {
+                if assessment.close {
+                    candidates.push(format!(
+                        "{}. #{} {}\n{} ({})\nConfirm with `flows close {owner}/{repo}#{}`\n{}",
+                        candidates.len() + 1,
+                        issue.number,
+                        issue.title,
+                        assessment.reason,
+                        signals.join(", "),
+                        issue.number,
+                        issue.html_url
+                    ));
+                }
+            }
+        }
+
+        if !candidates.is_empty() {
+            send_message(
+                workspace,
+                channel,
+                format!(
+                    ":broom: Issues in {owner}/{repo} that look resolved and could be closed:\n{}",
+                    candidates.join("\n")
+                ),
+            );
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Rotation {
+    pub(crate) owner: String,
+    pub(crate) repo: String,
+    /// GitHub logins, taking turns in this order.
+    pub(crate) members: Vec<String>,
+    /// Whether the day's issues are also assigned to the person on duty on GitHub.
+    pub(crate) assign: bool,
+}
+
+impl Rotation {
+    pub(crate) fn matches(&self, owner: &str, repo: &str) -> bool {
+        self.owner.eq_ignore_ascii_case(owner) && self.repo.eq_ignore_ascii_case(repo)
+    }
+
+    pub(crate) fn on_duty(&self, today: NaiveDate) -> Option<&String> {
+        let day = today.num_days_from_ce() as usize;
+        self.members.get(day % self.members.len().max(1))
+    }
+}
+
+pub(crate) fn rotations_key(workspace: &str, channel: &str) -> String {
+    format!("rotations:{workspace}/{channel}")
+}
+
+pub(crate) fn triage_key(workspace: &str, channel: &str) -> String {
+    format!("triage:{workspace}/{channel}")
+}
+
+pub(crate) fn load_rotations(workspace: &str, channel: &str) -> Vec<Rotation> {
+    store::load(&rotations_key(workspace, channel)).unwrap_or_default()
+}
+
+pub(crate) fn save_rotations(workspace: &str, channel: &str, rotations: &[Rotation]) {
+    store::save(&rotations_key(workspace, channel), rotations);
+}
+
+pub(crate) async fn triage_rotations(workspace: &str, channel: &str, tz: Tz, slot: &ScheduleSlot) {
+    let rotations = load_rotations(workspace, channel);
+    if rotations.is_empty() || !claim_slot(&triage_key(workspace, channel), slot) {
+        return;
+    }
+
+    let octocrab = get_octo(&Default);
+    let users = load_slack_users(workspace);
+    let today = Utc::now().with_timezone(&tz).date_naive();
+    let day_ago = (Utc::now() - Duration::days(1)).format("%Y-%m-%dT%H:%M:%SZ");
+
+    for rotation in rotations {
+        let (owner, repo) = (&rotation.owner, &rotation.repo);
+        let Some(login) = rotation.on_duty(today) else {
+            continue;
+        };
+        if is_snoozed(workspace, owner, repo) {
+            continue;
+        }
+
+        let issues = match github_request(None, || async {
+            octocrab
+                .search()
+                .issues_and_pull_requests(&format!(
+                    "repo:{owner}/{repo} is:issue is:open no:label created:>{day_ago}"
+                ))
+                .per_page(20u8)
+                .send()
+                .await
+        })
+        .await
+        {
+            Ok(page) => page.items,
+            Err(e) => {
+                Event::warn("triage")
+                    .err(&e)
+                    .log(&format!("{owner}/{repo} could not be read"));
+                continue;
+            }
+        };
+        if issues.is_empty() {
+            continue;
+        }
+
+        let mut batch = MessageBatch::new(&Sinks, workspace, channel);
+        batch.push(
+            format!(
+                ":clipboard: {} is on triage duty for {owner}/{repo} today, with {} new unlabeled issues:",
+                mention(&users, login),
+                issues.len()
+            ),
+        );
+
+        let route = IssueRoute {
+            workspace: workspace.to_string(),
+            owner: owner.clone(),
+            repo: repo.clone(),
+            channel: channel.to_string(),
+        };
+        for issue in issues {
+            let issue_number = issue.number;
+            if rotation.assign {
+                let assigned = github_request(None, || async {
+                    octocrab
+                        .issues(owner, repo)
+                        .add_assignees(issue_number, &[login.as_str()])
+                        .await
+                })
+                .await;
+                let outcome = match &assigned {
+                    Ok(_) => "assigned".to_string(),
+                    Err(e) => format!("failed: {e}"),
+                };
+                audit(
+                    "flow",
+                    &format!("assigned #{issue_number} to {login}"),
+                    Some((owner, repo)),
+                    &outcome,
+                    false,
+                );
+                if let Err(e) = assigned {
+                    Event::warn("triage").err(&e).log(&format!(
+                        "{owner}/{repo}#{issue_number} could not be assigned to {login}"
+                    ));
+                    batch.push(format!(
+                        ":warning: #{issue_number} could not be assigned to {login} on GitHub: {e}"
+                    ));
+                }
+            }
+            let fallback = format!("{}\n{}", issue.title, issue.html_url);
+            let summary = match routed_issue_summary(&route, issue).await {
+                Ok(summary) => summary,
+                Err(e) => {
+                    Event::warn("triage").err(&e).log(&format!(
+                        "{owner}/{repo}#{issue_number} could not be summarized"
+                    ));
+                    fallback
+                }
+            };
+            batch.push(summary);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fakes::{FailingSummarizer, FixtureIssues, RecordingSink, SMALL_ISSUE};
+    use crate::summarizer::Summarizer;
+
+    #[test]
+    fn breaches_that_could_not_be_summarized_are_alerted_once() {
+        let fixture = FixtureIssues::load(SMALL_ISSUE);
+        let issue = fixture.issue.clone();
+        let mut ctx = Some(fixture.context());
+        let now = issue.created_at + Duration::hours(50);
+        let mut sla = Sla {
+            owner: "octocat".to_string(),
+            repo: "Hello-World".to_string(),
+            label: "*".to_string(),
+            within: 24 * 60,
+            alerted: Vec::new(),
+        };
+        let sink = RecordingSink::default();
+        let mut batch = MessageBatch::new(&sink, "octo-team", "issues");
+        let alerted = futures::executor::block_on(alert_breaches(
+            &mut sla,
+            vec![issue.clone()],
+            now,
+            &mut batch,
+            |_| {
+                let ctx = ctx.take().expect("the breach is summarized once");
+                async { Ok(FailingSummarizer.summarize(ctx).await?.text) }
+            },
+        ));
+        batch.flush();
+
+        assert!(alerted);
+        assert_eq!(sla.alerted, [issue.number]);
+        assert_eq!(
+            sink.texts("issues"),
+            [format!(
+                ":alarm_clock: SLA breached: issue #{} in octocat/Hello-World has had no maintainer reply for 50h (SLA: {}).\n{}\n{}",
+                issue.number,
+                format_minutes(24 * 60),
+                issue.title,
+                issue.html_url
+            )]
+        );
+    }
+}
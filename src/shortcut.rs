@@ -0,0 +1,192 @@
+//! The message shortcut that summarizes the GitHub issue or pull request a
+//! message links to, in the thread of the message.
+
+use crate::app_home::{slack_api, slack_channel};
+use crate::audit::audit;
+use crate::command::{quota_remaining, record_usage};
+use crate::config::{
+    channel_language, home_channel, is_opted_out, may_run_expensive, repo_refusal,
+};
+use crate::deps::Deps;
+use crate::github::{github_request, is_rate_limited};
+use crate::http::request_json;
+use crate::logging::Event;
+use crate::summarizer::{cached_summary, classify_issue, moderate};
+use chrono::Utc;
+use github_flows::{get_octo, GithubLogin::Default};
+use http_req::request::Method;
+use regex::Regex;
+use serde_json::{json, Value};
+
+/// The callback id of the message shortcut.
+pub(crate) const SUMMARIZE_LINK_CALLBACK: &str = "summarize_link";
+
+/// The first GitHub issue or pull request the text links to.
+pub(crate) fn linked_issue(text: &str) -> Option<(String, String, u64)> {
+    let link =
+        Regex::new(r"https://github\.com/([\w.-]+)/([\w.-]+)/(?:issues|pull)/(\d+)").unwrap();
+    let cap = link.captures(text)?;
+    Some((cap[1].to_string(), cap[2].to_string(), cap[3].parse().ok()?))
+}
+
+/// Tells the user who used the shortcut, and only them, through the
+/// `response_url` of the shortcut, which works in channels the bot is not
+/// a member of.
+fn tell_user(response_url: &str, text: &str) {
+    let message = json!({ "response_type": "ephemeral", "text": text });
+    if let Err(e) = request_json(Method::POST, response_url, &[], Some(&message)) {
+        Event::warn("shortcut")
+            .err(e)
+            .log("the user could not be told");
+    }
+}
+
+/// Summarizes the issue the message of the shortcut links to and posts the
+/// summary in the thread of the message. Like the commands, it is for the
+/// users of `allowed_users` and counts against their daily quota.
+#[tokio::main(flavor = "current_thread")]
+pub(crate) async fn summarize_link(request: Value) {
+    let user = request["user"]["id"].as_str().unwrap_or("");
+    let response_url = request["response_url"].as_str().unwrap_or("");
+    let channel_id = request["channel"]["id"].as_str().unwrap_or("");
+    let message = &request["message"];
+    let thread = message["thread_ts"]
+        .as_str()
+        .or(message["ts"].as_str())
+        .unwrap_or("");
+
+    let Some((owner, repo, issue_number)) = linked_issue(message["text"].as_str().unwrap_or(""))
+    else {
+        tell_user(
+            response_url,
+            "The message does not link to a GitHub issue or pull request.",
+        );
+        return;
+    };
+    if !may_run_expensive(user) {
+        tell_user(
+            response_url,
+            "Only the users in `allowed_users` can ask for summaries.",
+        );
+        return;
+    }
+    if let Some(refusal) = repo_refusal(&owner, &repo) {
+        tell_user(response_url, &refusal);
+        return;
+    }
+    // the quota is the workspace's, the usage is counted in the channel
+    let (workspace, channel) = request["channel"]["name"]
+        .as_str()
+        .and_then(slack_channel)
+        .unwrap_or_else(|| (home_channel().0, channel_id.to_string()));
+    if quota_remaining(&workspace, user, Utc::now()) == Some(0) {
+        tell_user(
+            response_url,
+            "You have used all summaries of your daily quota, it renews at midnight UTC.",
+        );
+        return;
+    }
+
+    let octocrab = get_octo(&Default);
+    let issue = match github_request(None, || async {
+        octocrab.issues(&owner, &repo).get(issue_number).await
+    })
+    .await
+    {
+        Ok(issue) => issue,
+        Err(error) if is_rate_limited(&error) => {
+            tell_user(
+                response_url,
+                "GitHub's rate limit is used up, please try again later.",
+            );
+            return;
+        }
+        Err(_e) => {
+            tell_user(
+                response_url,
+                &format!("Could not find {owner}/{repo}#{issue_number} on GitHub."),
+            );
+            return;
+        }
+    };
+    if is_opted_out(&issue.user.login) {
+        tell_user(
+            response_url,
+            &format!(
+                "{owner}/{repo}#{issue_number} was opened by {}, who opted out of AI processing.",
+                issue.user.login
+            ),
+        );
+        return;
+    }
+
+    let labels = issue
+        .labels
+        .iter()
+        .map(|lab| lab.name.clone())
+        .collect::<Vec<String>>();
+    let issue_type = classify_issue(
+        issue.number,
+        &issue.title,
+        issue.body.as_deref().unwrap_or(""),
+        &labels,
+    )
+    .await;
+    // the channel's language, when it is one the flow serves
+    let language = channel_language(&workspace, &channel);
+    let url = issue.html_url.to_string();
+    let summarized = cached_summary(
+        &Deps::live(),
+        &owner,
+        &repo,
+        issue,
+        issue_type,
+        language.as_deref(),
+    )
+    .await;
+    let summary = match summarized {
+        Ok(summary) => moderate(issue_number, &url, summary).await,
+        Err(e) => {
+            audit(
+                user,
+                "summarize link",
+                Some((&owner, &repo)),
+                &format!("failed: {e}"),
+                true,
+            );
+            tell_user(
+                response_url,
+                &format!(":warning: {owner}/{repo}#{issue_number} could not be summarized: {e}"),
+            );
+            return;
+        }
+    };
+
+    let reply = json!({ "channel": channel_id, "thread_ts": thread, "text": summary });
+    let outcome = match slack_api("chat.postMessage", &reply) {
+        Ok(_) => {
+            record_usage(&workspace, &channel, user, 1, Utc::now());
+            format!("posted the summary of #{issue_number}")
+        }
+        Err(e) => {
+            let hint = match e.as_str() {
+                "not_in_channel" | "channel_not_found" => {
+                    ", please invite the bot to the channel".to_string()
+                }
+                _ => format!(" ({e})"),
+            };
+            tell_user(
+                response_url,
+                &format!("The summary could not be posted in the thread{hint}."),
+            );
+            format!("failed: {e}")
+        }
+    };
+    audit(
+        user,
+        "summarize link",
+        Some((&owner, &repo)),
+        &outcome,
+        true,
+    );
+}
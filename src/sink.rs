@@ -0,0 +1,521 @@
+//! Destinations that the bot's messages are delivered to.
+
+use crate::config::{
+    discord_bot, google_chat_spaces, is_discord, is_google_chat, is_matrix, is_mattermost,
+    is_slack, is_teams, is_telegram, is_zulip, matrix_homeserver, teams_channels, var,
+    zulip_stream,
+};
+use crate::http::request_form;
+use crate::http::request_json;
+use crate::logging::{run_id, Event};
+use crate::render::{
+    adaptive_card, discord_embed, google_chat_card, markdown, matrix_html, telegram_markdown,
+    TELEGRAM_MESSAGE_LIMIT,
+};
+use crate::store;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use discord_flows::get_client;
+use hmac_sha256::HMAC;
+use http_req::request::Method;
+use serde_json::{json, Value};
+use slack_flows::send_message_to_channel;
+
+/// A destination for the messages of the bot, addressed by the workspace and
+/// channel the message belongs to.
+pub(crate) trait Sink {
+    fn send(&self, workspace: &str, channel: &str, text: &str);
+}
+
+/// Posts messages to the Slack channel.
+pub(crate) struct SlackSink;
+
+impl Sink for SlackSink {
+    fn send(&self, workspace: &str, channel: &str, text: &str) {
+        if is_slack(workspace) {
+            send_message_to_channel(workspace, channel, text.to_string());
+        }
+    }
+}
+
+/// Posts messages to the Discord channel as embeds.
+pub(crate) struct DiscordSink;
+
+impl Sink for DiscordSink {
+    fn send(&self, workspace: &str, channel: &str, text: &str) {
+        if !is_discord(workspace) {
+            return;
+        }
+        let Ok(channel_id) = channel.parse::<u64>() else {
+            Event::warn("discord").log(&format!("{channel} is not a Discord channel id"));
+            return;
+        };
+        // the client sends its requests synchronously, so blocking on them
+        // does not need the runtime of the caller
+        let client = get_client(discord_bot());
+        let message = json!({ "embeds": [discord_embed(text)] });
+        if let Err(e) = futures::executor::block_on(client.send_message(channel_id, &message)) {
+            Event::error("discord")
+                .err(e)
+                .log("the message could not be posted");
+        }
+    }
+}
+
+/// Posts messages to the Teams channel as Adaptive Cards, through the
+/// incoming webhook of the channel.
+pub(crate) struct TeamsSink;
+
+impl Sink for TeamsSink {
+    fn send(&self, workspace: &str, channel: &str, text: &str) {
+        if !is_teams(workspace) {
+            return;
+        }
+        let Some(url) = teams_channels().remove(channel) else {
+            Event::warn("teams").log(&format!("{channel} has no incoming webhook"));
+            return;
+        };
+        post_json("teams", &url, &adaptive_card(text), &[]);
+    }
+}
+
+/// Posts messages to the Google Chat space as cards, through the incoming
+/// webhook of the space.
+pub(crate) struct GoogleChatSink;
+
+impl Sink for GoogleChatSink {
+    fn send(&self, workspace: &str, space: &str, text: &str) {
+        if !is_google_chat(workspace) {
+            return;
+        }
+        let Some(url) = google_chat_spaces().remove(space) else {
+            Event::warn("google-chat").log(&format!("{space} has no incoming webhook"));
+            return;
+        };
+        post_json("google-chat", &url, &google_chat_card(text), &[]);
+    }
+}
+
+/// Zulip's limit for the name of a topic, in characters.
+const ZULIP_TOPIC_LIMIT: usize = 60;
+
+/// Posts messages to the Zulip stream of the `zulip_site`, as the bot of
+/// `zulip_email` and `zulip_api_key`. The messages of a repository go to
+/// its topic of the day, `<owner>/<repo> <YYYY-MM-DD>`, so a day's new
+/// issues and releases are one thread.
+pub(crate) struct ZulipSink;
+
+impl Sink for ZulipSink {
+    fn send(&self, workspace: &str, channel: &str, text: &str) {
+        if !is_zulip(workspace) {
+            return;
+        }
+        let (Some(site), Some(email), Some(key)) =
+            (var("zulip_site"), var("zulip_email"), var("zulip_api_key"))
+        else {
+            Event::warn("zulip").log("zulip_site, zulip_email and zulip_api_key have to be set");
+            return;
+        };
+        let (stream, repo) = zulip_stream(channel);
+        let day = Utc::now().format("%Y-%m-%d");
+        let topic = match repo {
+            Some(repo) => format!("{repo} {day}"),
+            None => format!("summaries {day}"),
+        };
+        let topic = topic.chars().take(ZULIP_TOPIC_LIMIT).collect::<String>();
+        let authorization = format!(
+            "Basic {}",
+            STANDARD.encode(format!("{}:{}", email.trim(), key.trim()))
+        );
+        let url = format!("{}/api/v1/messages", site.trim().trim_end_matches('/'));
+        let content = markdown(text);
+        let fields = [
+            ("type", "stream"),
+            ("to", stream),
+            ("topic", topic.as_str()),
+            ("content", content.as_str()),
+        ];
+        let headers = [("Authorization", authorization.as_str())];
+        if let Err(e) = request_form(Method::POST, &url, &headers, &fields) {
+            Event::error("zulip")
+                .err(e)
+                .log("the message could not be posted");
+        }
+    }
+}
+
+/// Posts messages to the Telegram chat through the Bot API, with the bot's
+/// token read from the `telegram_token` environment variable.
+pub(crate) struct TelegramSink;
+
+impl Sink for TelegramSink {
+    fn send(&self, workspace: &str, chat: &str, text: &str) {
+        if !is_telegram(workspace) {
+            return;
+        }
+        let Some(token) = var("telegram_token") else {
+            Event::warn("telegram").log("telegram_token is not set");
+            return;
+        };
+        let url = format!("https://api.telegram.org/bot{}/sendMessage", token.trim());
+        // escaping can push a long message over Telegram's limit, which is
+        // then sent cut as plain text rather than as broken MarkdownV2
+        let rendered = telegram_markdown(text);
+        let message = match rendered.chars().count() <= TELEGRAM_MESSAGE_LIMIT {
+            true => json!({
+                "chat_id": chat,
+                "text": rendered,
+                "parse_mode": "MarkdownV2",
+                "disable_web_page_preview": true,
+            }),
+            false => json!({
+                "chat_id": chat,
+                "text": text.chars().take(TELEGRAM_MESSAGE_LIMIT).collect::<String>(),
+                "disable_web_page_preview": true,
+            }),
+        };
+        post_json("telegram", &url, &message, &[]);
+    }
+}
+
+/// Posts messages to the Matrix room through the Client-Server API of the
+/// `matrix_homeserver`, as the bot of `matrix_access_token`. Messages are
+/// sent as notices, which clients and other bots don't answer, with an HTML
+/// body next to the plain one.
+pub(crate) struct MatrixSink;
+
+impl Sink for MatrixSink {
+    fn send(&self, workspace: &str, room: &str, text: &str) {
+        if !is_matrix(workspace) {
+            return;
+        }
+        let (Some(homeserver), Some(token)) = (matrix_homeserver(), var("matrix_access_token"))
+        else {
+            Event::warn("matrix").log("matrix_homeserver and matrix_access_token have to be set");
+            return;
+        };
+        // the homeserver sends a message once per transaction id
+        let nanos = Utc::now().timestamp_nanos_opt().unwrap_or_default();
+        let txn = format!("{}-{nanos}", run_id());
+        let url = format!(
+            "{homeserver}/_matrix/client/v3/rooms/{}/send/m.room.message/{}",
+            form_urlencoded::byte_serialize(room.as_bytes()).collect::<String>(),
+            form_urlencoded::byte_serialize(txn.as_bytes()).collect::<String>()
+        );
+        let message = json!({
+            "msgtype": "m.notice",
+            "body": markdown(text),
+            "format": "org.matrix.custom.html",
+            "formatted_body": matrix_html(text),
+        });
+        let authorization = format!("Bearer {}", token.trim());
+        if let Err(e) = request_json(
+            Method::PUT,
+            &url,
+            &[("Authorization", authorization.as_str())],
+            Some(&message),
+        ) {
+            Event::error("matrix")
+                .err(e)
+                .log("the message could not be posted");
+        }
+    }
+}
+
+/// Posts messages to the Mattermost channel through the incoming webhook
+/// of the `mattermost_webhook` environment variable, which has to allow
+/// overriding its channel to serve more than one.
+pub(crate) struct MattermostSink;
+
+impl Sink for MattermostSink {
+    fn send(&self, workspace: &str, channel: &str, text: &str) {
+        if !is_mattermost(workspace) {
+            return;
+        }
+        let Some(url) = var("mattermost_webhook") else {
+            Event::warn("mattermost").log("mattermost_webhook is not set");
+            return;
+        };
+        let message = json!({ "channel": channel, "text": markdown(text) });
+        post_json("mattermost", url.trim(), &message, &[]);
+    }
+}
+
+/// Posts every message, of every channel, as JSON to the HTTPS endpoint of
+/// the `outbound_webhook` environment variable. With
+/// `outbound_webhook_secret` set, the body is signed with HMAC-SHA256 in
+/// the `X-Summarizer-Signature: sha256=<hex>` header.
+pub(crate) struct WebhookSink;
+
+impl Sink for WebhookSink {
+    fn send(&self, workspace: &str, channel: &str, text: &str) {
+        let Some(url) = var("outbound_webhook").filter(|u| !u.trim().is_empty()) else {
+            return;
+        };
+        if !url.trim().starts_with("https://") {
+            Event::warn("webhook").log("outbound_webhook is not an HTTPS URL");
+            return;
+        }
+        let message = json!({
+            "workspace": workspace,
+            "channel": channel,
+            "text": text,
+            "run": run_id(),
+            "sent_at": Utc::now().to_rfc3339(),
+        });
+        let signature = var("outbound_webhook_secret")
+            .filter(|s| !s.is_empty())
+            .map(|secret| {
+                let mac = HMAC::mac(message.to_string().as_bytes(), secret.as_bytes());
+                let hex = mac.iter().map(|b| format!("{b:02x}")).collect::<String>();
+                format!("sha256={hex}")
+            });
+        let headers = match &signature {
+            Some(signature) => vec![("X-Summarizer-Signature", signature.as_str())],
+            None => vec![],
+        };
+        post_json("webhook", url.trim(), &message, &headers);
+    }
+}
+
+/// Posts the JSON body to the URL, logging the failures under the stage.
+fn post_json(stage: &'static str, url: &str, body: &Value, headers: &[(&str, &str)]) {
+    if let Err(e) = request_json(Method::POST, url, headers, Some(body)) {
+        Event::error(stage)
+            .err(e)
+            .log("the message could not be posted");
+    }
+}
+
+/// The sinks every message is delivered to. Each one only delivers the
+/// messages of its own platform's channels.
+pub(crate) fn sinks() -> Vec<Box<dyn Sink>> {
+    vec![
+        Box::new(SlackSink),
+        Box::new(DiscordSink),
+        Box::new(TeamsSink),
+        Box::new(GoogleChatSink),
+        Box::new(ZulipSink),
+        Box::new(TelegramSink),
+        Box::new(MatrixSink),
+        Box::new(MattermostSink),
+        Box::new(WebhookSink),
+    ]
+}
+
+/// Delivers messages to every sink, like `send_message`.
+pub(crate) struct Sinks;
+
+impl Sink for Sinks {
+    fn send(&self, workspace: &str, channel: &str, text: &str) {
+        send_message(workspace, channel, text.to_string());
+    }
+}
+
+/// Delivers a message of the channel to every sink.
+pub(crate) fn send_message(workspace: &str, channel: &str, text: String) {
+    for sink in sinks() {
+        sink.send(workspace, channel, &text);
+    }
+}
+
+/// How long a delivered summary is remembered, well beyond the time within
+/// which the platform retries an invocation.
+pub(crate) const DELIVERY_MARKER_SECS: i64 = 24 * 60 * 60;
+
+fn delivery_key(run: &str, issue_number: u64) -> String {
+    format!("delivered:{run}:{issue_number}")
+}
+
+/// Whether the run delivered the summary of the issue, so an invocation that
+/// is retried does not post it twice.
+pub(crate) fn is_delivered(run: &str, issue_number: u64) -> bool {
+    store::load::<DateTime<Utc>>(&delivery_key(run, issue_number)).is_some()
+}
+
+/// Records that the summary of the issue is delivered in the run. It is
+/// recorded once the message that holds the summary was sent, so a run that
+/// is killed before does not skip it when it is resumed.
+pub(crate) fn mark_delivered(run: &str, issue_number: u64) {
+    store::save_for(
+        &delivery_key(run, issue_number),
+        &Utc::now(),
+        DELIVERY_MARKER_SECS,
+    );
+}
+
+/// Slack collapses messages longer than about 4000 characters, so batched
+/// messages are kept below that.
+pub(crate) const MESSAGE_LIMIT: usize = 3900;
+
+/// Collects the messages of a channel and delivers them joined into as few
+/// messages as `MESSAGE_LIMIT` allows. Whatever is still collected is
+/// delivered when the batch is dropped.
+pub(crate) struct MessageBatch<'a> {
+    sink: &'a dyn Sink,
+    workspace: &'a str,
+    channel: &'a str,
+    pending: String,
+    /// The issues whose summaries are in `pending`.
+    pending_issues: Vec<u64>,
+    /// The issues whose summaries were sent since they were last taken.
+    sent_issues: Vec<u64>,
+}
+
+impl<'a> MessageBatch<'a> {
+    pub(crate) fn new(
+        sink: &'a dyn Sink,
+        workspace: &'a str,
+        channel: &'a str,
+    ) -> MessageBatch<'a> {
+        MessageBatch {
+            sink,
+            workspace,
+            channel,
+            pending: String::new(),
+            pending_issues: Vec::new(),
+            sent_issues: Vec::new(),
+        }
+    }
+
+    /// Adds a message, delivering the collected ones first when it would not
+    /// fit into the same message. A message longer than `MESSAGE_LIMIT` is
+    /// added in parts.
+    pub(crate) fn push(&mut self, text: String) {
+        if text.len() > MESSAGE_LIMIT {
+            for part in split_message(&text) {
+                self.push(part);
+            }
+            return;
+        }
+        if !self.pending.is_empty() && self.pending.len() + 2 + text.len() > MESSAGE_LIMIT {
+            self.flush();
+        }
+        if !self.pending.is_empty() {
+            self.pending.push_str("\n\n");
+        }
+        self.pending.push_str(&text);
+    }
+
+    /// Adds the summary of the issue, which `take_sent` returns once the
+    /// message holding it was sent.
+    pub(crate) fn push_summary(&mut self, issue_number: u64, summary: String) {
+        self.push(summary);
+        self.pending_issues.push(issue_number);
+    }
+
+    pub(crate) fn flush(&mut self) {
+        if !self.pending.is_empty() {
+            let text = std::mem::take(&mut self.pending);
+            self.sink.send(self.workspace, self.channel, &text);
+            self.sent_issues.append(&mut self.pending_issues);
+        }
+    }
+
+    /// The issues whose summaries were sent since this was last called.
+    pub(crate) fn take_sent(&mut self) -> Vec<u64> {
+        std::mem::take(&mut self.sent_issues)
+    }
+}
+
+/// Splits the text into parts of at most `MESSAGE_LIMIT` bytes, between
+/// lines, or between characters where a line is longer than that.
+fn split_message(text: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    for mut line in text.split_inclusive('\n') {
+        if !part.is_empty() && part.len() + line.len() > MESSAGE_LIMIT {
+            parts.push(std::mem::take(&mut part));
+        }
+        while line.len() > MESSAGE_LIMIT {
+            let mut end = MESSAGE_LIMIT;
+            while !line.is_char_boundary(end) {
+                end -= 1;
+            }
+            parts.push(line[..end].to_string());
+            line = &line[end..];
+        }
+        part.push_str(line);
+    }
+    parts.push(part);
+    parts
+        .into_iter()
+        .map(|part| part.trim_end_matches('\n').to_string())
+        .filter(|part| !part.is_empty())
+        .collect()
+}
+
+impl Drop for MessageBatch<'_> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fakes::{RecordingSink, SentMessage};
+
+    #[test]
+    fn batched_messages_are_joined_until_the_limit() {
+        let sink = RecordingSink::default();
+        let long = "x".repeat(MESSAGE_LIMIT - 10);
+        {
+            let mut batch = MessageBatch::new(&sink, "octo-team", "issues");
+            batch.push("Digest of octocat/Hello-World".to_string());
+            batch.push("No new comments.".to_string());
+            batch.push(long.clone());
+            assert_eq!(sink.texts("issues").len(), 1);
+        }
+        assert_eq!(
+            *sink.messages.borrow(),
+            [
+                SentMessage {
+                    workspace: "octo-team".to_string(),
+                    channel: "issues".to_string(),
+                    text: "Digest of octocat/Hello-World\n\nNo new comments.".to_string(),
+                },
+                SentMessage {
+                    workspace: "octo-team".to_string(),
+                    channel: "issues".to_string(),
+                    text: long,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn messages_longer_than_the_limit_are_split() {
+        let sink = RecordingSink::default();
+        let line = "x".repeat(2000);
+        let long_line = "é".repeat(MESSAGE_LIMIT);
+        {
+            let mut batch = MessageBatch::new(&sink, "octo-team", "issues");
+            batch.push(format!("{line}\n{line}\n{line}"));
+            batch.push(long_line.clone());
+        }
+        let texts = sink.texts("issues");
+        assert!(texts.iter().all(|text| text.len() <= MESSAGE_LIMIT));
+        assert_eq!(texts[..3], [line.clone(), line.clone(), line]);
+        assert_eq!(texts.len(), 5);
+        assert_eq!(texts[3..].concat(), long_line);
+    }
+
+    #[test]
+    fn summaries_count_as_sent_once_their_message_was_sent() {
+        let sink = RecordingSink::default();
+        let mut batch = MessageBatch::new(&sink, "octo-team", "issues");
+        batch.push_summary(1347, "#1347 is being discussed.".to_string());
+        batch.push_summary(2210, "#2210 is being discussed.".to_string());
+        assert!(batch.take_sent().is_empty());
+        assert!(sink.texts("issues").is_empty());
+
+        batch.flush();
+        assert_eq!(batch.take_sent(), [1347, 2210]);
+        assert!(batch.take_sent().is_empty());
+        assert_eq!(
+            sink.texts("issues"),
+            ["#1347 is being discussed.\n\n#2210 is being discussed."]
+        );
+    }
+}
@@ -0,0 +1,155 @@
+//! The project pulse site: dated digest pages committed to a repository as
+//! the sources of an mdBook or a Hugo site, which its CI builds.
+
+use crate::audit::audit;
+use crate::config::{site_branch, site_format, site_repo, SiteFormat};
+use crate::github::read_repo_file;
+use crate::render::markdown;
+use chrono::{DateTime, Utc};
+use github_flows::{get_octo, GithubLogin::Default};
+
+/// The book the first digest page sets up when the site has no
+/// `SUMMARY.md` yet.
+const BOOK_TOML: &str = "[book]\ntitle = \"Project pulse\"\nsrc = \"src\"\n";
+
+fn join(root: &str, path: &str) -> String {
+    match root.is_empty() {
+        true => path.to_string(),
+        false => format!("{root}/{path}"),
+    }
+}
+
+fn refused(e: github_flows::octocrab::Error, site_owner: &str, site_repo: &str) -> String {
+    match e {
+        github_flows::octocrab::Error::GitHub { source, .. } => format!(
+            "GitHub refused the page ({}). Please make sure the connected GitHub account can push to the branch of {site_owner}/{site_repo}.",
+            source.message
+        ),
+        _ => "the request to GitHub failed.".to_string(),
+    }
+}
+
+/// Lists the page in mdBook's `SUMMARY.md`, newest first: above the first
+/// chapter, below the title and any prefix chapters.
+fn list_in_summary(summary: &str, title: &str, link: &str) -> String {
+    let entry = format!("- [{title}]({link})\n");
+    let mut lines = summary.split_inclusive('\n').collect::<Vec<_>>();
+    let first_chapter = lines
+        .iter()
+        .position(|line| {
+            let line = line.trim_start();
+            line.starts_with("- [") || line.starts_with("* [")
+        })
+        .unwrap_or(lines.len());
+    if first_chapter == lines.len() && lines.last().is_some_and(|line| !line.ends_with('\n')) {
+        lines.push("\n");
+    }
+    let mut listed = lines[..first_chapter].concat();
+    listed.push_str(&entry);
+    listed.push_str(&lines[first_chapter..].concat());
+    listed
+}
+
+/// Commits the digest as a dated page of the `site_repo`, on the
+/// `site_branch`, and for an mdBook lists it in the book's `SUMMARY.md`.
+/// Returns the URL of the page's source.
+pub(crate) async fn export_digest_to_site(
+    owner: &str,
+    repo: &str,
+    now: DateTime<Utc>,
+    summaries: &[String],
+) -> Option<Result<String, String>> {
+    let (site_owner, site_name, root) = site_repo()?;
+    let title = format!("{owner}/{repo}, {}", now.format("%Y-%m-%d"));
+    let body = summaries
+        .iter()
+        .map(|summary| markdown(summary))
+        .collect::<Vec<_>>()
+        .join("\n\n---\n\n");
+    let stamp = now.format("%Y-%m-%d-%H%M");
+    let (path, content) = match site_format() {
+        SiteFormat::MdBook => (
+            join(&root, &format!("src/{owner}/{repo}/{stamp}.md")),
+            format!("# {title}\n\n{body}\n"),
+        ),
+        // Hugo lists the pages of a section by their date, newest first
+        SiteFormat::Hugo => (
+            join(&root, &format!("content/pulse/{owner}-{repo}-{stamp}.md")),
+            format!(
+                "---\ntitle: \"{title}\"\ndate: {}\ntags: [\"{owner}/{repo}\"]\n---\n\n{body}\n",
+                now.to_rfc3339()
+            ),
+        ),
+    };
+
+    let octocrab = get_octo(&Default);
+    let repos = octocrab.repos(&site_owner, &site_name);
+    let message = format!(
+        "Add the digest of {owner}/{repo}, {}",
+        now.format("%Y-%m-%d")
+    );
+    let mut create = repos.create_file(&path, &message, content);
+    if let Some(branch) = site_branch() {
+        create = create.branch(branch);
+    }
+    let mut exported = create
+        .send()
+        .await
+        .map(|update| {
+            update
+                .content
+                .html_url
+                .unwrap_or_else(|| format!("{site_owner}/{site_name}/{path}"))
+        })
+        .map_err(|e| refused(e, &site_owner, &site_name));
+
+    if exported.is_ok() && site_format() == SiteFormat::MdBook {
+        let summary_path = join(&root, "src/SUMMARY.md");
+        let existing = read_repo_file(
+            &site_owner,
+            &site_name,
+            &summary_path,
+            site_branch().as_deref(),
+        )
+        .await;
+        let (summary, sha) = match existing {
+            Some((summary, sha)) => (summary, Some(sha)),
+            None => ("# Summary\n\n".to_string(), None),
+        };
+        let summary = list_in_summary(
+            &summary,
+            &format!("{title} {}", now.format("%H:%M")),
+            &format!("{owner}/{repo}/{stamp}.md"),
+        );
+        let mut update = match &sha {
+            Some(sha) => repos.update_file(&summary_path, &message, summary, sha),
+            None => repos.create_file(&summary_path, &message, summary),
+        };
+        if let Some(branch) = site_branch() {
+            update = update.branch(branch);
+        }
+        if let Err(e) = update.send().await {
+            exported = Err(refused(e, &site_owner, &site_name));
+        } else if sha.is_none() {
+            // a site that already has a book.toml keeps it
+            let mut book = repos.create_file(join(&root, "book.toml"), &message, BOOK_TOML);
+            if let Some(branch) = site_branch() {
+                book = book.branch(branch);
+            }
+            let _ = book.send().await;
+        }
+    }
+
+    let outcome = match &exported {
+        Ok(url) => format!("committed {url}"),
+        Err(e) => format!("failed: {e}"),
+    };
+    audit(
+        "flow",
+        &format!("exported the digest of {owner}/{repo} to the site"),
+        Some((&site_owner, &site_name)),
+        &outcome,
+        false,
+    );
+    Some(exported)
+}
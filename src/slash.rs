@@ -0,0 +1,172 @@
+//! The slash command of the Slack app, and the autocomplete of the
+//! repository field of the compose modal.
+
+use crate::app_home::{run_as_command, slack_channel, trigger_word};
+use crate::config::{repo_orgs, repo_refusal};
+use crate::linear::linear_team;
+use crate::logging::Event;
+use crate::modal::open_compose_modal;
+use crate::store;
+use github_flows::{get_octo, GithubLogin::Default};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use webhook_flows::send_response;
+
+/// How long the repositories of an organization are cached.
+const REPO_LIST_SECS: i64 = 60 * 60;
+
+/// The most repositories read of an organization, in pages of 100.
+const REPO_LIST_PAGES: u32 = 10;
+
+/// Slack's limit for the options of a menu.
+const OPTIONS_LIMIT: usize = 100;
+
+pub(crate) fn repo_list_key(org: &str) -> String {
+    format!("repo_list:{}", org.to_lowercase())
+}
+
+fn respond_ephemeral(text: &str) {
+    send_response(
+        200,
+        vec![("Content-Type".to_string(), "application/json".to_string())],
+        json!({ "response_type": "ephemeral", "text": text })
+            .to_string()
+            .into_bytes(),
+    );
+}
+
+/// The `<owner>/<repo>` names of the organization's repositories that are
+/// not archived, cached for `REPO_LIST_SECS`. The repositories of an
+/// organization are listed with the private ones the connected account can
+/// see, those of a user only with the public ones.
+async fn org_repos(org: &str) -> Vec<String> {
+    if let Some(repos) = store::load::<Vec<String>>(&repo_list_key(org)) {
+        return repos;
+    }
+    let octocrab = get_octo(&Default);
+    let mut repos = Vec::new();
+    for owner_kind in ["orgs", "users"] {
+        for page in 1..=REPO_LIST_PAGES {
+            let route = format!("{owner_kind}/{org}/repos");
+            let page = page.to_string();
+            let listed = match octocrab
+                .get::<Vec<Value>, _, _>(&route, Some(&[("per_page", "100"), ("page", &page)]))
+                .await
+            {
+                Ok(listed) => listed,
+                Err(_e) => break,
+            };
+            repos.extend(
+                listed
+                    .iter()
+                    .filter(|repo| repo["archived"] != true)
+                    .filter_map(|repo| repo["full_name"].as_str().map(str::to_string)),
+            );
+            if listed.len() < 100 {
+                break;
+            }
+        }
+        if !repos.is_empty() {
+            break;
+        }
+    }
+    if repos.is_empty() {
+        Event::warn("autocomplete").log(&format!("no repositories of {org} could be listed"));
+    } else {
+        store::save_for(&repo_list_key(org), &repos, REPO_LIST_SECS);
+    }
+    repos
+}
+
+/// The options Slack loads while the repository field is typed in: the
+/// repositories of `repo_orgs` the bot may summarize that contain what is
+/// typed, and a Linear team when one is typed as `linear:<TEAM>`.
+#[tokio::main(flavor = "current_thread")]
+pub(crate) async fn repo_options(typed: &str) -> Value {
+    let typed = typed.trim();
+    let mut names = Vec::new();
+    if linear_team(typed).is_some() {
+        names.push(typed.to_string());
+    }
+    let needle = typed.to_lowercase();
+    for org in repo_orgs() {
+        names.extend(org_repos(&org).await.into_iter().filter(|name| {
+            name.to_lowercase().contains(&needle)
+                && name
+                    .split_once('/')
+                    .is_some_and(|(owner, repo)| repo_refusal(owner, repo).is_none())
+        }));
+    }
+    names.truncate(OPTIONS_LIMIT);
+    let options = names
+        .iter()
+        .map(|name| json!({ "text": { "type": "plain_text", "text": name }, "value": name }))
+        .collect::<Vec<Value>>();
+    json!({ "options": options })
+}
+
+/// Why the repository of the slash command is likely a typo: its owner is
+/// one of `repo_orgs`, which has no repository of the name. Tells the
+/// repositories of similar names instead.
+#[tokio::main(flavor = "current_thread")]
+async fn typo_hint(owner_repo: &str) -> Option<String> {
+    let (owner, repo) = owner_repo.split_once('/')?;
+    if !repo_orgs()
+        .iter()
+        .any(|org| org.eq_ignore_ascii_case(owner))
+    {
+        return None;
+    }
+    let repos = org_repos(owner).await;
+    if repos.is_empty() || repos.iter().any(|r| r.eq_ignore_ascii_case(owner_repo)) {
+        return None;
+    }
+    let needle = repo.to_lowercase();
+    let similar = repos
+        .iter()
+        .filter(|name| {
+            let name = name.to_lowercase();
+            let name = name.split_once('/').map_or(name.as_str(), |(_, r)| r);
+            name.contains(&needle) || needle.contains(name)
+        })
+        .take(5)
+        .map(|name| format!("`{name}`"))
+        .collect::<Vec<_>>();
+    Some(match similar.is_empty() {
+        true => format!("{owner} has no repository named {repo}."),
+        false => format!(
+            "{owner} has no repository named {repo}, did you mean {}?",
+            similar.join(", ")
+        ),
+    })
+}
+
+/// Handles the slash command: with arguments they are run as the digest
+/// command typed in the channel, as in `/summarize WasmEdge/WasmEdge 7`,
+/// without any the compose modal opens.
+pub(crate) fn handle_slash_command(fields: &HashMap<String, String>) {
+    let field = |name: &str| fields.get(name).map(String::as_str).unwrap_or("");
+    let Some((workspace, channel)) = slack_channel(field("channel_name")) else {
+        respond_ephemeral("This channel is not one of the channels the bot serves.");
+        return;
+    };
+    let args = field("text").trim();
+    if args.is_empty() {
+        // an empty answer posts nothing
+        send_response(200, vec![], vec![]);
+        open_compose_modal(field("trigger_id"), Some(&channel));
+        return;
+    }
+    let repo = args.split_whitespace().next().unwrap_or("");
+    if let Some(hint) = typo_hint(repo) {
+        respond_ephemeral(&hint);
+        return;
+    }
+    respond_ephemeral("Working on it.");
+    run_as_command(
+        field("user_id"),
+        &workspace,
+        &channel,
+        &format!("{} {args}", trigger_word()),
+    );
+}
@@ -0,0 +1,1949 @@
+//! Everything that asks the model: summaries, classifications and reports.
+
+use crate::config::{
+    self, is_opted_out, max_comments_fetched, max_completions_per_issue, max_map_chunks,
+    moderation_enabled, openai_timeout_secs, prompt_profile, IssueRoute, PromptProfile,
+};
+use crate::deps::{Deps, IssueSource, LiveIssues};
+use crate::github::{
+    comments_page, count_issues, github_request, notable_pull_requests, previous_release_date,
+    resolution_references, LabelSuggestion, COMMENTS_PER_PAGE,
+};
+use crate::logging::{add_tokens, Event};
+use crate::metrics::{count, count_by, time_stage, Counter};
+use crate::ops::{model_failed, report_ops, OpsFailure};
+use crate::run_archive::record;
+use crate::{store, Error, Result};
+use chrono::{DateTime, Duration, Utc};
+use github_flows::{
+    get_octo,
+    octocrab::models::{issues::Issue, pulls::PullRequest},
+    GithubLogin::Default,
+};
+use openai_flows::{
+    chat::{ChatModel, ChatOptions, ChatResponse},
+    OpenAIFlows,
+};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::OnceLock;
+use tiktoken_rs::{cl100k_base, CoreBPE};
+
+pub(crate) const OPENAI_RETRIES: u32 = 4;
+
+pub(crate) const OPENAI_BACKOFF_BASE_MS: u64 = 2000;
+
+pub(crate) const OPENAI_BACKOFF_MAX_MS: u64 = 60000;
+
+pub(crate) const RELEASE_FIXES_LISTED: usize = 20;
+
+pub(crate) async fn get_summary<I: IssueSource, S: Summarizer>(
+    deps: &Deps<'_, I, S>,
+    owner: &str,
+    repo: &str,
+    issue: Issue,
+    issue_type: IssueType,
+    language: Option<&str>,
+) -> Result<String> {
+    Ok(
+        generate_summary(deps, owner, repo, issue, issue_type, language)
+            .await?
+            .render(),
+    )
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct IssueSummary {
+    pub(crate) issue_type: IssueType,
+    pub(crate) summary: String,
+    pub(crate) repro: String,
+    #[serde(default)]
+    pub(crate) failures: String,
+    pub(crate) confidence: String,
+    pub(crate) url: String,
+}
+
+/// The parts of an issue a [`Summarizer`] works from.
+pub(crate) struct IssueContext {
+    pub(crate) number: u64,
+    pub(crate) title: String,
+    pub(crate) author: String,
+    pub(crate) author_role: String,
+    pub(crate) labels: String,
+    pub(crate) body: String,
+    /// `(commenter, comment)` pairs in the order they were posted.
+    pub(crate) comments: Vec<(String, String)>,
+    /// The language the summary is written in, English when not given.
+    pub(crate) language: Option<String>,
+    /// The prompt profile of the repository, if it has one.
+    pub(crate) profile: Option<PromptProfile>,
+}
+
+/// The outcome of summarizing an issue. An empty `text` means no summary
+/// could be generated, which `failure` explains.
+#[derive(Default)]
+pub(crate) struct Summary {
+    pub(crate) text: String,
+    pub(crate) failure: Option<String>,
+    /// The parts of the discussion that could not be summarized.
+    pub(crate) failed_parts: Vec<String>,
+    pub(crate) parts: usize,
+    /// The parts of the discussion left out over `max_map_chunks`.
+    pub(crate) left_out_parts: usize,
+}
+
+/// A backend that turns an issue discussion into a summary. The commands and
+/// the rendering only see [`Summary`], so backends can be swapped freely.
+pub(crate) trait Summarizer {
+    async fn summarize(&self, ctx: IssueContext) -> Result<Summary>;
+}
+
+/// Summarizes with OpenAI, in one request when the discussion fits into the
+/// context window and with map/reduce over chunks of it otherwise.
+pub(crate) struct OpenAiSummarizer;
+
+impl IssueContext {
+    /// The issue post and the comments as segments of the discussion.
+    pub(crate) fn segments(&self, bpe: &CoreBPE) -> Vec<Segment> {
+        let IssueContext {
+            title,
+            author,
+            author_role,
+            labels,
+            body,
+            comments,
+            ..
+        } = self;
+
+        let mut segments = Vec::new();
+
+        let issue_creator_input = format!("User '{author}', who holds the role of '{author_role}', has submitted an issue titled '{title}', labeled as '{labels}', with the following post: '{body}'.");
+        segments.push(Segment::new(bpe, issue_creator_input));
+
+        for (commenter, comment_body) in comments {
+            let commenter_input = format!("{commenter} commented: {comment_body}");
+            segments.push(Segment::new(bpe, commenter_input));
+        }
+
+        segments
+    }
+
+    /// The system prompt of every request, with the prompt profile and the
+    /// language of the summary.
+    pub(crate) fn system_prompt(&self) -> String {
+        let system = config::prompt("summary", "As an AI co-owner of a GitHub repository, you are responsible for conducting a comprehensive analysis of GitHub issues. Your analytic focus encompasses distinct elements, including the issue's title, associated labels, body text, the identity of the issue's creator, their role, and the nature of the comments on the issue. Utilizing these data points, your task is to generate a succinct, context-aware summary of the issue.");
+        let profile = self.profile.clone().unwrap_or_default();
+        let mut system = profile.system.unwrap_or(system.to_string());
+        if let Some(style) = &profile.style {
+            system = format!("{system} {style}");
+        }
+        if let Some(language) = &self.language {
+            system = format!("{system} Always write the summary in {language}.");
+        }
+        system
+    }
+
+    /// The prompt summarizing one chunk of a long discussion.
+    pub(crate) fn map_prompt(&self, text_chunk: &str) -> String {
+        let title = &self.title;
+        format!("Given the issue titled '{title}' and a particular segment of body or comment text '{text_chunk}', focus on extracting the central arguments, proposed solutions, and instances of agreement or conflict among the participants. Generate an interim summary capturing the essential information in this section. This will be used later to form a comprehensive summary of the entire discussion.")
+    }
+
+    /// The prompt combining the summaries of the chunks.
+    pub(crate) fn reduce_prompt(&self, map_out: &str) -> String {
+        let IssueContext {
+            title,
+            author,
+            author_role,
+            labels,
+            ..
+        } = self;
+        format!("User '{author}', in the role of '{author_role}', has filed an issue titled '{title}', labeled as '{labels}'. The key information you've extracted from the issue's body text and comments in segmented form are: {map_out}. Concentrate on the principal arguments, suggested solutions, and areas of consensus or disagreement among the participants. From these elements, generate a concise summary of the entire issue to inform the next course of action. {CONFIDENCE_INSTRUCTION}")
+    }
+
+    /// The prompt summarizing a discussion that fits into one request.
+    pub(crate) fn single_prompt(discussion: &str) -> String {
+        format!("{discussion}, concentrate on the principal arguments, suggested solutions, and areas of consensus or disagreement among the participants. From these elements, generate a concise summary of the entire issue to inform the next course of action. {CONFIDENCE_INSTRUCTION}")
+    }
+
+    /// The prompts the discussion is summarized with, before any of them is
+    /// sent.
+    pub(crate) fn prompts(&self, bpe: &CoreBPE) -> Result<Prompts> {
+        let segments = self.segments(bpe);
+        if Segment::total_tokens(&segments) <= CHUNK_TOKENS {
+            let discussion = segments
+                .into_iter()
+                .map(|s| s.text)
+                .collect::<Vec<_>>()
+                .join("\n");
+            return Ok(Prompts::Single(IssueContext::single_prompt(&discussion)));
+        }
+
+        let mut chunks = pack_segments(bpe, segments, CHUNK_TOKENS)?;
+        let limit = map_chunks_limit();
+        let mut left_out_parts = 0;
+        if chunks.len() > limit {
+            // the first chunk holds the issue post, the last ones the latest comments
+            left_out_parts = chunks.len() - limit;
+            chunks.drain(1..=left_out_parts);
+        }
+        Ok(Prompts::MapReduce {
+            map_prompts: chunks.iter().map(|c| self.map_prompt(c)).collect(),
+            left_out_parts,
+        })
+    }
+}
+
+/// Tokens of the discussion one request reads at most.
+pub(crate) const CHUNK_TOKENS: usize = 2800;
+
+/// How a discussion is summarized: in one request when it fits into the
+/// context window, and with a request per chunk that [`IssueContext::reduce_prompt`]
+/// combines otherwise.
+#[derive(Debug)]
+pub(crate) enum Prompts {
+    Single(String),
+    MapReduce {
+        map_prompts: Vec<String>,
+        left_out_parts: usize,
+    },
+}
+
+impl Summarizer for OpenAiSummarizer {
+    async fn summarize(&self, ctx: IssueContext) -> Result<Summary> {
+        let bpe = tokenizer()?;
+
+        let chat_id = format!("Issue#{}", ctx.number);
+        let system = ctx.system_prompt();
+
+        let co = ChatOptions {
+            model: ChatModel::GPT35Turbo,
+            restart: true,
+            system_prompt: Some(&system),
+        };
+
+        let mut summary = Summary::default();
+
+        match ctx.prompts(bpe)? {
+            Prompts::MapReduce {
+                map_prompts,
+                left_out_parts,
+            } => {
+                let mut map_out = "".to_string();
+                summary.left_out_parts = left_out_parts;
+
+                for map_question in map_prompts {
+                    summary.parts += 1;
+                    match chat_completion(&chat_id, &map_question, &co).await {
+                        Ok(r) => {
+                            map_out.push_str(&r.choice);
+                        }
+                        Err(e) => {
+                            summary
+                                .failed_parts
+                                .push(format!("part {} ({e})", summary.parts));
+                        }
+                    }
+                }
+
+                let reduce_question = ctx.reduce_prompt(&map_out);
+
+                match chat_completion(&chat_id, &reduce_question, &co).await {
+                    Ok(r) => summary.text = r.choice,
+                    Err(e) => summary.failure = Some(e.to_string()),
+                }
+            }
+            Prompts::Single(question) => match chat_completion(&chat_id, &question, &co).await {
+                Ok(r) => summary.text = r.choice,
+                Err(e) => summary.failure = Some(e.to_string()),
+            },
+        }
+
+        Ok(summary)
+    }
+}
+
+/// How many tokens of comments a summary reads at most, enough for the
+/// chunks of the map/reduce that may be summarized.
+pub(crate) fn comment_token_budget() -> usize {
+    map_chunks_limit() * CHUNK_TOKENS
+}
+
+/// The chunks of a long discussion that are summarized, leaving one
+/// completion for the combined summary.
+pub(crate) fn map_chunks_limit() -> usize {
+    max_map_chunks().min(max_completions_per_issue() - 1)
+}
+
+/// Comments of long threads are chosen from a pool of the newest comments
+/// that is this many times the budget.
+pub(crate) const COMMENT_POOL_FACTOR: usize = 3;
+
+/// The most recent comments of a long thread that are always read.
+pub(crate) const RECENT_COMMENTS: usize = 20;
+
+/// A rough token count that is good enough to choose comments by, so only the
+/// chosen ones are tokenized.
+pub(crate) fn estimate_tokens(text: &str) -> usize {
+    text.len() / 4 + 1
+}
+
+/// Fetches the comments of the issue from the newest page back into a pool
+/// of about `COMMENT_POOL_FACTOR` times the token budget, and at most
+/// `max_comments_fetched`, so the oldest pages of huge threads are never
+/// loaded. When the pool does not fit into
+/// the budget, the `RECENT_COMMENTS` most recent comments are read, followed
+/// by maintainer comments and the most reacted comments. The comments are
+/// returned oldest first as `(commenter, comment)` pairs.
+pub(crate) async fn select_comments(
+    owner: &str,
+    repo: &str,
+    issue_number: u64,
+    comments_count: usize,
+) -> Vec<(String, String)> {
+    let pages = comments_count.div_ceil(COMMENTS_PER_PAGE);
+    let budget = comment_token_budget();
+    let max_comments = max_comments_fetched();
+    let mut pool = Vec::new();
+    let mut pool_tokens = 0;
+
+    for page in (1..=pages).rev() {
+        if pool.len() >= max_comments {
+            break;
+        }
+        let comments = match comments_page(owner, repo, issue_number, page).await {
+            Ok(comments) => comments,
+            Err(e) => {
+                Event::warn("comments")
+                    .issue(issue_number)
+                    .err(&e)
+                    .log(&format!("page {page} could not be read"));
+                break;
+            }
+        };
+        for comment in comments.into_iter().rev().take(max_comments - pool.len()) {
+            let tokens = estimate_tokens(&comment.author) + estimate_tokens(&comment.body);
+            pool_tokens += tokens;
+            pool.push((comment, tokens));
+        }
+        if pool_tokens >= COMMENT_POOL_FACTOR * budget {
+            break;
+        }
+    }
+
+    // the pool is newest first, so a comment's index is its recency rank
+    let mut chosen = vec![false; pool.len()];
+    if pool_tokens <= budget {
+        chosen.fill(true);
+    } else {
+        let mut ranked = (0..pool.len()).collect::<Vec<usize>>();
+        ranked.sort_by_key(|&i| {
+            let (comment, _) = &pool[i];
+            let score = match i < RECENT_COMMENTS {
+                true => u64::MAX,
+                false => comment.reactions + if comment.maintainer { 10 } else { 0 },
+            };
+            (std::cmp::Reverse(score), i)
+        });
+
+        let mut tokens_left = budget;
+        for i in ranked {
+            let tokens = pool[i].1;
+            if tokens <= tokens_left {
+                tokens_left -= tokens;
+                chosen[i] = true;
+            }
+        }
+    }
+
+    let selected = pool
+        .into_iter()
+        .zip(chosen)
+        .rev()
+        .filter(|(_, chosen)| *chosen)
+        .map(|((comment, _), _)| (comment.author, comment.body))
+        .collect::<Vec<_>>();
+
+    if selected.len() < comments_count {
+        Event::info("comments").issue(issue_number).log(&format!(
+            "{} of {comments_count} comments are left out of the summary",
+            comments_count - selected.len()
+        ));
+    }
+    selected
+}
+
+/// The comments with those of the people who opted out of AI processing
+/// withheld. Every comment sent to the model goes through here.
+pub(crate) fn redact_opted_out(comments: Vec<(String, String)>) -> Vec<(String, String)> {
+    comments
+        .into_iter()
+        .map(|(commenter, comment)| match is_opted_out(&commenter) {
+            true => (
+                commenter,
+                "(comment withheld, its author opted out of AI processing)".to_string(),
+            ),
+            false => (commenter, comment),
+        })
+        .collect()
+}
+
+/// Fetches the comments of the issue that its summary reads.
+pub(crate) async fn issue_context(
+    issues: &impl IssueSource,
+    owner: &str,
+    repo: &str,
+    issue: &Issue,
+) -> IssueContext {
+    IssueContext {
+        number: issue.number,
+        title: issue.title.clone(),
+        author: issue.user.login.clone(),
+        author_role: issue.author_association.clone(),
+        labels: issue
+            .labels
+            .iter()
+            .map(|lab| lab.name.clone())
+            .collect::<Vec<String>>()
+            .join(", "),
+        body: issue.body.clone().unwrap_or_default(),
+        comments: redact_opted_out(issues.comments(owner, repo, issue).await),
+        language: None,
+        profile: prompt_profile(owner, repo),
+    }
+}
+
+pub(crate) async fn generate_summary<I: IssueSource, S: Summarizer>(
+    deps: &Deps<'_, I, S>,
+    owner: &str,
+    repo: &str,
+    issue: Issue,
+    issue_type: IssueType,
+    language: Option<&str>,
+) -> Result<IssueSummary> {
+    if is_opted_out(&issue.user.login) {
+        return Err(Error::OptedOut(issue.user.login));
+    }
+    let mut ctx = issue_context(deps.issues, owner, repo, &issue).await;
+    ctx.language = language.map(str::to_string);
+    let comments = ctx
+        .comments
+        .iter()
+        .map(|(author, body)| json!({ "author": author, "body": body }))
+        .collect::<Vec<_>>();
+    record(
+        "issue",
+        Some(issue.number),
+        json!({ "owner": owner, "repo": repo, "issue": issue, "comments": comments }),
+    );
+    let issue_number = issue.number;
+    let issue_title = issue.title;
+    let issue_body = issue.body.unwrap_or("".to_string());
+    let issue_url = issue.html_url;
+    let issue_comments_count = issue.comments as usize;
+    let comments_read = ctx.comments.len();
+    let Summary {
+        text: mut _summary,
+        failure,
+        failed_parts,
+        parts,
+        left_out_parts,
+    } = deps
+        .summarizer
+        .summarize(ctx)
+        .await
+        .inspect_err(|_e| count(Counter::SummaryFailures))?;
+
+    if let Some(e) = failure {
+        count(Counter::SummaryFailures);
+        return Err(Error::Model(e));
+    }
+    count(Counter::Summaries);
+
+    let mut failures_block = String::new();
+    if !failed_parts.is_empty() {
+        failures_block.push_str(&format!(
+            ":warning: {} of {parts} parts of the discussion could not be summarized: {}\n",
+            failed_parts.len(),
+            failed_parts.join(", ")
+        ));
+    }
+
+    if left_out_parts > 0 {
+        failures_block.push_str(&format!(
+            ":scissors: {left_out_parts} parts of the discussion were left out to stay within {} parts per issue\n",
+            map_chunks_limit()
+        ));
+    }
+
+    let self_reported = take_confidence(&mut _summary);
+    let confidence = Confidence::assess(
+        self_reported,
+        _summary.is_empty(),
+        (failed_parts.len() + left_out_parts, parts + left_out_parts),
+        (comments_read, issue_comments_count),
+    );
+    let confidence_block = match confidence.is_low() {
+        true => format!("{}\n", confidence.render()),
+        false => "".to_string(),
+    };
+
+    let repro_block = match issue_type {
+        IssueType::Bug => extract_repro(issue_number, &issue_title, &issue_body)
+            .await
+            .map(|r| format!("{}\n", r.render()))
+            .unwrap_or_default(),
+        _ => "".to_string(),
+    };
+
+    let summary = IssueSummary {
+        issue_type,
+        summary: _summary,
+        repro: repro_block,
+        failures: failures_block,
+        confidence: confidence_block,
+        url: issue_url.to_string(),
+    };
+    record("summary", Some(issue_number), json!(summary));
+    Ok(summary)
+}
+
+/// The tokenizer of the chat model. Building it is expensive in WASM, so it
+/// is built once and shared by every summary of the run.
+pub(crate) fn tokenizer() -> Result<&'static CoreBPE> {
+    static BPE: OnceLock<CoreBPE> = OnceLock::new();
+    if let Some(bpe) = BPE.get() {
+        return Ok(bpe);
+    }
+    let bpe = cl100k_base().map_err(|e| Error::Tokenizer(e.to_string()))?;
+    Ok(BPE.get_or_init(|| bpe))
+}
+
+/// The tokens of the text, estimated when the tokenizer can't be built.
+pub(crate) fn count_tokens(text: &str) -> usize {
+    match tokenizer() {
+        Ok(bpe) => bpe.encode_ordinary(text).len(),
+        Err(_e) => estimate_tokens(text),
+    }
+}
+
+/// The text cut after `max_tokens` tokens. Without the tokenizer it is cut
+/// at the length `estimate_tokens` puts at `max_tokens`.
+pub(crate) fn truncate_tokens(text: &str, max_tokens: usize) -> String {
+    let Ok(bpe) = tokenizer() else {
+        return text.chars().take(max_tokens * 4).collect();
+    };
+    let tokens = bpe.encode_ordinary(text);
+    match tokens.len() > max_tokens {
+        true => bpe
+            .decode(tokens[..max_tokens].to_vec())
+            .unwrap_or_default(),
+        false => text.to_string(),
+    }
+}
+
+/// A piece of the discussion, such as the issue post or a comment, with its
+/// token count. The text is only joined into chunks when the prompts are
+/// built, so no token vector of the whole discussion is kept around.
+pub(crate) struct Segment {
+    pub(crate) text: String,
+    pub(crate) tokens: usize,
+}
+
+impl Segment {
+    pub(crate) fn new(bpe: &CoreBPE, text: String) -> Segment {
+        let tokens = bpe.encode_ordinary(&text).len();
+        Segment { text, tokens }
+    }
+
+    /// The tokens of the segments joined by newlines.
+    pub(crate) fn total_tokens(segments: &[Segment]) -> usize {
+        segments.iter().map(|s| s.tokens + 1).sum()
+    }
+}
+
+/// Packs whole segments into chunks of at most `max` tokens, keeping each
+/// comment in one piece. Only segments that are longer than a chunk on their
+/// own are split.
+pub(crate) fn pack_segments(
+    bpe: &CoreBPE,
+    segments: Vec<Segment>,
+    max: usize,
+) -> Result<Vec<String>> {
+    let mut chunks = Vec::new();
+    let mut current = Vec::new();
+    let mut current_tokens = 0;
+
+    for segment in segments {
+        if !current.is_empty() && current_tokens + segment.tokens + 1 > max {
+            chunks.push(current.join("\n"));
+            current.clear();
+            current_tokens = 0;
+        }
+        if segment.tokens > max {
+            let mut tokens = bpe.encode_ordinary(&segment.text);
+            while !tokens.is_empty() {
+                chunks.push(take_chunk(bpe, &mut tokens, max)?);
+            }
+            continue;
+        }
+        current_tokens += segment.tokens + 1;
+        current.push(segment.text);
+    }
+    if !current.is_empty() {
+        chunks.push(current.join("\n"));
+    }
+
+    Ok(chunks)
+}
+
+/// Tokens a completion adds on top of the discussion it reads, for the
+/// instructions and the answer, when estimating what a digest costs.
+pub(crate) const COMPLETION_OVERHEAD_TOKENS: usize = 600;
+
+/// The price in US dollars of 1000 tokens of the chat model.
+pub(crate) const COST_PER_1K_TOKENS: f64 = 0.002;
+
+/// What summarizing an issue would take, worked out without asking the model.
+pub(crate) struct SummaryPlan {
+    pub(crate) comments_read: usize,
+    pub(crate) parts: usize,
+    pub(crate) completions: usize,
+    pub(crate) tokens: usize,
+}
+
+impl SummaryPlan {
+    pub(crate) fn cost(&self) -> f64 {
+        self.tokens as f64 / 1000.0 * COST_PER_1K_TOKENS
+    }
+}
+
+/// Fetches and tokenizes the discussion of the issue like a summary would,
+/// and counts the completions the summary would request. `issue_type` is
+/// `None` when the labels do not tell the type, which takes a completion to
+/// classify it.
+pub(crate) async fn plan_summary(
+    owner: &str,
+    repo: &str,
+    issue: &Issue,
+    issue_type: Option<IssueType>,
+) -> Result<SummaryPlan> {
+    let bpe = tokenizer()?;
+    let ctx = issue_context(&LiveIssues, owner, repo, issue).await;
+    let segments = ctx.segments(bpe);
+    let discussion_tokens = Segment::total_tokens(&segments);
+
+    let parts = match discussion_tokens > CHUNK_TOKENS {
+        true => pack_segments(bpe, segments, CHUNK_TOKENS)?
+            .len()
+            .min(map_chunks_limit()),
+        false => 1,
+    };
+    let mut completions = match parts {
+        1 => 1,
+        parts => parts + 1,
+    };
+    // the sentiment, and the classification or the repro info of bugs
+    completions += 1;
+    match issue_type {
+        None | Some(IssueType::Bug) => completions += 1,
+        Some(_) => {}
+    }
+
+    Ok(SummaryPlan {
+        comments_read: ctx.comments.len(),
+        parts,
+        completions,
+        tokens: discussion_tokens + completions * COMPLETION_OVERHEAD_TOKENS,
+    })
+}
+
+/// Takes up to `max` tokens off the front of `tokens` and decodes them. A
+/// token boundary can fall inside a multi-byte character, in which case the
+/// chunk is shortened by the partial character's tokens.
+pub(crate) fn take_chunk(bpe: &CoreBPE, tokens: &mut Vec<usize>, max: usize) -> Result<String> {
+    let mut len = tokens.len().min(max);
+    loop {
+        match bpe.decode(tokens[..len].to_vec()) {
+            Ok(text) => {
+                tokens.drain(..len);
+                return Ok(text);
+            }
+            // a character is at most 4 bytes, so a few tokens less always helps
+            Err(_e) if len > 1 && tokens.len().min(max) - len < 4 => len -= 1,
+            Err(e) => return Err(Error::Tokenizer(e.to_string())),
+        }
+    }
+}
+
+/// Why a chat completion failed, after it was retried if the failure was
+/// transient.
+#[derive(Debug)]
+pub(crate) struct ChatError {
+    pub(crate) message: String,
+    /// The code or type of the error OpenAI answered with, empty for other
+    /// errors.
+    pub(crate) kind: String,
+    pub(crate) retryable: bool,
+}
+
+impl ChatError {
+    /// Classifies the error openai-flows returns, which is the body of the
+    /// failed response or the transport error.
+    pub(crate) fn from_response(body: &str) -> ChatError {
+        let Ok(json) = serde_json::from_str::<serde_json::Value>(body) else {
+            // network errors and gateway pages are worth another try
+            return ChatError {
+                message: body.chars().take(200).collect(),
+                kind: String::new(),
+                retryable: true,
+            };
+        };
+        let error = &json["error"];
+        let kind = error["code"]
+            .as_str()
+            .or(error["type"].as_str())
+            .unwrap_or("");
+        ChatError {
+            message: error["message"]
+                .as_str()
+                .map(String::from)
+                .unwrap_or(body.chars().take(200).collect()),
+            kind: kind.to_string(),
+            retryable: matches!(
+                kind,
+                "rate_limit_exceeded" | "server_error" | "service_unavailable" | "timeout"
+            ),
+        }
+    }
+
+    /// Reports the error to the ops channel: a refused key or a used up
+    /// quota right away, other errors once they keep happening.
+    fn report(&self) {
+        let detail = format!("OpenAI answered: {}", self.message);
+        match self.kind.as_str() {
+            "invalid_api_key" => {
+                report_ops(OpsFailure::OpenaiAuth, &detail);
+            }
+            "insufficient_quota" => {
+                report_ops(OpsFailure::OpenaiQuota, &detail);
+            }
+            _ => model_failed(&self.message),
+        }
+    }
+}
+
+impl std::fmt::Display for ChatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// The wait before the given retry: exponential, capped, with up to 50%
+/// jitter so concurrent requests don't retry in lockstep.
+pub(crate) fn openai_backoff(attempt: u32) -> std::time::Duration {
+    let base = (OPENAI_BACKOFF_BASE_MS << attempt).min(OPENAI_BACKOFF_MAX_MS);
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u64)
+        .unwrap_or(0);
+    std::time::Duration::from_millis(base + nanos % (base / 2 + 1))
+}
+
+/// Chat ids end with the number of the issue they are about, such as
+/// `Issue#12`.
+fn chat_issue(chat_id: &str) -> Option<u64> {
+    chat_id
+        .rsplit_once('#')
+        .and_then(|(_, n)| n.parse::<u64>().ok())
+}
+
+fn chat_event(event: Event, chat_id: &str) -> Event {
+    match chat_issue(chat_id) {
+        Some(issue_number) => event.issue(issue_number),
+        None => event,
+    }
+}
+
+/// Requests a chat completion, retrying rate limits and server errors with
+/// exponential backoff. Other errors are returned right away, as is the last
+/// error once `openai_timeout_secs` passed. openai-flows blocks while it waits
+/// for a response, so a request in flight is never cut short.
+pub(crate) async fn chat_completion(
+    chat_id: &str,
+    question: &str,
+    co: &ChatOptions<'_>,
+) -> Result<ChatResponse, ChatError> {
+    let mut openai = OpenAIFlows::new();
+    // openai-flows retries with a fixed interval, the backoff is done here instead
+    openai.set_retry_times(0);
+
+    let timeout = std::time::Duration::from_secs(openai_timeout_secs());
+    let started = std::time::Instant::now();
+    let mut attempt = 0;
+    loop {
+        let attempt_started = std::time::Instant::now();
+        count(Counter::OpenaiCalls);
+        let answer = openai.chat_completion(chat_id, question, co).await;
+        time_stage("openai", attempt_started);
+        let error = match answer {
+            Ok(r) => {
+                let system = co.system_prompt.unwrap_or("");
+                record(
+                    "completion",
+                    chat_issue(chat_id),
+                    json!({
+                        "chat_id": chat_id,
+                        "system_prompt": system,
+                        "prompt": question,
+                        "answer": r.choice,
+                    }),
+                );
+                let tokens_in = estimate_tokens(system) + estimate_tokens(question);
+                let tokens_out = estimate_tokens(&r.choice);
+                count_by(Counter::TokensIn, tokens_in as u64);
+                count_by(Counter::TokensOut, tokens_out as u64);
+                add_tokens(tokens_in + tokens_out);
+                return Ok(r);
+            }
+            Err(body) => ChatError::from_response(&body),
+        };
+        if !error.retryable || attempt >= OPENAI_RETRIES {
+            chat_event(Event::warn("openai"), chat_id)
+                .took(started)
+                .err(&error)
+                .log("the request failed");
+            error.report();
+            return Err(error);
+        }
+        let backoff = openai_backoff(attempt);
+        chat_event(Event::debug("openai"), chat_id)
+            .err(&error)
+            .log(&format!("retrying in {}ms", backoff.as_millis()));
+        if started.elapsed() + backoff > timeout {
+            chat_event(Event::warn("openai"), chat_id)
+                .took(started)
+                .err(&error)
+                .log("the request timed out");
+            let error = ChatError {
+                message: format!(
+                    "no answer within {}s, last error: {}",
+                    timeout.as_secs(),
+                    error.message
+                ),
+                kind: error.kind,
+                retryable: false,
+            };
+            error.report();
+            return Err(error);
+        }
+        tokio::time::sleep(backoff).await;
+        attempt += 1;
+    }
+}
+
+/// Checks that the model answers, which fails when the OpenAI key of the flow
+/// is missing or invalid.
+pub(crate) async fn check_model() -> Result<(), String> {
+    let co = ChatOptions {
+        model: ChatModel::GPT35Turbo,
+        restart: true,
+        system_prompt: None,
+    };
+    chat_completion("Selfcheck", "Reply with the single word OK.", &co)
+        .await
+        .map(|_r| ())
+        .map_err(|e| e.message)
+}
+
+pub(crate) const CONFIDENCE_INSTRUCTION: &str = "Finally, on a separate last line, rate how confident you are that your summary faithfully reflects the whole discussion, in the form 'Confidence: N' where N is an integer from 0 to 100.";
+
+pub(crate) const LOW_CONFIDENCE_THRESHOLD: u8 = 60;
+
+/// Removes the trailing `Confidence: N` line requested by [CONFIDENCE_INSTRUCTION]
+/// from the model's answer and returns the reported score.
+pub(crate) fn take_confidence(summary: &mut String) -> Option<u8> {
+    let re = Regex::new(r"(?im)^\W*confidence\W*(\d{1,3})\b.*$").unwrap();
+    let score = re
+        .captures(summary)
+        .and_then(|cap| cap.get(1))
+        .and_then(|n| n.as_str().parse::<u8>().ok())
+        .map(|n| n.min(100));
+    *summary = re.replace_all(summary, "").trim().to_string();
+    score
+}
+
+#[derive(Debug)]
+pub(crate) struct Confidence {
+    pub(crate) score: u8,
+    pub(crate) reasons: Vec<String>,
+}
+
+impl Confidence {
+    pub(crate) fn assess(
+        self_reported: Option<u8>,
+        empty_summary: bool,
+        (chunks_failed, chunks_total): (usize, usize),
+        (comments_read, comments_total): (usize, usize),
+    ) -> Confidence {
+        let mut score = self_reported.unwrap_or(70) as i32;
+        let mut reasons = Vec::new();
+
+        if empty_summary {
+            score = 0;
+            reasons.push("the model returned no summary".to_string());
+        }
+        if chunks_failed > 0 {
+            score -= (50 * chunks_failed / chunks_total.max(1)) as i32 + 10;
+            reasons.push(format!(
+                "{chunks_failed} of {chunks_total} chunks could not be summarized"
+            ));
+        }
+        if comments_read < comments_total {
+            score -= (30 * (comments_total - comments_read) / comments_total) as i32;
+            reasons.push(format!(
+                "only {comments_read} of {comments_total} comments were read"
+            ));
+        }
+        if self_reported.is_none() && !empty_summary {
+            reasons.push("the model did not report a confidence".to_string());
+        }
+
+        Confidence {
+            score: score.clamp(0, 100) as u8,
+            reasons,
+        }
+    }
+
+    pub(crate) fn is_low(&self) -> bool {
+        self.score < LOW_CONFIDENCE_THRESHOLD
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct ModerationVerdict {
+    #[serde(default)]
+    pub(crate) harassment: bool,
+    #[serde(default)]
+    pub(crate) slurs: bool,
+    #[serde(default)]
+    pub(crate) personal_data: bool,
+}
+
+/// Redacts e-mail addresses and phone numbers, which are caught without
+/// asking the model.
+pub(crate) fn redact_personal_data(text: &str) -> String {
+    let email = Regex::new(r"[\w.+-]+@[\w-]+(?:\.[\w-]+)+").unwrap();
+    let phone = Regex::new(r"\+?\d[\d ()-]{8,}\d").unwrap();
+    let text = email.replace_all(text, "[redacted]");
+    phone.replace_all(&text, "[redacted]").to_string()
+}
+
+/// Runs a summary through the content filter when `moderation` is on. E-mail
+/// addresses and phone numbers are redacted, and a summary the model finds
+/// harassing, containing slurs or other personal data is withheld with a
+/// notice pointing to the issue. A summary that cannot be checked is
+/// withheld as well.
+pub(crate) async fn moderate(issue_number: u64, url: &str, summary: String) -> String {
+    if !moderation_enabled() {
+        return summary;
+    }
+    let summary = redact_personal_data(&summary);
+    let chat_id = format!("Moderation#{issue_number}");
+    let co = ChatOptions {
+        model: ChatModel::GPT35Turbo,
+        restart: true,
+        system_prompt: Some("You review text before it is posted to a workplace chat. You always answer with a single JSON object and nothing else."),
+    };
+    let question = format!("Does the following text contain harassment or insults aimed at people, slurs, or personal data such as addresses, account credentials or identity numbers? Reply with a JSON object of the form {{\"harassment\": <bool>, \"slurs\": <bool>, \"personal_data\": <bool>}}.\n{summary}");
+
+    let verdict = match chat_completion(&chat_id, &question, &co).await {
+        Ok(r) => serde_json::from_str::<ModerationVerdict>(json_object(&r.choice)).ok(),
+        Err(_e) => None,
+    };
+    let reason = match verdict {
+        Some(v) if !v.harassment && !v.slurs && !v.personal_data => return summary,
+        Some(v) => [
+            (v.harassment, "harassment"),
+            (v.slurs, "slurs"),
+            (v.personal_data, "personal data"),
+        ]
+        .into_iter()
+        .filter(|(flagged, _)| *flagged)
+        .map(|(_, category)| category)
+        .collect::<Vec<_>>()
+        .join(", "),
+        None => "the content filter could not check it".to_string(),
+    };
+    Event::info("moderation")
+        .issue(issue_number)
+        .log(&format!("withheld: {reason}"));
+    format!(
+        "The summary of #{issue_number} was withheld ({reason}), please see GitHub directly: {url}"
+    )
+}
+
+pub(crate) fn json_object(text: &str) -> &str {
+    text.find('{')
+        .zip(text.rfind('}'))
+        .filter(|(start, end)| start < end)
+        .map(|(start, end)| &text[start..=end])
+        .unwrap_or("")
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct ReproInfo {
+    pub(crate) os: Option<String>,
+    pub(crate) version: Option<String>,
+    #[serde(default)]
+    pub(crate) steps: Vec<String>,
+}
+
+impl ReproInfo {
+    pub(crate) fn missing(&self) -> Vec<&'static str> {
+        let mut missing = Vec::new();
+        if self.os.is_none() {
+            missing.push("OS");
+        }
+        if self.version.is_none() {
+            missing.push("version");
+        }
+        if self.steps.is_empty() {
+            missing.push("reproduction steps");
+        }
+        missing
+    }
+}
+
+pub(crate) async fn extract_repro(
+    issue_number: u64,
+    issue_title: &str,
+    issue_body: &str,
+) -> Option<ReproInfo> {
+    let body_excerpt = truncate_tokens(issue_body, 2000);
+
+    let chat_id = format!("Repro#{issue_number}");
+    let co = ChatOptions {
+        model: ChatModel::GPT35Turbo,
+        restart: true,
+        system_prompt: Some("You triage GitHub bug reports. You always answer with a single JSON object and nothing else."),
+    };
+    let question = format!("From the bug report titled '{issue_title}' with the following post: '{body_excerpt}', extract the operating system, the affected software version, and the minimal steps to reproduce the problem. Reply with a JSON object of the form {{\"os\": <string or null>, \"version\": <string or null>, \"steps\": [<string>, ...]}}. Use null or an empty list when the report does not state the information; never guess.");
+
+    match chat_completion(&chat_id, &question, &co).await {
+        Ok(r) => serde_json::from_str::<ReproInfo>(json_object(&r.choice))
+            .ok()
+            .map(|mut info| {
+                info.os = info.os.filter(|s| !s.trim().is_empty());
+                info.version = info.version.filter(|s| !s.trim().is_empty());
+                info.steps.retain(|s| !s.trim().is_empty());
+                info
+            }),
+        Err(_e) => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum IssueType {
+    Bug,
+    FeatureRequest,
+    Question,
+    Support,
+}
+
+impl IssueType {
+    const ALL: [IssueType; 4] = [
+        IssueType::Bug,
+        IssueType::FeatureRequest,
+        IssueType::Question,
+        IssueType::Support,
+    ];
+
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            IssueType::Bug => "bug",
+            IssueType::FeatureRequest => "feature_request",
+            IssueType::Question => "question",
+            IssueType::Support => "support",
+        }
+    }
+
+    pub(crate) fn parse(s: &str) -> Option<IssueType> {
+        match s.trim().to_lowercase().as_str() {
+            "bug" | "bugs" => Some(IssueType::Bug),
+            "feature" | "features" | "feature_request" | "enhancement" => {
+                Some(IssueType::FeatureRequest)
+            }
+            "question" | "questions" => Some(IssueType::Question),
+            "support" => Some(IssueType::Support),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn from_labels(labels: &[String]) -> Option<IssueType> {
+        labels.iter().find_map(|label| {
+            let label = label.to_lowercase();
+            if label.contains("bug") {
+                Some(IssueType::Bug)
+            } else if label.contains("feature") || label.contains("enhancement") {
+                Some(IssueType::FeatureRequest)
+            } else if label.contains("question") {
+                Some(IssueType::Question)
+            } else if label.contains("support") {
+                Some(IssueType::Support)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+pub(crate) async fn classify_issue(
+    issue_number: u64,
+    issue_title: &str,
+    issue_body: &str,
+    labels: &[String],
+) -> IssueType {
+    if let Some(issue_type) = IssueType::from_labels(labels) {
+        return issue_type;
+    }
+
+    #[derive(Deserialize)]
+    struct Classification {
+        #[serde(rename = "type")]
+        issue_type: IssueType,
+    }
+
+    let body_excerpt = truncate_tokens(issue_body, 1000);
+
+    let chat_id = format!("Classify#{issue_number}");
+    let co = ChatOptions {
+        model: ChatModel::GPT35Turbo,
+        restart: true,
+        system_prompt: Some("You triage GitHub issues. You always answer with a single JSON object and nothing else."),
+    };
+    let allowed = IssueType::ALL
+        .iter()
+        .map(|t| format!("\"{}\"", t.as_str()))
+        .collect::<Vec<String>>()
+        .join(", ");
+    let question = format!("Classify the GitHub issue titled '{issue_title}' with the following post: '{body_excerpt}'. Reply with a JSON object of the form {{\"type\": \"<type>\"}} where <type> is exactly one of {allowed}.");
+
+    match chat_completion(&chat_id, &question, &co).await {
+        Ok(r) => serde_json::from_str::<Classification>(json_object(&r.choice))
+            .map(|c| c.issue_type)
+            .unwrap_or(IssueType::Question),
+        Err(_e) => IssueType::Question,
+    }
+}
+
+pub(crate) async fn get_sentiment(issue_number: u64, summary: &str) -> String {
+    let chat_id = format!("Sentiment#{issue_number}");
+    let co = ChatOptions {
+        model: ChatModel::GPT35Turbo,
+        restart: true,
+        system_prompt: Some("You rate the overall tone of GitHub issue discussions."),
+    };
+    let question = format!("Based on the following issue summary, reply with exactly one word describing the tone of the discussion: positive, neutral, or negative.\n{summary}");
+
+    match chat_completion(&chat_id, &question, &co).await {
+        Ok(r) => {
+            let answer = r.choice.to_lowercase();
+            ["positive", "negative"]
+                .into_iter()
+                .find(|s| answer.contains(s))
+                .unwrap_or("neutral")
+                .to_string()
+        }
+        Err(_e) => "neutral".to_string(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct HeatAssessment {
+    pub(crate) score: u8,
+    pub(crate) reason: String,
+}
+
+pub(crate) async fn assess_heat(
+    issues: &impl IssueSource,
+    route: &IssueRoute,
+    issue: &Issue,
+    since: DateTime<Utc>,
+) -> Option<HeatAssessment> {
+    let comments = issues
+        .comments_since(&route.owner, &route.repo, issue, since)
+        .await?;
+
+    let mut recent = Vec::new();
+    for (commenter, comment) in redact_opted_out(comments).into_iter().rev() {
+        recent.push(format!("{commenter} commented: {comment}"));
+        if count_tokens(&recent.join("\n")) > 2800 {
+            recent.pop();
+            break;
+        }
+    }
+    recent.reverse();
+    let recent = recent.join("\n");
+
+    let chat_id = format!("Heat#{}", issue.number);
+    let co = ChatOptions {
+        model: ChatModel::GPT35Turbo,
+        restart: true,
+        system_prompt: Some("You help maintainers moderate GitHub discussions. You always answer with a single JSON object and nothing else."),
+    };
+    let question = format!("The following are the most recent comments on the GitHub issue titled '{}':\n{recent}\nRate how heated this discussion is, considering hostility, frustration, personal attacks and escalating disagreement. Reply with a JSON object of the form {{\"score\": <integer from 0 to 100>, \"reason\": \"<one sentence explaining why the thread is or is not heated>\"}}.", issue.title);
+
+    match chat_completion(&chat_id, &question, &co).await {
+        Ok(r) => serde_json::from_str::<HeatAssessment>(json_object(&r.choice)).ok(),
+        Err(_e) => None,
+    }
+}
+
+pub(crate) async fn suggest_labels(owner: &str, repo: &str, issue: &Issue) -> Vec<LabelSuggestion> {
+    let octocrab = get_octo(&Default);
+    let repo_labels = match github_request(None, || async {
+        octocrab
+            .issues(owner, repo)
+            .list_labels_for_repo()
+            .per_page(100u8)
+            .send()
+            .await
+    })
+    .await
+    {
+        Ok(page) => page
+            .items
+            .into_iter()
+            .map(|lab| lab.name)
+            .collect::<Vec<String>>(),
+        Err(e) => {
+            Event::warn("labels")
+                .err(&e)
+                .log(&format!("the labels of {owner}/{repo} could not be read"));
+            return Vec::new();
+        }
+    };
+    if repo_labels.is_empty() {
+        return Vec::new();
+    }
+
+    #[derive(Deserialize)]
+    struct Suggestions {
+        labels: Vec<LabelSuggestion>,
+    }
+
+    let body_excerpt = truncate_tokens(issue.body.as_deref().unwrap_or(""), 2000);
+
+    let chat_id = format!("Labels#{}", issue.number);
+    let co = ChatOptions {
+        model: ChatModel::GPT35Turbo,
+        restart: true,
+        system_prompt: Some("You triage GitHub issues. You always answer with a single JSON object and nothing else."),
+    };
+    let question = format!("The repository {owner}/{repo} uses the following labels: {}. Suggest the labels that apply to the issue titled '{}' with the following post: '{body_excerpt}'. Only use labels from the list. Reply with a JSON object of the form {{\"labels\": [{{\"name\": \"<label>\", \"confidence\": <integer from 0 to 100>}}]}}.", repo_labels.join(", "), issue.title);
+
+    let existing = issue
+        .labels
+        .iter()
+        .map(|lab| lab.name.to_lowercase())
+        .collect::<Vec<String>>();
+    match chat_completion(&chat_id, &question, &co).await {
+        Ok(r) => serde_json::from_str::<Suggestions>(json_object(&r.choice))
+            .map(|s| s.labels)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|mut suggestion| {
+                // Use the repository's spelling of the label.
+                suggestion.name = repo_labels
+                    .iter()
+                    .find(|lab| lab.eq_ignore_ascii_case(suggestion.name.trim()))?
+                    .to_string();
+                suggestion.confidence = suggestion.confidence.min(100);
+                (!existing.contains(&suggestion.name.to_lowercase())).then_some(suggestion)
+            })
+            .collect(),
+        Err(_e) => Vec::new(),
+    }
+}
+
+pub(crate) async fn get_postmortem(
+    issues: &impl IssueSource,
+    owner: &str,
+    repo: &str,
+    issue: &Issue,
+) -> String {
+    let references = resolution_references(owner, repo, issue.number).await;
+
+    let body_excerpt = truncate_tokens(issue.body.as_deref().unwrap_or(""), 1000);
+
+    // The resolution is usually discussed at the end of the thread, so the
+    // most recent comments that fit the budget are used.
+    let comments = redact_opted_out(issues.comments(owner, repo, issue).await);
+    let mut budget = 2400;
+    let mut recent = Vec::new();
+    for (commenter, comment) in comments.into_iter().rev() {
+        let comment = format!("{commenter} commented: {comment}");
+        let tokens = count_tokens(&comment);
+        if tokens > budget {
+            break;
+        }
+        budget -= tokens;
+        recent.push(comment);
+    }
+    recent.reverse();
+
+    let references_text = match references.is_empty() {
+        true => "none found".to_string(),
+        false => references.join("; "),
+    };
+
+    let chat_id = format!("Postmortem#{}", issue.number);
+    let co = ChatOptions {
+        model: ChatModel::GPT35Turbo,
+        restart: true,
+        system_prompt: Some("As an AI co-owner of a GitHub repository, you write short resolution reports for closed GitHub issues so that the team understands how they were resolved."),
+    };
+    let question = format!("The issue titled '{}' has been closed. It was opened with the following post: '{body_excerpt}'. The last comments of the discussion are:\n{}\nPull requests and commits linked to the issue: {references_text}.\nWrite a resolution summary with three short sections: 'Root cause:' explaining what caused the problem, 'Fix:' explaining what resolved it, and 'Involved:' listing the pull requests and commits that were part of the fix. Say so when the discussion does not make one of them clear.", issue.title, recent.join("\n"));
+
+    match chat_completion(&chat_id, &question, &co).await {
+        Ok(r) => r.choice,
+        Err(_e) => format!(
+            "The resolution summary could not be generated. Linked pull requests and commits: {references_text}."
+        ),
+    }
+}
+
+pub(crate) async fn get_release_announcement(
+    owner: &str,
+    repo: &str,
+    release: &serde_json::Value,
+) -> String {
+    let name = release["name"]
+        .as_str()
+        .filter(|n| !n.is_empty())
+        .or(release["tag_name"].as_str())
+        .unwrap_or("a new release");
+    let url = release["html_url"].as_str().unwrap_or("");
+
+    let notes = truncate_tokens(release["body"].as_str().unwrap_or(""), 2000);
+
+    let pull_requests =
+        notable_pull_requests(owner, repo, release["id"].as_u64().unwrap_or(0)).await;
+
+    let chat_id = format!("Release#{owner}/{repo}");
+    let co = ChatOptions {
+        model: ChatModel::GPT35Turbo,
+        restart: true,
+        system_prompt: Some("You announce software releases to the team chat of the project in a friendly, concise way that fits a Slack channel."),
+    };
+    let question = format!("{owner}/{repo} has published {name}. The release notes are: '{notes}'. The most discussed pull requests merged since the previous release are: {}. Write a short announcement that highlights what is new and most notable for users, mentioning the notable pull requests by number. Do not use headings.", pull_requests.join("; "));
+
+    let body = match chat_completion(&chat_id, &question, &co).await {
+        Ok(r) => r.choice,
+        Err(_e) => notes,
+    };
+    format!(":tada: {owner}/{repo} {name} has been released!\n{body}\n{url}")
+}
+
+/// Lists the issues closed as completed between the previous release and this
+/// one, each with a one-line summary.
+pub(crate) async fn get_release_fixes(
+    owner: &str,
+    repo: &str,
+    release: &serde_json::Value,
+) -> Option<String> {
+    let release_id = release["id"].as_u64().unwrap_or(0);
+    let previous = previous_release_date(owner, repo, release_id).await?;
+    let published = release["published_at"]
+        .as_str()
+        .and_then(|d| d.parse::<DateTime<Utc>>().ok())
+        .unwrap_or(Utc::now());
+    let tag = release["tag_name"].as_str().unwrap_or("this release");
+
+    let octocrab = get_octo(&Default);
+    let query = format!(
+        "repo:{owner}/{repo} is:issue is:closed reason:completed closed:{}..{}",
+        previous.format("%Y-%m-%dT%H:%M:%SZ"),
+        published.format("%Y-%m-%dT%H:%M:%SZ")
+    );
+    let page = github_request(None, || async {
+        octocrab
+            .search()
+            .issues_and_pull_requests(&query)
+            .per_page(RELEASE_FIXES_LISTED as u8)
+            .send()
+            .await
+    })
+    .await
+    .ok()?;
+    if page.items.is_empty() {
+        return None;
+    }
+
+    let mut fixes = vec![format!(":package: What {tag} of {owner}/{repo} fixes:")];
+    for issue in &page.items {
+        let summary = get_one_line_summary(
+            issue.number,
+            &issue.title,
+            issue.body.as_deref().unwrap_or(""),
+        )
+        .await;
+        fixes.push(format!(
+            "- #{} {}: {summary}\n  {}",
+            issue.number, issue.title, issue.html_url
+        ));
+    }
+    let total = page.total_count.unwrap_or(0) as usize;
+    if total > page.items.len() {
+        fixes.push(format!(
+            "…and {} more closed issues.",
+            total - page.items.len()
+        ));
+    }
+    Some(fixes.join("\n"))
+}
+
+pub(crate) async fn get_pr_summary(pull: &PullRequest) -> String {
+    let body = truncate_tokens(pull.body.as_deref().unwrap_or(""), 2800);
+
+    let chat_id = format!("PR#{}", pull.number);
+    let co = ChatOptions {
+        model: ChatModel::GPT35Turbo,
+        restart: true,
+        system_prompt: Some(
+            "As an AI assistant, you summarize GitHub pull requests for their reviewers.",
+        ),
+    };
+    let question = format!(
+        "Summarize in one or two sentences what the pull request titled '{}' changes and why, based on its description:\n{body}",
+        pull.title.as_deref().unwrap_or("")
+    );
+
+    match chat_completion(&chat_id, &question, &co).await {
+        Ok(r) => r.choice,
+        Err(_e) => String::new(),
+    }
+}
+
+pub(crate) async fn get_milestone_report(
+    owner: &str,
+    repo: &str,
+    milestone: &serde_json::Value,
+    days_left: i64,
+) -> String {
+    let title = milestone["title"].as_str().unwrap_or("");
+    let open = milestone["open_issues"].as_u64().unwrap_or(0);
+    let closed = milestone["closed_issues"].as_u64().unwrap_or(0);
+    let total = open + closed;
+    let done = match total {
+        0 => 100,
+        total => closed * 100 / total,
+    };
+
+    let octocrab = get_octo(&Default);
+    let yesterday = (Utc::now() - Duration::days(1)).format("%Y-%m-%dT%H:%M:%SZ");
+    let closed_today = count_issues(&format!(
+        "repo:{owner}/{repo} is:issue milestone:\"{title}\" closed:>{yesterday}"
+    ))
+    .await;
+    let remaining = match github_request(None, || async {
+        octocrab
+            .search()
+            .issues_and_pull_requests(&format!(
+                "repo:{owner}/{repo} is:issue is:open milestone:\"{title}\""
+            ))
+            .per_page(30u8)
+            .send()
+            .await
+    })
+    .await
+    {
+        Ok(page) => page.items,
+        Err(e) => {
+            Event::warn("milestones").err(&e).log(&format!(
+                "the issues of milestone {title} could not be read"
+            ));
+            Vec::new()
+        }
+    };
+    let remaining = remaining
+        .iter()
+        .map(|issue| {
+            let labels = issue
+                .labels
+                .iter()
+                .map(|lab| lab.name.clone())
+                .collect::<Vec<String>>()
+                .join(", ");
+            format!(
+                "#{} {} [{labels}] ({} comments, updated {})",
+                issue.number,
+                issue.title,
+                issue.comments,
+                issue.updated_at.format("%Y-%m-%d")
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    let when = match days_left {
+        0 => "is due today".to_string(),
+        1 => "is due tomorrow".to_string(),
+        n => format!("is due in {n} days"),
+    };
+    let mut report = format!(
+        ":calendar: Milestone {title} of {owner}/{repo} {when}: {closed}/{total} issues closed ({done}%), {closed_today} closed in the last day, {open} remaining."
+    );
+    if remaining.is_empty() {
+        report.push_str(&format!(
+            "\n{}",
+            milestone["html_url"].as_str().unwrap_or("")
+        ));
+        return report;
+    }
+
+    let chat_id = format!("Milestone#{}", milestone["number"]);
+    let co = ChatOptions {
+        model: ChatModel::GPT35Turbo,
+        restart: true,
+        system_prompt: Some(
+            "As an AI release manager, you help maintainers ship their milestones on time.",
+        ),
+    };
+    let question = format!("The milestone '{title}' {when} and still has these open issues:\n{remaining}\nIn a few sentences, summarize the remaining work, point out the issues most at risk of missing the due date and say whether the milestone looks on track.");
+
+    if let Ok(r) = chat_completion(&chat_id, &question, &co).await {
+        report.push_str(&format!("\n{}", r.choice));
+    }
+    report.push_str(&format!(
+        "\n{}",
+        milestone["html_url"].as_str().unwrap_or("")
+    ));
+    report
+}
+
+pub(crate) async fn get_one_line_summary(issue_number: u64, title: &str, body: &str) -> String {
+    let body = truncate_tokens(body, 1000);
+
+    let chat_id = format!("OneLine#{issue_number}");
+    let co = ChatOptions {
+        model: ChatModel::GPT35Turbo,
+        restart: true,
+        system_prompt: Some(
+            "As an AI assistant, you summarize GitHub issues in a single short sentence.",
+        ),
+    };
+    let question = format!("Summarize the GitHub issue titled '{title}' in one sentence of at most 25 words, based on its description:\n{body}");
+
+    match chat_completion(&chat_id, &question, &co).await {
+        Ok(r) => r.choice.trim().to_string(),
+        Err(_e) => title.to_string(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct CloseAssessment {
+    pub(crate) close: bool,
+    pub(crate) reason: String,
+}
+
+pub(crate) async fn assess_close(
+    issue: &Issue,
+    signals: &[String],
+    last_comment: &str,
+) -> Option<CloseAssessment> {
+    let last_comment = truncate_tokens(last_comment, 1000);
+
+    let chat_id = format!("Close#{}", issue.number);
+    let co = ChatOptions {
+        model: ChatModel::GPT35Turbo,
+        restart: true,
+        system_prompt: Some("You help maintainers keep their GitHub issue trackers tidy. You always answer with a single JSON object and nothing else."),
+    };
+    let question = format!("The open GitHub issue titled '{}' may already be resolved because {}.\n{last_comment}\nDecide whether the issue can be closed. Reply with a JSON object of the form {{\"close\": <true or false>, \"reason\": \"<one sentence explaining why>\"}}.", issue.title, signals.join(", "));
+
+    match chat_completion(&chat_id, &question, &co).await {
+        Ok(r) => serde_json::from_str::<CloseAssessment>(json_object(&r.choice)).ok(),
+        Err(_e) => None,
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct CachedSummary {
+    /// The issue's `updated_at` when it was summarized.
+    pub(crate) updated_at: DateTime<Utc>,
+    pub(crate) summary: IssueSummary,
+    #[serde(default)]
+    pub(crate) language: Option<String>,
+}
+
+/// Summaries kept per repository for the history command.
+pub(crate) const HISTORY_KEPT: usize = 50;
+
+/// A summary that was posted, kept for the history command.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct HistoryEntry {
+    pub(crate) issue_number: u64,
+    pub(crate) title: String,
+    pub(crate) summarized_at: DateTime<Utc>,
+    pub(crate) text: String,
+}
+
+pub(crate) fn history_key(owner: &str, repo: &str) -> String {
+    format!("history:{}/{}", owner.to_lowercase(), repo.to_lowercase())
+}
+
+/// The kept summaries of the repository, oldest first.
+pub(crate) fn load_history(owner: &str, repo: &str) -> Vec<HistoryEntry> {
+    store::load(&history_key(owner, repo)).unwrap_or_default()
+}
+
+/// Keeps the summary as the latest of the issue, dropping the oldest
+/// summaries beyond `HISTORY_KEPT`.
+pub(crate) fn record_history(owner: &str, repo: &str, issue_number: u64, title: &str, text: &str) {
+    let mut history = load_history(owner, repo);
+    history.retain(|entry| entry.issue_number != issue_number);
+    history.push(HistoryEntry {
+        issue_number,
+        title: title.to_string(),
+        summarized_at: Utc::now(),
+        text: text.to_string(),
+    });
+    let excess = history.len().saturating_sub(HISTORY_KEPT);
+    history.drain(..excess);
+    store::save(&history_key(owner, repo), &history);
+}
+
+pub(crate) fn summary_cache_key(owner: &str, repo: &str, issue_number: u64) -> String {
+    format!("summary:{owner}/{repo}#{issue_number}")
+}
+
+/// Folds the comments posted since the previous summary into it, returning
+/// `None` when the summary could not be updated.
+pub(crate) async fn update_summary(
+    issues: &impl IssueSource,
+    owner: &str,
+    repo: &str,
+    issue: &Issue,
+    previous: &str,
+    since: DateTime<Utc>,
+) -> Option<(String, usize)> {
+    let new_comments = redact_opted_out(issues.comments_since(owner, repo, issue, since).await?);
+    if new_comments.is_empty() {
+        return Some((previous.to_string(), 0));
+    }
+
+    let mut delta = Vec::new();
+    for (commenter, comment) in &new_comments {
+        delta.push(format!("{commenter} commented: {comment}"));
+        if count_tokens(&delta.join("\n")) > 2800 {
+            // too much happened to fold in, summarize from scratch instead
+            return None;
+        }
+    }
+    let delta = delta.join("\n");
+
+    let chat_id = format!("Issue#{}", issue.number);
+    let co = ChatOptions {
+        model: ChatModel::GPT35Turbo,
+        restart: true,
+        system_prompt: Some("As an AI co-owner of a GitHub repository, you keep the summaries of GitHub issues up to date as their discussion goes on."),
+    };
+    let question = format!("This is the summary of the GitHub issue titled '{}' so far:\n{previous}\nSince then, these comments were posted:\n{delta}\nUpdate the summary so it reflects the whole discussion, concentrating on the principal arguments, suggested solutions, and areas of consensus or disagreement among the participants. Reply with the updated summary only, in the language of the summary so far.", issue.title);
+
+    match chat_completion(&chat_id, &question, &co).await {
+        Ok(r) if !r.choice.trim().is_empty() => Some((r.choice, new_comments.len())),
+        _ => None,
+    }
+}
+
+pub(crate) const CACHE_STATS_KEY: &str = "cache_stats";
+
+/// How `cached_summary` answered since the counters were first written:
+/// from the cache, by updating a cached summary with the new comments, or by
+/// summarizing the whole issue.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct CacheStats {
+    pub(crate) hits: u64,
+    pub(crate) updates: u64,
+    pub(crate) misses: u64,
+}
+
+impl CacheStats {
+    fn count(counter: fn(&mut CacheStats) -> &mut u64) {
+        let mut stats = store::load::<CacheStats>(CACHE_STATS_KEY).unwrap_or_default();
+        *counter(&mut stats) += 1;
+        store::save(CACHE_STATS_KEY, &stats);
+    }
+
+    /// The share of summaries that did not need the whole issue summarized.
+    pub(crate) fn hit_rate(&self) -> Option<f64> {
+        let total = self.hits + self.updates + self.misses;
+        (total > 0).then(|| (self.hits + self.updates) as f64 / total as f64)
+    }
+}
+
+/// Summarizes the issue for a Slack command, reusing the summary from a
+/// previous run when the issue has not changed since, and only folding in
+/// the new comments when it has.
+pub(crate) async fn cached_summary<I: IssueSource, S: Summarizer>(
+    deps: &Deps<'_, I, S>,
+    owner: &str,
+    repo: &str,
+    issue: Issue,
+    issue_type: IssueType,
+    language: Option<&str>,
+) -> Result<String> {
+    let key = summary_cache_key(owner, repo, issue.number);
+    let updated_at = issue.updated_at;
+    let cached = store::load::<CachedSummary>(&key)
+        .filter(|c| c.summary.issue_type == issue_type && c.language.as_deref() == language);
+
+    let (summary, note) = match cached {
+        Some(cached) if cached.updated_at >= updated_at => {
+            let note = format!(
+                "(Served from cache, the issue has not changed since {})",
+                cached.updated_at.format("%Y-%m-%d %H:%M UTC")
+            );
+            CacheStats::count(|s| &mut s.hits);
+            return Ok(format!("{}\n{note}", cached.summary.render()));
+        }
+        Some(mut cached) => {
+            match update_summary(
+                deps.issues,
+                owner,
+                repo,
+                &issue,
+                &cached.summary.summary,
+                cached.updated_at,
+            )
+            .await
+            {
+                Some((summary, new_comments)) => {
+                    CacheStats::count(|s| &mut s.updates);
+                    cached.summary.summary = summary;
+                    let note = format!(
+                        "(Updated with {new_comments} new comments since {})",
+                        cached.updated_at.format("%Y-%m-%d %H:%M UTC")
+                    );
+                    (cached.summary, Some(note))
+                }
+                None => {
+                    CacheStats::count(|s| &mut s.misses);
+                    (
+                        generate_summary(deps, owner, repo, issue, issue_type, language).await?,
+                        None,
+                    )
+                }
+            }
+        }
+        None => {
+            CacheStats::count(|s| &mut s.misses);
+            (
+                generate_summary(deps, owner, repo, issue, issue_type, language).await?,
+                None,
+            )
+        }
+    };
+
+    // failed summaries are not cached so the next request tries again
+    if !summary.summary.is_empty() {
+        let cache = CachedSummary {
+            updated_at,
+            summary: summary.clone(),
+            language: language.map(str::to_string),
+        };
+        store::save(&key, &cache);
+    }
+
+    Ok(match note {
+        Some(note) => format!("{}\n{note}", summary.render()),
+        None => summary.render(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fakes::{
+        assert_snapshot, FixtureIssues, MockSummarizer, CHUNKED_ISSUE, LARGE_ISSUE, SMALL_ISSUE,
+    };
+
+    fn segment(text: &str, tokens: usize) -> Segment {
+        Segment {
+            text: text.to_string(),
+            tokens,
+        }
+    }
+
+    #[test]
+    fn segments_are_packed_until_a_chunk_is_full() {
+        let bpe = tokenizer().unwrap();
+        let segments = vec![segment("a", 3), segment("b", 3), segment("c", 3)];
+        // each segment takes a token more for the newline joining it
+        assert_eq!(pack_segments(bpe, segments, 8).unwrap(), ["a\nb", "c"]);
+
+        let segments = vec![segment("a", 3), segment("b", 3)];
+        assert_eq!(pack_segments(bpe, segments, 7).unwrap(), ["a", "b"]);
+    }
+
+    #[test]
+    fn small_discussions_are_summarized_in_one_request() {
+        let fixture = FixtureIssues::load(SMALL_ISSUE);
+        let ctx = fixture.context();
+        let Prompts::Single(prompt) = ctx.prompts(tokenizer().unwrap()).unwrap() else {
+            panic!("the discussion fits into one request");
+        };
+        assert!(prompt.contains("titled 'Crash when the config file is empty', labeled as 'bug'"));
+        for (commenter, comment) in &ctx.comments {
+            assert!(prompt.contains(&format!("{commenter} commented: {comment}")));
+        }
+        assert!(prompt.ends_with(CONFIDENCE_INSTRUCTION));
+    }
+
+    #[test]
+    fn long_discussions_are_packed_into_chunks_of_whole_comments() {
+        let bpe = tokenizer().unwrap();
+        let ctx = FixtureIssues::load(LARGE_ISSUE).context();
+        let segments = ctx.segments(bpe);
+        assert!(Segment::total_tokens(&segments) > CHUNK_TOKENS);
+        let texts = segments.iter().map(|s| s.text.clone()).collect::<Vec<_>>();
+
+        let chunks = pack_segments(bpe, segments, CHUNK_TOKENS).unwrap();
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            assert!(bpe.encode_ordinary(chunk).len() <= CHUNK_TOKENS);
+        }
+        for text in &texts {
+            assert_eq!(chunks.iter().filter(|c| c.contains(text)).count(), 1);
+        }
+
+        let Prompts::MapReduce {
+            map_prompts,
+            left_out_parts,
+        } = ctx.prompts(bpe).unwrap()
+        else {
+            panic!("the discussion is longer than a chunk");
+        };
+        assert_eq!(left_out_parts, 0);
+        assert_eq!(
+            map_prompts,
+            chunks.iter().map(|c| ctx.map_prompt(c)).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn comments_longer_than_a_chunk_are_split() {
+        let bpe = tokenizer().unwrap();
+        let ctx = FixtureIssues::load(CHUNKED_ISSUE).context();
+        let segments = ctx.segments(bpe);
+        assert!(segments[1].tokens > CHUNK_TOKENS);
+        let texts = segments.iter().map(|s| s.text.clone()).collect::<Vec<_>>();
+
+        let chunks = pack_segments(bpe, segments, CHUNK_TOKENS).unwrap();
+        assert!(chunks.len() >= 4);
+        assert_eq!(chunks[0], texts[0]);
+        assert_eq!(chunks[1..chunks.len() - 1].concat(), texts[1]);
+        assert_eq!(chunks[chunks.len() - 1], texts[2]);
+        for chunk in &chunks {
+            assert!(bpe.encode_ordinary(chunk).len() <= CHUNK_TOKENS);
+        }
+    }
+
+    #[test]
+    fn the_chunk_summaries_are_combined() {
+        let summarizer = MockSummarizer::default();
+        let ctx = FixtureIssues::load(LARGE_ISSUE).context();
+        let mut summary = futures::executor::block_on(summarizer.summarize(ctx)).unwrap();
+
+        let prompts = summarizer.prompts.borrow();
+        assert_eq!(prompts.len(), summary.parts + 1);
+        assert!(prompts[prompts.len() - 1].contains(
+            "in segmented form are: Interim summary of part 1. Interim summary of part 2."
+        ));
+        assert_eq!(take_confidence(&mut summary.text), Some(85));
+        assert_eq!(
+            summary.text,
+            "Incremental builds are slow after the file watcher rewrite is being discussed."
+        );
+    }
+
+    /// Every prompt the summary of the recorded issue sends, in order.
+    fn prompts(fixture: &str) -> String {
+        let summarizer = MockSummarizer::default();
+        let ctx = FixtureIssues::load(fixture).context();
+        let mut prompts = vec![ctx.system_prompt()];
+        futures::executor::block_on(summarizer.summarize(ctx)).unwrap();
+        prompts.extend(summarizer.prompts.take());
+        prompts
+            .iter()
+            .enumerate()
+            .map(|(i, prompt)| match i {
+                0 => format!("--- system ---\n{prompt}\n"),
+                i => format!("--- prompt {i} of {} ---\n{prompt}\n", prompts.len() - 1),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn prompts_of_small_issues() {
+        assert_snapshot!("prompts_small", prompts(SMALL_ISSUE));
+    }
+
+    #[test]
+    fn prompts_of_large_issues() {
+        assert_snapshot!("prompts_large", prompts(LARGE_ISSUE));
+    }
+
+    #[test]
+    fn prompts_of_issues_with_comments_longer_than_a_chunk() {
+        assert_snapshot!("prompts_chunked", prompts(CHUNKED_ISSUE));
+    }
+
+    #[test]
+    fn the_confidence_line_is_taken_from_the_summary() {
+        let mut summary = "The fix is agreed on.\n\nConfidence: 85".to_string();
+        assert_eq!(take_confidence(&mut summary), Some(85));
+        assert_eq!(summary, "The fix is agreed on.");
+
+        let mut summary = "The fix is agreed on.\n**Confidence**: 250%".to_string();
+        assert_eq!(take_confidence(&mut summary), Some(100));
+        assert_eq!(summary, "The fix is agreed on.");
+
+        let mut summary = "The fix is agreed on.".to_string();
+        assert_eq!(take_confidence(&mut summary), None);
+        assert_eq!(summary, "The fix is agreed on.");
+    }
+
+    #[test]
+    fn the_confidence_drops_with_what_was_not_read() {
+        let confidence = Confidence::assess(Some(90), false, (0, 3), (20, 20));
+        assert_eq!(confidence.score, 90);
+        assert!(confidence.reasons.is_empty());
+
+        let confidence = Confidence::assess(None, false, (0, 1), (5, 5));
+        assert_eq!(confidence.score, 70);
+        assert_eq!(
+            confidence.reasons,
+            ["the model did not report a confidence"]
+        );
+
+        let confidence = Confidence::assess(Some(90), false, (1, 4), (10, 20));
+        assert_eq!(confidence.score, 90 - 22 - 15);
+        assert_eq!(
+            confidence.reasons,
+            [
+                "1 of 4 chunks could not be summarized",
+                "only 10 of 20 comments were read"
+            ]
+        );
+        assert!(confidence.is_low());
+
+        let confidence = Confidence::assess(Some(90), true, (0, 1), (5, 5));
+        assert_eq!(confidence.score, 0);
+        assert_eq!(confidence.reasons, ["the model returned no summary"]);
+    }
+}
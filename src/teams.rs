@@ -0,0 +1,129 @@
+//! Commands from Microsoft Teams, received through an outgoing webhook.
+
+use crate::command::handler;
+use crate::config::{self, teams_channels, TEAMS_WORKSPACE};
+use crate::logging::{start_run, Event};
+use crate::sink::send_message;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac_sha256::HMAC;
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use slack_flows::SlackMessage;
+use std::collections::HashMap;
+use std::time::Instant;
+use webhook_flows::send_response;
+
+#[derive(Debug, Deserialize)]
+struct TeamsMessage {
+    #[serde(default)]
+    text: String,
+    from: TeamsUser,
+}
+
+#[derive(Debug, Deserialize)]
+struct TeamsUser {
+    id: String,
+}
+
+/// Whether the request is signed with the security token Teams gave when
+/// the outgoing webhook was created, read from the `teams_webhook_secret`
+/// environment variable. Teams sends the base64 HMAC-SHA256 of the body in
+/// the `Authorization: HMAC <signature>` header.
+pub(crate) fn is_signed(headers: &[(String, String)], body: &[u8]) -> bool {
+    let Some(key) =
+        config::var("teams_webhook_secret").and_then(|s| STANDARD.decode(s.trim()).ok())
+    else {
+        return false;
+    };
+    let expected = STANDARD.encode(HMAC::mac(body, key));
+    headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("authorization"))
+        .filter_map(|(_, value)| value.strip_prefix("HMAC "))
+        .any(|signature| signature.trim() == expected)
+}
+
+/// The text of a Teams message as the commands expect it: the mention of
+/// the webhook is dropped and the HTML Teams sends the text as is stripped.
+pub(crate) fn teams_command_text(text: &str) -> String {
+    let mention = Regex::new(r"<at>[^<]*</at>").unwrap();
+    let tag = Regex::new(r"<[^>]+>").unwrap();
+    let text = mention.replace_all(text, "");
+    let text = tag.replace_all(&text, " ");
+    text.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&amp;", "&")
+        .trim()
+        .to_string()
+}
+
+fn respond(status: u16, text: &str) {
+    send_response(
+        status,
+        vec![("Content-Type".to_string(), "application/json".to_string())],
+        json!({ "type": "message", "text": text })
+            .to_string()
+            .into_bytes(),
+    );
+}
+
+/// Handles a request of the outgoing webhook of a Teams channel like a Slack
+/// message. The webhook URL names the channel of `teams_channels` it is
+/// for, as in `?channel=<name>`. Teams waits only a few seconds for the
+/// response, so the request is acknowledged first and the results are
+/// posted through the incoming webhook of the channel.
+pub(crate) fn handle_teams_request(
+    headers: Vec<(String, String)>,
+    query: HashMap<String, Value>,
+    body: Vec<u8>,
+) {
+    start_run("teams");
+    if !is_signed(&headers, &body) {
+        Event::warn("teams").log("refused a request without a valid signature");
+        respond(
+            401,
+            "The request is not signed with the webhook's security token.",
+        );
+        return;
+    }
+    let channel = query
+        .get("channel")
+        .and_then(|c| c.as_str())
+        .unwrap_or_default()
+        .to_string();
+    if !teams_channels().contains_key(&channel) {
+        respond(
+            200,
+            &format!("The webhook is not set up for a channel of teams_channels ({channel})."),
+        );
+        return;
+    }
+    let message = match serde_json::from_slice::<TeamsMessage>(&body) {
+        Ok(message) => message,
+        Err(e) => {
+            Event::warn("teams").err(e).log("not a Teams message");
+            respond(400, "The request is not a Teams message.");
+            return;
+        }
+    };
+    respond(200, "Working on it.");
+
+    let sm = SlackMessage {
+        event_type: "message".to_string(),
+        channel: channel.clone(),
+        user: message.from.id,
+        text: teams_command_text(&message.text),
+        channel_type: "channel".to_string(),
+    };
+    let started = Instant::now();
+    match handler(TEAMS_WORKSPACE, &channel, sm) {
+        Ok(()) => Event::info("command").took(started).log("handled"),
+        Err(e) => {
+            Event::error("command").took(started).err(&e).log("failed");
+            send_message(TEAMS_WORKSPACE, &channel, format!(":warning: {e}"));
+        }
+    }
+}
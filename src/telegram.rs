@@ -0,0 +1,111 @@
+//! Commands from Telegram chats, received through the Bot API webhook.
+
+use crate::command::handler;
+use crate::config::{self, telegram_chats, TELEGRAM_WORKSPACE};
+use crate::logging::{start_run, Event};
+use crate::sink::send_message;
+use serde::Deserialize;
+use slack_flows::SlackMessage;
+use std::time::Instant;
+use webhook_flows::send_response;
+
+/// The header the Bot API sends the `secret_token` of the webhook in.
+pub(crate) const SECRET_HEADER: &str = "x-telegram-bot-api-secret-token";
+
+#[derive(Debug, Deserialize)]
+struct Update {
+    message: Option<TelegramMessage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TelegramMessage {
+    chat: Chat,
+    from: Option<TelegramUser>,
+    text: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Chat {
+    id: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct TelegramUser {
+    id: i64,
+    #[serde(default)]
+    is_bot: bool,
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+/// Whether the webhook request comes from the Bot API rather than Teams.
+pub(crate) fn is_telegram_request(headers: &[(String, String)]) -> bool {
+    header(headers, SECRET_HEADER).is_some()
+}
+
+/// The text of a Telegram message as the commands expect it: a leading `/`
+/// is dropped, so `/flows summarize` also works where the bot only sees
+/// commands, and so is the `@<bot>` suffix Telegram adds to commands in
+/// groups.
+pub(crate) fn telegram_command_text(text: &str) -> String {
+    let text = text.trim().trim_start_matches('/');
+    match text.split_once(char::is_whitespace) {
+        Some((first, rest)) => format!("{} {rest}", first.split('@').next().unwrap_or(first)),
+        None => text.split('@').next().unwrap_or(text).to_string(),
+    }
+}
+
+/// Handles an update of the Bot API webhook like a Slack message. The
+/// webhook is registered with a `secret_token`, read from the
+/// `telegram_webhook_secret` environment variable, and updates without it
+/// are refused. Only the chats of `telegram_chats` are served.
+pub(crate) fn handle_telegram_request(headers: Vec<(String, String)>, body: Vec<u8>) {
+    start_run("telegram");
+    let secret = config::var("telegram_webhook_secret").unwrap_or_default();
+    if secret.trim().is_empty() || header(&headers, SECRET_HEADER) != Some(secret.trim()) {
+        Event::warn("telegram").log("refused an update without the webhook's secret");
+        send_response(401, vec![], vec![]);
+        return;
+    }
+    // the Bot API resends updates until they are acknowledged
+    send_response(200, vec![], vec![]);
+
+    let update = match serde_json::from_slice::<Update>(&body) {
+        Ok(update) => update,
+        Err(e) => {
+            Event::warn("telegram").err(e).log("not a Telegram update");
+            return;
+        }
+    };
+    let Some(message) = update.message else {
+        return;
+    };
+    let (Some(from), Some(text)) = (message.from, message.text) else {
+        return;
+    };
+    let chat = message.chat.id.to_string();
+    if from.is_bot || !telegram_chats().contains(&chat) {
+        return;
+    }
+
+    let sm = SlackMessage {
+        event_type: "message".to_string(),
+        channel: chat.clone(),
+        user: from.id.to_string(),
+        text: telegram_command_text(&text),
+        channel_type: "channel".to_string(),
+    };
+    let started = Instant::now();
+    match handler(TELEGRAM_WORKSPACE, &chat, sm) {
+        Ok(()) => Event::info("command").took(started).log("handled"),
+        Err(e) => {
+            Event::error("command").took(started).err(&e).log("failed");
+            send_message(TELEGRAM_WORKSPACE, &chat, format!(":warning: {e}"));
+        }
+    }
+}
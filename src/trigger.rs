@@ -0,0 +1,199 @@
+//! The generic webhook trigger, which lets external systems such as Zapier,
+//! a cron job or CI start a digest with a JSON payload.
+
+use crate::audit::audit;
+use crate::command::{enter_run_queue, leave_run_queue, summarize};
+use crate::config::{
+    self, issue_limit, repo_refusal, resolved_settings, served_channels, OutputMode,
+};
+use crate::deps::Deps;
+use crate::linear::{linear_team, LINEAR_OWNER};
+use crate::logging::{run_id, start_run, Event};
+use crate::sink::send_message;
+use serde::Deserialize;
+use serde_json::json;
+use webhook_flows::send_response;
+
+/// The header the `trigger_token` is sent in.
+const TOKEN_HEADER: &str = "x-summarizer-token";
+
+/// The payload of a trigger, such as
+/// `{"repo": "WasmEdge/WasmEdge", "days": 7, "channel": "general"}`.
+#[derive(Debug, Deserialize)]
+struct TriggerPayload {
+    repo: String,
+    days: Option<i64>,
+    channel: String,
+    #[serde(default)]
+    labels: Vec<String>,
+    limit: Option<usize>,
+    output: Option<String>,
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+pub(crate) fn is_trigger_request(headers: &[(String, String)]) -> bool {
+    header(headers, TOKEN_HEADER).is_some()
+}
+
+/// Whether the trigger is set up, with the token it is called with in
+/// `trigger_token`.
+pub(crate) fn trigger_enabled() -> bool {
+    config::var("trigger_token").is_some_and(|token| !token.trim().is_empty())
+}
+
+fn respond(status: u16, body: serde_json::Value) {
+    send_response(
+        status,
+        vec![("Content-Type".to_string(), "application/json".to_string())],
+        body.to_string().into_bytes(),
+    );
+}
+
+/// The served channel the payload names, by its name or as
+/// `<workspace>/<channel>` where a name is served in several workspaces.
+fn served_channel(name: &str) -> Option<(String, String)> {
+    let name = name.trim().trim_start_matches('#');
+    served_channels()
+        .into_iter()
+        .find(|(workspace, channel)| channel == name || format!("{workspace}/{channel}") == name)
+}
+
+/// Handles a trigger: checks the token, answers 202 with the id of the run
+/// right away, since callers like Zapier give up on slow answers, and then
+/// posts the digest to the channel like a scheduled one.
+pub(crate) fn handle_trigger_request(headers: Vec<(String, String)>, body: Vec<u8>) {
+    start_run("trigger");
+    let token = config::var("trigger_token").unwrap_or_default();
+    if token.trim().is_empty() || header(&headers, TOKEN_HEADER) != Some(token.trim()) {
+        Event::warn("trigger").log("refused a trigger without the token");
+        respond(401, json!({ "error": "the token is missing or wrong" }));
+        return;
+    }
+    let payload = match serde_json::from_slice::<TriggerPayload>(&body) {
+        Ok(payload) => payload,
+        Err(e) => {
+            respond(
+                400,
+                json!({ "error": format!("the payload needs repo and channel: {e}") }),
+            );
+            return;
+        }
+    };
+    let Some((workspace, channel)) = served_channel(&payload.channel) else {
+        respond(
+            400,
+            json!({ "error": format!("{} is not a channel the bot serves", payload.channel) }),
+        );
+        return;
+    };
+    let owner_repo = match linear_team(payload.repo.trim()) {
+        Some(team) => format!("{LINEAR_OWNER}/{team}"),
+        None => payload.repo.trim().to_string(),
+    };
+    let Some((owner, repo)) = owner_repo
+        .split_once('/')
+        .filter(|(owner, repo)| !owner.is_empty() && !repo.is_empty())
+    else {
+        respond(
+            400,
+            json!({ "error": "repo has to be <github_owner>/<github_repo> or linear:<TEAM>" }),
+        );
+        return;
+    };
+    if let Some(refusal) = repo_refusal(owner, repo) {
+        respond(403, json!({ "error": refusal }));
+        return;
+    }
+    let output = match payload.output.as_deref().map(str::trim) {
+        Some(mode) => match OutputMode::parse(mode) {
+            Some(mode) => Some(mode),
+            None => {
+                respond(400, json!({ "error": "output has to be full or brief" }));
+                return;
+            }
+        },
+        None => None,
+    };
+
+    let run = run_id();
+    respond(
+        202,
+        json!({ "run": run, "repo": owner_repo, "channel": format!("{workspace}/{channel}") }),
+    );
+    run_trigger(&run, &workspace, &channel, owner, repo, &payload, output);
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn run_trigger(
+    run: &str,
+    workspace: &str,
+    channel: &str,
+    owner: &str,
+    repo: &str,
+    payload: &TriggerPayload,
+    output: Option<OutputMode>,
+) {
+    let settings = resolved_settings(workspace, channel);
+    let days = payload.days.or(settings.days).unwrap_or(7);
+    if !enter_run_queue(workspace, channel, run, false).await {
+        send_message(
+            workspace,
+            channel,
+            format!("The digest for {owner}/{repo} requested through the webhook was skipped, the runs before it took too long."),
+        );
+        return;
+    }
+    send_message(
+        workspace,
+        channel,
+        format!("Digest for {owner}/{repo} (last {days} days), requested through the webhook:"),
+    );
+    let labels = payload
+        .labels
+        .iter()
+        .map(|label| label.trim().to_string())
+        .filter(|label| !label.is_empty())
+        .collect::<Vec<_>>();
+    let summarized = summarize(
+        &Deps::live(),
+        run,
+        workspace,
+        channel,
+        owner,
+        repo,
+        days,
+        &[],
+        &labels,
+        output,
+        issue_limit(payload.limit, &settings),
+        // shown when the search fails, to check the names against
+        &format!("{{\"repo\": \"{}\", \"days\": {days}}}", payload.repo),
+    )
+    .await;
+    leave_run_queue(workspace, channel, run);
+    let outcome = match &summarized {
+        Ok(posted) => format!("posted {posted} summaries"),
+        Err(e) => {
+            Event::error("trigger").err(e).log("the digest failed");
+            send_message(
+                workspace,
+                channel,
+                format!(":warning: The digest requested through the webhook failed: {e}"),
+            );
+            format!("failed: {e}")
+        }
+    };
+    audit(
+        "webhook",
+        "triggered digest",
+        Some((owner, repo)),
+        &outcome,
+        true,
+    );
+}
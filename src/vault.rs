@@ -0,0 +1,185 @@
+//! Notes of the summarized issues for a git-backed Markdown vault, such as
+//! one Obsidian syncs with git, committed to a GitHub repository.
+
+use crate::audit::audit;
+use crate::config::{vault_branch, vault_repo};
+use crate::github::read_repo_file;
+use crate::render::markdown;
+use chrono::Utc;
+use github_flows::{get_octo, GithubLogin::Default};
+use serde_json::{json, Value};
+
+/// The summary of a note sits between these markers, so that an updated
+/// summary replaces only it and annotations around it stay.
+const SUMMARY_START: &str = "<!-- summary -->";
+const SUMMARY_END: &str = "<!-- /summary -->";
+
+/// A summarized issue to write a note of.
+pub(crate) struct VaultNote {
+    pub(crate) number: u64,
+    pub(crate) title: String,
+    pub(crate) labels: Vec<String>,
+    pub(crate) url: String,
+    pub(crate) summary: String,
+}
+
+fn note_path(dir: &str, owner: &str, repo: &str, number: u64) -> String {
+    format!("{dir}/{owner}/{repo}/{repo}-{number}.md")
+        .trim_start_matches('/')
+        .to_string()
+}
+
+/// The note of the issue. The front matter and the summary of an existing
+/// note are replaced, whatever else was written in it is kept.
+fn render_note(owner: &str, repo: &str, note: &VaultNote, existing: Option<&str>) -> String {
+    // JSON strings and arrays are valid YAML
+    let front_matter = format!(
+        "---\nrepo: {}\nnumber: {}\ntitle: {}\nlabels: {}\nurl: {}\nsummarized: {}\ntags: [github-issue]\n---\n",
+        json!(format!("{owner}/{repo}")),
+        note.number,
+        json!(note.title),
+        json!(note.labels),
+        json!(note.url),
+        Utc::now().to_rfc3339()
+    );
+    let summary = format!(
+        "{SUMMARY_START}\n{}\n{SUMMARY_END}",
+        markdown(&note.summary)
+    );
+    let Some(existing) = existing else {
+        return format!(
+            "{front_matter}\n# {owner}/{repo}#{}: {}\n\n{summary}\n\n## Notes\n\n",
+            note.number, note.title
+        );
+    };
+    let body = existing
+        .strip_prefix("---\n")
+        .and_then(|rest| rest.split_once("\n---\n"))
+        .map_or(existing, |(_, body)| body);
+    let body = match (body.find(SUMMARY_START), body.find(SUMMARY_END)) {
+        (Some(start), Some(end)) if start < end => format!(
+            "{}{summary}{}",
+            &body[..start],
+            &body[end + SUMMARY_END.len()..]
+        ),
+        // the markers were removed, the summary goes below what is there
+        _ => format!("{}\n\n{summary}\n", body.trim_end()),
+    };
+    format!("{front_matter}{body}")
+}
+
+fn as_error(e: github_flows::octocrab::Error, vault_owner: &str, vault_name: &str) -> String {
+    match e {
+        github_flows::octocrab::Error::GitHub { source, .. } => format!(
+            "GitHub refused the notes ({}). Please make sure the connected GitHub account can push to {vault_owner}/{vault_name}.",
+            source.message
+        ),
+        _ => "the request to GitHub failed.".to_string(),
+    }
+}
+
+/// Writes the notes of the summarized issues of a repository to the
+/// `vault_repo` in a single commit on the `vault_branch`, so a digest adds
+/// one commit however many issues it covers. Returns the URL of the commit.
+pub(crate) async fn export_notes_to_vault(
+    owner: &str,
+    repo: &str,
+    notes: &[VaultNote],
+) -> Option<Result<String, String>> {
+    let (vault_owner, vault_name, dir) = vault_repo()?;
+    if notes.is_empty() {
+        return None;
+    }
+    let exported = commit_notes(owner, repo, notes, &vault_owner, &vault_name, &dir).await;
+    let outcome = match &exported {
+        Ok(url) => format!("committed {} notes in {url}", notes.len()),
+        Err(e) => format!("failed: {e}"),
+    };
+    audit(
+        "flow",
+        &format!("exported notes of {owner}/{repo} to the vault"),
+        Some((&vault_owner, &vault_name)),
+        &outcome,
+        false,
+    );
+    Some(exported)
+}
+
+/// Commits the notes through the Git Data API: a tree with the notes on top
+/// of the branch's, a commit of it, and the branch moved to the commit.
+async fn commit_notes(
+    owner: &str,
+    repo: &str,
+    notes: &[VaultNote],
+    vault_owner: &str,
+    vault_name: &str,
+    dir: &str,
+) -> Result<String, String> {
+    let octocrab = get_octo(&Default);
+    let error = |e| as_error(e, vault_owner, vault_name);
+    let route = format!("repos/{vault_owner}/{vault_name}");
+    let branch = match vault_branch() {
+        Some(branch) => branch,
+        None => octocrab
+            .get::<Value, _, ()>(&route, None)
+            .await
+            .map_err(error)?["default_branch"]
+            .as_str()
+            .unwrap_or("main")
+            .to_string(),
+    };
+    let head = octocrab
+        .get::<Value, _, ()>(format!("{route}/git/ref/heads/{branch}"), None)
+        .await
+        .map_err(error)?;
+    let parent = head["object"]["sha"].as_str().unwrap_or_default();
+    let commit = octocrab
+        .get::<Value, _, ()>(format!("{route}/git/commits/{parent}"), None)
+        .await
+        .map_err(error)?;
+
+    let mut tree = Vec::new();
+    for note in notes {
+        let path = note_path(dir, owner, repo, note.number);
+        let existing = read_repo_file(vault_owner, vault_name, &path, Some(&branch)).await;
+        let content = render_note(
+            owner,
+            repo,
+            note,
+            existing.as_ref().map(|(c, _)| c.as_str()),
+        );
+        tree.push(json!({ "path": path, "mode": "100644", "type": "blob", "content": content }));
+    }
+    let tree = octocrab
+        .post::<_, Value>(
+            format!("{route}/git/trees"),
+            Some(&json!({ "base_tree": commit["tree"]["sha"], "tree": tree })),
+        )
+        .await
+        .map_err(error)?;
+    let message = match notes {
+        [note] => format!("Add the summary of {owner}/{repo}#{}", note.number),
+        _ => format!(
+            "Add the summaries of {} issues of {owner}/{repo}",
+            notes.len()
+        ),
+    };
+    let new_commit = octocrab
+        .post::<_, Value>(
+            format!("{route}/git/commits"),
+            Some(&json!({ "message": message, "tree": tree["sha"], "parents": [parent] })),
+        )
+        .await
+        .map_err(error)?;
+    octocrab
+        .patch::<Value, _, _>(
+            format!("{route}/git/refs/heads/{branch}"),
+            Some(&json!({ "sha": new_commit["sha"] })),
+        )
+        .await
+        .map_err(error)?;
+    Ok(new_commit["html_url"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string())
+}